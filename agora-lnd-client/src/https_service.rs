@@ -3,7 +3,7 @@ use {
   hyper::{client::connect::HttpConnector, Body, Request, Response},
   hyper_openssl::HttpsConnector,
   openssl::ssl::{SslConnector, SslMethod},
-  openssl::x509::X509,
+  openssl::x509::{store::X509StoreBuilder, X509},
   std::task::{Context, Poll},
   tonic::body::BoxBody,
 };
@@ -18,12 +18,21 @@ impl HttpsService {
   pub(crate) fn new(
     authority: Authority,
     certificate: Option<X509>,
+    pin_certificate: bool,
   ) -> Result<Self, openssl::error::ErrorStack> {
     let mut http_connector = HttpConnector::new();
     http_connector.enforce_http(false);
+    // `SslConnector::builder` loads the system's trusted root certificates,
+    // so LND endpoints with CA-signed certificates work without `certificate`
     let mut ssl_connector = SslConnector::builder(SslMethod::tls_client())?;
     if let Some(certificate) = certificate {
-      ssl_connector.cert_store_mut().add_cert(certificate)?;
+      if pin_certificate {
+        let mut cert_store = X509StoreBuilder::new()?;
+        cert_store.add_cert(certificate)?;
+        ssl_connector.set_cert_store(cert_store.build());
+      } else {
+        ssl_connector.cert_store_mut().add_cert(certificate)?;
+      }
     }
     let hyper_client =
       hyper::Client::builder()
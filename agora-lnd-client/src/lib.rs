@@ -2,25 +2,29 @@ use {
   crate::https_service::HttpsService,
   http::uri::Authority,
   lnrpc::{
-    lightning_client::LightningClient, AddInvoiceResponse, Invoice, ListInvoiceRequest, PaymentHash,
+    lightning_client::LightningClient, AddInvoiceResponse, GetInfoRequest, Invoice,
+    InvoiceSubscription, ListInvoiceRequest, PaymentHash, SendRequest,
   },
   openssl::x509::X509,
-  std::convert::TryInto,
+  std::{collections::HashMap, convert::TryInto},
   tonic::{
     metadata::AsciiMetadataValue,
     service::interceptor::{InterceptedService, Interceptor},
-    Code, Request, Status,
+    Code, Request, Status, Streaming,
   },
 };
 
 #[cfg(test)]
 use {lnd_test_context::LndTestContext, std::sync::Arc};
 
-pub use millisatoshi::Millisatoshi;
+pub use millisatoshi::{Millisatoshi, ParseMillisatoshiError};
 
 mod https_service;
 mod millisatoshi;
 
+/// TLV record type under which keysend payments carry their preimage.
+const KEYSEND_PREIMAGE_RECORD: u64 = 5482373484;
+
 pub mod lnrpc {
   use crate::millisatoshi::Millisatoshi;
   use std::convert::TryInto;
@@ -64,10 +68,11 @@ impl Client {
   pub async fn new(
     authority: Authority,
     certificate: Option<X509>,
+    pin_certificate: bool,
     macaroon: Option<Vec<u8>>,
     #[cfg(test)] lnd_test_context: LndTestContext,
   ) -> Result<Client, openssl::error::ErrorStack> {
-    let grpc_service = HttpsService::new(authority, certificate)?;
+    let grpc_service = HttpsService::new(authority, certificate, pin_certificate)?;
 
     let macaroon = macaroon.map(|macaroon| {
       hex::encode_upper(macaroon)
@@ -101,8 +106,35 @@ impl Client {
     &mut self,
     memo: &str,
     value_msat: Millisatoshi,
+    expiry_seconds: Option<i64>,
+  ) -> Result<AddInvoiceResponse, Status> {
+    let invoice = Self::invoice(memo, value_msat, expiry_seconds)?;
+    Ok(self.inner.add_invoice(invoice).await?.into_inner())
+  }
+
+  /// Add an invoice whose payment request commits to `description_hash`
+  /// instead of `memo`, as required by LNURL-pay. The memo is still stored
+  /// with the invoice.
+  pub async fn add_invoice_with_description_hash(
+    &mut self,
+    memo: &str,
+    value_msat: Millisatoshi,
+    description_hash: [u8; 32],
+    expiry_seconds: Option<i64>,
   ) -> Result<AddInvoiceResponse, Status> {
-    let request = tonic::Request::new(Invoice {
+    let invoice = Invoice {
+      description_hash: description_hash.to_vec(),
+      ..Self::invoice(memo, value_msat, expiry_seconds)?
+    };
+    Ok(self.inner.add_invoice(invoice).await?.into_inner())
+  }
+
+  fn invoice(
+    memo: &str,
+    value_msat: Millisatoshi,
+    expiry_seconds: Option<i64>,
+  ) -> Result<Invoice, Status> {
+    Ok(Invoice {
       memo: memo.to_owned(),
       value_msat: value_msat.value().try_into().map_err(|source| {
         Status::new(
@@ -110,9 +142,70 @@ impl Client {
           format!("invalid value for `value_msat`: {}", source),
         )
       })?,
+      expiry: expiry_seconds.unwrap_or_default(),
       ..Invoice::default()
+    })
+  }
+
+  /// Send `amount` to the node with public key `destination` with a
+  /// spontaneous keysend payment, which doesn't need an invoice from the
+  /// receiver. Requires a macaroon with permission to send payments.
+  pub async fn keysend(&mut self, destination: &[u8], amount: Millisatoshi) -> Result<(), Status> {
+    let mut preimage = [0; 32];
+    openssl::rand::rand_bytes(&mut preimage).map_err(|error| {
+      Status::new(
+        Code::Internal,
+        format!("failed to generate preimage: {}", error),
+      )
+    })?;
+    let mut dest_custom_records = HashMap::new();
+    dest_custom_records.insert(KEYSEND_PREIMAGE_RECORD, preimage.to_vec());
+    let request = tonic::Request::new(SendRequest {
+      dest: destination.to_vec(),
+      amt_msat: amount.value().try_into().map_err(|source| {
+        Status::new(
+          Code::InvalidArgument,
+          format!("invalid value for `amt_msat`: {}", source),
+        )
+      })?,
+      payment_hash: openssl::sha::sha256(&preimage).to_vec(),
+      dest_custom_records,
+      ..SendRequest::default()
     });
-    Ok(self.inner.add_invoice(request).await?.into_inner())
+    let response = self.inner.send_payment_sync(request).await?.into_inner();
+    if response.payment_error.is_empty() {
+      Ok(())
+    } else {
+      Err(Status::new(Code::Unknown, response.payment_error))
+    }
+  }
+
+  /// Height of the best block known to the node. Requires a macaroon with
+  /// permission to read node info, which the invoice macaroon doesn't have.
+  pub async fn block_height(&mut self) -> Result<u32, Status> {
+    let request = tonic::Request::new(GetInfoRequest {});
+    Ok(
+      self
+        .inner
+        .get_info(request)
+        .await?
+        .into_inner()
+        .block_height,
+    )
+  }
+
+  /// Subscribe to updates of this node's invoices. If `settle_index` is
+  /// non-zero, invoices settled after the invoice with that settle index
+  /// are sent first, so that a subscriber can catch up after reconnecting.
+  pub async fn subscribe_invoices(
+    &mut self,
+    settle_index: u64,
+  ) -> Result<Streaming<Invoice>, Status> {
+    let request = tonic::Request::new(InvoiceSubscription {
+      add_index: 0,
+      settle_index,
+    });
+    Ok(self.inner.subscribe_invoices(request).await?.into_inner())
   }
 
   pub async fn lookup_invoice(&mut self, r_hash: [u8; 32]) -> Result<Option<Invoice>, Status> {
@@ -214,7 +307,7 @@ jlZBq5hr8Nv2qStFfw9qzw==
   async fn add_invoice() {
     let mut client = Client::with_test_context(LndTestContext::new().await).await;
     let response = client
-      .add_invoice("", Millisatoshi::new(1_000))
+      .add_invoice("", Millisatoshi::new(1_000), None)
       .await
       .unwrap();
     assert!(
@@ -228,7 +321,7 @@ jlZBq5hr8Nv2qStFfw9qzw==
   async fn add_invoice_memo_and_value() {
     let mut client = Client::with_test_context(LndTestContext::new().await).await;
     let r_hash = client
-      .add_invoice("test-memo", Millisatoshi::new(42_000))
+      .add_invoice("test-memo", Millisatoshi::new(42_000), None)
       .await
       .unwrap()
       .r_hash;
@@ -241,19 +334,89 @@ jlZBq5hr8Nv2qStFfw9qzw==
     assert_eq!(invoice.value, 42);
   }
 
+  #[tokio::test]
+  async fn add_invoice_expiry() {
+    let mut client = Client::with_test_context(LndTestContext::new().await).await;
+    let r_hash = client
+      .add_invoice("", Millisatoshi::new(1_000), Some(600))
+      .await
+      .unwrap()
+      .r_hash;
+    let invoice = client
+      .lookup_invoice(r_hash.try_into().unwrap())
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(invoice.expiry, 600);
+  }
+
+  #[tokio::test]
+  async fn add_invoice_with_description_hash() {
+    let mut client = Client::with_test_context(LndTestContext::new().await).await;
+    let r_hash = client
+      .add_invoice_with_description_hash("test-memo", Millisatoshi::new(1_000), [1; 32], None)
+      .await
+      .unwrap()
+      .r_hash;
+    let invoice = client
+      .lookup_invoice(r_hash.try_into().unwrap())
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(invoice.memo, "test-memo");
+    assert_eq!(invoice.description_hash, [1; 32]);
+  }
+
+  #[tokio::test]
+  async fn subscribe_invoices() {
+    let mut client = Client::with_test_context(LndTestContext::new().await).await;
+    let mut invoices = client.subscribe_invoices(0).await.unwrap();
+    let created = client
+      .add_invoice("test-memo", Millisatoshi::new(1_000), None)
+      .await
+      .unwrap();
+    let invoice = invoices.message().await.unwrap().unwrap();
+    assert_eq!(invoice.r_hash, created.r_hash);
+    assert_eq!(invoice.memo, "test-memo");
+  }
+
+  #[tokio::test]
+  async fn block_height() {
+    let lnd_test_context = LndTestContext::new().await;
+    let cert = std::fs::read_to_string(lnd_test_context.cert_path()).unwrap();
+    let macaroon = tokio::fs::read(lnd_test_context.admin_macaroon_path())
+      .await
+      .unwrap();
+    let mut client = Client::new(
+      lnd_test_context.lnd_rpc_authority().parse().unwrap(),
+      Some(X509::from_pem(cert.as_bytes()).unwrap()),
+      false,
+      Some(macaroon),
+      lnd_test_context,
+    )
+    .await
+    .unwrap();
+    let before = client.block_height().await.unwrap();
+    client._lnd_test_context.generate_blocks(2).await;
+    while client.block_height().await.unwrap() < before + 2 {
+      std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert_eq!(client.block_height().await.unwrap(), before + 2);
+  }
+
   #[tokio::test]
   async fn lookup_invoice() {
     let mut client = Client::with_test_context(LndTestContext::new().await).await;
     let _ignored1 = client
-      .add_invoice("foo", Millisatoshi::new(1_000))
+      .add_invoice("foo", Millisatoshi::new(1_000), None)
       .await
       .unwrap();
     let created = client
-      .add_invoice("bar", Millisatoshi::new(2_000))
+      .add_invoice("bar", Millisatoshi::new(2_000), None)
       .await
       .unwrap();
     let _ignored2 = client
-      .add_invoice("baz", Millisatoshi::new(3_000))
+      .add_invoice("baz", Millisatoshi::new(3_000), None)
       .await
       .unwrap();
     let retrieved = client
@@ -289,7 +452,7 @@ jlZBq5hr8Nv2qStFfw9qzw==
   async fn lookup_invoice_not_found_some_invoices() {
     let mut client = Client::with_test_context(LndTestContext::new().await).await;
     let _ignored1 = client
-      .add_invoice("foo", Millisatoshi::new(1_000))
+      .add_invoice("foo", Millisatoshi::new(1_000), None)
       .await
       .unwrap();
     assert_eq!(client.lookup_invoice([0; 32]).await.unwrap(), None);
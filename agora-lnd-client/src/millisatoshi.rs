@@ -4,20 +4,83 @@ use {
     de::{self, Visitor},
     Deserialize, Deserializer,
   },
-  std::fmt::{self, Display, Formatter},
+  std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+  },
 };
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Millisatoshi(u64);
 
 impl Millisatoshi {
-  pub(crate) fn value(self) -> u64 {
+  pub fn value(self) -> u64 {
     self.0
   }
 
   pub fn new(value: u64) -> Self {
     Self(value)
   }
+
+  /// Round to the nearest whole satoshi, rounding halves up.
+  pub fn round_to_satoshi(self) -> Self {
+    Self(self.0.saturating_add(500) / 1000 * 1000)
+  }
+}
+
+/// Parses amounts in `msat`, `sat`, or `btc`, like `1000 sat`, `1.5 sat`,
+/// or `0.00001 btc`, or integer numbers of millisatoshis without a unit.
+/// Fractional amounts must be whole numbers of millisatoshis.
+impl FromStr for Millisatoshi {
+  type Err = ParseMillisatoshiError;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    let regex =
+      Regex::new(r"^([0-9]+)(?:\.([0-9]+))?(?: (msat|sat|btc))?$").expect("regex is valid");
+    let captures = regex.captures(input).ok_or(ParseMillisatoshiError)?;
+    let decimals = match captures.get(3).map(|unit| unit.as_str()) {
+      None | Some("msat") => 0,
+      Some("sat") => 3,
+      Some("btc") => 11,
+      Some(_) => unreachable!("regex only matches known units"),
+    };
+    let fraction = captures
+      .get(2)
+      .map(|fraction| fraction.as_str().trim_end_matches('0'))
+      .unwrap_or_default();
+    if fraction.len() > decimals {
+      return Err(ParseMillisatoshiError);
+    }
+    let whole = captures[1]
+      .parse::<u64>()
+      .ok()
+      .and_then(|whole| whole.checked_mul(10u64.pow(decimals as u32)))
+      .ok_or(ParseMillisatoshiError)?;
+    let fraction = if fraction.is_empty() {
+      0
+    } else {
+      fraction
+        .parse::<u64>()
+        .map_err(|_| ParseMillisatoshiError)?
+        * 10u64.pow((decimals - fraction.len()) as u32)
+    };
+    whole
+      .checked_add(fraction)
+      .map(Millisatoshi)
+      .ok_or(ParseMillisatoshiError)
+  }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct ParseMillisatoshiError;
+
+impl Display for ParseMillisatoshiError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(
+      f,
+      "expected amount in `msat`, `sat`, or `btc`, e.g. \"1000 sat\", that is a whole number of millisatoshis"
+    )
+  }
 }
 
 impl<'de> Deserialize<'de> for Millisatoshi {
@@ -42,15 +105,12 @@ impl<'de> Visitor<'de> for MillisatoshiVisitor {
   where
     E: de::Error,
   {
-    let regex = Regex::new(r"^([0-9]*) sat$").expect("regex is valid");
-    let captures = regex.captures(value).ok_or_else(|| {
+    value.parse().map_err(|_| {
       de::Error::invalid_value(
         de::Unexpected::Str(value),
-        &"integer number of satoshis, including unit, e.g. \"1000 sat\"",
+        &"amount in `msat`, `sat`, or `btc`, e.g. \"1000 sat\", that is a whole number of millisatoshis",
       )
-    })?;
-    let value = captures[1].parse::<u64>().unwrap();
-    Ok(Millisatoshi(value * 1000))
+    })
   }
 }
 
@@ -90,7 +150,7 @@ mod tests {
 
   fn invalid_value(input: &str) {
     let expected = format!(
-      "invalid value: string \"{}\", expected integer number of satoshis, including unit, e.g. \"1000 sat\" at line 1 column 1",
+      "invalid value: string \"{}\", expected amount in `msat`, `sat`, or `btc`, e.g. \"1000 sat\", that is a whole number of millisatoshis at line 1 column 1",
       serde_yaml::from_str::<String>(input).unwrap(),
     );
     assert_eq!(
@@ -113,22 +173,58 @@ mod tests {
 
   #[test]
   fn wrong_unit() {
-    invalid_value("1 msat");
+    invalid_value("1 bit");
+    invalid_value("1 SAT");
+  }
+
+  #[test]
+  fn missing_space() {
+    invalid_value("1sat");
   }
 
   #[test]
   fn missing_unit() {
-    invalid_value("\"1\"");
+    assert_eq!(
+      serde_yaml::from_str::<Millisatoshi>("\"1\"").unwrap(),
+      Millisatoshi::new(1)
+    );
+    invalid_value("1.5");
   }
 
   #[test]
   fn number_type() {
-    invalid_value("1");
+    assert_eq!(
+      serde_yaml::from_str::<Millisatoshi>("1").unwrap(),
+      Millisatoshi::new(1)
+    );
   }
 
   #[test]
   fn decimal_point() {
-    invalid_value("1.1 sat");
+    assert_eq!(
+      serde_yaml::from_str::<Millisatoshi>("1.1 sat").unwrap(),
+      Millisatoshi::new(1_100)
+    );
+    assert_eq!(
+      serde_yaml::from_str::<Millisatoshi>("1.001000 sat").unwrap(),
+      Millisatoshi::new(1_001)
+    );
+    invalid_value("1.0001 sat");
+    invalid_value("1.1 msat");
+    invalid_value("1. sat");
+    invalid_value(".1 sat");
+  }
+
+  #[test]
+  fn units() {
+    assert_eq!("3 msat".parse(), Ok(Millisatoshi::new(3)));
+    assert_eq!("3 sat".parse(), Ok(Millisatoshi::new(3_000)));
+    assert_eq!("3 btc".parse(), Ok(Millisatoshi::new(300_000_000_000)));
+    assert_eq!("0.00000000001 btc".parse(), Ok(Millisatoshi::new(1)));
+    assert_eq!(
+      "0.000000000001 btc".parse::<Millisatoshi>(),
+      Err(ParseMillisatoshiError)
+    );
   }
 
   #[test]
@@ -136,6 +232,28 @@ mod tests {
     invalid_value("-1 sat");
   }
 
+  #[test]
+  fn missing_number() {
+    invalid_value("\" sat\"");
+  }
+
+  #[test]
+  fn overflow() {
+    invalid_value("18446744073709552 sat");
+    invalid_value("184467440.73709552 btc");
+    invalid_value("18446744073709551616 msat");
+  }
+
+  #[test]
+  fn from_str() {
+    assert_eq!("3 sat".parse(), Ok(Millisatoshi::new(3_000)));
+    assert_eq!("3".parse(), Ok(Millisatoshi::new(3)));
+    assert_eq!(
+      "3 sats".parse::<Millisatoshi>(),
+      Err(ParseMillisatoshiError)
+    );
+  }
+
   #[test]
   fn list_input() {
     assert_eq!(
@@ -146,6 +264,26 @@ mod tests {
     );
   }
 
+  #[test]
+  fn round_to_satoshi() {
+    assert_eq!(
+      Millisatoshi::new(0).round_to_satoshi(),
+      Millisatoshi::new(0)
+    );
+    assert_eq!(
+      Millisatoshi::new(1_499).round_to_satoshi(),
+      Millisatoshi::new(1_000)
+    );
+    assert_eq!(
+      Millisatoshi::new(1_500).round_to_satoshi(),
+      Millisatoshi::new(2_000)
+    );
+    assert_eq!(
+      Millisatoshi::new(2_000).round_to_satoshi(),
+      Millisatoshi::new(2_000)
+    );
+  }
+
   #[test]
   fn display_singular() {
     assert_eq!(Millisatoshi::new(1000).to_string(), "1 satoshi");
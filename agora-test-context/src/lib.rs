@@ -6,7 +6,7 @@ use {
     fs,
     io::{BufRead, BufReader, Read},
     path::{Path, PathBuf},
-    process::{Child, ChildStderr, Command, Stdio},
+    process::{Child, ChildStderr, Command, ExitStatus, Stdio},
   },
   tempfile::TempDir,
 };
@@ -67,6 +67,10 @@ impl AgoraTestContext {
     self.collected_stderr
   }
 
+  pub fn pid(&self) -> u32 {
+    self.child.id()
+  }
+
   pub fn port(&self) -> u16 {
     self.port
   }
@@ -107,6 +111,15 @@ impl AgoraTestContext {
     self.get(url).text().unwrap()
   }
 
+  pub fn wait(mut self) -> (ExitStatus, String) {
+    let status = self.child.wait().unwrap();
+    self
+      .stderr
+      .read_to_string(&mut self.collected_stderr)
+      .unwrap();
+    (status, self.collected_stderr)
+  }
+
   pub fn write(&self, path: &str, content: &str) -> PathBuf {
     let path = self.files_directory().join(path);
     std::fs::create_dir_all(path.parent().unwrap()).unwrap();
@@ -120,6 +133,7 @@ pub struct Builder {
   args: Vec<String>,
   backtraces: bool,
   current_dir: Option<String>,
+  env: Vec<(String, String)>,
   files_directory: String,
   http_port: Option<u16>,
   tempdir: TempDir,
@@ -148,6 +162,17 @@ impl Builder {
     Self { backtraces, ..self }
   }
 
+  pub fn env(self, key: &str, value: &str) -> Self {
+    Self {
+      env: self
+        .env
+        .into_iter()
+        .chain(Some((key.to_owned(), value.to_owned())))
+        .collect(),
+      ..self
+    }
+  }
+
   pub fn build(self) -> AgoraTestContext {
     let mut command = Command::new(executable_path("agora"));
 
@@ -182,6 +207,8 @@ impl Builder {
       command.env("AGORA_SUPPRESS_BACKTRACE", "");
     }
 
+    command.envs(self.env);
+
     let mut child = dbg!(command).spawn().unwrap();
 
     let mut first_line = String::new();
@@ -190,8 +217,10 @@ impl Builder {
     child_stderr.read_line(&mut first_line).unwrap();
     eprintln!("First line: {}", first_line);
     let port_string = first_line
+      .split('`')
+      .nth(1)
+      .unwrap_or(&first_line)
       .trim()
-      .trim_end_matches('`')
       .split(':')
       .last()
       .expect(&format!(
@@ -234,6 +263,7 @@ impl Builder {
       address: Some("localhost".to_owned()),
       args: Vec::new(),
       backtraces: false,
+      env: Vec::new(),
       files_directory: "files".to_owned(),
       http_port: Some(0),
       tempdir: tempfile::tempdir().unwrap(),
@@ -181,6 +181,12 @@ impl LndTestContext {
     self.lnd_dir().join("tls.cert")
   }
 
+  pub fn admin_macaroon_path(&self) -> PathBuf {
+    self
+      .lnd_dir()
+      .join("data/chain/bitcoin/regtest/admin.macaroon")
+  }
+
   pub fn invoice_macaroon_path(&self) -> PathBuf {
     self
       .lnd_dir()
@@ -262,6 +268,11 @@ impl LndTestContext {
     }
   }
 
+  pub async fn generate_blocks(&self, n: i32) {
+    self.mine_blocks(n).await;
+    self.wait_to_sync().await;
+  }
+
   pub async fn generate_lnd_btc(&self) {
     self.generate_bitcoind_btc().await;
     let lnd_new_address = self.run_lncli_command(("newaddress", "p2wkh")).await["address"]
@@ -0,0 +1,153 @@
+use {
+  crate::common::*,
+  hyper::{body::HttpBody, Method, Uri, Version},
+  std::{fs::File, sync::Mutex},
+};
+
+/// Access log from `--access-log`, with one line per request in the
+/// Combined Log Format used by Apache and nginx, so that existing tools can
+/// analyze it.
+#[derive(Clone, Debug)]
+pub(crate) struct AccessLog {
+  file: Arc<Mutex<File>>,
+}
+
+/// The parts of a request that are logged, which are saved before the
+/// request is handled, since handling consumes it.
+pub(crate) struct AccessLogRequest {
+  method: Method,
+  uri: Uri,
+  version: Version,
+  referer: Option<String>,
+  user_agent: Option<String>,
+}
+
+impl AccessLogRequest {
+  pub(crate) fn new(request: &Request<Body>) -> Self {
+    let header = |name| {
+      request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+    };
+    Self {
+      method: request.method().clone(),
+      uri: request.uri().clone(),
+      version: request.version(),
+      referer: header(header::REFERER),
+      user_agent: header(header::USER_AGENT),
+    }
+  }
+}
+
+impl AccessLog {
+  pub(crate) fn open(environment: &Environment, arguments: &Arguments) -> Result<Option<Self>> {
+    let path = match &arguments.access_log {
+      Some(path) => environment.working_directory.join(path),
+      None => return Ok(None),
+    };
+    let file = fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&path)
+      .context(error::FilesystemIo { path: &path })?;
+    Ok(Some(Self {
+      file: Arc::new(Mutex::new(file)),
+    }))
+  }
+
+  pub(crate) fn log(
+    &self,
+    remote_ip: Option<IpAddr>,
+    request: &AccessLogRequest,
+    response: &Response<Body>,
+  ) {
+    let line = Self::line(remote_ip, request, response, SystemTime::now());
+    if let Err(error) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+      log::warn!("Failed to write to access log: {}", error);
+    }
+  }
+
+  fn line(
+    remote_ip: Option<IpAddr>,
+    request: &AccessLogRequest,
+    response: &Response<Body>,
+    time: SystemTime,
+  ) -> String {
+    // Streamed bodies, like file downloads, have no known size up front
+    let bytes = HttpBody::size_hint(response.body())
+      .exact()
+      .map(|bytes| bytes.to_string())
+      .unwrap_or_else(|| "-".to_owned());
+    let quoted = |value: Option<&str>| match value {
+      Some(value) => format!("\"{}\"", escape(value)),
+      None => "\"-\"".to_owned(),
+    };
+    format!(
+      "{} - - [{}] \"{} {} {:?}\" {} {} {} {}\n",
+      remote_ip
+        .map(|remote_ip| remote_ip.to_string())
+        .unwrap_or_else(|| "-".to_owned()),
+      clf_time(time),
+      request.method,
+      escape(&request.uri.to_string()),
+      request.version,
+      response.status().as_u16(),
+      bytes,
+      quoted(request.referer.as_deref()),
+      quoted(request.user_agent.as_deref()),
+    )
+  }
+}
+
+/// Escape quotes, backslashes, and control characters, so that values sent
+/// by clients can't break the log's format.
+fn escape(value: &str) -> String {
+  value.chars().flat_map(char::escape_default).collect()
+}
+
+/// Time in the Common Log Format, like `10/Oct/2000:13:55:36 +0000`, in UTC.
+fn clf_time(time: SystemTime) -> String {
+  const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+  ];
+  let timestamp = humantime::format_rfc3339_seconds(time).to_string();
+  let month = timestamp[5..7].parse::<usize>().unwrap_or(1);
+  format!(
+    "{}/{}/{}:{} +0000",
+    &timestamp[8..10],
+    MONTHS[(month - 1) % 12],
+    &timestamp[0..4],
+    &timestamp[11..19],
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn line() {
+    let request = AccessLogRequest::new(
+      &Request::builder()
+        .uri("/files/foo?bar")
+        .header(header::USER_AGENT, "curl/7.68.0 \"quoted\"")
+        .body(Body::empty())
+        .unwrap(),
+    );
+    let response = Response::builder()
+      .status(StatusCode::NOT_FOUND)
+      .body(Body::from("hello"))
+      .unwrap();
+    assert_eq!(
+      AccessLog::line(
+        Some(IpAddr::from([127, 0, 0, 1])),
+        &request,
+        &response,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_001),
+      ),
+      "127.0.0.1 - - [09/Sep/2001:01:46:41 +0000] \"GET /files/foo?bar HTTP/1.1\" 404 5 \"-\" \"curl/7.68.0 \\\"quoted\\\"\"\n",
+    );
+  }
+}
@@ -0,0 +1,313 @@
+use {
+  crate::common::*,
+  flate2::{write::DeflateEncoder, Compression, Crc},
+  hyper::body::Sender,
+  tokio::io::AsyncReadExt,
+};
+
+/// Archives use the original zip format, without zip64 extensions, so
+/// entry counts and offsets are limited to 16 and 32 bits.
+pub(crate) const MAX_ENTRIES: usize = u16::MAX as usize;
+pub(crate) const MAX_SIZE: u64 = u32::MAX as u64;
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+
+/// Version 2.0, needed for deflate and data descriptors.
+const VERSION: u16 = 20;
+/// Sizes and checksum follow the data, and names are UTF-8.
+const FLAGS: u16 = 0x0008 | 0x0800;
+const DEFLATE: u16 = 8;
+/// Made on Unix, so that external attributes hold Unix permissions.
+const VERSION_MADE_BY: u16 = 0x0300 | VERSION;
+const EXTERNAL_ATTRIBUTES: u32 = 0o100_644 << 16;
+
+pub(crate) struct ArchiveEntry {
+  pub(crate) name: String,
+  pub(crate) path: InputPath,
+  pub(crate) modified: SystemTime,
+}
+
+/// Stream a zip archive of `entries`, built on the fly. Entries are deflated
+/// as they are read, and their checksums and sizes follow their data in
+/// data descriptors, so no more than one chunk of a file is buffered.
+/// Errors after the response has started abort the body, so clients see a
+/// truncated download instead of a corrupt archive.
+pub(crate) fn stream(entries: Vec<ArchiveEntry>) -> Body {
+  let (mut sender, body) = Body::channel();
+  task::spawn(async move {
+    if let Err(error) = send(&mut sender, entries).await {
+      log::warn!("Failed to stream zip archive: {}", error);
+      sender.abort();
+    }
+  });
+  body
+}
+
+async fn send(sender: &mut Sender, entries: Vec<ArchiveEntry>) -> Result<()> {
+  async fn send_data(sender: &mut Sender, data: Vec<u8>) -> Result<()> {
+    if data.is_empty() {
+      return Ok(());
+    }
+    sender
+      .send_data(data.into())
+      .await
+      .map_err(|error| Error::internal(format!("Failed to send archive data: {}", error)))
+  }
+
+  let mut writer = ZipWriter::new();
+  let mut buffer = vec![0; 64 * 1024];
+  for ArchiveEntry {
+    name,
+    path,
+    modified,
+  } in entries
+  {
+    let mut file = tokio::fs::File::open(&path)
+      .await
+      .with_context(|| Error::filesystem_io(&path))?;
+    let (mut file_entry, header) = writer.start_entry(name, modified);
+    send_data(sender, header).await?;
+    loop {
+      let read = file
+        .read(&mut buffer)
+        .await
+        .with_context(|| Error::filesystem_io(&path))?;
+      if read == 0 {
+        break;
+      }
+      send_data(sender, writer.write(&mut file_entry, &buffer[..read])).await?;
+    }
+    send_data(sender, writer.finish_entry(file_entry)?).await?;
+  }
+  send_data(sender, writer.finish()?).await
+}
+
+struct FileEntry {
+  name: String,
+  time: u16,
+  date: u16,
+  offset: u64,
+  crc: Crc,
+  size: u64,
+  compressed_size: u64,
+  encoder: DeflateEncoder<Vec<u8>>,
+}
+
+struct ZipWriter {
+  offset: u64,
+  entries: usize,
+  central_directory: Vec<u8>,
+}
+
+impl ZipWriter {
+  fn new() -> Self {
+    Self {
+      offset: 0,
+      entries: 0,
+      central_directory: Vec::new(),
+    }
+  }
+
+  fn start_entry(&mut self, name: String, modified: SystemTime) -> (FileEntry, Vec<u8>) {
+    let (time, date) = dos_date_time(modified);
+
+    let mut header = Vec::new();
+    put_u32(&mut header, LOCAL_FILE_HEADER_SIGNATURE);
+    put_u16(&mut header, VERSION);
+    put_u16(&mut header, FLAGS);
+    put_u16(&mut header, DEFLATE);
+    put_u16(&mut header, time);
+    put_u16(&mut header, date);
+    // Checksum and sizes are written to the data descriptor instead
+    put_u32(&mut header, 0);
+    put_u32(&mut header, 0);
+    put_u32(&mut header, 0);
+    put_u16(&mut header, name.len() as u16);
+    put_u16(&mut header, 0);
+    header.extend_from_slice(name.as_bytes());
+
+    let entry = FileEntry {
+      name,
+      time,
+      date,
+      offset: self.offset,
+      crc: Crc::new(),
+      size: 0,
+      compressed_size: 0,
+      encoder: DeflateEncoder::new(Vec::new(), Compression::default()),
+    };
+    self.offset += header.len() as u64;
+    (entry, header)
+  }
+
+  fn write(&mut self, entry: &mut FileEntry, data: &[u8]) -> Vec<u8> {
+    entry.crc.update(data);
+    entry.size += data.len() as u64;
+    entry
+      .encoder
+      .write_all(data)
+      .expect("writing to a Vec cannot fail");
+    let compressed = std::mem::take(entry.encoder.get_mut());
+    entry.compressed_size += compressed.len() as u64;
+    self.offset += compressed.len() as u64;
+    compressed
+  }
+
+  fn finish_entry(&mut self, mut entry: FileEntry) -> Result<Vec<u8>> {
+    let mut output = entry
+      .encoder
+      .finish()
+      .expect("writing to a Vec cannot fail");
+    entry.compressed_size += output.len() as u64;
+    let crc = entry.crc.sum();
+    let compressed_size = Self::check_size(entry.compressed_size)?;
+    let uncompressed_size = Self::check_size(entry.size)?;
+    let offset = Self::check_size(entry.offset)?;
+
+    put_u32(&mut output, DATA_DESCRIPTOR_SIGNATURE);
+    put_u32(&mut output, crc);
+    put_u32(&mut output, compressed_size);
+    put_u32(&mut output, uncompressed_size);
+    self.offset += output.len() as u64;
+
+    let record = &mut self.central_directory;
+    put_u32(record, CENTRAL_DIRECTORY_HEADER_SIGNATURE);
+    put_u16(record, VERSION_MADE_BY);
+    put_u16(record, VERSION);
+    put_u16(record, FLAGS);
+    put_u16(record, DEFLATE);
+    put_u16(record, entry.time);
+    put_u16(record, entry.date);
+    put_u32(record, crc);
+    put_u32(record, compressed_size);
+    put_u32(record, uncompressed_size);
+    put_u16(record, entry.name.len() as u16);
+    // Extra field, comment, and disk number
+    put_u16(record, 0);
+    put_u16(record, 0);
+    put_u16(record, 0);
+    // Internal attributes
+    put_u16(record, 0);
+    put_u32(record, EXTERNAL_ATTRIBUTES);
+    put_u32(record, offset);
+    record.extend_from_slice(entry.name.as_bytes());
+    self.entries += 1;
+
+    Ok(output)
+  }
+
+  fn finish(self) -> Result<Vec<u8>> {
+    if self.entries > MAX_ENTRIES {
+      return Err(Error::internal(format!(
+        "Zip archive has more than {} entries",
+        MAX_ENTRIES
+      )));
+    }
+    let offset = Self::check_size(self.offset)?;
+    let size = Self::check_size(self.central_directory.len() as u64)?;
+
+    let mut output = self.central_directory;
+    put_u32(&mut output, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+    // Disk numbers
+    put_u16(&mut output, 0);
+    put_u16(&mut output, 0);
+    put_u16(&mut output, self.entries as u16);
+    put_u16(&mut output, self.entries as u16);
+    put_u32(&mut output, size);
+    put_u32(&mut output, offset);
+    // Comment length
+    put_u16(&mut output, 0);
+    Ok(output)
+  }
+
+  fn check_size(size: u64) -> Result<u32> {
+    if size > MAX_SIZE {
+      return Err(Error::internal(format!(
+        "Zip archive exceeds {} bytes",
+        MAX_SIZE
+      )));
+    }
+    Ok(size as u32)
+  }
+}
+
+fn put_u16(output: &mut Vec<u8>, value: u16) {
+  output.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(output: &mut Vec<u8>, value: u32) {
+  output.extend_from_slice(&value.to_le_bytes());
+}
+
+/// MS-DOS time and date, in UTC, with a resolution of two seconds. DOS dates
+/// start in 1980, so earlier times are clamped to its first day.
+fn dos_date_time(time: SystemTime) -> (u16, u16) {
+  let timestamp = humantime::format_rfc3339_seconds(time).to_string();
+  let field = |range: std::ops::Range<usize>| -> u16 { timestamp[range].parse().unwrap_or(0) };
+  let year = field(0..4);
+  if year < 1980 {
+    return (0, (1 << 5) | 1);
+  }
+  let time = (field(11..13) << 11) | (field(14..16) << 5) | (field(17..19) / 2);
+  let date = ((year - 1980).min(127) << 9) | (field(5..7) << 5) | field(8..10);
+  (time, date)
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, flate2::read::DeflateDecoder, std::io::Read};
+
+  #[test]
+  fn dos_date_time() {
+    assert_eq!(
+      super::dos_date_time(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_001)),
+      // 2001-09-09T01:46:41Z
+      (1 << 11 | 46 << 5 | 20, 21 << 9 | 9 << 5 | 9)
+    );
+    assert_eq!(super::dos_date_time(SystemTime::UNIX_EPOCH), (0, 33));
+  }
+
+  #[test]
+  fn writes_entries_and_central_directory() {
+    let mut writer = ZipWriter::new();
+    let mut archive = Vec::new();
+    let (mut entry, header) = writer.start_entry("dir/foo".into(), SystemTime::UNIX_EPOCH);
+    archive.extend(header);
+    archive.extend(writer.write(&mut entry, b"foo"));
+    archive.extend(writer.finish_entry(entry).unwrap());
+    let data_end = archive.len();
+    archive.extend(writer.finish().unwrap());
+
+    let u16_at = |i: usize| u16::from_le_bytes([archive[i], archive[i + 1]]);
+    let u32_at =
+      |i: usize| u32::from_le_bytes([archive[i], archive[i + 1], archive[i + 2], archive[i + 3]]);
+
+    assert_eq!(u32_at(0), LOCAL_FILE_HEADER_SIGNATURE);
+    assert_eq!(&archive[30..37], b"dir/foo");
+
+    let descriptor = data_end - 16;
+    assert_eq!(u32_at(descriptor), DATA_DESCRIPTOR_SIGNATURE);
+    assert_eq!(u32_at(descriptor + 4), 0x8c73_6521);
+    assert_eq!(u32_at(descriptor + 12), 3);
+    let compressed_size = u32_at(descriptor + 8) as usize;
+    assert_eq!(37 + compressed_size, descriptor);
+    let mut decompressed = String::new();
+    DeflateDecoder::new(&archive[37..descriptor])
+      .read_to_string(&mut decompressed)
+      .unwrap();
+    assert_eq!(decompressed, "foo");
+
+    assert_eq!(u32_at(data_end), CENTRAL_DIRECTORY_HEADER_SIGNATURE);
+    assert_eq!(u32_at(data_end + 42), 0);
+    assert_eq!(&archive[data_end + 46..data_end + 53], b"dir/foo");
+
+    let end = archive.len() - 22;
+    assert_eq!(u32_at(end), END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+    assert_eq!(u16_at(end + 10), 1);
+    assert_eq!(u32_at(end + 12) as usize, end - data_end);
+    assert_eq!(u32_at(end + 16) as usize, data_end);
+  }
+}
@@ -1,14 +1,27 @@
 use {
   crate::common::*,
+  hyper::Uri,
   structopt::clap::{AppSettings, ArgGroup},
 };
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Serialize, StructOpt)]
+#[serde(rename_all = "kebab-case")]
 #[structopt(
+  group = ArgGroup::with_name("directory-group").required(true),
   group = ArgGroup::with_name("port").multiple(true).required(true),
-  settings = if cfg!(test) { &[AppSettings::ColorNever] } else { &[] })
+  group = ArgGroup::with_name("lnd-rpc-macaroon"),
+  settings = if cfg!(test) {
+    &[AppSettings::ColorNever, AppSettings::SubcommandsNegateReqs]
+  } else {
+    &[AppSettings::SubcommandsNegateReqs]
+  })
 ]
 pub(crate) struct Arguments {
+  #[structopt(
+    long,
+    help = "Append a line for each request to <access-log>, in the Combined Log Format used by Apache and nginx."
+  )]
+  pub(crate) access_log: Option<PathBuf>,
   #[structopt(
     long,
     help = "Store TLS certificates fetched from Let's Encrypt via the ACME protocol in <acme-cache-directory>."
@@ -25,14 +38,104 @@ pub(crate) struct Arguments {
     help = "Listen on <address> for incoming requests."
   )]
   pub(crate) address: String,
-  #[structopt(long, help = "Serve files from <directory>")]
-  pub(crate) directory: PathBuf,
+  #[structopt(
+    long,
+    help = "Serve agora under the URL path prefix <base-path>, for example `/share` when running behind a reverse proxy at `https://example.com/share/`. Requests outside of <base-path> receive a `404 Not Found` response."
+  )]
+  pub(crate) base_path: Option<String>,
+  #[structopt(
+    long,
+    help = "Listen for HTTP requests on every address that <address> resolves to, instead of only the first, for example on both `127.0.0.1` and `::1` for `localhost`."
+  )]
+  pub(crate) bind_all_addresses: bool,
+  #[structopt(
+    long,
+    help = "Keep the contents of up to 1024 free and paid files no larger than <cache-file-max-size> bytes in memory, and serve them from there instead of reading them from disk for every request. Cached files are reread once their modification time or size changes. Larger files are always streamed from disk."
+  )]
+  pub(crate) cache_file_max_size: Option<NonZeroU64>,
+  #[structopt(
+    long,
+    help = "Redirect requests whose `Host` header isn't <canonical-host> to the same path and query on <canonical-host> with `301 Moved Permanently`, for example to redirect `www.example.com` to `example.com`. Include the port if it isn't the default one. Health checks are never redirected."
+  )]
+  #[serde(serialize_with = "serialize_authority")]
+  pub(crate) canonical_host: Option<Authority>,
+  #[structopt(
+    long,
+    help = "Load site-wide defaults from the YAML file at <config>, which takes the same settings as `.agora.yaml` files, except for `merge` and `proxy`. Settings in `.agora.yaml` files take precedence."
+  )]
+  pub(crate) config: Option<PathBuf>,
+  #[structopt(
+    long,
+    help = "Split HTML directory listings into pages of <dir-listing-page-size> entries, selected with the `page` query parameter, with links to the previous and next pages. By default listings are not paginated. JSON and plain text listings always include all entries."
+  )]
+  pub(crate) dir_listing_page_size: Option<NonZeroUsize>,
+  #[structopt(
+    long,
+    help = "Respond with 404 to requests for <disable-route>. May be given multiple times. Routes are `files` for listings and downloads, `health` for health checks, `invoice` for invoice pages, `invoice-qr` for invoice QR codes, `invoice-status` for invoice statuses, `lnurlp` for LNURL-pay endpoints, and `static` for built-in assets."
+  )]
+  pub(crate) disable_route: Vec<Route>,
+  #[structopt(
+    long,
+    help = "Send every `404 Not Found` response no sooner than 100 milliseconds after its request was received, so that response times don't reveal whether a path is missing, or exists but is hidden, like dotfiles and escaping symlinks."
+  )]
+  pub(crate) disable_hidden_file_404_leak: bool,
+  #[structopt(
+    long,
+    help = "Persist counts of paid downloads to <download-counts-file>, so that `max-downloads` limits survive restarts. Without it, download counts are kept in memory."
+  )]
+  pub(crate) download_counts_file: Option<PathBuf>,
+  #[structopt(long, group = "directory-group", help = "Serve files from <directory>")]
+  pub(crate) directory: Option<PathBuf>,
+  #[structopt(
+    long,
+    help = "Render error pages from the template at <error-template>, replacing `{status}` with the status code and `{message}` with its description. Markdown templates, ending in `.md`, are shown in agora's page layout. Other templates are served as complete HTML documents."
+  )]
+  pub(crate) error_template: Option<PathBuf>,
+  #[structopt(
+    long,
+    default_value = "3600",
+    help = "If the price feed can't be reached, keep converting fiat prices at the last exchange rates fetched until they are <exchange-rate-max-staleness> seconds old. After that, requests for files with a fiat price fail until the price feed can be reached again."
+  )]
+  pub(crate) exchange_rate_max_staleness: u64,
+  #[structopt(
+    long,
+    default_value = "300",
+    help = "Fetch exchange rates from the price feed again once they are <exchange-rate-refresh-interval> seconds old."
+  )]
+  pub(crate) exchange_rate_refresh_interval: u64,
+  #[structopt(
+    long,
+    help = "Only read the type of each entry when listing directories, instead of its full metadata, and leave out file sizes and modification times. This makes listings of large directories on slow storage, like network filesystems, much faster."
+  )]
+  pub(crate) fast_listing: bool,
+  #[structopt(
+    long,
+    default_value = "files",
+    help = "Serve files under `/<files-prefix>/`, for example `d` to serve them under `/d/`. If empty, files are served at the root, except for paths used by other routes, like `/static/`, `/health`, and `/robots.txt`."
+  )]
+  pub(crate) files_prefix: String,
+  #[structopt(
+    long,
+    help = "Follow symlinks that point outside of <directory>, instead of hiding them from listings and refusing to serve them. Only pass this if every symlink in <directory> is meant to be served. `.agora.yaml` files are never read through such symlinks."
+  )]
+  pub(crate) follow_escaping_symlinks: bool,
+  #[structopt(
+    long,
+    help = "List directories before files in directory listings, unless `listing-dirs-first` is set to false in `.agora.yaml`."
+  )]
+  pub(crate) group_directories_first: bool,
   #[structopt(
     long,
     group = "port",
     help = "Listen on <http-port> for incoming HTTP requests."
   )]
   pub(crate) http_port: Option<u16>,
+  #[structopt(
+    long,
+    default_value = "100",
+    help = "Allow at most <http2-max-concurrent-streams> concurrent requests on a single HTTP/2 connection."
+  )]
+  pub(crate) http2_max_concurrent_streams: u32,
   #[structopt(
     long,
     group = "port",
@@ -50,19 +153,322 @@ pub(crate) struct Arguments {
     long,
     help = "Connect to LND gRPC server with host and port <lnd-rpc-authority>. By default a locally running LND instance will expose its gRPC API on `localhost:10009`."
   )]
+  #[serde(serialize_with = "serialize_authority")]
   pub(crate) lnd_rpc_authority: Option<Authority>,
+  #[structopt(
+    long,
+    help = "Respond to requests for image files that cannot be read with a placeholder image, instead of an error page. The response keeps the error status code."
+  )]
+  pub(crate) image_error_placeholder: bool,
+  #[structopt(
+    long,
+    use_delimiter = true,
+    default_value = ".index.md,README.md",
+    help = "Render the first Markdown file in each directory named one of the comma-separated <index-filenames>, ignoring case, with its listing. Files named with `index` in `.agora.yaml` take precedence. Paid files are never rendered."
+  )]
+  pub(crate) index_filenames: Vec<String>,
+  #[structopt(
+    long,
+    help = "Render the `.header.md` and `.footer.md` files of the closest ancestor directory that has them in listings of directories that don't have their own."
+  )]
+  pub(crate) inherit_partials: bool,
+  #[structopt(
+    long,
+    help = "Use <invoice-memo-template> as the memo of invoices for paid files, which wallets show to buyers, replacing `{path}` with the path of the file, and `{price}` with its price, for example `Agora: {path} ({price})`. `{path}` must occur exactly once. By default, the memo is just the path.",
+    requires = "lnd-rpc-authority"
+  )]
+  pub(crate) invoice_memo_template: Option<MemoTemplate>,
+  #[structopt(
+    long,
+    help = "Render directory listings from the template at <listing-template>, served as complete HTML documents. The text between `{entries}` and `{/entries}` is repeated for each entry, replacing `{name}`, `{href}`, `{size}`, `{is_dir}`, and `{paid}` with the entry's values. `{path}`, `{header}`, `{index}`, and `{footer}` are replaced with the path of the directory and its rendered Markdown files."
+  )]
+  pub(crate) listing_template: Option<PathBuf>,
+  #[structopt(
+    long,
+    help = "Retry connecting to LND at startup up to <lnd-connect-retries> times, waiting one second before the first retry, and twice as long before each one after that, up to a minute. Without a connection after the last retry, agora starts anyway, and keeps retrying in the background.",
+    requires = "lnd-rpc-authority"
+  )]
+  pub(crate) lnd_connect_retries: Option<u32>,
+  #[structopt(
+    long,
+    help = "Give up on each attempt to connect to LND at startup after <lnd-connect-timeout> seconds.",
+    requires = "lnd-rpc-authority"
+  )]
+  pub(crate) lnd_connect_timeout: Option<u64>,
+  #[structopt(
+    long,
+    help = "Query LND every <lnd-keepalive-interval> seconds, to keep the connection to LND established and log warnings when LND becomes unreachable.",
+    requires = "lnd-rpc-authority"
+  )]
+  pub(crate) lnd_keepalive_interval: Option<NonZeroU64>,
   #[structopt(
     long,
     help = "Read LND's TLS certificate from <lnd-rpc-cert-path>. Needed if LND uses a self-signed certificate. By default LND writes its TLS certificate to `~/.lnd/tls.cert`.",
     requires = "lnd-rpc-authority"
   )]
   pub(crate) lnd_rpc_cert_path: Option<PathBuf>,
+  #[structopt(
+    long,
+    help = "Only trust the certificate from `--lnd-rpc-cert-path` when connecting to LND. By default the system's trusted root certificates are also accepted, so LND instances with CA-signed certificates can be used without `--lnd-rpc-cert-path`.",
+    requires = "lnd-rpc-cert-path"
+  )]
+  pub(crate) lnd_rpc_cert_pinned: bool,
+  #[structopt(
+    long,
+    help = "Use the hex-encoded LND gRPC macaroon <lnd-rpc-macaroon-hex>, instead of reading it from a file with `--lnd-rpc-macaroon-path`, for example when passing secrets in environment variables. Can also be given with the `LND_RPC_MACAROON_HEX` environment variable.",
+    env = "LND_RPC_MACAROON_HEX",
+    hide_env_values = true,
+    group = "lnd-rpc-macaroon",
+    requires = "lnd-rpc-authority"
+  )]
+  pub(crate) lnd_rpc_macaroon_hex: Option<MacaroonHex>,
   #[structopt(
     long,
     help = "Read LND gRPC macaroon from <lnd-rpc-macaroon-path>. Needed if LND requires macaroon authentication. The macaroon must include permissions for creating and querying invoices. By default LND writes its invoice macaroon to `~/.lnd/data/chain/bitcoin/mainnet/invoice.macaroon`.",
+    group = "lnd-rpc-macaroon",
     requires = "lnd-rpc-authority"
   )]
   pub(crate) lnd_rpc_macaroon_path: Option<PathBuf>,
+  #[structopt(
+    long,
+    help = "Serve LNURL-pay endpoints for paid files under `/lnurlp/`, so they can be paid from LNURL-aware wallets. Callback URLs point to <lnurl-base-url>, the public URL of this agora instance, for example `https://example.com`."
+  )]
+  pub(crate) lnurl_base_url: Option<String>,
+  #[structopt(
+    long,
+    default_value = "text",
+    help = "Write log messages and errors to stderr in <log-format>, either `text`, or `json` for one JSON object per line, with `timestamp`, `level`, `target`, and `message` fields. The log level is set with the `RUST_LOG` environment variable."
+  )]
+  pub(crate) log_format: LogFormat,
+  #[structopt(
+    long,
+    help = "Serve at most <max-concurrent-downloads> files at the same time. Further file requests fail with `503 Service Unavailable` and a `Retry-After` header until a download finishes. Directory listings and invoice pages are not limited."
+  )]
+  pub(crate) max_concurrent_downloads: Option<NonZeroUsize>,
+  #[structopt(
+    long,
+    help = "Refuse to create invoices for more than <max-invoice-amount>, for example `100000 sat`, to guard against typos in `base-price`. Requests for paid files with a higher price fail with an internal error."
+  )]
+  #[serde(serialize_with = "serialize_millisatoshi")]
+  pub(crate) max_invoice_amount: Option<Millisatoshi>,
+  #[structopt(
+    long,
+    default_value = "65536",
+    help = "Reject requests whose body is larger than <max-request-body> bytes with `413 Payload Too Large`, and requests with a body of unknown length, like a chunked one, with `411 Length Required`. Agora never needs request bodies, except for the small XML bodies of WebDAV `PROPFIND` requests."
+  )]
+  pub(crate) max_request_body: u64,
+  #[structopt(
+    long,
+    default_value = "256",
+    help = "Perform at most <max-tls-handshakes> TLS handshakes for incoming HTTPS connections at the same time. Further connections wait to be accepted until a handshake finishes, so that a flood of connections can't exhaust memory."
+  )]
+  pub(crate) max_tls_handshakes: NonZeroUsize,
+  #[structopt(
+    long,
+    help = "Serve counters for responses, downloads, bytes served, and invoices at `/metrics`, in the Prometheus text format. Off by default, since the counters reveal how much a site is used."
+  )]
+  pub(crate) metrics: bool,
+  #[structopt(
+    long,
+    help = "Only accept HTTPS connections using TLS <min-tls-version> or newer. Either `1.2` or `1.3`. By default both TLS 1.2 and TLS 1.3 are accepted.",
+    requires = "https-port"
+  )]
+  pub(crate) min_tls_version: Option<TlsVersion>,
+  #[structopt(
+    long,
+    help = "Respond with 403 to requests for directory listings and zip archives, so files are only reachable by direct links. Directories with an `.index.md` file, or an `index.html` file with `--serve-index-html`, show only that index, without a listing."
+  )]
+  pub(crate) no_listing: bool,
+  #[structopt(
+    long,
+    help = "Persist the invoices whose revenue splits have been paid to <paid-splits-file>, so that splits are paid exactly once per invoice across restarts, and invoices settled while agora wasn't running are caught up on. Without it, only invoices settled while agora is running pay splits.",
+    requires = "lnd-rpc-authority"
+  )]
+  pub(crate) paid_splits_file: Option<PathBuf>,
+  #[structopt(
+    long,
+    help = "Convert fiat prices of paid files, set with `fiat-price` in `.agora.yaml`, to bitcoin at the exchange rates served at <price-feed-url>, as a JSON object mapping three-letter currency codes to the price of one bitcoin, for example `{\"USD\": 65000.5}`.",
+    requires = "lnd-rpc-authority"
+  )]
+  #[serde(serialize_with = "serialize_uri")]
+  pub(crate) price_feed_url: Option<Uri>,
+  #[structopt(
+    long,
+    help = "Print the effective configuration, including defaults, as YAML, and exit. Paths to LND's certificate and macaroon are printed, but not their contents."
+  )]
+  #[serde(skip)]
+  pub(crate) print_config: bool,
+  #[structopt(
+    long,
+    help = "Limit the speed at which each file download is sent to <rate-limit> bytes per second. The limit applies to each response separately, not to all downloads together."
+  )]
+  pub(crate) rate_limit: Option<NonZeroU64>,
+  #[structopt(
+    long,
+    help = "Only give access to paid files once <require-payment-confirmations> blocks have been mined since their invoice's payment was accepted. Payments of normal invoices are final once they are settled, so this is only useful for setups that settle invoices externally, like hold invoices. Requires a macaroon with permission to read node info.",
+    requires = "lnd-rpc-authority"
+  )]
+  pub(crate) require_payment_confirmations: Option<u32>,
+  #[structopt(
+    long,
+    help = "Serve the file at <robots> as `/robots.txt`. By default, crawlers are disallowed from `/files/` if LND is configured, since paid files may be served, and allowed everywhere otherwise."
+  )]
+  pub(crate) robots: Option<PathBuf>,
+  #[structopt(
+    long,
+    help = "Serve a directory's `index.html` file verbatim instead of a generated listing, if it exists and is free. Listings are still served when requested as JSON or plain text."
+  )]
+  pub(crate) serve_index_html: bool,
+  #[structopt(
+    long,
+    help = "Respond to all requests with the contents of <serve-single>, for example to show a maintenance or coming soon page. Built-in static assets under `/static/` are still served."
+  )]
+  pub(crate) serve_single: Option<PathBuf>,
+  #[structopt(
+    long,
+    help = "POST a JSON notification with `payment_hash`, `memo`, and `amount_msat` to <settlement-webhook> whenever an invoice of the LND node is settled. Failed notifications are retried with exponential backoff.",
+    requires = "lnd-rpc-authority"
+  )]
+  #[serde(serialize_with = "serialize_uri")]
+  pub(crate) settlement_webhook: Option<Uri>,
+  #[structopt(
+    long,
+    default_value = "30",
+    help = "On SIGINT or SIGTERM, stop accepting connections, and wait at most <shutdown-timeout> seconds for in-flight requests, like downloads, to finish before exiting."
+  )]
+  pub(crate) shutdown_timeout: u64,
+  #[structopt(
+    long,
+    help = "Serve a sitemap of free files at `/sitemap.xml`, with their modification times, so that search engines can find them. Paid files, hidden files, and directories with `listing: false` or `basic-auth` are left out."
+  )]
+  pub(crate) sitemap: bool,
+  #[structopt(
+    long,
+    help = "Let clients and caches reuse free files for <static-cache-max-age> seconds without revalidating them. By default, free files are revalidated before reuse. Paid files and listings are never cached."
+  )]
+  pub(crate) static_cache_max_age: Option<u64>,
+  #[structopt(
+    long,
+    default_value = "10",
+    help = "Close incoming HTTPS connections whose TLS handshake hasn't finished after <tls-handshake-timeout> seconds, so that slow clients can't hold on to one of the `--max-tls-handshakes` handshakes forever."
+  )]
+  pub(crate) tls_handshake_timeout: u64,
+  #[structopt(
+    long,
+    help = "Link web seeds in generated `.torrent` files to agora's files route at <torrent-base-url>, for example `https://example.com`.",
+    requires = "torrent-min-size"
+  )]
+  pub(crate) torrent_base_url: Option<String>,
+  #[structopt(
+    long,
+    help = "Offer `.torrent` files, at `?torrent`, for free files of at least <torrent-min-size> bytes. The torrents use the file's direct download URL as a web seed.",
+    requires = "torrent-base-url"
+  )]
+  pub(crate) torrent_min_size: Option<u64>,
+  #[structopt(
+    long,
+    help = "Announce generated `.torrent` files to <torrent-tracker>. May be given multiple times.",
+    requires = "torrent-min-size"
+  )]
+  pub(crate) torrent_tracker: Vec<String>,
+  #[structopt(
+    long,
+    default_value = "enforce",
+    help = "Redirect requests according to <trailing-slash-policy>. With `enforce`, directory paths are redirected to end with a slash and file paths to not end with one. With `inverse`, the reverse. With `off`, requests are never redirected."
+  )]
+  pub(crate) trailing_slash_policy: TrailingSlashPolicy,
+  #[structopt(
+    long,
+    help = "Trust the `X-Forwarded-For`, `X-Forwarded-Host`, and `X-Forwarded-Proto` headers set by a reverse proxy, and use them for client addresses in logs, and for HTTPS redirects. Only pass this if agora can only be reached through a proxy that sets these headers, since clients can otherwise set them to anything."
+  )]
+  pub(crate) trust_proxy: bool,
+  #[structopt(
+    long,
+    help = "Once a paid file's invoice is settled, redirect to a URL for the file signed with <url-signing-key>, which can be used to download it for an hour, without looking up the invoice in LND again. Use a long random string, and keep it secret, since anyone who knows it can create signed URLs for any file. Can also be given with the `URL_SIGNING_KEY` environment variable.",
+    env = "URL_SIGNING_KEY",
+    hide_env_values = true,
+    requires = "lnd-rpc-authority"
+  )]
+  #[serde(serialize_with = "serialize_redacted")]
+  pub(crate) url_signing_key: Option<String>,
+  #[structopt(subcommand)]
+  #[serde(skip)]
+  pub(crate) subcommand: Option<Subcommand>,
+}
+
+impl Arguments {
+  /// `--directory`, which is only optional when running a subcommand.
+  pub(crate) fn directory(&self) -> &Path {
+    self
+      .directory
+      .as_deref()
+      .expect("`--directory` is required without a subcommand")
+  }
+
+  /// `--base-path`, with a leading and without a trailing slash, or the
+  /// empty string if agora is served at the root.
+  pub(crate) fn base_path(&self) -> String {
+    match self.base_path.as_deref().map(|path| path.trim_matches('/')) {
+      Some(path) if !path.is_empty() => format!("/{}", path),
+      _ => String::new(),
+    }
+  }
+
+  /// Path under which files are served, relative to `--base-path`, with a
+  /// leading and trailing slash, like `/files/`, or `/` if files are served
+  /// at the root.
+  pub(crate) fn files_path(&self) -> String {
+    match self.files_prefix.trim_matches('/') {
+      "" => "/".to_owned(),
+      prefix => format!("/{}/", prefix),
+    }
+  }
+
+  pub(crate) fn to_yaml(&self) -> Result<String> {
+    serde_yaml::to_string(self).context(error::ArgumentsSerialize)
+  }
+}
+
+fn serialize_authority<S: serde::Serializer>(
+  authority: &Option<Authority>,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  authority
+    .as_ref()
+    .map(|authority| authority.as_str())
+    .serialize(serializer)
+}
+
+fn serialize_millisatoshi<S: serde::Serializer>(
+  amount: &Option<Millisatoshi>,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  amount
+    .map(|amount| {
+      if amount.value() % 1000 == 0 {
+        format!("{} sat", amount.value() / 1000)
+      } else {
+        format!("{} msat", amount.value())
+      }
+    })
+    .serialize(serializer)
+}
+
+fn serialize_redacted<S: serde::Serializer>(
+  secret: &Option<String>,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  secret.as_ref().map(|_| "<redacted>").serialize(serializer)
+}
+
+fn serialize_uri<S: serde::Serializer>(
+  uri: &Option<Uri>,
+  serializer: S,
+) -> Result<S::Ok, S::Error> {
+  uri
+    .as_ref()
+    .map(|uri| uri.to_string())
+    .serialize(serializer)
 }
 
 #[cfg(test)]
@@ -125,6 +531,200 @@ mod tests {
     );
   }
 
+  #[test]
+  fn min_tls_version_requires_https_port() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--min-tls-version=1.3",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "--https-port <https-port>",
+    );
+  }
+
+  #[test]
+  fn invalid_min_tls_version() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--https-port=0",
+        "--acme-cache-directory=cache",
+        "--acme-domain=localhost",
+        "--min-tls-version=1.1",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "Invalid TLS version `1.1`, expected `1.2` or `1.3`",
+    );
+  }
+
+  #[test]
+  fn lnd_connect_arguments_require_lnd_rpc_authority() {
+    for argument in &["--lnd-connect-retries=3", "--lnd-connect-timeout=5"] {
+      assert_contains(
+        &Arguments::from_iter_safe(&["agora", "--directory=www", "--http-port=0", argument])
+          .unwrap_err()
+          .to_string(),
+        "--lnd-rpc-authority <lnd-rpc-authority>",
+      );
+    }
+  }
+
+  #[test]
+  fn lnd_keepalive_interval_requires_lnd_rpc_authority() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--lnd-keepalive-interval=60",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "--lnd-rpc-authority <lnd-rpc-authority>",
+    );
+  }
+
+  #[test]
+  fn lnd_keepalive_interval_must_not_be_zero() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--lnd-rpc-authority=localhost:10009",
+        "--lnd-keepalive-interval=0",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "number would be zero for non-zero type",
+    );
+  }
+
+  #[test]
+  fn require_payment_confirmations_requires_lnd_rpc_authority() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--require-payment-confirmations=3",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "--lnd-rpc-authority <lnd-rpc-authority>",
+    );
+  }
+
+  #[test]
+  fn price_feed_url_requires_lnd_rpc_authority() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--price-feed-url=https://example.com/rates",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "--lnd-rpc-authority <lnd-rpc-authority>",
+    );
+  }
+
+  #[test]
+  fn settlement_webhook_requires_lnd_rpc_authority() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--settlement-webhook=https://example.com/hook",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "--lnd-rpc-authority <lnd-rpc-authority>",
+    );
+  }
+
+  #[test]
+  fn paid_splits_file_requires_lnd_rpc_authority() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--paid-splits-file=paid-splits.yaml",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "--lnd-rpc-authority <lnd-rpc-authority>",
+    );
+  }
+
+  #[test]
+  fn lnd_rpc_cert_pinned_requires_lnd_rpc_cert_path() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--lnd-rpc-authority=localhost:10009",
+        "--lnd-rpc-cert-pinned",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "--lnd-rpc-cert-path <lnd-rpc-cert-path>",
+    );
+  }
+
+  #[test]
+  fn lnd_rpc_macaroon_hex_conflicts_with_lnd_rpc_macaroon_path() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--lnd-rpc-authority=localhost:10009",
+        "--lnd-rpc-macaroon-hex=0201",
+        "--lnd-rpc-macaroon-path=invoice.macaroon",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "cannot be used with",
+    );
+  }
+
+  #[test]
+  fn lnd_rpc_macaroon_hex_must_be_valid_hex() {
+    assert_contains(
+      &Arguments::from_iter_safe(&[
+        "agora",
+        "--directory=www",
+        "--http-port=0",
+        "--lnd-rpc-authority=localhost:10009",
+        "--lnd-rpc-macaroon-hex=xyz",
+      ])
+      .unwrap_err()
+      .to_string(),
+      "Invalid LND RPC macaroon hex",
+    );
+  }
+
+  #[test]
+  fn http2_max_concurrent_streams_has_a_default() {
+    assert_eq!(
+      Arguments::from_iter_safe(&["agora", "--directory=www", "--http-port=0"])
+        .unwrap()
+        .http2_max_concurrent_streams,
+      100
+    );
+  }
+
   #[test]
   fn require_at_least_one_port_argument() {
     assert_contains(
@@ -138,4 +738,79 @@ mod tests {
       .unindent(),
     );
   }
+
+  #[test]
+  fn subcommands_dont_require_server_arguments() {
+    let arguments = Arguments::from_iter_safe(&["agora", "check", "--directory=www"]).unwrap();
+    assert_eq!(arguments.directory, None);
+    match arguments.subcommand {
+      Some(Subcommand::Check { directory }) => assert_eq!(directory, Path::new("www")),
+      subcommand => panic!("unexpected subcommand: {:?}", subcommand),
+    }
+  }
+
+  #[test]
+  fn to_yaml() {
+    let yaml = Arguments::from_iter_safe(&[
+      "agora",
+      "--directory=www",
+      "--http-port=0",
+      "--disable-route=invoice-qr",
+      "--lnd-rpc-authority=localhost:10009",
+      "--max-invoice-amount=100000 sat",
+      "--settlement-webhook=https://example.com/hook",
+      "--url-signing-key=secret",
+    ])
+    .unwrap()
+    .to_yaml()
+    .unwrap();
+    assert_contains(&yaml, "address: 0.0.0.0\n");
+    assert_contains(&yaml, "disable-route:\n  - invoice-qr\n");
+    assert_contains(&yaml, "directory: www\n");
+    assert_contains(&yaml, "http-port: 0\n");
+    assert_contains(&yaml, "https-port: ~\n");
+    assert_contains(&yaml, "lnd-rpc-authority: \"localhost:10009\"\n");
+    assert_contains(&yaml, "max-invoice-amount: 100000 sat\n");
+    assert_contains(&yaml, "settlement-webhook: \"https://example.com/hook\"\n");
+    assert_contains(&yaml, "trailing-slash-policy: enforce\n");
+    assert_contains(&yaml, "url-signing-key: \"<redacted>\"\n");
+    assert!(!yaml.contains("print-config"));
+    assert!(!yaml.contains("secret"));
+  }
+
+  #[test]
+  fn base_path_is_normalized() {
+    let base_path = |args: &[&str]| {
+      Arguments::from_iter_safe(
+        ["agora", "--directory=www", "--http-port=0"]
+          .iter()
+          .chain(args),
+      )
+      .unwrap()
+      .base_path()
+    };
+    assert_eq!(base_path(&[]), "");
+    assert_eq!(base_path(&["--base-path=/"]), "");
+    assert_eq!(base_path(&["--base-path=share"]), "/share");
+    assert_eq!(base_path(&["--base-path=/share/"]), "/share");
+    assert_eq!(base_path(&["--base-path=/a/b"]), "/a/b");
+  }
+
+  #[test]
+  fn files_path_is_normalized() {
+    let files_path = |args: &[&str]| {
+      Arguments::from_iter_safe(
+        ["agora", "--directory=www", "--http-port=0"]
+          .iter()
+          .chain(args),
+      )
+      .unwrap()
+      .files_path()
+    };
+    assert_eq!(files_path(&[]), "/files/");
+    assert_eq!(files_path(&["--files-prefix="]), "/");
+    assert_eq!(files_path(&["--files-prefix=/"]), "/");
+    assert_eq!(files_path(&["--files-prefix=d"]), "/d/");
+    assert_eq!(files_path(&["--files-prefix=/d/"]), "/d/");
+  }
 }
@@ -0,0 +1,25 @@
+use std::time::Duration;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Delay before retry number `attempt`, starting at zero, which doubles
+/// from one second up to a minute.
+pub(crate) fn backoff(attempt: u32) -> Duration {
+  2u32
+    .checked_pow(attempt)
+    .map_or(MAX_BACKOFF, |factor| Duration::from_secs(factor.into()))
+    .min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn doubles_up_to_max() {
+    assert_eq!(backoff(0), Duration::from_secs(1));
+    assert_eq!(backoff(3), Duration::from_secs(8));
+    assert_eq!(backoff(6), MAX_BACKOFF);
+    assert_eq!(backoff(100), MAX_BACKOFF);
+  }
+}
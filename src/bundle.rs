@@ -0,0 +1,271 @@
+use {
+  crate::common::*,
+  std::{
+    convert::TryInto,
+    io::{Read, Seek, SeekFrom},
+    path::Component,
+  },
+};
+
+/// Marks the end of executables that carry a bundled directory.
+const MAGIC: &[u8; 8] = b"agorabnd";
+
+/// Length of the trailer at the end of executables that carry a bundled
+/// directory, which is the length of the bundle, followed by `MAGIC`.
+const TRAILER_LENGTH: usize = 16;
+
+const DIRECTORY: u8 = 0;
+const FILE: u8 = 1;
+
+/// A directory bundled into a copy of the agora executable with
+/// `agora bundle`, which is served when it's run without `--directory`.
+/// Bundles are appended to the executable, as a list of entries, each of
+/// which is a type byte, the entry's length-prefixed path, and, for files,
+/// their length-prefixed contents, followed by a trailer. When served,
+/// bundles are extracted to a temporary directory, so that they are served
+/// exactly like `--directory`, including the `.agora.yaml` files in them.
+pub(crate) struct Bundle;
+
+impl Bundle {
+  /// Write a copy of `executable`, with `directory` bundled into it, to
+  /// `output`. A directory already bundled into `executable` is replaced.
+  /// Symlinks are left out, since they would point to different files
+  /// wherever the bundle is extracted.
+  pub(crate) fn create(executable: &Path, directory: &Path, output: &Path) -> Result<()> {
+    let mut bytes = fs::read(executable).context(error::FilesystemIo { path: executable })?;
+    let length = Self::bundle_start(&bytes, executable)?.unwrap_or(bytes.len());
+    bytes.truncate(length);
+
+    let start = bytes.len();
+    Self::write_entries(directory, "", &mut bytes)?;
+    let bundle_length = (bytes.len() - start) as u64;
+    bytes.extend_from_slice(&bundle_length.to_le_bytes());
+    bytes.extend_from_slice(MAGIC);
+
+    fs::write(output, &bytes).context(error::FilesystemIo { path: output })?;
+    let permissions = fs::metadata(executable)
+      .context(error::FilesystemIo { path: executable })?
+      .permissions();
+    fs::set_permissions(output, permissions).context(error::FilesystemIo { path: output })?;
+    Ok(())
+  }
+
+  /// Extract the directory bundled into `executable` to a new temporary
+  /// directory, which is removed when it's dropped, or return `None` if
+  /// `executable` doesn't carry one.
+  pub(crate) fn extract(executable: &Path) -> Result<Option<TempDir>> {
+    let io = || error::FilesystemIo { path: executable };
+    let mut file = fs::File::open(executable).context(io())?;
+    let length = file.metadata().context(io())?.len();
+    if length < TRAILER_LENGTH as u64 {
+      return Ok(None);
+    }
+    let mut trailer = [0; TRAILER_LENGTH];
+    file
+      .seek(SeekFrom::End(-(TRAILER_LENGTH as i64)))
+      .and_then(|_| file.read_exact(&mut trailer))
+      .context(io())?;
+    let bundle_length = match Self::parse_trailer(&trailer) {
+      Some(bundle_length) => bundle_length,
+      None => return Ok(None),
+    };
+    if bundle_length > length - TRAILER_LENGTH as u64 {
+      return Err(error::BundleCorrupt { path: executable }.build());
+    }
+
+    let mut bundle = Vec::new();
+    file
+      .seek(SeekFrom::Start(
+        length - TRAILER_LENGTH as u64 - bundle_length,
+      ))
+      .and_then(|_| file.take(bundle_length).read_to_end(&mut bundle))
+      .context(io())?;
+    let entries = Self::parse_entries(&bundle)
+      .ok_or_else(|| error::BundleCorrupt { path: executable }.build())?;
+
+    let tempdir = tempfile::Builder::new()
+      .prefix("agora-bundle")
+      .tempdir()
+      .context(error::FilesystemIo {
+        path: env::temp_dir(),
+      })?;
+    for (kind, path, contents) in entries {
+      let path = tempdir.path().join(path);
+      if kind == DIRECTORY {
+        fs::create_dir_all(&path)
+      } else {
+        fs::write(&path, contents)
+      }
+      .context(error::FilesystemIo { path })?;
+    }
+    Ok(Some(tempdir))
+  }
+
+  /// Offset of the bundle in `executable`, the bytes of the executable at
+  /// `path`, if it carries one.
+  fn bundle_start(executable: &[u8], path: &Path) -> Result<Option<usize>> {
+    if executable.len() < TRAILER_LENGTH {
+      return Ok(None);
+    }
+    let end = executable.len() - TRAILER_LENGTH;
+    match Self::parse_trailer(&executable[end..]) {
+      Some(bundle_length) if bundle_length <= end as u64 => Ok(Some(end - bundle_length as usize)),
+      Some(_) => Err(error::BundleCorrupt { path }.build()),
+      None => Ok(None),
+    }
+  }
+
+  fn parse_trailer(trailer: &[u8]) -> Option<u64> {
+    if &trailer[8..] != MAGIC {
+      return None;
+    }
+    Some(u64::from_le_bytes(
+      trailer[..8].try_into().expect("trailer length is correct"),
+    ))
+  }
+
+  /// Append the entries of `directory`, whose path in the bundle is
+  /// `prefix`, to `bytes`, in order, so that directories come before their
+  /// contents.
+  fn write_entries(directory: &Path, prefix: &str, bytes: &mut Vec<u8>) -> Result<()> {
+    let mut entries = directory
+      .read_dir()
+      .and_then(|entries| entries.collect::<io::Result<Vec<fs::DirEntry>>>())
+      .context(error::FilesystemIo { path: directory })?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+      let path = entry.path();
+      let file_type = entry
+        .file_type()
+        .context(error::FilesystemIo { path: &path })?;
+      let file_name = entry
+        .file_name()
+        .into_string()
+        .map_err(|_| error::BundleFileName { path: &path }.build())?;
+      let name = format!("{}{}", prefix, file_name);
+      if file_type.is_dir() {
+        Self::write_entry(bytes, DIRECTORY, &name, None);
+        Self::write_entries(&path, &format!("{}/", name), bytes)?;
+      } else if file_type.is_file() {
+        let contents = fs::read(&path).context(error::FilesystemIo { path: &path })?;
+        Self::write_entry(bytes, FILE, &name, Some(&contents));
+      }
+    }
+    Ok(())
+  }
+
+  fn write_entry(bytes: &mut Vec<u8>, kind: u8, path: &str, contents: Option<&[u8]>) {
+    bytes.push(kind);
+    bytes.extend_from_slice(&(path.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(path.as_bytes());
+    if let Some(contents) = contents {
+      bytes.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+      bytes.extend_from_slice(contents);
+    }
+  }
+
+  /// Parse the entries of `bundle`, returning `None` if it's corrupt, or
+  /// contains paths that would be extracted outside of its directory.
+  fn parse_entries(mut bundle: &[u8]) -> Option<Vec<(u8, &str, &[u8])>> {
+    fn take<'a>(bundle: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+      if bundle.len() < length {
+        return None;
+      }
+      let (taken, rest) = bundle.split_at(length);
+      *bundle = rest;
+      Some(taken)
+    }
+
+    fn take_length_prefixed<'a>(bundle: &mut &'a [u8]) -> Option<&'a [u8]> {
+      let length = u64::from_le_bytes(take(bundle, 8)?.try_into().ok()?);
+      take(bundle, length.try_into().ok()?)
+    }
+
+    let mut entries = Vec::new();
+    while !bundle.is_empty() {
+      let kind = take(&mut bundle, 1)?[0];
+      let path = str::from_utf8(take_length_prefixed(&mut bundle)?).ok()?;
+      if path.is_empty()
+        || !Path::new(path)
+          .components()
+          .all(|component| matches!(component, Component::Normal(_)))
+      {
+        return None;
+      }
+      let contents = match kind {
+        DIRECTORY => &[][..],
+        FILE => take_length_prefixed(&mut bundle)?,
+        _ => return None,
+      };
+      entries.push((kind, path, contents));
+    }
+    Some(entries)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trip() {
+    let tempdir = TempDir::new().unwrap();
+    let executable = tempdir.path().join("agora");
+    fs::write(&executable, "executable").unwrap();
+    let directory = tempdir.path().join("www");
+    fs::create_dir_all(directory.join("dir/empty")).unwrap();
+    fs::write(directory.join(".agora.yaml"), "paid: false").unwrap();
+    fs::write(directory.join("dir/foo.txt"), "foo").unwrap();
+
+    let output = tempdir.path().join("bundled");
+    Bundle::create(&executable, &directory, &output).unwrap();
+    let extracted = Bundle::extract(&output).unwrap().unwrap();
+    assert_eq!(
+      fs::read_to_string(extracted.path().join(".agora.yaml")).unwrap(),
+      "paid: false"
+    );
+    assert_eq!(
+      fs::read_to_string(extracted.path().join("dir/foo.txt")).unwrap(),
+      "foo"
+    );
+    assert!(extracted.path().join("dir/empty").is_dir());
+
+    fs::remove_file(directory.join("dir/foo.txt")).unwrap();
+    let rebundled = tempdir.path().join("rebundled");
+    Bundle::create(&output, &directory, &rebundled).unwrap();
+    let bytes = fs::read(&rebundled).unwrap();
+    assert!(bytes.starts_with(b"executable"));
+    assert_eq!(
+      Bundle::bundle_start(&bytes, &rebundled).unwrap(),
+      Some("executable".len())
+    );
+    let extracted = Bundle::extract(&rebundled).unwrap().unwrap();
+    assert!(!extracted.path().join("dir/foo.txt").exists());
+  }
+
+  #[test]
+  fn executables_without_bundle() {
+    let tempdir = TempDir::new().unwrap();
+    let executable = tempdir.path().join("agora");
+    fs::write(&executable, "executable").unwrap();
+    assert!(Bundle::extract(&executable).unwrap().is_none());
+  }
+
+  #[test]
+  fn paths_outside_of_bundle_are_rejected() {
+    for path in &["../foo", "/foo", "foo/../../bar", ""] {
+      let mut bundle = Vec::new();
+      Bundle::write_entry(&mut bundle, FILE, path, Some(b"foo"));
+      assert_eq!(Bundle::parse_entries(&bundle), None);
+    }
+  }
+
+  #[test]
+  fn truncated_bundles_are_rejected() {
+    let mut bundle = Vec::new();
+    Bundle::write_entry(&mut bundle, FILE, "foo", Some(b"foo"));
+    assert!(Bundle::parse_entries(&bundle).is_some());
+    bundle.pop();
+    assert_eq!(Bundle::parse_entries(&bundle), None);
+  }
+}
@@ -1,17 +1,33 @@
 pub(crate) use {
   crate::{
     arguments::Arguments,
+    bundle::Bundle,
     display_size::DisplaySize,
+    download_counts::DownloadCounts,
     environment::Environment,
     error::{self, Error, Result},
     error_page, html,
     https_redirect_service::HttpsRedirectService,
     https_request_handler::HttpsRequestHandler,
     input_path::InputPath,
-    redirect::redirect,
+    listing_template::{ListingTemplate, ListingTemplateEntry, ListingTemplatePartials},
+    lnd_status::LndStatus,
+    log_format::LogFormat,
+    macaroon_hex::MacaroonHex,
+    memo_template::MemoTemplate,
+    metrics::{Metrics, Payment},
+    percent,
+    redirect::{permanent_redirect, redirect},
     request_handler::RequestHandler,
+    route::Route,
     server::Server,
+    settlement_webhook::SettlementWebhook,
+    shutdown::Shutdown,
     stderr::Stderr,
+    subcommand::Subcommand,
+    tls_version::TlsVersion,
+    torrent::Torrents,
+    trailing_slash_policy::TrailingSlashPolicy,
   },
   agora_lnd_client::Millisatoshi,
   futures::{
@@ -27,9 +43,10 @@ pub(crate) use {
   },
   lexiclean::Lexiclean,
   maud::Markup,
-  serde::Deserialize,
+  serde::{Deserialize, Serialize},
   snafu::{IntoError, ResultExt},
   std::{
+    cmp::Ordering,
     convert::Infallible,
     env,
     ffi::OsString,
@@ -38,19 +55,19 @@ pub(crate) use {
     future,
     io::{self, Write},
     mem::MaybeUninit,
-    net::{SocketAddr, ToSocketAddrs},
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    num::{NonZeroU64, NonZeroUsize},
     path::{Path, PathBuf},
     pin::Pin,
     str,
     sync::Arc,
     task::{Context, Poll},
+    time::SystemTime,
   },
   structopt::StructOpt,
+  tempfile::TempDir,
   tokio::task,
 };
 
 #[cfg(test)]
-pub(crate) use ::{
-  std::{future::Future, time::Duration},
-  tempfile::TempDir,
-};
+pub(crate) use std::{future::Future, time::Duration};
@@ -0,0 +1,277 @@
+use {
+  crate::common::*,
+  flate2::{write::GzEncoder, Compression},
+  hyper::{
+    body::{Bytes, HttpBody},
+    HeaderMap,
+  },
+  pin_project::pin_project,
+};
+
+/// Bodies known to be smaller than this aren't worth compressing.
+const MIN_SIZE: u64 = 1024;
+
+/// Compress `response` with gzip if the request's headers show that the
+/// client accepts it, and its content type is compressible. Compression
+/// happens on the fly, and the compressed data is flushed after every chunk,
+/// so streamed bodies are never buffered, and bodies of unknown length, like
+/// those of FIFOs, reach the client as they're produced. Responses to range
+/// requests are left untouched, since ranges refer to the uncompressed
+/// representation. Responses with compressible content types always get
+/// `Vary: accept-encoding`, even if they aren't compressed, so that caches
+/// don't serve compressed responses to clients that can't decode them.
+pub(crate) fn compress(
+  request_headers: &HeaderMap,
+  mut response: Response<Body>,
+) -> Response<Body> {
+  if !is_compressible(&response) {
+    return response;
+  }
+
+  if !varies_on_accept_encoding(response.headers()) {
+    response
+      .headers_mut()
+      .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+  }
+
+  if !accepts(request_headers, "gzip")
+    || request_headers.contains_key(header::RANGE)
+    || response.headers().contains_key(header::CONTENT_ENCODING)
+    || HttpBody::size_hint(response.body())
+      .exact()
+      .map_or(false, |size| size < MIN_SIZE)
+  {
+    return response;
+  }
+
+  let (mut parts, body) = response.into_parts();
+  parts.headers.remove(header::CONTENT_LENGTH);
+  // The compressed body differs byte-for-byte, so a strong ETag becomes weak
+  if let Some(etag) = parts.headers.get(header::ETAG) {
+    if !etag.as_bytes().starts_with(b"W/") {
+      let weak = [b"W/", etag.as_bytes()].concat();
+      parts.headers.insert(
+        header::ETAG,
+        HeaderValue::from_bytes(&weak).expect("weakened ETag is a valid header value"),
+      );
+    }
+  }
+  parts
+    .headers
+    .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+  Response::from_parts(parts, Body::wrap_stream(GzipStream::new(body)))
+}
+
+/// Whether the request's `Accept-Encoding` headers list `coding`, without
+/// ruling it out with `q=0`.
+pub(crate) fn accepts(request_headers: &HeaderMap, coding: &str) -> bool {
+  request_headers
+    .get_all(header::ACCEPT_ENCODING)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .any(|listed| {
+      let mut parameters = listed.split(';').map(str::trim);
+      parameters.next() == Some(coding)
+        && parameters.all(|parameter| {
+          !parameter
+            .strip_prefix("q=")
+            .map_or(false, |q| q.parse::<f32>().map_or(false, |q| q == 0.0))
+        })
+    })
+}
+
+fn varies_on_accept_encoding(response_headers: &HeaderMap) -> bool {
+  response_headers
+    .get_all(header::VARY)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(','))
+    .map(str::trim)
+    .any(|listed| listed == "*" || listed.eq_ignore_ascii_case("accept-encoding"))
+}
+
+fn is_compressible(response: &Response<Body>) -> bool {
+  let content_type = match response
+    .headers()
+    .get(header::CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+  {
+    Some(content_type) => content_type,
+    None => return false,
+  };
+  let essence = content_type
+    .split(';')
+    .next()
+    .unwrap_or_default()
+    .trim()
+    .to_ascii_lowercase();
+  essence.starts_with("text/")
+    || [
+      "application/javascript",
+      "application/json",
+      "application/xml",
+      "image/svg+xml",
+    ]
+    .contains(&essence.as_str())
+}
+
+#[pin_project]
+struct GzipStream {
+  #[pin]
+  body: Body,
+  encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl GzipStream {
+  fn new(body: Body) -> Self {
+    Self {
+      body,
+      encoder: Some(GzEncoder::new(Vec::new(), Compression::default())),
+    }
+  }
+}
+
+impl Stream for GzipStream {
+  type Item = Result<Bytes, hyper::Error>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let mut projected = self.project();
+    loop {
+      let encoder = match projected.encoder {
+        Some(encoder) => encoder,
+        None => return Poll::Ready(None),
+      };
+
+      match futures::ready!(projected.body.as_mut().poll_data(cx)) {
+        Some(Ok(chunk)) => {
+          encoder
+            .write_all(&chunk)
+            .and_then(|()| encoder.flush())
+            .expect("writing to a Vec cannot fail");
+          let compressed = std::mem::take(encoder.get_mut());
+          if !compressed.is_empty() {
+            return Poll::Ready(Some(Ok(compressed.into())));
+          }
+        }
+        Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+        None => {
+          let compressed = projected
+            .encoder
+            .take()
+            .expect("encoder is present")
+            .finish()
+            .expect("writing to a Vec cannot fail");
+          return Poll::Ready(Some(Ok(compressed.into())));
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, flate2::read::GzDecoder, std::io::Read};
+
+  fn request_headers(accept_encoding: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+      header::ACCEPT_ENCODING,
+      HeaderValue::from_str(accept_encoding).unwrap(),
+    );
+    headers
+  }
+
+  #[test]
+  fn accepts() {
+    assert!(super::accepts(&request_headers("gzip"), "gzip"));
+    assert!(super::accepts(
+      &request_headers("deflate, gzip;q=0.5"),
+      "gzip"
+    ));
+    assert!(!super::accepts(&request_headers("gzip;q=0"), "gzip"));
+    assert!(!super::accepts(&request_headers("br"), "gzip"));
+    assert!(!super::accepts(&HeaderMap::new(), "gzip"));
+    assert!(super::accepts(&request_headers("gzip, br"), "br"));
+    assert!(!super::accepts(&request_headers("br;q=0, gzip"), "br"));
+  }
+
+  #[tokio::test]
+  async fn compresses_text() {
+    let text = "hello ".repeat(1000);
+    let response = Response::builder()
+      .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+      .header(header::ETAG, "\"abc\"")
+      .body(Body::from(text.clone()))
+      .unwrap();
+    let response = compress(&request_headers("gzip"), response);
+    assert_eq!(
+      response.headers().get(header::CONTENT_ENCODING).unwrap(),
+      "gzip"
+    );
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), "W/\"abc\"");
+    let compressed = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(compressed.len() < text.len());
+    let mut decompressed = String::new();
+    GzDecoder::new(compressed.as_ref())
+      .read_to_string(&mut decompressed)
+      .unwrap();
+    assert_eq!(decompressed, text);
+  }
+
+  #[test]
+  fn leaves_small_and_incompressible_bodies_untouched() {
+    let small = Response::builder()
+      .header(header::CONTENT_TYPE, "text/plain")
+      .body(Body::from("hello"))
+      .unwrap();
+    assert!(!compress(&request_headers("gzip"), small)
+      .headers()
+      .contains_key(header::CONTENT_ENCODING));
+
+    let video = Response::builder()
+      .header(header::CONTENT_TYPE, "video/mp4")
+      .body(Body::from(vec![0; 4096]))
+      .unwrap();
+    let video = compress(&request_headers("gzip"), video);
+    assert!(!video.headers().contains_key(header::CONTENT_ENCODING));
+    assert!(!video.headers().contains_key(header::VARY));
+  }
+
+  #[test]
+  fn uncompressed_text_varies_on_accept_encoding() {
+    for body in &["hello".to_owned(), "hello ".repeat(1000)] {
+      let response = Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Body::from(body.clone()))
+        .unwrap();
+      let response = compress(&request_headers("identity"), response);
+      assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+      assert_eq!(
+        response.headers().get(header::VARY).unwrap(),
+        "accept-encoding"
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn streamed_chunks_are_flushed() {
+    let (mut sender, body) = Body::channel();
+    let response = Response::builder()
+      .header(header::CONTENT_TYPE, "text/plain")
+      .body(body)
+      .unwrap();
+    let mut body = compress(&request_headers("gzip"), response).into_body();
+    sender.send_data("hello".into()).await.unwrap();
+    let chunk = tokio::time::timeout(Duration::from_secs(10), body.data())
+      .await
+      .unwrap()
+      .unwrap()
+      .unwrap();
+    let mut decompressed = [0; 5];
+    GzDecoder::new(chunk.as_ref())
+      .read_exact(&mut decompressed)
+      .unwrap();
+    assert_eq!(&decompressed, b"hello");
+  }
+}
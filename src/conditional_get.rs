@@ -0,0 +1,139 @@
+use {crate::common::*, hyper::HeaderMap, std::future::Future};
+
+/// Validators for a cacheable response, which let clients revalidate their
+/// cached copy with `If-None-Match` or `If-Modified-Since`, instead of
+/// downloading it again.
+pub(crate) struct Validators {
+  etag: String,
+  last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+  /// Strong validator derived from a hash of the response's content.
+  pub(crate) fn strong(hash: &[u8]) -> Self {
+    Self {
+      etag: format!("\"{}\"", hex::encode(hash)),
+      last_modified: None,
+    }
+  }
+
+  /// Weak validator derived from a file's size and modification time.
+  pub(crate) fn weak(len: u64, modified: SystemTime) -> Self {
+    let nanos = modified
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .map(|duration| duration.as_nanos())
+      .unwrap_or_default();
+    Self {
+      etag: format!("W/\"{:x}-{:x}\"", len, nanos),
+      last_modified: Some(modified),
+    }
+  }
+
+  /// Whether the client's cached copy is still fresh. `If-Modified-Since`
+  /// is only considered without `If-None-Match`, as RFC 7232 requires.
+  pub(crate) fn not_modified(&self, request_headers: &HeaderMap) -> bool {
+    if request_headers.contains_key(header::IF_NONE_MATCH) {
+      return request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || Self::weak_eq(tag, &self.etag));
+    }
+
+    match (
+      self.last_modified,
+      request_headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok()),
+    ) {
+      (Some(last_modified), Some(since)) => {
+        // HTTP dates have a resolution of one second
+        last_modified
+          .duration_since(since)
+          .map_or(true, |duration| duration.as_secs() == 0)
+      }
+      _ => false,
+    }
+  }
+
+  fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+  }
+
+  /// Respond with `304 Not Modified` if the client's cached copy is fresh,
+  /// and otherwise with the response produced by `response`.
+  pub(crate) async fn respond<F>(
+    &self,
+    request_headers: &HeaderMap,
+    response: F,
+  ) -> Result<Response<Body>>
+  where
+    F: Future<Output = Result<Response<Body>>>,
+  {
+    let mut response = if self.not_modified(request_headers) {
+      Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .body(Body::empty())
+        .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))?
+    } else {
+      response.await?
+    };
+    self.add_headers(response.headers_mut());
+    Ok(response)
+  }
+
+  /// Add the validators, and `Cache-Control: no-cache`, so the response may
+  /// be stored but is revalidated before reuse.
+  fn add_headers(&self, headers: &mut HeaderMap) {
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    headers.insert(
+      header::ETAG,
+      HeaderValue::from_str(&self.etag).expect("ETags are valid header values"),
+    );
+    if let Some(last_modified) = self.last_modified {
+      headers.insert(
+        header::LAST_MODIFIED,
+        HeaderValue::from_str(&httpdate::fmt_http_date(last_modified))
+          .expect("HTTP dates are valid header values"),
+      );
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, std::time::Duration};
+
+  fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(name, HeaderValue::from_str(value).unwrap());
+    headers
+  }
+
+  #[test]
+  fn if_none_match() {
+    let validators = Validators::strong(&[0xab, 0xcd]);
+    assert!(validators.not_modified(&headers(header::IF_NONE_MATCH, "\"abcd\"")));
+    assert!(validators.not_modified(&headers(header::IF_NONE_MATCH, "\"x\", W/\"abcd\"")));
+    assert!(validators.not_modified(&headers(header::IF_NONE_MATCH, "*")));
+    assert!(!validators.not_modified(&headers(header::IF_NONE_MATCH, "\"ef\"")));
+    assert!(!validators.not_modified(&HeaderMap::new()));
+  }
+
+  #[test]
+  fn if_modified_since() {
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_millis(1_000_000_500);
+    let validators = Validators::weak(5, modified);
+    let since = |time| headers(header::IF_MODIFIED_SINCE, &httpdate::fmt_http_date(time));
+    assert!(validators.not_modified(&since(modified)));
+    assert!(validators.not_modified(&since(modified + Duration::from_secs(10))));
+    assert!(!validators.not_modified(&since(modified - Duration::from_secs(10))));
+
+    let mut headers = since(modified);
+    headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+    assert!(!validators.not_modified(&headers));
+  }
+}
@@ -0,0 +1,108 @@
+use {
+  crate::common::*,
+  std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+  },
+};
+
+/// Settled invoices per paid file, keyed by the file's path relative to the
+/// base directory. Optionally persisted to a YAML file so counts survive
+/// restarts.
+#[derive(Clone, Debug)]
+pub(crate) struct DownloadCounts {
+  path: Option<PathBuf>,
+  settled: Arc<Mutex<Settled>>,
+}
+
+type Settled = BTreeMap<String, BTreeSet<String>>;
+
+impl DownloadCounts {
+  pub(crate) fn load(path: Option<PathBuf>) -> Result<Self> {
+    let settled = match &path {
+      Some(path) => match fs::read_to_string(path) {
+        Ok(yaml) => {
+          serde_yaml::from_str(&yaml).context(error::DownloadCountsDeserialize { path })?
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Settled::new(),
+        Err(source) => return Err(error::FilesystemIo { path }.into_error(source)),
+      },
+      None => Settled::new(),
+    };
+
+    Ok(Self {
+      path,
+      settled: Arc::new(Mutex::new(settled)),
+    })
+  }
+
+  pub(crate) fn count(&self, file_path: &str) -> u64 {
+    self
+      .settled
+      .lock()
+      .unwrap()
+      .get(file_path)
+      .map(|invoices| invoices.len() as u64)
+      .unwrap_or(0)
+  }
+
+  /// Record a download of `file_path` paid for by the invoice with hash
+  /// `r_hash`. Repeated downloads with the same invoice are counted once.
+  /// Returns whether this is the first download with the invoice.
+  pub(crate) fn record(&self, file_path: &str, r_hash: [u8; 32]) -> Result<bool> {
+    let mut settled = self.settled.lock().unwrap();
+
+    let inserted = settled
+      .entry(file_path.to_owned())
+      .or_default()
+      .insert(hex::encode(r_hash));
+
+    if let (true, Some(path)) = (inserted, &self.path) {
+      let yaml = serde_yaml::to_string(&*settled).context(error::DownloadCountsSerialize)?;
+      let tmp = path.with_extension("tmp");
+      fs::write(&tmp, yaml).context(error::FilesystemIo { path: &tmp })?;
+      fs::rename(&tmp, path).context(error::FilesystemIo { path })?;
+    }
+
+    Ok(inserted)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counts_distinct_invoices() {
+    let counts = DownloadCounts::load(None).unwrap();
+    assert_eq!(counts.count("foo"), 0);
+    assert!(counts.record("foo", [0; 32]).unwrap());
+    assert!(!counts.record("foo", [0; 32]).unwrap());
+    assert_eq!(counts.count("foo"), 1);
+    assert!(counts.record("foo", [1; 32]).unwrap());
+    assert_eq!(counts.count("foo"), 2);
+    assert_eq!(counts.count("bar"), 0);
+  }
+
+  #[test]
+  fn counts_are_persisted() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("downloads.yaml");
+    let counts = DownloadCounts::load(Some(path.clone())).unwrap();
+    counts.record("dir/foo", [0; 32]).unwrap();
+    counts.record("dir/foo", [1; 32]).unwrap();
+    let counts = DownloadCounts::load(Some(path)).unwrap();
+    assert_eq!(counts.count("dir/foo"), 2);
+  }
+
+  #[test]
+  fn invalid_counts_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("downloads.yaml");
+    fs::write(&path, "{{{").unwrap();
+    assert_matches!(
+      DownloadCounts::load(Some(path.clone())),
+      Err(Error::DownloadCountsDeserialize { path: error_path, .. }) if error_path == path
+    );
+  }
+}
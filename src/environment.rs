@@ -4,21 +4,46 @@ pub(crate) struct Environment {
   pub(crate) arguments: Vec<OsString>,
   pub(crate) working_directory: PathBuf,
   pub(crate) stderr: Stderr,
+  /// Where the directory bundled into the executable was extracted to,
+  /// which is removed when agora exits.
+  _bundled_directory: Option<TempDir>,
   #[cfg(test)]
   _working_directory_tempdir: TempDir,
 }
 
 impl Environment {
   pub(crate) fn production() -> Result<Self> {
+    let mut arguments = env::args_os().into_iter().collect::<Vec<OsString>>();
+    let bundled_directory = Self::bundled_directory(&mut arguments)?;
     Ok(Environment {
-      arguments: env::args_os().into_iter().collect(),
+      arguments,
       stderr: Stderr::production(),
       working_directory: env::current_dir().context(error::CurrentDir)?,
+      _bundled_directory: bundled_directory,
       #[cfg(test)]
       _working_directory_tempdir: TempDir::new().unwrap(),
     })
   }
 
+  /// Without `--directory`, serve the directory bundled into the executable
+  /// with `agora bundle`, if it carries one, by extracting it, and passing
+  /// where it was extracted to as `--directory`.
+  fn bundled_directory(arguments: &mut Vec<OsString>) -> Result<Option<TempDir>> {
+    if arguments.iter().skip(1).any(|argument| {
+      let argument = argument.to_string_lossy();
+      argument == "--directory" || argument.starts_with("--directory=")
+    }) {
+      return Ok(None);
+    }
+    let bundled_directory = Bundle::extract(&env::current_exe().context(error::CurrentExe)?)?;
+    if let Some(bundled_directory) = &bundled_directory {
+      let mut argument = OsString::from("--directory=");
+      argument.push(bundled_directory.path());
+      arguments.insert(1, argument);
+    }
+    Ok(bundled_directory)
+  }
+
   #[cfg(test)]
   pub(crate) fn test() -> Self {
     let tempdir = tempfile::Builder::new()
@@ -35,6 +60,7 @@ impl Environment {
       ],
       stderr: Stderr::test(),
       working_directory: tempdir.path().to_owned(),
+      _bundled_directory: None,
       _working_directory_tempdir: tempdir,
     }
   }
@@ -21,30 +21,88 @@ pub(crate) enum Error {
   },
   #[snafu(display("`{}` did not resolve to an IP address", input))]
   AddressResolutionNoAddresses { input: String, backtrace: Backtrace },
+  #[snafu(display("Directory `{}` is too large for a zip archive", path.display()))]
+  ArchiveTooLarge { backtrace: Backtrace, path: PathBuf },
+  #[snafu(display("Failed to serialize arguments: {}", source))]
+  ArgumentsSerialize {
+    backtrace: Backtrace,
+    source: serde_yaml::Error,
+  },
+  #[snafu(display("Bundled directory in executable `{}` is corrupt", path.display()))]
+  BundleCorrupt { backtrace: Backtrace, path: PathBuf },
+  #[snafu(display("Can't bundle `{}`, since its path isn't valid unicode", path.display()))]
+  BundleFileName { backtrace: Backtrace, path: PathBuf },
   #[snafu(context(false), display("{}", source))]
   Clap {
     backtrace: Backtrace,
     source: clap::Error,
   },
+  #[snafu(display("Found {} invalid config file(s)", count))]
+  ConfigCheck { backtrace: Backtrace, count: usize },
   #[snafu(display("Failed to deserialize config file at `{}`: {}", path.display(), source))]
   ConfigDeserialize {
     backtrace: Backtrace,
     path: PathBuf,
     source: serde_yaml::Error,
   },
+  #[snafu(display(
+    "Config file at `{}` references unset environment variable `{}`",
+    path.display(),
+    name
+  ))]
+  ConfigEnvironmentVariable {
+    backtrace: Backtrace,
+    name: String,
+    path: PathBuf,
+  },
   #[snafu(display("Missing base price for paid file `{}`", path.display()))]
   ConfigMissingBasePrice { path: PathBuf, backtrace: Backtrace },
+  #[snafu(display(
+    "Config `{}` combines `proxy` with `price-per-byte`, but proxied files have no known size",
+    path.display()
+  ))]
+  ConfigProxyPricePerByte { backtrace: Backtrace, path: PathBuf },
   #[snafu(display("Failed to retrieve current directory: {}", source))]
   CurrentDir {
     backtrace: Backtrace,
     source: io::Error,
   },
+  #[snafu(display("Failed to retrieve path of current executable: {}", source))]
+  CurrentExe {
+    backtrace: Backtrace,
+    source: io::Error,
+  },
   #[snafu(display("{}", message))]
   Custom {
     backtrace: Backtrace,
     status_code: StatusCode,
     message: String,
   },
+  #[snafu(display("Listing directory `{}` is disabled by `--no-listing`", path.display()))]
+  DirectoryListingDisabled { backtrace: Backtrace, path: PathBuf },
+  #[snafu(display(
+    "Failed to deserialize download counts file at `{}`: {}",
+    path.display(),
+    source
+  ))]
+  DownloadCountsDeserialize {
+    backtrace: Backtrace,
+    path: PathBuf,
+    source: serde_yaml::Error,
+  },
+  #[snafu(display("Failed to serialize download counts: {}", source))]
+  DownloadCountsSerialize {
+    backtrace: Backtrace,
+    source: serde_yaml::Error,
+  },
+  #[snafu(display(
+    "Download refused, already serving the maximum of {} concurrent downloads",
+    max_concurrent_downloads
+  ))]
+  DownloadLimitReached {
+    backtrace: Backtrace,
+    max_concurrent_downloads: NonZeroUsize,
+  },
   #[snafu(display("IO error accessing filesystem at `{}`: {}", path.display(), source))]
   FilesystemIo {
     backtrace: Backtrace,
@@ -53,6 +111,16 @@ pub(crate) enum Error {
   },
   #[snafu(display("Forbidden access to hidden file: {}", path.display()))]
   HiddenFileAccess { backtrace: Backtrace, path: PathBuf },
+  #[snafu(display(
+    "Invalid `index` in config for `{}`: `{}` is not a file name",
+    dir.display(),
+    index.display()
+  ))]
+  IndexFileName {
+    backtrace: Backtrace,
+    dir: PathBuf,
+    index: PathBuf,
+  },
   #[snafu(display(
     "Internal error, this is probably a bug in agora: {}\n\
       Consider filing an issue: https://github.com/soenkehahn/agora/issues/new/",
@@ -73,6 +141,20 @@ pub(crate) enum Error {
     source: Utf8Error,
     uri_path: String,
   },
+  #[snafu(display(
+    "Invoice amount of {} for `{}` exceeds `--max-invoice-amount` of {}",
+    amount,
+    path.display(),
+    max
+  ))]
+  InvoiceAmountExceedsMaximum {
+    amount: Millisatoshi,
+    backtrace: Backtrace,
+    max: Millisatoshi,
+    path: PathBuf,
+  },
+  #[snafu(display("Invoice amount for `{}` is too large", path.display()))]
+  InvoiceAmountOverflow { backtrace: Backtrace, path: PathBuf },
   #[snafu(display("Invalid invoice ID: {}", source))]
   InvoiceId {
     backtrace: Backtrace,
@@ -95,6 +177,23 @@ pub(crate) enum Error {
     r_hash: [u8; 32],
     request_tail: String,
   },
+  #[snafu(display("Lightning address not found: {}", username))]
+  LightningAddressNotFound {
+    backtrace: Backtrace,
+    username: String,
+  },
+  #[snafu(display("Listing page not found: {}", page))]
+  ListingPageNotFound { backtrace: Backtrace, page: String },
+  #[snafu(display("Failed to serialize listing: {}", source))]
+  ListingSerialize {
+    backtrace: Backtrace,
+    source: serde_json::Error,
+  },
+  #[snafu(display(
+    "Invalid listing template `{}`, expected `{{entries}}` followed by `{{/entries}}` exactly once",
+    path.display()
+  ))]
+  ListingTemplateParse { backtrace: Backtrace, path: PathBuf },
   #[snafu(display("Invoice request requires LND client configuration: {}", uri_path))]
   LndNotConfiguredInvoiceRequest {
     backtrace: Backtrace,
@@ -117,6 +216,50 @@ pub(crate) enum Error {
     backtrace: Backtrace,
     source: tonic::Status,
   },
+  #[snafu(display("LNURL-pay request requires `--lnurl-base-url`: {}", uri_path))]
+  LnurlNotConfigured {
+    backtrace: Backtrace,
+    uri_path: String,
+  },
+  #[snafu(display("Failed to serialize LNURL-pay response: {}", source))]
+  LnurlSerialize {
+    backtrace: Backtrace,
+    source: serde_json::Error,
+  },
+  #[snafu(display("LNURL-pay is only available for paid files: `{}`", file_path))]
+  LnurlUnavailable {
+    backtrace: Backtrace,
+    file_path: String,
+  },
+  #[snafu(display("Invalid log format `{}`, expected `text` or `json`", input))]
+  LogFormatParse { backtrace: Backtrace, input: String },
+  #[snafu(display("Invalid LND RPC macaroon hex: {}", source))]
+  MacaroonHexParse {
+    backtrace: Backtrace,
+    source: hex::FromHexError,
+  },
+  #[snafu(display(
+    "Invalid invoice memo template `{}`, expected `{{path}}` exactly once",
+    input
+  ))]
+  MemoTemplateParse { backtrace: Backtrace, input: String },
+  #[snafu(display("Merged path `{}` is not a directory", path.display()))]
+  MergeSourceNotDirectory { backtrace: Backtrace, path: PathBuf },
+  #[snafu(display(
+    "Failed to deserialize paid splits file at `{}`: {}",
+    path.display(),
+    source
+  ))]
+  PaidSplitsDeserialize {
+    backtrace: Backtrace,
+    path: PathBuf,
+    source: serde_yaml::Error,
+  },
+  #[snafu(display("Failed to serialize paid splits: {}", source))]
+  PaidSplitsSerialize {
+    backtrace: Backtrace,
+    source: serde_yaml::Error,
+  },
   #[snafu(display(
     "Payment request `{}` too long for QR code: {}",
     payment_request,
@@ -127,11 +270,67 @@ pub(crate) enum Error {
     payment_request: String,
     source: qrcodegen::DataTooLong,
   },
+  #[snafu(display("Price feed at `{}` has no exchange rate for `{}`", url, currency))]
+  PriceFeedCurrency {
+    backtrace: Backtrace,
+    currency: String,
+    url: hyper::Uri,
+  },
+  #[snafu(display(
+    "File `{}` has a fiat price, but no price feed was configured with `--price-feed-url`",
+    path.display()
+  ))]
+  PriceFeedMissing { backtrace: Backtrace, path: PathBuf },
+  #[snafu(display("OpenSSL error setting up price feed client: {}", source))]
+  PriceFeedTls {
+    backtrace: Backtrace,
+    source: openssl::error::ErrorStack,
+  },
+  #[snafu(display("No fresh exchange rates from price feed at `{}`: {}", url, message))]
+  PriceFeedUnavailable {
+    backtrace: Backtrace,
+    message: String,
+    url: hyper::Uri,
+  },
+  #[snafu(display("Error requesting proxied file from `{}`: {}", url, source))]
+  ProxyRequest {
+    backtrace: Backtrace,
+    source: hyper::Error,
+    url: hyper::Uri,
+  },
+  #[snafu(display("Proxied file at `{}` responded with {}", url, status))]
+  ProxyStatus {
+    backtrace: Backtrace,
+    status: StatusCode,
+    url: hyper::Uri,
+  },
+  #[snafu(display("OpenSSL error setting up proxy client: {}", source))]
+  ProxyTls {
+    backtrace: Backtrace,
+    source: openssl::error::ErrorStack,
+  },
+  #[snafu(display("Request body has no `Content-Length`"))]
+  RequestBodyLengthRequired { backtrace: Backtrace },
+  #[snafu(display(
+    "Request body of {} bytes exceeds maximum of {} bytes",
+    length,
+    max_request_body
+  ))]
+  RequestBodyTooLarge {
+    backtrace: Backtrace,
+    length: u64,
+    max_request_body: u64,
+  },
   #[snafu(display("Request handler panicked: {}", source))]
   RequestHandlerPanic {
     backtrace: Backtrace,
     source: JoinError,
   },
+  #[snafu(display(
+    "Invalid route `{}`, expected `files`, `health`, `invoice`, `invoice-qr`, `lnurlp`, or `static`",
+    input
+  ))]
+  RouteParse { backtrace: Backtrace, input: String },
   #[snafu(display("URI path did not match any route: {}", uri_path))]
   RouteNotFound { uri_path: String },
   #[snafu(display("Failed running HTTP server: {}", source))]
@@ -139,12 +338,32 @@ pub(crate) enum Error {
     backtrace: Backtrace,
     source: hyper::Error,
   },
+  #[snafu(display("OpenSSL error setting up settlement webhook client: {}", source))]
+  SettlementWebhookTls {
+    backtrace: Backtrace,
+    source: openssl::error::ErrorStack,
+  },
+  #[snafu(display("Failed to install signal handler: {}", source))]
+  SignalHandlerInstall {
+    backtrace: Backtrace,
+    source: io::Error,
+  },
   #[snafu(display("I/O error on socket address `{}`: {}", socket_addr, source))]
   SocketIo {
     backtrace: Backtrace,
     socket_addr: SocketAddr,
     source: io::Error,
   },
+  #[snafu(display("Signed URL for `{}` has expired", file_path))]
+  SignedUrlExpired {
+    backtrace: Backtrace,
+    file_path: String,
+  },
+  #[snafu(display("Invalid signed URL for `{}`", file_path))]
+  SignedUrlInvalid {
+    backtrace: Backtrace,
+    file_path: String,
+  },
   #[snafu(display("Static asset not found: {}", uri_path))]
   StaticAssetNotFound {
     backtrace: Backtrace,
@@ -157,6 +376,29 @@ pub(crate) enum Error {
   },
   #[snafu(display("Forbidden access to escaping symlink: `{}`", path.display()))]
   SymlinkAccess { backtrace: Backtrace, path: PathBuf },
+  #[snafu(display("Invalid TLS version `{}`, expected `1.2` or `1.3`", input))]
+  TlsVersionParse { backtrace: Backtrace, input: String },
+  #[snafu(display("No torrent available for `{}`", file_path))]
+  TorrentUnavailable {
+    backtrace: Backtrace,
+    file_path: String,
+  },
+  #[snafu(display(
+    "Invalid trailing slash policy `{}`, expected `enforce`, `off`, or `inverse`",
+    input
+  ))]
+  TrailingSlashPolicyParse { backtrace: Backtrace, input: String },
+  #[snafu(display("Missing or invalid basic auth credentials for `{}`", path.display()))]
+  Unauthorized { backtrace: Backtrace, path: PathBuf },
+  #[snafu(display("OpenSSL error signing URL: {}", source))]
+  UrlSign {
+    backtrace: Backtrace,
+    source: openssl::error::ErrorStack,
+  },
+  #[snafu(display("Unsupported WebDAV depth `{}`, expected `0` or `1`", depth))]
+  WebdavDepth { backtrace: Backtrace, depth: String },
+  #[snafu(display("Paid file `{}` is not available over WebDAV", path.display()))]
+  WebdavPaidFile { backtrace: Backtrace, path: PathBuf },
 }
 
 impl Error {
@@ -170,29 +412,77 @@ impl Error {
       | InvalidUriPath { .. }
       | InvoiceId { .. }
       | InvoicePathMismatch { .. } => StatusCode::BAD_REQUEST,
+      DirectoryListingDisabled { .. }
+      | SignedUrlExpired { .. }
+      | SignedUrlInvalid { .. }
+      | WebdavDepth { .. } => StatusCode::FORBIDDEN,
+      Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+      DownloadLimitReached { .. } => StatusCode::SERVICE_UNAVAILABLE,
+      RequestBodyLengthRequired { .. } => StatusCode::LENGTH_REQUIRED,
+      RequestBodyTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+      ProxyRequest { .. } | ProxyStatus { .. } => StatusCode::BAD_GATEWAY,
       HiddenFileAccess { .. }
       | InvoiceNotFound { .. }
+      | LightningAddressNotFound { .. }
+      | ListingPageNotFound { .. }
       | LndNotConfiguredInvoiceRequest { .. }
+      | LnurlNotConfigured { .. }
+      | LnurlUnavailable { .. }
       | RouteNotFound { .. }
       | StaticAssetNotFound { .. }
-      | SymlinkAccess { .. } => StatusCode::NOT_FOUND,
+      | SymlinkAccess { .. }
+      | TorrentUnavailable { .. }
+      | WebdavPaidFile { .. } => StatusCode::NOT_FOUND,
       AddressResolutionIo { .. }
       | AddressResolutionNoAddresses { .. }
+      | ArchiveTooLarge { .. }
+      | ArgumentsSerialize { .. }
+      | BundleCorrupt { .. }
+      | BundleFileName { .. }
       | Clap { .. }
+      | ConfigCheck { .. }
       | ConfigDeserialize { .. }
+      | ConfigEnvironmentVariable { .. }
       | ConfigMissingBasePrice { .. }
+      | ConfigProxyPricePerByte { .. }
       | CurrentDir { .. }
+      | CurrentExe { .. }
+      | DownloadCountsDeserialize { .. }
+      | DownloadCountsSerialize { .. }
       | FilesystemIo { .. }
+      | IndexFileName { .. }
       | Internal { .. }
+      | InvoiceAmountExceedsMaximum { .. }
+      | InvoiceAmountOverflow { .. }
+      | ListingSerialize { .. }
+      | ListingTemplateParse { .. }
       | LndNotConfiguredPaidFileRequest { .. }
       | LndRpcCertificateParse { .. }
       | LndRpcConnect { .. }
       | LndRpcStatus { .. }
+      | LnurlSerialize { .. }
+      | LogFormatParse { .. }
+      | MacaroonHexParse { .. }
+      | MemoTemplateParse { .. }
+      | MergeSourceNotDirectory { .. }
+      | PaidSplitsDeserialize { .. }
+      | PaidSplitsSerialize { .. }
       | PaymentRequestTooLongForQrCode { .. }
+      | PriceFeedCurrency { .. }
+      | PriceFeedMissing { .. }
+      | PriceFeedTls { .. }
+      | PriceFeedUnavailable { .. }
+      | ProxyTls { .. }
       | RequestHandlerPanic { .. }
+      | RouteParse { .. }
       | ServerRun { .. }
+      | SettlementWebhookTls { .. }
+      | SignalHandlerInstall { .. }
       | SocketIo { .. }
-      | StderrWrite { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+      | StderrWrite { .. }
+      | TlsVersionParse { .. }
+      | TrailingSlashPolicyParse { .. }
+      | UrlSign { .. } => StatusCode::INTERNAL_SERVER_ERROR,
       Custom { status_code, .. } => *status_code,
     }
   }
@@ -209,25 +499,38 @@ impl Error {
 
   pub(crate) fn print_backtrace(&self, write_color: &mut impl WriteColor) {
     if let Some(backtrace) = ErrorCompat::backtrace(self) {
-      BacktracePrinter::new()
-        .add_frame_filter(Box::new(|frames| {
-          frames.retain(
-            |frame| match frame.filename.as_ref().and_then(|x| x.to_str()) {
-              Some(file) => {
-                !(file.starts_with("/rustc/")
-                  || file.contains(&format!(
-                    "{}.cargo{}registry{}",
-                    MAIN_SEPARATOR, MAIN_SEPARATOR, MAIN_SEPARATOR
-                  )))
-              }
-              None => false,
-            },
-          );
-        }))
+      Self::backtrace_printer()
         .print_trace(backtrace, write_color)
         .ok();
     }
   }
+
+  /// The backtrace printed by `print_backtrace`, without colors, for
+  /// `--log-format json`.
+  pub(crate) fn backtrace_string(&self) -> Option<String> {
+    ErrorCompat::backtrace(self).and_then(|backtrace| {
+      Self::backtrace_printer()
+        .format_trace_to_string(backtrace)
+        .ok()
+    })
+  }
+
+  fn backtrace_printer() -> BacktracePrinter {
+    BacktracePrinter::new().add_frame_filter(Box::new(|frames| {
+      frames.retain(
+        |frame| match frame.filename.as_ref().and_then(|x| x.to_str()) {
+          Some(file) => {
+            !(file.starts_with("/rustc/")
+              || file.contains(&format!(
+                "{}.cargo{}registry{}",
+                MAIN_SEPARATOR, MAIN_SEPARATOR, MAIN_SEPARATOR
+              )))
+          }
+          None => false,
+        },
+      );
+    }))
+  }
 }
 
 #[derive(Debug)]
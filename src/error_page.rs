@@ -1,21 +1,209 @@
-use {crate::common::*, maud::html};
+use {
+  crate::{common::*, static_assets::StaticAssets},
+  hyper::HeaderMap,
+  maud::{html, PreEscaped},
+};
+
+/// Body of error responses for clients that prefer JSON. The message is
+/// the status's canonical reason, like in HTML error pages, since error
+/// messages can contain filesystem paths, or reveal hidden files.
+#[derive(Serialize)]
+struct JsonError<'a> {
+  error: &'a str,
+  status: u16,
+}
+
+/// Error page template from `--error-template`, loaded at startup so that a
+/// missing template is reported right away, instead of on the first error.
+#[derive(Clone, Debug)]
+pub(crate) struct ErrorTemplate {
+  html: Arc<str>,
+  markdown: bool,
+}
+
+impl ErrorTemplate {
+  pub(crate) fn load(environment: &Environment, arguments: &Arguments) -> Result<Option<Self>> {
+    let path = match &arguments.error_template {
+      Some(path) => environment.working_directory.join(path),
+      None => return Ok(None),
+    };
+    let template = fs::read_to_string(&path).context(error::FilesystemIo { path: &path })?;
+    let markdown = path.extension() == Some("md".as_ref());
+    let html = if markdown {
+      let mut html = String::new();
+      pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(&template));
+      html
+    } else {
+      template
+    };
+    Ok(Some(Self {
+      html: html.into(),
+      markdown,
+    }))
+  }
+
+  fn render(&self, base_path: &str, status: StatusCode) -> Response<Body> {
+    let message = status.canonical_reason().unwrap_or("Error");
+    let html = self
+      .html
+      .replace("{status}", status.as_str())
+      .replace("{message}", message);
+    if self.markdown {
+      html::wrap_body(base_path, message, html! {}, PreEscaped(html))
+    } else {
+      Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .body(Body::from(html))
+        .expect("builder arguments are valid")
+    }
+  }
+}
+
+/// Report an error that occurred while handling a request. With
+/// `--log-format json`, it's logged instead of being written to stderr
+/// with its backtrace, so that stderr only contains JSON lines.
+pub(crate) fn log_error(
+  stderr: &mut Stderr,
+  log_format: LogFormat,
+  remote_ip: Option<IpAddr>,
+  error: &Error,
+) {
+  match log_format {
+    LogFormat::Text => {
+      error.print_backtrace(stderr);
+      writeln!(stderr, "{}", error).ok();
+    }
+    LogFormat::Json => log::error!("{}", error),
+  }
+  match error {
+    Error::SymlinkAccess { .. } => match remote_ip {
+      Some(remote_ip) => log::warn!("Request from {} denied: {}", remote_ip, error),
+      None => log::warn!("Request denied: {}", error),
+    },
+    Error::HiddenFileAccess { .. } => log::debug!("Request denied: {}", error),
+    _ => {}
+  }
+}
 
 pub(crate) fn map_error(
-  mut stderr: Stderr,
+  request_headers: &HeaderMap,
+  image_error_placeholder: bool,
+  error_template: Option<&ErrorTemplate>,
+  base_path: &str,
   result: Result<Response<Body>, Error>,
 ) -> Response<Body> {
   result.unwrap_or_else(|error| {
-    error.print_backtrace(&mut stderr);
-    writeln!(stderr, "{}", error).ok();
-    let mut response = html::wrap_body(
-      &error.status().canonical_reason().unwrap_or("Error"),
-      html! {
-        h1 {
-          (error.status())
-        }
-      },
-    );
+    if image_error_placeholder {
+      if let Some(mut response) = image_placeholder(&error) {
+        *response.status_mut() = error.status();
+        return response;
+      }
+    }
+    let mut response = if prefers_json(request_headers) {
+      json_response(error.status())
+    } else {
+      match error_template {
+        Some(error_template) => error_template.render(base_path, error.status()),
+        None => html::wrap_body(
+          base_path,
+          &error.status().canonical_reason().unwrap_or("Error"),
+          html! {},
+          html! {
+            h1 {
+              (error.status())
+            }
+          },
+        ),
+      }
+    };
     *response.status_mut() = error.status();
+    match error {
+      Error::DownloadLimitReached { .. } => {
+        response
+          .headers_mut()
+          .insert(header::RETRY_AFTER, HeaderValue::from_static("5"));
+      }
+      Error::Unauthorized { .. } => {
+        response.headers_mut().insert(
+          header::WWW_AUTHENTICATE,
+          HeaderValue::from_static("Basic realm=\"agora\", charset=\"UTF-8\""),
+        );
+      }
+      _ => {}
+    }
     response
   })
 }
+
+/// Whether the client prefers JSON to HTML, according to its `Accept`
+/// header, which browsers always send with `text/html` in it.
+fn prefers_json(request_headers: &HeaderMap) -> bool {
+  let accepted = request_headers
+    .get_all(header::ACCEPT)
+    .iter()
+    .filter_map(|accept| accept.to_str().ok())
+    .flat_map(|accept| accept.split(','))
+    .map(|media_range| media_range.split(';').next().unwrap_or_default().trim())
+    .collect::<Vec<&str>>();
+  accepted.contains(&"application/json") && !accepted.contains(&"text/html")
+}
+
+fn json_response(status: StatusCode) -> Response<Body> {
+  let body = serde_json::to_string(&JsonError {
+    error: status.canonical_reason().unwrap_or("Error"),
+    status: status.as_u16(),
+  })
+  .expect("JSON errors can be serialized");
+  Response::builder()
+    .header(header::CONTENT_TYPE, "application/json")
+    .body(Body::from(body))
+    .expect("builder arguments are valid")
+}
+
+/// Requests for images that fail before the response is sent can be
+/// answered with a placeholder image, so that embeds don't show up as
+/// broken. Failures in the middle of a response can't be handled, since by
+/// then the status and headers have already been sent. Requests for hidden
+/// images and escaping symlinks get the same placeholder as those for
+/// missing images, so the response doesn't reveal that they exist.
+fn image_placeholder(error: &Error) -> Option<Response<Body>> {
+  let path = match error {
+    Error::FilesystemIo { path, .. }
+    | Error::HiddenFileAccess { path, .. }
+    | Error::SymlinkAccess { path, .. } => path,
+    _ => return None,
+  };
+  if mime_guess::from_path(path)
+    .first()
+    .map(|mime| mime.type_() == mime_guess::mime::IMAGE)
+    .unwrap_or(false)
+  {
+    StaticAssets::serve(&["broken-image.svg"]).ok()
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn headers(accept: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+    headers
+  }
+
+  #[test]
+  fn prefers_json() {
+    assert!(super::prefers_json(&headers("application/json")));
+    assert!(super::prefers_json(&headers(
+      "application/json;q=0.9, text/plain"
+    )));
+    assert!(!super::prefers_json(&headers(
+      "text/html,application/xhtml+xml,application/json;q=0.9"
+    )));
+    assert!(!super::prefers_json(&headers("*/*")));
+    assert!(!super::prefers_json(&HeaderMap::new()));
+  }
+}
@@ -0,0 +1,86 @@
+use {crate::common::*, hyper::body::Bytes, std::collections::VecDeque};
+
+/// Contents of small files, keyed by path, and only valid as long as the
+/// file's modification time and length are unchanged. Holds at most
+/// `capacity` files, evicting the least recently used.
+#[derive(Debug)]
+pub(crate) struct FileCache {
+  capacity: usize,
+  /// Most recently used last
+  entries: VecDeque<(PathBuf, SystemTime, u64, Bytes)>,
+}
+
+impl FileCache {
+  pub(crate) const CAPACITY: usize = 1024;
+
+  pub(crate) fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      entries: VecDeque::new(),
+    }
+  }
+
+  /// Cached contents of the file at `path`, unless it was modified since
+  /// it was cached, in which case the stale entry is removed.
+  pub(crate) fn get(&mut self, path: &Path, modified: SystemTime, len: u64) -> Option<Bytes> {
+    let index = self.position(path)?;
+    let entry = self.entries.remove(index)?;
+    if entry.1 != modified || entry.2 != len {
+      return None;
+    }
+    let contents = entry.3.clone();
+    self.entries.push_back(entry);
+    Some(contents)
+  }
+
+  pub(crate) fn insert(&mut self, path: PathBuf, modified: SystemTime, contents: Bytes) {
+    if let Some(index) = self.position(&path) {
+      self.entries.remove(index);
+    }
+    if self.entries.len() >= self.capacity {
+      self.entries.pop_front();
+    }
+    let len = contents.len() as u64;
+    self.entries.push_back((path, modified, len, contents));
+  }
+
+  fn position(&self, path: &Path) -> Option<usize> {
+    self
+      .entries
+      .iter()
+      .position(|(entry_path, _modified, _len, _contents)| entry_path == path)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn evicts_least_recently_used() {
+    let time = SystemTime::UNIX_EPOCH;
+    let mut cache = FileCache::new(2);
+    cache.insert("a".into(), time, "a".into());
+    cache.insert("b".into(), time, "b".into());
+    assert_eq!(cache.get("a".as_ref(), time, 1), Some("a".into()));
+    cache.insert("c".into(), time, "c".into());
+    assert_eq!(cache.get("b".as_ref(), time, 1), None);
+    assert_eq!(cache.get("a".as_ref(), time, 1), Some("a".into()));
+    assert_eq!(cache.get("c".as_ref(), time, 1), Some("c".into()));
+  }
+
+  #[test]
+  fn modified_files_are_invalidated() {
+    let time = SystemTime::UNIX_EPOCH;
+    let mut cache = FileCache::new(2);
+    cache.insert("a".into(), time, "a".into());
+    assert_eq!(cache.get("a".as_ref(), time, 2), None);
+    assert_eq!(cache.get("a".as_ref(), time, 1), None);
+    cache.insert("a".into(), time, "a".into());
+    assert_eq!(
+      cache.get("a".as_ref(), time + Duration::from_secs(1), 1),
+      None
+    );
+    assert_eq!(cache.get("a".as_ref(), time, 1), None);
+  }
+}
@@ -2,6 +2,7 @@ use {
   crate::common::*,
   hyper::body::Bytes,
   pin_project::pin_project,
+  std::convert::TryFrom,
   tokio::{
     fs::File,
     io::{AsyncRead, ReadBuf},
@@ -13,6 +14,7 @@ pub(crate) struct FileStream {
   #[pin]
   file: File,
   path: InputPath,
+  remaining: Option<u64>,
 }
 
 impl FileStream {
@@ -22,8 +24,17 @@ impl FileStream {
         .await
         .with_context(|| Error::filesystem_io(&file_path))?,
       path: file_path,
+      remaining: None,
     })
   }
+
+  /// Only yield the first `len` bytes of the file.
+  pub(crate) fn take(self, len: u64) -> Self {
+    Self {
+      remaining: Some(len),
+      ..self
+    }
+  }
 }
 
 impl Stream for FileStream {
@@ -31,12 +42,21 @@ impl Stream for FileStream {
 
   fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
     let data = &mut [MaybeUninit::uninit(); 8 * 1024];
-    let mut buf = ReadBuf::uninit(data);
 
     let projected = self.project();
 
     let file = projected.file;
     let path = projected.path;
+    let remaining = projected.remaining;
+
+    let len = match *remaining {
+      Some(0) => return Poll::Ready(None),
+      Some(remaining) => data
+        .len()
+        .min(usize::try_from(remaining).unwrap_or(usize::MAX)),
+      None => data.len(),
+    };
+    let mut buf = ReadBuf::uninit(&mut data[..len]);
 
     let poll = file
       .poll_read(cx, &mut buf)
@@ -50,6 +70,10 @@ impl Stream for FileStream {
       return Poll::Ready(None);
     }
 
+    if let Some(remaining) = remaining {
+      *remaining -= buf.filled().len() as u64;
+    }
+
     Poll::Ready(Some(Ok(Bytes::copy_from_slice(buf.filled()))))
   }
 }
@@ -80,4 +104,26 @@ mod tests {
 
     assert_eq!(output, input);
   }
+
+  #[tokio::test]
+  async fn take_yields_first_bytes() {
+    let tempdir = tempfile::tempdir().unwrap();
+    let dir = tempdir.path();
+    let file_path = InputPath::new_unchecked(dir, "foo.txt");
+
+    let input = (0..20_000).map(|i| i as u8).collect::<Vec<u8>>();
+
+    std::fs::write(&file_path, &input).unwrap();
+
+    let mut stream = FileStream::new(file_path).await.unwrap().take(10_000);
+
+    let mut output = Vec::new();
+
+    while let Some(result) = stream.next().await {
+      let bytes = result.unwrap();
+      output.extend(bytes);
+    }
+
+    assert_eq!(output, &input[..10_000]);
+  }
 }
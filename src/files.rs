@@ -1,185 +1,1795 @@
 use {
-  crate::{common::*, file_stream::FileStream, vfs::Vfs},
-  agora_lnd_client::lnrpc::invoice::InvoiceState,
+  crate::{
+    archive::{self, ArchiveEntry},
+    common::*,
+    compression,
+    conditional_get::Validators,
+    file_cache::FileCache,
+    file_stream::FileStream,
+    highlighter::Highlighter,
+    invoice_status_cache::{InvoiceStatus, InvoiceStatusCache},
+    price_feed::PriceFeed,
+    qr_code_cache::QrCodeCache,
+    recent_invoices::RecentInvoices,
+    sitemap, table_of_contents,
+    throttle::Throttle,
+    url_signer::UrlSigner,
+    vfs::{AmountPrecision, DirEntry, ExtraAssets, ListingOrder, Meta, SortKey, Splits, Vfs},
+    webdav::{self, Depth, Resource},
+  },
+  agora_lnd_client::lnrpc::{invoice::InvoiceState, Invoice},
+  hyper::{
+    body::{Bytes, Sender},
+    client::HttpConnector,
+    Uri,
+  },
+  hyper_openssl::HttpsConnector,
   maud::html,
-  percent_encoding::{AsciiSet, NON_ALPHANUMERIC},
+  std::{
+    convert::TryFrom,
+    sync::Mutex,
+    time::{Duration, Instant},
+  },
+  tokio::sync::{OwnedSemaphorePermit, Semaphore},
 };
 
+enum ListingFormat {
+  Html,
+  Json,
+  Text,
+}
+
+/// The page of a paginated listing that is being served.
+struct ListingPage {
+  number: usize,
+  count: usize,
+  entries: usize,
+}
+
+#[derive(Serialize)]
+struct JsonEntry {
+  name: String,
+  is_dir: bool,
+  size: Option<u64>,
+  paid: bool,
+  base_price_msat: Option<u64>,
+  modified: Option<String>,
+  href: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LnurlPayRequest {
+  callback: String,
+  max_sendable: u64,
+  min_sendable: u64,
+  metadata: String,
+  tag: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LnurlPayInvoice {
+  pr: String,
+  routes: Vec<()>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  success_action: Option<LnurlSuccessAction>,
+}
+
+#[derive(Serialize)]
+struct LnurlSuccessAction {
+  tag: &'static str,
+  description: String,
+  url: String,
+}
+
+#[derive(Serialize)]
+struct LnurlError {
+  status: &'static str,
+  reason: String,
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct Files {
   vfs: Vfs,
   lnd_client: Option<agora_lnd_client::Client>,
+  trailing_slash_policy: TrailingSlashPolicy,
+  download_counts: DownloadCounts,
+  download_permits: Option<(Arc<Semaphore>, NonZeroUsize)>,
+  file_cache: Option<(Arc<Mutex<FileCache>>, NonZeroU64)>,
+  torrents: Option<Torrents>,
+  base_path: String,
+  files_path: String,
+  dir_listing_page_size: Option<NonZeroUsize>,
+  lnurl_base_url: Option<String>,
+  serve_index_html: bool,
+  no_listing: bool,
+  max_invoice_amount: Option<Millisatoshi>,
+  price_feed: Option<PriceFeed>,
+  rate_limit: Option<NonZeroU64>,
+  require_payment_confirmations: Option<u32>,
+  static_cache_max_age: Option<u64>,
+  metrics: Arc<Metrics>,
+  qr_codes: Arc<Mutex<QrCodeCache>>,
+  invoice_statuses: Arc<Mutex<InvoiceStatusCache>>,
+  recent_invoices: Arc<Mutex<RecentInvoices>>,
+  url_signer: Option<UrlSigner>,
+  invoice_memo_template: MemoTemplate,
+  proxy_client: hyper::Client<HttpsConnector<HttpConnector>>,
+  highlighter: Arc<Highlighter>,
+  listing_template: Option<ListingTemplate>,
 }
 
-impl Files {
-  pub(crate) fn new(
-    base_directory: InputPath,
-    lnd_client: Option<agora_lnd_client::Client>,
-  ) -> Self {
-    Self {
-      vfs: Vfs::new(base_directory),
-      lnd_client,
+impl Files {
+  pub(crate) fn new(
+    base_directory: InputPath,
+    global_config: Option<InputPath>,
+    arguments: &Arguments,
+    lnd_client: Option<agora_lnd_client::Client>,
+    download_counts: DownloadCounts,
+    metrics: Arc<Metrics>,
+    listing_template: Option<ListingTemplate>,
+  ) -> Result<Self> {
+    Ok(Self {
+      vfs: Vfs::new(
+        base_directory,
+        global_config.as_ref(),
+        arguments.follow_escaping_symlinks,
+        arguments.fast_listing,
+        arguments.group_directories_first,
+        arguments.inherit_partials,
+        arguments.index_filenames.clone(),
+      )?,
+      lnd_client,
+      trailing_slash_policy: arguments.trailing_slash_policy,
+      download_counts,
+      download_permits: arguments
+        .max_concurrent_downloads
+        .map(|max| (Arc::new(Semaphore::new(max.get())), max)),
+      file_cache: arguments.cache_file_max_size.map(|max_size| {
+        (
+          Arc::new(Mutex::new(FileCache::new(FileCache::CAPACITY))),
+          max_size,
+        )
+      }),
+      torrents: Torrents::new(arguments),
+      base_path: arguments.base_path(),
+      files_path: arguments.files_path(),
+      dir_listing_page_size: arguments.dir_listing_page_size,
+      lnurl_base_url: arguments.lnurl_base_url.as_ref().map(|lnurl_base_url| {
+        format!(
+          "{}{}",
+          lnurl_base_url.trim_end_matches('/'),
+          arguments.base_path()
+        )
+      }),
+      serve_index_html: arguments.serve_index_html,
+      no_listing: arguments.no_listing,
+      max_invoice_amount: arguments.max_invoice_amount,
+      price_feed: arguments
+        .price_feed_url
+        .clone()
+        .map(|url| {
+          PriceFeed::new(
+            url,
+            Duration::from_secs(arguments.exchange_rate_refresh_interval),
+            Duration::from_secs(arguments.exchange_rate_max_staleness),
+          )
+        })
+        .transpose()?,
+      rate_limit: arguments.rate_limit,
+      require_payment_confirmations: arguments.require_payment_confirmations,
+      static_cache_max_age: arguments.static_cache_max_age,
+      metrics,
+      qr_codes: Arc::new(Mutex::new(QrCodeCache::new(QrCodeCache::CAPACITY))),
+      invoice_statuses: Arc::new(Mutex::new(InvoiceStatusCache::new(
+        InvoiceStatusCache::CAPACITY,
+      ))),
+      recent_invoices: Arc::new(Mutex::new(RecentInvoices::new(RecentInvoices::CAPACITY))),
+      url_signer: arguments
+        .url_signing_key
+        .as_deref()
+        .map(UrlSigner::new)
+        .transpose()?,
+      invoice_memo_template: arguments.invoice_memo_template.clone().unwrap_or_default(),
+      proxy_client: hyper::Client::builder().build(HttpsConnector::new().context(error::ProxyTls)?),
+      highlighter: Arc::new(Highlighter::new()),
+      listing_template,
+    })
+  }
+
+  pub(crate) async fn serve(
+    &mut self,
+    request: &Request<Body>,
+    tail: &[&str],
+  ) -> Result<Response<Body>> {
+    let resolved = self.vfs.resolve(tail)?;
+    let resolved = resolved.split_inclusive('/').collect::<Vec<&str>>();
+    let file_path = self.vfs.file_path(&resolved.join(""))?;
+    let proxied = self.vfs.proxy_url(&file_path)?.is_some();
+    let is_dir = if proxied {
+      self.vfs.check_prefixes(&resolved)?;
+      false
+    } else {
+      self.vfs.file_type(&resolved)?.is_dir()
+    };
+
+    if !is_dir {
+      self.vfs.check_extension(&file_path)?;
+    }
+
+    self.authorize(request, &file_path, is_dir).await?;
+
+    // The base directory is always served at `/files/`
+    if !tail.is_empty() {
+      let path = request.uri().path();
+      match self.trailing_slash_policy.trailing_slash(is_dir) {
+        Some(true) if !path.ends_with('/') => return redirect(String::from(path) + "/"),
+        Some(false) if path.ends_with('/') => {
+          return redirect(path.trim_end_matches('/').to_owned())
+        }
+        _ => {}
+      }
+    }
+
+    if is_dir {
+      if Self::archive_requested(request) {
+        self.serve_archive(&file_path).await
+      } else {
+        self.serve_dir(request, tail, &file_path).await
+      }
+    } else if Self::torrent_requested(request) && !proxied {
+      self.serve_torrent(&resolved, &file_path).await
+    } else {
+      self.access_file(request, &resolved, &file_path).await
+    }
+  }
+
+  /// Respond to a WebDAV `PROPFIND` request with the properties of a file or
+  /// directory, and, with `Depth: 1`, those of a directory's entries. Paid
+  /// files are left out, since they can only be downloaded after paying an
+  /// invoice in a browser.
+  pub(crate) async fn serve_propfind(
+    &mut self,
+    request: &Request<Body>,
+    tail: &[&str],
+  ) -> Result<Response<Body>> {
+    let depth = Depth::from_request(request)?;
+    let resolved = self.vfs.resolve(tail)?;
+    let resolved = resolved.split_inclusive('/').collect::<Vec<&str>>();
+    let file_path = self.vfs.file_path(&resolved.join(""))?;
+    let is_dir = self.vfs.file_type(&resolved)?.is_dir();
+
+    if !is_dir {
+      self.vfs.check_extension(&file_path)?;
+    }
+
+    self.authorize(request, &file_path, is_dir).await?;
+
+    if !is_dir && self.vfs.paid(&file_path)? {
+      return Err(
+        error::WebdavPaidFile {
+          path: file_path.display_path().to_owned(),
+        }
+        .build(),
+      );
+    }
+
+    let metadata = tokio::fs::metadata(&file_path)
+      .await
+      .with_context(|| Error::filesystem_io(&file_path))?;
+    let mut path = tail.join("");
+    if is_dir && !path.is_empty() && !path.ends_with('/') {
+      path.push('/');
+    }
+    let mut resources = vec![Resource {
+      href: format!(
+        "{}{}{}",
+        self.base_path,
+        self.files_path,
+        percent::encode_path(&path)
+      ),
+      display_name: tail
+        .last()
+        .map(|name| name.trim_end_matches('/').to_owned())
+        .unwrap_or_default(),
+      is_collection: is_dir,
+      content_length: if is_dir { None } else { Some(metadata.len()) },
+      last_modified: metadata.modified().ok(),
+    }];
+
+    if is_dir && depth == Depth::One {
+      if self.no_listing || !self.vfs.listing(&file_path)? {
+        return Err(Self::listing_disabled(&file_path));
+      }
+      for entry in self
+        .vfs
+        .read_dir(&file_path, ListingOrder::default())
+        .await?
+      {
+        if entry.paid {
+          continue;
+        }
+        let is_dir = entry.file_type.is_dir();
+        let mut path = Self::entry_path(tail, &entry);
+        if is_dir {
+          path.push('/');
+        }
+        resources.push(Resource {
+          href: format!(
+            "{}{}{}",
+            self.base_path,
+            self.files_path,
+            percent::encode_path(&path)
+          ),
+          display_name: entry.file_name.to_string_lossy().into_owned(),
+          is_collection: is_dir,
+          content_length: entry.file_size,
+          last_modified: entry.modified,
+        });
+      }
+    }
+
+    Ok(webdav::multistatus_response(&resources))
+  }
+
+  /// Require the `basic-auth` credentials configured for `path`, if any, in
+  /// the request's `Authorization` header.
+  async fn authorize(&self, request: &Request<Body>, path: &InputPath, is_dir: bool) -> Result<()> {
+    let basic_auth = match self.vfs.basic_auth(path, is_dir)? {
+      Some(basic_auth) => basic_auth,
+      None => return Ok(()),
+    };
+    let authorization = request.headers().get(header::AUTHORIZATION).cloned();
+    let authorized = task::spawn_blocking(move || basic_auth.verify(authorization.as_ref()))
+      .await
+      .map_err(|error| Error::internal(format!("Failed to verify credentials: {}", error)))?;
+    if authorized {
+      Ok(())
+    } else {
+      Err(
+        error::Unauthorized {
+          path: path.display_path().to_owned(),
+        }
+        .build(),
+      )
+    }
+  }
+
+  fn render_index(&self, dir: &InputPath) -> Result<Option<Markup>> {
+    match self.vfs.index_file_markdown(dir)? {
+      None => Ok(None),
+      Some(markdown) => Ok(Some(
+        self.render_markdown(&markdown, self.vfs.index_toc(dir)?),
+      )),
+    }
+  }
+
+  fn render_markdown(&self, markdown: &str, toc: bool) -> Markup {
+    use pulldown_cmark::{html, Options, Parser};
+
+    let options = Options::ENABLE_FOOTNOTES
+      | Options::ENABLE_STRIKETHROUGH
+      | Options::ENABLE_TABLES
+      | Options::ENABLE_TASKLISTS;
+    let events = self
+      .highlighter
+      .highlight_code_blocks(Parser::new_ext(markdown, options));
+
+    if !toc {
+      let mut html = String::new();
+      html::push_html(&mut html, events.into_iter());
+      return maud::PreEscaped(html);
+    }
+
+    let (events, headings) = table_of_contents::add_heading_ids(events);
+    let mut html = String::new();
+    html::push_html(&mut html, events.into_iter());
+    maud::html! {
+      (table_of_contents::render(&headings))
+      (maud::PreEscaped(html))
+    }
+  }
+
+  fn torrent_requested(request: &Request<Body>) -> bool {
+    request.uri().query().map_or(false, |query| {
+      form_urlencoded::parse(query.as_bytes()).any(|(key, _value)| key == "torrent")
+    })
+  }
+
+  fn torrent_offered(&self, entry: &DirEntry) -> bool {
+    match (&self.torrents, entry.file_size) {
+      (Some(torrents), Some(file_size)) => {
+        entry.file_type.is_file() && !entry.paid && torrents.offered(file_size)
+      }
+      _ => false,
+    }
+  }
+
+  async fn serve_torrent(&self, tail: &[&str], path: &InputPath) -> Result<Response<Body>> {
+    let file_path = tail.join("");
+
+    let torrents = match &self.torrents {
+      Some(torrents)
+        if !self.vfs.paid(path)?
+          && torrents.offered(
+            path
+              .as_ref()
+              .metadata()
+              .with_context(|| Error::filesystem_io(path))?
+              .len(),
+          ) =>
+      {
+        torrents
+      }
+      _ => return Err(error::TorrentUnavailable { file_path }.build()),
+    };
+
+    let web_seed = torrents.web_seed(&percent::encode_path(&file_path));
+    let torrent = torrents.get(path, web_seed).await?;
+
+    let file_name = path
+      .display_path()
+      .file_name()
+      .map(|name| name.to_string_lossy().into_owned())
+      .unwrap_or_default();
+
+    Response::builder()
+      .header(header::CONTENT_TYPE, "application/x-bittorrent")
+      .header(
+        header::CONTENT_DISPOSITION,
+        format!(
+          "attachment; filename=\"{}.torrent\"",
+          file_name.replace('"', "")
+        ),
+      )
+      .body(torrent.to_vec().into())
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+
+  fn archive_requested(request: &Request<Body>) -> bool {
+    request.uri().query().map_or(false, |query| {
+      form_urlencoded::parse(query.as_bytes())
+        .any(|(key, value)| key == "archive" && value == "zip")
+    })
+  }
+
+  /// Serve the free files in `dir` and its subdirectories as a zip archive.
+  /// Entries are found with `Vfs::read_dir`, so hidden files and escaping
+  /// symlinks are left out, as are paid files, symlinked directories, and
+  /// directories with `listing: false`, and subdirectories that require
+  /// other `basic-auth` credentials than `dir`.
+  async fn serve_archive(&self, dir: &InputPath) -> Result<Response<Body>> {
+    if self.no_listing {
+      return Err(Self::listing_disabled(dir));
+    }
+
+    let basic_auth = self.vfs.basic_auth(dir, true)?;
+
+    let mut entries = Vec::new();
+    let mut size = 0;
+    let mut walk = self.vfs.walk(dir.clone(), |dir| {
+      Ok(self.vfs.listing(dir)? && self.vfs.basic_auth(dir, true)? == basic_auth)
+    });
+    while let Some(files) = walk.next_dir().await? {
+      for (name, entry) in files {
+        if entry.paid {
+          continue;
+        }
+        let metadata = entry
+          .path
+          .as_ref()
+          .metadata()
+          .with_context(|| Error::filesystem_io(&entry.path))?;
+        if !metadata.is_file() {
+          continue;
+        }
+        // Headers, and deflate's overhead for incompressible data
+        size += metadata.len() + metadata.len() / 1024 + 128 + 2 * name.len() as u64;
+        entries.push(ArchiveEntry {
+          name,
+          path: entry.path,
+          modified: metadata.modified().unwrap_or_else(|_| SystemTime::now()),
+        });
+      }
+    }
+
+    if entries.len() > archive::MAX_ENTRIES || size > archive::MAX_SIZE {
+      return Err(
+        error::ArchiveTooLarge {
+          path: dir.display_path().to_owned(),
+        }
+        .build(),
+      );
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let file_name = dir
+      .display_path()
+      .file_name()
+      .map(|name| name.to_string_lossy().into_owned())
+      .unwrap_or_else(|| "archive".to_owned());
+
+    let permit = self.download_permit()?;
+    Response::builder()
+      .header(header::CONTENT_TYPE, "application/zip")
+      .header(
+        header::CONTENT_DISPOSITION,
+        format!(
+          "attachment; filename=\"{}.zip\"",
+          file_name.replace('"', "")
+        ),
+      )
+      .body(self.body(Payment::Free, permit, archive::stream(entries)))
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+
+  /// Serve a sitemap of the free files in the base directory and its
+  /// subdirectories, at URLs starting with `origin`. Entries are found with
+  /// `Vfs::read_dir`, like those of zip archives, except that directories
+  /// with `basic-auth` are left out, since crawlers can't access them. The
+  /// sitemap is streamed while the tree is walked, so that it isn't
+  /// buffered for large trees.
+  pub(crate) fn serve_sitemap(&self, origin: &str) -> Result<Response<Body>> {
+    let base_directory = self.vfs.file_path("")?;
+    if self.no_listing {
+      return Err(Self::listing_disabled(&base_directory));
+    }
+
+    let (mut sender, body) = Body::channel();
+    let files = self.clone();
+    let url_prefix = format!("{}{}{}", origin, self.base_path, self.files_path);
+    task::spawn(async move {
+      if let Err(error) = files
+        .send_sitemap(&mut sender, base_directory, &url_prefix)
+        .await
+      {
+        log::warn!("Failed to stream sitemap: {}", error);
+        sender.abort();
+      }
+    });
+
+    Response::builder()
+      .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+      .body(body)
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+
+  async fn send_sitemap(
+    &self,
+    sender: &mut Sender,
+    base_directory: InputPath,
+    url_prefix: &str,
+  ) -> Result<()> {
+    sitemap::send_data(sender, sitemap::HEADER.to_owned()).await?;
+    let mut walk = self.vfs.walk(base_directory, |dir| {
+      Ok(self.vfs.listing(dir)? && self.vfs.basic_auth(dir, true)?.is_none())
+    });
+    while let Some(files) = walk.next_dir().await? {
+      let mut xml = String::new();
+      for (path, entry) in files {
+        if !entry.paid && entry.path.as_ref().is_file() {
+          xml.push_str(&sitemap::url(
+            &format!("{}{}", url_prefix, percent::encode_path(&path)),
+            entry.modified,
+          ));
+        }
+      }
+      sitemap::send_data(sender, xml).await?;
+    }
+    sitemap::send_data(sender, sitemap::FOOTER.to_owned()).await
+  }
+
+  /// Order requested with `?sort=name|size|modified` and `?order=asc|desc`.
+  /// Unknown values are ignored.
+  fn listing_order(request: &Request<Body>) -> ListingOrder {
+    let mut order = ListingOrder::default();
+    if let Some(query) = request.uri().query() {
+      for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match (key.as_ref(), value.as_ref()) {
+          ("sort", "name") => order.key = SortKey::Name,
+          ("sort", "size") => order.key = SortKey::Size,
+          ("sort", "modified") => order.key = SortKey::Modified,
+          ("order", "asc") => order.descending = false,
+          ("order", "desc") => order.descending = true,
+          _ => {}
+        }
+      }
+    }
+    order
+  }
+
+  fn listing_format(request: &Request<Body>) -> ListingFormat {
+    let format_parameter = request.uri().query().and_then(|query| {
+      form_urlencoded::parse(query.as_bytes())
+        .filter(|(key, _value)| key == "format")
+        .last()
+        .map(|(_key, value)| value.into_owned())
+    });
+
+    match format_parameter.as_deref() {
+      Some("json") => return ListingFormat::Json,
+      Some("txt") => return ListingFormat::Text,
+      _ => {}
+    }
+
+    let accepted = request
+      .headers()
+      .get(header::ACCEPT)
+      .and_then(|accept| accept.to_str().ok())
+      .unwrap_or_default()
+      .split(',')
+      .map(|media_range| media_range.split(';').next().unwrap_or_default().trim())
+      .collect::<Vec<&str>>();
+
+    if accepted.contains(&"text/html") {
+      ListingFormat::Html
+    } else if accepted.contains(&"application/json") {
+      ListingFormat::Json
+    } else if accepted.contains(&"text/plain") {
+      ListingFormat::Text
+    } else {
+      ListingFormat::Html
+    }
+  }
+
+  async fn serve_dir(
+    &self,
+    request: &Request<Body>,
+    tail: &[&str],
+    dir: &InputPath,
+  ) -> Result<Response<Body>> {
+    let listing = !self.no_listing && self.vfs.listing(dir)?;
+    let entries = if listing {
+      self.vfs.read_dir(dir, Self::listing_order(request)).await?
+    } else {
+      Vec::new()
+    };
+
+    match Self::listing_format(request) {
+      ListingFormat::Html => {
+        if self.serve_index_html {
+          if let Some(index_html) = self.vfs.index_html(dir)? {
+            return self.serve_free_file(request, &index_html).await;
+          }
+        }
+      }
+      ListingFormat::Json | ListingFormat::Text if self.no_listing => {
+        return Err(Self::listing_disabled(dir))
+      }
+      ListingFormat::Json => return self.serve_dir_json(tail, &entries),
+      ListingFormat::Text => return Ok(Self::serve_dir_text(&entries)),
+    }
+
+    let index = self.render_index(dir)?;
+    if self.no_listing && index.is_none() {
+      return Err(Self::listing_disabled(dir));
+    }
+
+    let header = self
+      .vfs
+      .header_file_markdown(dir)?
+      .map(|markdown| self.render_markdown(&markdown, false));
+    let footer = self
+      .vfs
+      .footer_file_markdown(dir)?
+      .map(|markdown| self.render_markdown(&markdown, false));
+
+    let (entries, page) = self.paginate(request, entries)?;
+
+    if let Some(listing_template) = &self.listing_template {
+      let entries = entries
+        .iter()
+        .map(|entry| ListingTemplateEntry {
+          name: entry.display_name(),
+          href: self.entry_href(tail, entry),
+          size: entry.file_size,
+          is_dir: entry.file_type.is_dir(),
+          paid: entry.paid,
+        })
+        .collect::<Vec<ListingTemplateEntry>>();
+      return Ok(listing_template.render(
+        &format!("/{}", tail.join("")),
+        &entries,
+        &ListingTemplatePartials {
+          header,
+          index,
+          footer,
+        },
+      ));
+    }
+
+    let body = html! {
+      @if let Some(header) = header {
+        div class="listing-header" {
+          (header)
+        }
+      }
+      @if let Some(page) = page {
+        nav class="pages" {
+          @if page.number > 1 {
+            a rel="prev" href=(Self::page_href(request, page.number - 1)) {
+              "previous"
+            }
+          }
+          span {
+            "page " (page.number) " of " (page.count) ", " (page.entries) " entries"
+          }
+          @if page.number < page.count {
+            a rel="next" href=(Self::page_href(request, page.number + 1)) {
+              "next"
+            }
+          }
+        }
+      }
+      @if listing {
+        ul class="listing" {
+          @for entry in entries {
+            @let file_name = entry.display_name();
+            @let encoded = self.entry_href(tail, &entry);
+            li {
+              a href=(encoded) class="view" {
+                (file_name)
+              }
+
+              @if let Some(file_size) = entry.file_size {
+                span class="filesize" {
+                  (file_size.display_size())
+                }
+              }
+              @if let Some(modified) = entry.modified {
+                @let timestamp = humantime::format_rfc3339_seconds(modified).to_string();
+                span class="mtime" {
+                  time datetime=(timestamp) {
+                    (timestamp[..16].replace('T', " "))
+                  }
+                }
+              }
+              @if entry.file_type.is_file() && !entry.paid {
+                a download href=(encoded) {
+                  (self.icon("download"))
+                }
+              }
+              @if self.torrent_offered(&entry) {
+                a class="torrent" href=(format!("{}?torrent", encoded)) {
+                  (self.icon("share-2"))
+                }
+              }
+              @if entry.paid && self.entry_preview(tail, &entry)? {
+                a class="preview" href=(format!("{}?preview", encoded)) {
+                  (self.icon("eye"))
+                }
+              }
+              @if entry.paid && self.entry_sold_out(tail, &entry)? {
+                span class="sold-out" {
+                  "sold out"
+                }
+              }
+            }
+          }
+        }
+      }
+      @if let Some(index) = index {
+        div {
+          (index)
+        }
+      }
+      @if let Some(footer) = footer {
+        div class="listing-footer" {
+          (footer)
+        }
+      }
+    };
+    Ok(html::wrap_body(
+      &self.base_path,
+      &format!("/{}", tail.join("")),
+      self.extra_head(&self.vfs.dir_extra_assets(dir)?),
+      body,
+    ))
+  }
+
+  /// Select the page of `entries` requested with the `page` query parameter,
+  /// if listings are paginated with `--dir-listing-page-size`. Listings that
+  /// fit on a single page are served without a page.
+  fn paginate(
+    &self,
+    request: &Request<Body>,
+    entries: Vec<DirEntry>,
+  ) -> Result<(Vec<DirEntry>, Option<ListingPage>)> {
+    let page_size = match self.dir_listing_page_size {
+      Some(page_size) => page_size.get(),
+      None => return Ok((entries, None)),
+    };
+
+    let page_parameter = request.uri().query().and_then(|query| {
+      form_urlencoded::parse(query.as_bytes())
+        .filter(|(key, _value)| key == "page")
+        .last()
+        .map(|(_key, value)| value.into_owned())
+    });
+
+    let count = ((entries.len() + page_size - 1) / page_size).max(1);
+
+    let number = match page_parameter {
+      Some(page) => match page.parse::<usize>() {
+        Ok(number) if number >= 1 && number <= count => number,
+        _ => return Err(error::ListingPageNotFound { page }.build()),
+      },
+      None => 1,
+    };
+
+    if count == 1 {
+      return Ok((entries, None));
+    }
+
+    let page = ListingPage {
+      number,
+      count,
+      entries: entries.len(),
+    };
+
+    let entries = entries
+      .into_iter()
+      .skip((number - 1) * page_size)
+      .take(page_size)
+      .collect();
+
+    Ok((entries, Some(page)))
+  }
+
+  /// Link to another page of the listing, keeping the other query
+  /// parameters, like `sort` and `order`.
+  fn page_href(request: &Request<Body>, number: usize) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    if let Some(request_query) = request.uri().query() {
+      query.extend_pairs(
+        form_urlencoded::parse(request_query.as_bytes()).filter(|(key, _value)| key != "page"),
+      );
+    }
+    query.append_pair("page", &number.to_string());
+    format!("?{}", query.finish())
+  }
+
+  fn listing_disabled(dir: &InputPath) -> Error {
+    error::DirectoryListingDisabled {
+      path: dir.display_path().to_owned(),
+    }
+    .build()
+  }
+
+  /// Path of a listing entry relative to the base directory.
+  fn entry_path(tail: &[&str], entry: &DirEntry) -> String {
+    let mut path = tail.join("");
+    if !path.is_empty() && !path.ends_with('/') {
+      path.push('/');
+    }
+    path.push_str(&entry.file_name.to_string_lossy());
+    path
+  }
+
+  /// Path of the file or directory a listing entry refers to, which differs
+  /// from its path in the listing if it comes from a merged directory.
+  fn entry_resolved_path(&self, tail: &[&str], entry: &DirEntry) -> Result<String> {
+    self.vfs.resolve(
+      &Self::entry_path(tail, entry)
+        .split_inclusive('/')
+        .collect::<Vec<&str>>(),
+    )
+  }
+
+  /// Whether a listing entry is sold out. Entries from merged directories
+  /// are counted under their real path, which is the path their invoices use.
+  fn entry_sold_out(&self, tail: &[&str], entry: &DirEntry) -> Result<bool> {
+    let path = self.entry_resolved_path(tail, entry)?;
+    self.sold_out(&path, &self.vfs.file_path(&path)?)
+  }
+
+  /// Whether a paid listing entry has a free preview.
+  fn entry_preview(&self, tail: &[&str], entry: &DirEntry) -> Result<bool> {
+    if !entry.file_type.is_file() {
+      return Ok(false);
+    }
+    let path = self.entry_resolved_path(tail, entry)?;
+    Ok(self.preview_bytes(&self.vfs.file_path(&path)?)?.is_some())
+  }
+
+  /// Link to a listing entry. Listings are only served at paths ending in a
+  /// slash under the default trailing slash policy, so links can be relative.
+  /// Otherwise they must be absolute, and point to the entry's canonical path.
+  fn entry_href(&self, tail: &[&str], entry: &DirEntry) -> String {
+    match self.trailing_slash_policy {
+      TrailingSlashPolicy::Enforce => percent::encode_path(&entry.display_name()),
+      TrailingSlashPolicy::Off | TrailingSlashPolicy::Inverse => self.entry_url(tail, entry),
+    }
+  }
+
+  /// Absolute link to a listing entry's canonical path.
+  fn entry_url(&self, tail: &[&str], entry: &DirEntry) -> String {
+    let mut path = Self::entry_path(tail, entry);
+    let is_dir = entry.file_type.is_dir();
+    if self
+      .trailing_slash_policy
+      .trailing_slash(is_dir)
+      .unwrap_or(is_dir)
+    {
+      path.push('/');
+    }
+    format!(
+      "{}{}{}",
+      self.base_path,
+      self.files_path,
+      percent::encode_path(&path)
+    )
+  }
+
+  /// Whether the paid file at `file_path` has reached its `max-downloads` limit.
+  fn sold_out(&self, file_path: &str, path: &InputPath) -> Result<bool> {
+    Ok(match self.vfs.max_downloads(path)? {
+      Some(max_downloads) => self.download_counts.count(file_path) >= max_downloads,
+      None => false,
+    })
+  }
+
+  fn serve_dir_json(&self, tail: &[&str], entries: &[DirEntry]) -> Result<Response<Body>> {
+    let entries = entries
+      .iter()
+      .map(|entry| {
+        let base_price_msat = if entry.paid {
+          let path = self.entry_resolved_path(tail, entry)?;
+          self
+            .vfs
+            .base_price(&self.vfs.file_path(&path)?)?
+            .map(Millisatoshi::value)
+        } else {
+          None
+        };
+        Ok(JsonEntry {
+          name: entry.file_name.to_string_lossy().into_owned(),
+          is_dir: entry.file_type.is_dir(),
+          size: entry.file_size,
+          paid: entry.paid,
+          base_price_msat,
+          modified: entry
+            .modified
+            .map(|modified| humantime::format_rfc3339_seconds(modified).to_string()),
+          href: self.entry_url(tail, entry),
+        })
+      })
+      .collect::<Result<Vec<JsonEntry>>>()?;
+
+    Response::builder()
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(Body::from(
+        serde_json::to_string(&entries).context(error::ListingSerialize)?,
+      ))
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+
+  fn serve_dir_text(entries: &[DirEntry]) -> Response<Body> {
+    let sizes = entries
+      .iter()
+      .map(|entry| {
+        entry
+          .file_size
+          .map(|file_size| file_size.display_size().to_string())
+          .unwrap_or_default()
+      })
+      .collect::<Vec<String>>();
+
+    let width = sizes.iter().map(String::len).max().unwrap_or(0);
+
+    let mut text = String::new();
+    for (entry, size) in entries.iter().zip(sizes) {
+      if width > 0 {
+        text.push_str(&format!("{:>width$}  ", size, width = width));
+      }
+      text.push_str(&entry.display_name());
+      text.push('\n');
+    }
+
+    Response::builder()
+      .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+      .body(Body::from(text))
+      .expect("builder arguments are valid")
+  }
+
+  fn extra_head(&self, assets: &ExtraAssets) -> Markup {
+    let url = |path: &String| {
+      format!(
+        "{}{}{}",
+        self.base_path,
+        self.files_path,
+        percent::encode_path(path)
+      )
+    };
+    html! {
+      @for path in &assets.css {
+        link rel="stylesheet" href=(url(path));
+      }
+      @for path in &assets.js {
+        script defer src=(url(path)) {}
+      }
+    }
+  }
+
+  /// OpenGraph tags for pages about the paid file at `path`, from its
+  /// `meta` config, using `default_image` if it doesn't configure an image.
+  fn open_graph(
+    &self,
+    file_path: &str,
+    path: &InputPath,
+    default_image: Option<&str>,
+  ) -> Result<Markup> {
+    let og = self.vfs.file_meta(path)?;
+    let image = match &og.image {
+      Some(image) if Meta::is_url(image) => Some(image.clone()),
+      Some(image) => Some(format!(
+        "{}{}{}",
+        self.base_path,
+        self.files_path,
+        percent::encode_path(image)
+      )),
+      None => default_image.map(str::to_owned),
+    };
+    let card = if og.image.is_some() {
+      "summary_large_image"
+    } else {
+      "summary"
+    };
+    Ok(html! {
+      meta property="og:type" content="website";
+      meta property="og:title" content=(og.title.as_deref().unwrap_or(file_path));
+      @if let Some(description) = &og.description {
+        meta name="description" content=(description);
+        meta property="og:description" content=(description);
+      }
+      @if let Some(image) = &image {
+        meta property="og:image" content=(image);
+      }
+      meta name="twitter:card" content=(card);
+    })
+  }
+
+  fn icon(&self, name: &str) -> Markup {
+    html! {
+      svg class="icon" {
+        use href=(format!("{}/static/feather-sprite.svg#{}", self.base_path, name)) {}
+      }
+    }
+  }
+
+  async fn access_file(
+    &mut self,
+    request: &Request<Body>,
+    tail: &[&str],
+    path: &InputPath,
+  ) -> Result<Response<Body>> {
+    if !self.vfs.paid(path)? {
+      return self.serve_free_file(request, path).await;
+    }
+
+    if self.signed_url_valid(request, &tail.join(""))? {
+      return self
+        .serve_purchased_file(request, path)
+        .await
+        .map(Self::noindex);
+    }
+
+    if Self::preview_requested(request) {
+      if let Some(preview_bytes) = self.preview_bytes(path)? {
+        return self
+          .serve_preview(path, preview_bytes)
+          .await
+          .map(Self::noindex);
+      }
+    }
+
+    self
+      .access_paid_file(request, tail, path)
+      .await
+      .map(Self::noindex)
+  }
+
+  /// Whether the request is for a URL signed with `--url-signing-key` for
+  /// `file_path`, in which case the file was already paid for. Requests
+  /// without a `token` aren't signed, and fail if the signature is invalid
+  /// or has expired.
+  fn signed_url_valid(&self, request: &Request<Body>, file_path: &str) -> Result<bool> {
+    let url_signer = match &self.url_signer {
+      Some(url_signer) => url_signer,
+      None => return Ok(false),
+    };
+    let mut token = None;
+    let mut expires = None;
+    for (key, value) in form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes())
+    {
+      match key.as_ref() {
+        "token" => token = Some(value.into_owned()),
+        "expires" => expires = Some(value.into_owned()),
+        _ => {}
+      }
+    }
+    let token = match token {
+      Some(token) => token,
+      None => return Ok(false),
+    };
+    let invalid = || {
+      error::SignedUrlInvalid {
+        file_path: file_path.to_owned(),
+      }
+      .build()
+    };
+    let expires = expires
+      .and_then(|expires| expires.parse::<u64>().ok())
+      .ok_or_else(invalid)?;
+    if !url_signer.verify(file_path, expires, &token)? {
+      return Err(invalid());
+    }
+    if UrlSigner::expired(expires, SystemTime::now()) {
+      return Err(
+        error::SignedUrlExpired {
+          file_path: file_path.to_owned(),
+        }
+        .build(),
+      );
+    }
+    Ok(true)
+  }
+
+  fn preview_requested(request: &Request<Body>) -> bool {
+    request.uri().query().map_or(false, |query| {
+      form_urlencoded::parse(query.as_bytes()).any(|(key, _value)| key == "preview")
+    })
+  }
+
+  /// Length of the free preview of the paid file at `path`, if it has one.
+  /// Proxied files have no preview, and neither do files no longer than
+  /// `preview-bytes`, whose preview would be the whole file.
+  fn preview_bytes(&self, path: &InputPath) -> Result<Option<u64>> {
+    let preview_bytes = match self.vfs.preview_bytes(path)? {
+      Some(preview_bytes) if preview_bytes > 0 => preview_bytes,
+      _ => return Ok(None),
+    };
+    if self.vfs.proxy_url(path)?.is_some() {
+      return Ok(None);
+    }
+    let len = path
+      .as_ref()
+      .metadata()
+      .with_context(|| Error::filesystem_io(path))?
+      .len();
+    Ok(if preview_bytes < len {
+      Some(preview_bytes)
+    } else {
+      None
+    })
+  }
+
+  /// Serve the first `preview_bytes` of a paid file for free, as a partial
+  /// response, marked with an `x-agora-preview` header.
+  async fn serve_preview(&self, path: &InputPath, preview_bytes: u64) -> Result<Response<Body>> {
+    let len = path
+      .as_ref()
+      .metadata()
+      .with_context(|| Error::filesystem_io(path))?
+      .len();
+    let mut builder = Response::builder()
+      .status(StatusCode::PARTIAL_CONTENT)
+      .header(header::CONTENT_LENGTH, preview_bytes)
+      .header(
+        header::CONTENT_RANGE,
+        format!("bytes 0-{}/{}", preview_bytes - 1, len),
+      )
+      .header("x-agora-preview", "true");
+    if let Some(content_type) = self.content_type(path)? {
+      builder = builder.header(header::CONTENT_TYPE, content_type);
+    }
+    let permit = self.download_permit()?;
+    let body = self.body(
+      Payment::Free,
+      permit,
+      FileStream::new(path.clone()).await?.take(preview_bytes),
+    );
+    builder
+      .body(body)
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+
+  /// Keep search engines from indexing responses for paid files and their
+  /// invoices, so that invoice URLs don't end up in search results.
+  fn noindex(mut response: Response<Body>) -> Response<Body> {
+    response.headers_mut().insert(
+      header::HeaderName::from_static("x-robots-tag"),
+      HeaderValue::from_static("noindex"),
+    );
+    response
+  }
+
+  async fn access_paid_file(
+    &mut self,
+    request: &Request<Body>,
+    tail: &[&str],
+    path: &InputPath,
+  ) -> Result<Response<Body>> {
+    let file_path = tail.join("");
+    if self.sold_out(&file_path, path)? {
+      let mut response = html::wrap_body(
+        &self.base_path,
+        &format!("Sold out: {}", file_path),
+        self.open_graph(&file_path, path, None)?,
+        html! {
+          div class="sold-out" {
+            span class="filename" {
+              (file_path)
+            }
+            " is sold out."
+          }
+        },
+      );
+      *response.status_mut() = StatusCode::GONE;
+      return Ok(response);
+    }
+
+    let lnd_client = self.lnd_client.as_mut().ok_or_else(|| {
+      error::LndNotConfiguredPaidFileRequest {
+        path: path.display_path().to_owned(),
+      }
+      .build()
+    })?;
+
+    let amount = Self::invoice_amount(
+      &self.vfs,
+      self.max_invoice_amount,
+      self.price_feed.as_ref(),
+      path,
+    )
+    .await?;
+
+    let client_id = RecentInvoices::client_id(request);
+
+    if let Some(client_id) = client_id {
+      let recent = self
+        .recent_invoices
+        .lock()
+        .unwrap()
+        .get(&file_path, client_id);
+      if let Some(r_hash) = recent {
+        let invoice = lnd_client
+          .lookup_invoice(r_hash)
+          .await
+          .context(error::LndRpcStatus)?;
+        match invoice {
+          Some(invoice)
+            if invoice.state() == InvoiceState::Open
+              && invoice.value_msat().value() == amount.value() =>
+          {
+            return redirect(format!(
+              "{}?invoice={}",
+              request.uri().path(),
+              hex::encode(r_hash),
+            ));
+          }
+          _ => self
+            .recent_invoices
+            .lock()
+            .unwrap()
+            .remove(&file_path, client_id),
+        }
+      }
+    }
+
+    let expiry_seconds = self.vfs.invoice_expiry(path)?;
+    let memo = self.invoice_memo_template.render(&file_path, amount);
+    let invoice = lnd_client
+      .add_invoice(&memo, amount, expiry_seconds)
+      .await
+      .context(error::LndRpcStatus)?;
+    self.metrics.invoice_created();
+
+    let r_hash = <[u8; 32]>::try_from(invoice.r_hash.as_slice())
+      .map_err(|error| Error::internal(format!("LND returned invalid payment hash: {}", error)))?;
+
+    let new_client_id = if client_id.is_none() {
+      let mut client_id = [0; 16];
+      openssl::rand::rand_bytes(&mut client_id)
+        .map_err(|error| Error::internal(format!("Failed to generate client ID: {}", error)))?;
+      Some(client_id)
+    } else {
+      None
+    };
+
+    if let Some(client_id) = client_id.or(new_client_id) {
+      self
+        .recent_invoices
+        .lock()
+        .unwrap()
+        .insert(&file_path, client_id, r_hash);
+    }
+
+    let mut response = redirect(format!(
+      "{}?invoice={}",
+      request.uri().path(),
+      hex::encode(r_hash),
+    ))?;
+
+    if let Some(client_id) = new_client_id {
+      response.headers_mut().insert(
+        header::SET_COOKIE,
+        HeaderValue::from_str(&format!(
+          "{}={}; Path={}/; HttpOnly; SameSite=Lax",
+          RecentInvoices::COOKIE,
+          hex::encode(client_id),
+          self.base_path,
+        ))
+        .expect("client ID cookie is a valid header value"),
+      );
+    }
+
+    Ok(response)
+  }
+
+  /// Amount to invoice for the paid file at `path`, which is the sum of its
+  /// base price, or its fiat price converted at the current exchange rate,
+  /// and its price per byte times its size, as long as it doesn't exceed
+  /// `--max-invoice-amount`.
+  async fn invoice_amount(
+    vfs: &Vfs,
+    max_invoice_amount: Option<Millisatoshi>,
+    price_feed: Option<&PriceFeed>,
+    path: &InputPath,
+  ) -> Result<Millisatoshi> {
+    let base_price = match vfs.fiat_price(path)? {
+      Some(fiat_price) => {
+        let price_feed = price_feed.ok_or_else(|| {
+          error::PriceFeedMissing {
+            path: path.display_path(),
+          }
+          .build()
+        })?;
+        let converted = price_feed
+          .convert(fiat_price.amount(), fiat_price.currency())
+          .await?
+          .ok_or_else(|| {
+            error::InvoiceAmountOverflow {
+              path: path.display_path(),
+            }
+            .build()
+          })?;
+        Some(converted)
+      }
+      None => vfs.base_price(path)?,
+    };
+    let price_per_byte = vfs.price_per_byte(path)?;
+    if base_price.is_none() && price_per_byte.is_none() {
+      return Err(
+        error::ConfigMissingBasePrice {
+          path: path.display_path(),
+        }
+        .build(),
+      );
+    }
+
+    let size_price = match price_per_byte {
+      Some(price_per_byte) => {
+        let len = path
+          .as_ref()
+          .metadata()
+          .with_context(|| Error::filesystem_io(path))?
+          .len();
+        price_per_byte.value().checked_mul(len)
+      }
+      None => Some(0),
+    };
+    let amount = size_price
+      .and_then(|size_price| size_price.checked_add(base_price.map_or(0, Millisatoshi::value)))
+      .map(Millisatoshi::new)
+      .ok_or_else(|| {
+        error::InvoiceAmountOverflow {
+          path: path.display_path(),
+        }
+        .build()
+      })?;
+
+    if let Some(max) = max_invoice_amount {
+      if amount.value() > max.value() {
+        return Err(
+          error::InvoiceAmountExceedsMaximum {
+            amount,
+            max,
+            path: path.display_path(),
+          }
+          .build(),
+        );
+      }
     }
+    Ok(amount)
   }
 
-  pub(crate) async fn serve(
-    &mut self,
+  /// Serve a free file, with a weak ETag and `Last-Modified` derived from
+  /// its metadata, so clients can revalidate cached copies, or reuse them
+  /// without revalidating for `--static-cache-max-age` seconds. Paid files are
+  /// served with `serve_file` instead, and never cached, so cached copies
+  /// can't be used to bypass payment.
+  async fn serve_free_file(
+    &self,
     request: &Request<Body>,
-    tail: &[&str],
+    path: &InputPath,
   ) -> Result<Response<Body>> {
-    let file_path = self.vfs.file_path(&tail.join(""))?;
-    let file_type = self.vfs.file_type(tail)?;
-
-    if !file_type.is_dir() {
-      if let Some(stripped) = request.uri().path().strip_suffix('/') {
-        return redirect(stripped.to_owned());
-      }
+    if let Some(url) = self.vfs.proxy_url(path)? {
+      return self
+        .serve_proxied_file(request, path, url, Payment::Free)
+        .await;
     }
+    let metadata = path
+      .as_ref()
+      .metadata()
+      .with_context(|| Error::filesystem_io(path))?;
+    let modified = metadata
+      .modified()
+      .with_context(|| Error::filesystem_io(path))?;
+    let mut response = Validators::weak(metadata.len(), modified)
+      .respond(
+        request.headers(),
+        self.serve_file(request, path, Payment::Free),
+      )
+      .await?;
+    if let Some(max_age) = self.static_cache_max_age {
+      response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&format!("public, max-age={}", max_age))
+          .expect("cache control with max age is a valid header value"),
+      );
+    }
+    Ok(response)
+  }
 
-    if file_type.is_dir() && !request.uri().path().ends_with('/') {
-      return redirect(String::from(request.uri().path()) + "/");
+  /// Serve the icon named `file_name` from the base directory, if the
+  /// operator put one there. Returns `None` if the built-in icon should be
+  /// served instead.
+  pub(crate) async fn serve_site_icon(
+    &self,
+    request: &Request<Body>,
+    file_name: &str,
+  ) -> Result<Option<Response<Body>>> {
+    match self.vfs.site_icon(file_name)? {
+      Some(path) => {
+        self.authorize(request, &path, false).await?;
+        Ok(Some(self.serve_free_file(request, &path).await?))
+      }
+      None => Ok(None),
     }
+  }
 
-    if file_type.is_dir() {
-      self.serve_dir(tail, &file_path).await
-    } else {
-      self.access_file(request, tail, &file_path).await
-    }
-  }
-
-  // Percent encode all unicode codepoints, even though
-  // they are allowed by the spec:
-  // https://url.spec.whatwg.org/#url-code-points
-  const ENCODE_CHARACTERS: AsciiSet = NON_ALPHANUMERIC
-    .remove(b'!')
-    .remove(b'$')
-    .remove(b'&')
-    .remove(b'\'')
-    .remove(b'(')
-    .remove(b')')
-    .remove(b'*')
-    .remove(b'+')
-    .remove(b',')
-    .remove(b'-')
-    .remove(b'.')
-    .remove(b'/')
-    .remove(b':')
-    .remove(b';')
-    .remove(b'=')
-    .remove(b'?')
-    .remove(b'@')
-    .remove(b'_')
-    .remove(b'~');
+  fn content_type(&self, path: &InputPath) -> Result<Option<String>> {
+    Ok(match self.vfs.content_type(path)? {
+      Some(content_type) => Some(content_type),
+      None => path
+        .mime_guess()
+        .first()
+        .map(|guess| guess.essence_str().to_owned()),
+    })
+  }
 
-  fn render_index(&self, dir: &InputPath) -> Result<Option<Markup>> {
-    use pulldown_cmark::{html, Options, Parser};
+  fn download_requested(request: &Request<Body>) -> bool {
+    request.uri().query().map_or(false, |query| {
+      form_urlencoded::parse(query.as_bytes()).any(|(key, _value)| key == "download")
+    })
+  }
 
-    let markdown = match self.vfs.index_file_markdown(dir)? {
+  /// `Content-Disposition: attachment` header for files that match
+  /// `force-download`, or are requested with `?download`, so browsers save
+  /// them instead of displaying them. Non-ASCII file names are encoded in
+  /// `filename*`, with an ASCII fallback in `filename`.
+  fn content_disposition(
+    &self,
+    request: &Request<Body>,
+    path: &InputPath,
+  ) -> Result<Option<HeaderValue>> {
+    if !Self::download_requested(request) && !self.vfs.force_download(path)? {
+      return Ok(None);
+    }
+    let file_name = match path.as_ref().file_name() {
+      Some(file_name) => file_name.to_string_lossy().into_owned(),
       None => return Ok(None),
-      Some(markdown) => markdown,
     };
-
-    let options = Options::ENABLE_FOOTNOTES
-      | Options::ENABLE_STRIKETHROUGH
-      | Options::ENABLE_TABLES
-      | Options::ENABLE_TASKLISTS;
-    let parser = Parser::new_ext(&markdown, options);
-    let mut html = String::new();
-    html::push_html(&mut html, parser);
-    Ok(Some(maud::PreEscaped(html)))
+    let fallback = file_name
+      .chars()
+      .map(|c| {
+        if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+          c
+        } else {
+          '_'
+        }
+      })
+      .collect::<String>();
+    let value = if fallback == file_name {
+      format!("attachment; filename=\"{}\"", file_name)
+    } else {
+      format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        percent::encode_header_parameter(&file_name)
+      )
+    };
+    Ok(Some(HeaderValue::from_str(&value).map_err(|error| {
+      Error::internal(format!(
+        "Invalid content disposition `{}`: {}",
+        value, error
+      ))
+    })?))
   }
 
-  async fn serve_dir(&self, tail: &[&str], dir: &InputPath) -> Result<Response<Body>> {
-    let body = html! {
-      ul class="listing" {
-        @for entry in self.vfs.read_dir(dir).await? {
-
-          @let file_name = {
-            let mut file_name = entry.file_name.to_string_lossy().into_owned();
-            if entry.file_type.is_dir() {
-              file_name.push('/');
-            }
-            file_name
-          };
-          @let encoded = percent_encoding::utf8_percent_encode(&file_name, &Self::ENCODE_CHARACTERS);
-          li {
-            a href=(encoded) class="view" {
-              (file_name)
-            }
+  async fn serve_file(
+    &self,
+    request: &Request<Body>,
+    path: &InputPath,
+    payment: Payment,
+  ) -> Result<Response<Body>> {
+    let mut builder = Response::builder().status(StatusCode::OK);
+    if let Some(content_type) = self.content_type(path)? {
+      builder = builder.header(header::CONTENT_TYPE, content_type);
+    }
+    if let Some(content_disposition) = self.content_disposition(request, path)? {
+      builder = builder.header(header::CONTENT_DISPOSITION, content_disposition);
+    }
+    let mut contents_path = path.clone();
+    if let Some((precompressed, coding)) = self.precompressed(request, path)? {
+      builder = builder
+        .header(header::CONTENT_ENCODING, coding)
+        .header(header::VARY, "accept-encoding");
+      contents_path = precompressed;
+    }
+    let permit = self.download_permit()?;
+    let body = match self.cached_contents(&contents_path).await? {
+      Some(contents) => self.body(
+        payment,
+        permit,
+        futures::stream::iter(Some(Ok::<Bytes, io::Error>(contents))),
+      ),
+      None => self.body(payment, permit, FileStream::new(contents_path).await?),
+    };
+    self.metrics.file_served(payment);
+    builder
+      .body(body)
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
 
-            @if let Some(file_size) = entry.file_size {
-              span class="filesize" {
-                (file_size.display_size())
-              }
-            }
-            @if entry.file_type.is_file() && !entry.paid {
-              a download href=(encoded) {
-                (Files::icon("download"))
-              }
-            }
-          }
+  /// Pre-compressed `.br` or `.gz` sidecar of the file at `path`, and its
+  /// content coding, if one exists and the client accepts that coding.
+  /// Brotli is preferred, since it usually compresses better.
+  fn precompressed(
+    &self,
+    request: &Request<Body>,
+    path: &InputPath,
+  ) -> Result<Option<(InputPath, &'static str)>> {
+    for (coding, extension) in &[("br", ".br"), ("gzip", ".gz")] {
+      if compression::accepts(request.headers(), coding) {
+        if let Some(precompressed) = self.vfs.precompressed(path, extension)? {
+          return Ok(Some((precompressed, coding)));
         }
       }
-      @if let Some(index) = self.render_index(dir)? {
-        div {
-          (index)
+    }
+    Ok(None)
+  }
+
+  /// Contents of the file at `path`, if it's small enough to be kept in the
+  /// `--cache-file-max-size` cache, read from disk if it's not cached yet,
+  /// or if it was modified since. Returns `None` for files that should be
+  /// streamed instead, like large files and FIFOs.
+  async fn cached_contents(&self, path: &InputPath) -> Result<Option<Bytes>> {
+    let (cache, max_size) = match &self.file_cache {
+      Some(file_cache) => file_cache,
+      None => return Ok(None),
+    };
+    let metadata = path
+      .as_ref()
+      .metadata()
+      .with_context(|| Error::filesystem_io(path))?;
+    if !metadata.is_file() || metadata.len() > max_size.get() {
+      return Ok(None);
+    }
+    let modified = metadata
+      .modified()
+      .with_context(|| Error::filesystem_io(path))?;
+    if let Some(contents) = cache
+      .lock()
+      .unwrap()
+      .get(path.as_ref(), modified, metadata.len())
+    {
+      return Ok(Some(contents));
+    }
+    let contents = Bytes::from(
+      tokio::fs::read(path)
+        .await
+        .with_context(|| Error::filesystem_io(path))?,
+    );
+    // A file that changed while it was read may be cached under the wrong
+    // modification time, so it's only served, not cached
+    if contents.len() as u64 == metadata.len() {
+      cache
+        .lock()
+        .unwrap()
+        .insert(path.as_ref().to_owned(), modified, contents.clone());
+    }
+    Ok(Some(contents))
+  }
+
+  /// Stream a proxied file from its upstream `url`, forwarding only its
+  /// content type. No request headers are forwarded, so clients can't use
+  /// the proxy to send arbitrary requests upstream.
+  async fn serve_proxied_file(
+    &self,
+    request: &Request<Body>,
+    path: &InputPath,
+    url: Uri,
+    payment: Payment,
+  ) -> Result<Response<Body>> {
+    let permit = self.download_permit()?;
+    let upstream = self
+      .proxy_client
+      .get(url.clone())
+      .await
+      .context(error::ProxyRequest { url: url.clone() })?;
+    if !upstream.status().is_success() {
+      return Err(
+        error::ProxyStatus {
+          status: upstream.status(),
+          url,
         }
-      }
+        .build(),
+      );
+    }
+    let content_type = match upstream.headers().get(header::CONTENT_TYPE) {
+      Some(content_type) => Some(content_type.clone()),
+      None => path
+        .mime_guess()
+        .first()
+        .and_then(|guess| HeaderValue::from_str(guess.essence_str()).ok()),
     };
-    Ok(html::wrap_body(&format!("/{}", tail.join("")), body))
+    let mut builder = Response::builder().status(StatusCode::OK);
+    if let Some(content_type) = content_type {
+      builder = builder.header(header::CONTENT_TYPE, content_type);
+    }
+    if let Some(content_disposition) = self.content_disposition(request, path)? {
+      builder = builder.header(header::CONTENT_DISPOSITION, content_disposition);
+    }
+    let body = self.body(payment, permit, upstream.into_body());
+    self.metrics.file_served(payment);
+    builder
+      .body(body)
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
   }
 
-  fn icon(name: &str) -> Markup {
-    html! {
-      svg class="icon" {
-        use href=(format!("/static/feather-sprite.svg#{}",name)) {}
+  /// Take one of the `--max-concurrent-downloads` permits, which is held
+  /// until the download's response body is dropped.
+  fn download_permit(&self) -> Result<Option<OwnedSemaphorePermit>> {
+    match &self.download_permits {
+      Some((semaphore, max_concurrent_downloads)) => semaphore
+        .clone()
+        .try_acquire_owned()
+        .map(Some)
+        .map_err(|_| {
+          error::DownloadLimitReached {
+            max_concurrent_downloads: *max_concurrent_downloads,
+          }
+          .build()
+        }),
+      None => Ok(None),
+    }
+  }
+
+  /// Wrap `stream` in a response body, limited to `--rate-limit`, and
+  /// counting the bytes sent. The download `permit`, if any, is released
+  /// once the body is dropped.
+  fn body<S, E>(&self, payment: Payment, permit: Option<OwnedSemaphorePermit>, stream: S) -> Body
+  where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+  {
+    let metrics = self.metrics.clone();
+    let stream = stream.inspect(move |chunk| {
+      let _permit = &permit;
+      if let Ok(chunk) = chunk {
+        metrics.bytes_served(payment, chunk.len());
       }
+    });
+    match self.rate_limit {
+      Some(rate_limit) => Body::wrap_stream(Throttle::new(stream, rate_limit)),
+      None => Body::wrap_stream(stream),
     }
   }
 
-  async fn access_file(
-    &mut self,
+  async fn serve_purchased_file(
+    &self,
     request: &Request<Body>,
-    tail: &[&str],
     path: &InputPath,
   ) -> Result<Response<Body>> {
-    if !self.vfs.paid(path)? {
-      return Self::serve_file(path).await;
+    match self.vfs.proxy_url(path)? {
+      Some(url) => {
+        self
+          .serve_proxied_file(request, path, url, Payment::Paid)
+          .await
+      }
+      None => self.serve_file(request, path, Payment::Paid).await,
     }
+  }
 
-    let lnd_client = self.lnd_client.as_mut().ok_or_else(|| {
-      error::LndNotConfiguredPaidFileRequest {
-        path: path.display_path().to_owned(),
-      }
-      .build()
-    })?;
+  fn serve_paid_file_unavailable(&self, file_path: &str, r_hash: [u8; 32]) -> Response<Body> {
+    let mut response = html::wrap_body(
+      &self.base_path,
+      &format!("No longer available: {}", file_path),
+      html! {},
+      html! {
+        div class="paid-file-unavailable" {
+          "Your payment for "
+          span class="filename" {
+            (file_path)
+          }
+          " was received, but the file is no longer available. "
+          "Please contact the operator of this site for support, and mention invoice "
+          code {
+            (hex::encode(r_hash))
+          }
+          "."
+        }
+      },
+    );
+    *response.status_mut() = StatusCode::GONE;
+    response
+  }
 
-    let file_path = tail.join("");
-    let base_price = self.vfs.base_price(path)?.ok_or_else(|| {
-      error::ConfigMissingBasePrice {
-        path: path.display_path(),
-      }
-      .build()
-    })?;
-    let invoice = lnd_client
-      .add_invoice(&file_path, base_price)
+  /// Number of blocks that still need to be mined before the payment of
+  /// `invoice`, which is settled, satisfies `--require-payment-confirmations`,
+  /// counting from the highest block at which one of its HTLCs was accepted.
+  async fn remaining_confirmations(&mut self, invoice: &Invoice) -> Result<Option<u32>> {
+    let required = match self.require_payment_confirmations {
+      Some(required) => required,
+      None => return Ok(None),
+    };
+    let lnd_client = self
+      .lnd_client
+      .as_mut()
+      .expect("`--require-payment-confirmations` requires `--lnd-rpc-authority`");
+    let block_height = lnd_client
+      .block_height()
       .await
       .context(error::LndRpcStatus)?;
-    redirect(format!(
-      "{}?invoice={}",
-      request.uri().path(),
-      hex::encode(invoice.r_hash),
-    ))
+    let accept_height = invoice
+      .htlcs
+      .iter()
+      .map(|htlc| htlc.accept_height.max(0) as u32)
+      .max()
+      .unwrap_or(block_height);
+    let confirmations = block_height.saturating_sub(accept_height);
+    if confirmations >= required {
+      Ok(None)
+    } else {
+      Ok(Some(required - confirmations))
+    }
   }
 
-  async fn serve_file(path: &InputPath) -> Result<Response<Body>> {
-    let mut builder = Response::builder().status(StatusCode::OK);
-    if let Some(guess) = path.mime_guess().first() {
-      builder = builder.header(header::CONTENT_TYPE, guess.essence_str());
-    }
-    builder
-      .body(Body::wrap_stream(FileStream::new(path.clone()).await?))
-      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  /// Page for invoices whose payment was received, but which don't give
+  /// access to their file yet, because the payment hasn't been settled, or
+  /// doesn't have enough confirmations.
+  fn serve_payment_pending(&self, file_path: &str, available: &str) -> Response<Body> {
+    let mut response = html::wrap_body(
+      &self.base_path,
+      &format!("Payment pending: {}", file_path),
+      html! {},
+      html! {
+        div class="payment-pending" {
+          "Your payment for "
+          span class="filename" {
+            (file_path)
+          }
+          " was received. The file will be available " (available) ". "
+          "Please reload this page later."
+        }
+      },
+    );
+    *response.status_mut() = StatusCode::ACCEPTED;
+    response
   }
 
   pub(crate) async fn serve_invoice(
@@ -187,6 +1797,18 @@ impl Files {
     request: &Request<Body>,
     request_tail: &[&str],
     r_hash: [u8; 32],
+  ) -> Result<Response<Body>> {
+    self
+      .invoice_response(request, request_tail, r_hash)
+      .await
+      .map(Self::noindex)
+  }
+
+  async fn invoice_response(
+    &mut self,
+    request: &Request<Body>,
+    request_tail: &[&str],
+    r_hash: [u8; 32],
   ) -> Result<Response<Body>> {
     let lnd_client = self.lnd_client.as_mut().ok_or_else(|| {
       error::LndNotConfiguredInvoiceRequest {
@@ -200,33 +1822,107 @@ impl Files {
       .context(error::LndRpcStatus)?
       .ok_or_else(|| error::InvoiceNotFound { r_hash }.build())?;
 
-    let request_tail = request_tail.join("");
-    if request_tail != invoice.memo {
-      return Err(
-        error::InvoicePathMismatch {
-          invoice_tail: invoice.memo,
-          request_tail,
-          r_hash,
-        }
-        .build(),
-      );
-    }
-
     let value = invoice.value_msat();
+    let request_tail = self.vfs.resolve(request_tail)?;
+    let file_path = match self.invoice_memo_template.path(&invoice.memo, value) {
+      Some(file_path) if file_path == request_tail => file_path.to_owned(),
+      _ => {
+        return Err(
+          error::InvoicePathMismatch {
+            invoice_tail: invoice.memo,
+            request_tail,
+            r_hash,
+          }
+          .build(),
+        )
+      }
+    };
+
+    self
+      .authorize(request, &self.vfs.file_path(&file_path)?, false)
+      .await?;
+
     match invoice.state() {
       InvoiceState::Settled => {
-        let path = self.vfs.file_path(&invoice.memo)?;
-        Self::serve_file(&path).await
+        self.qr_codes.lock().unwrap().remove(r_hash);
+        let path = self.vfs.file_path(&file_path)?;
+        let proxy_url = self.vfs.proxy_url(&path)?;
+        if proxy_url.is_none() && !path.as_ref().is_file() {
+          log::error!(
+            "Invoice {} for `{}` was paid, but the file is no longer available. \
+            The payment of {} may need to be refunded.",
+            hex::encode(r_hash),
+            file_path,
+            value,
+          );
+          return Ok(self.serve_paid_file_unavailable(&file_path, r_hash));
+        }
+        if let Some(remaining) = self.remaining_confirmations(&invoice).await? {
+          return Ok(self.serve_payment_pending(
+            &file_path,
+            &format!(
+              "after {} more {}",
+              remaining,
+              if remaining == 1 {
+                "confirmation"
+              } else {
+                "confirmations"
+              }
+            ),
+          ));
+        }
+        self.download_counts.record(&file_path, r_hash)?;
+        if let Some(url_signer) = &self.url_signer {
+          return redirect(format!(
+            "{}{}{}?{}",
+            self.base_path,
+            self.files_path,
+            percent::encode_path(&file_path),
+            url_signer.query(&file_path, SystemTime::now())?
+          ));
+        }
+        self.serve_purchased_file(request, &path).await
+      }
+      // Payments for hold invoices are accepted, but only settled once the
+      // invoice's preimage is revealed
+      InvoiceState::Accepted => {
+        Ok(self.serve_payment_pending(&file_path, "once the payment has been settled"))
       }
       _ => {
-        let qr_code_url = format!("/invoice/{}.svg", hex::encode(invoice.r_hash));
-        let filename = invoice.memo;
-        Ok(html::wrap_body(
+        let qr_code_url = format!(
+          "{}/invoice/{}.svg",
+          self.base_path,
+          hex::encode(invoice.r_hash)
+        );
+        let status_url = format!("{}/invoice/{}/status", self.base_path, hex::encode(r_hash));
+        let path = self.vfs.file_path(&file_path)?;
+        let extra_assets = self.vfs.file_extra_assets(&path)?;
+        let invoice_value = value;
+        let value = match self.vfs.invoice_amount_precision(&path)? {
+          AmountPrecision::Millisatoshi => value,
+          AmountPrecision::Satoshi => value.round_to_satoshi(),
+        };
+        // Fiat prices are only shown if they are the whole price
+        let fiat_price = match self.vfs.price_per_byte(&path)? {
+          Some(_) => None,
+          None => self.vfs.fiat_price(&path)?,
+        };
+        let filename = file_path;
+        let mut response = html::wrap_body(
+          &self.base_path,
           &format!("Invoice for {}", filename),
           html! {
-            div class="invoice" {
+            (self.extra_head(&extra_assets))
+            (self.open_graph(&filename, &path, Some(&qr_code_url))?)
+          },
+          html! {
+            div class="invoice" data-status-url=(status_url) {
               div class="label" {
-                "Lightning Payment Request for " (value) " to access "
+                "Lightning Payment Request for " (value)
+                @if let Some(fiat_price) = &fiat_price {
+                  " (" (fiat_price) ")"
+                }
+                " to access "
                 span class="filename" {
                     (filename)
                 }
@@ -237,9 +1933,11 @@ impl Files {
                 button class="clipboard-copy" onclick=(
                   format!("navigator.clipboard.writeText(\"{}\")", invoice.payment_request)
                 ) {
-                  (Files::icon("clipboard"))
+                  (self.icon("clipboard"))
+                }
+                span class="payment-request-text" title="Click to select the payment request" {
+                  (invoice.payment_request)
                 }
-                (invoice.payment_request)
               }
 
               div class="links" {
@@ -268,26 +1966,242 @@ impl Files {
                   "Pay the invoice for " (value) " above "
                   "with your Lightning Network wallet by "
                   "scanning the QR code, "
-                  "copying the payment request string, or "
+                  "copying the payment request string, which is selected in full by clicking on it, or "
                   "clicking the \"Open invoice in wallet\" link."
                 }
                 li {
-                  "Click the \"Access file\" link or reload the page."
+                  "Wait for the page to reload, or click the \"Access file\" link."
                 }
               }
             }
           },
-        ))
+        );
+        Self::add_invoice_headers(
+          response.headers_mut(),
+          &invoice.payment_request,
+          invoice_value,
+          invoice.expiry,
+        )?;
+        Ok(response)
       }
     }
   }
 
-  pub(crate) async fn serve_invoice_qr_code(
+  /// Describe the invoice on an invoice page in headers, for scripts that
+  /// pay for files without parsing HTML.
+  fn add_invoice_headers(
+    headers: &mut header::HeaderMap,
+    payment_request: &str,
+    amount: Millisatoshi,
+    expiry_seconds: i64,
+  ) -> Result<()> {
+    for (name, value) in &[
+      ("x-agora-payment-request", payment_request.to_owned()),
+      ("x-agora-amount-msat", amount.value().to_string()),
+      ("x-agora-invoice-expiry", expiry_seconds.to_string()),
+    ] {
+      headers.insert(
+        header::HeaderName::from_static(*name),
+        HeaderValue::from_str(value).map_err(|error| {
+          Error::internal(format!("Invalid value for header `{}`: {}", name, error))
+        })?,
+      );
+    }
+    Ok(())
+  }
+
+  /// The paid file that the settled `invoice` was created for, if any.
+  /// Invoices for lightning addresses, and those of other applications
+  /// using the same LND node, aren't for paid files.
+  pub(crate) fn settled_file(&self, invoice: &Invoice) -> Result<Option<InputPath>> {
+    let path = match self
+      .invoice_memo_template
+      .path(&invoice.memo, invoice.value_msat())
+      .and_then(|file_path| self.vfs.file_path(file_path).ok())
+    {
+      Some(path) => path,
+      None => return Ok(None),
+    };
+    if !path.as_ref().is_file() && self.vfs.proxy_url(&path)?.is_none() {
+      return Ok(None);
+    }
+    Ok(if self.vfs.paid(&path)? {
+      Some(path)
+    } else {
+      None
+    })
+  }
+
+  pub(crate) fn splits(&self, path: &InputPath) -> Result<Option<Splits>> {
+    self.vfs.splits(path)
+  }
+
+  /// Serve LNURL-pay for the paid file at `tail`. Without an `amount`
+  /// parameter, responds with the pay request, which never expires. With
+  /// one, the request is the wallet's callback, and is answered with a new
+  /// invoice and a success action linking to the invoice page, from which
+  /// the file can be accessed once paid.
+  pub(crate) async fn serve_lnurlp(
     &mut self,
     request: &Request<Body>,
-    r_hash: [u8; 32],
+    tail: &[&str],
   ) -> Result<Response<Body>> {
-    use qrcodegen::{QrCode, QrCodeEcc};
+    let lnurl_base_url = self.lnurl_base_url.clone().ok_or_else(|| {
+      error::LnurlNotConfigured {
+        uri_path: request.uri().path().to_owned(),
+      }
+      .build()
+    })?;
+
+    let file_path = self.vfs.resolve(tail)?;
+    let path = self.vfs.file_path(&file_path)?;
+    let is_file = path.as_ref().is_file() || self.vfs.proxy_url(&path)?.is_some();
+    if !is_file || !self.vfs.paid(&path)? {
+      return Err(error::LnurlUnavailable { file_path }.build());
+    }
+
+    // Wallets don't send credentials, so files that require `basic-auth`
+    // can't be paid for with LNURL-pay
+    self.authorize(request, &path, false).await?;
+
+    if self.sold_out(&file_path, &path)? {
+      return Self::lnurl_response(&LnurlError {
+        status: "ERROR",
+        reason: format!("{} is sold out.", file_path),
+      });
+    }
+
+    let price = Self::invoice_amount(
+      &self.vfs,
+      self.max_invoice_amount,
+      self.price_feed.as_ref(),
+      &path,
+    )
+    .await?;
+    let encoded_path = percent::encode_path(&file_path);
+    let metadata =
+      serde_json::to_string(&[["text/plain", format!("Access to {}", file_path).as_str()]])
+        .context(error::LnurlSerialize)?;
+
+    let amount = match Self::lnurl_amount(request) {
+      Some(amount) => amount,
+      None => {
+        return Self::lnurl_response(&LnurlPayRequest {
+          callback: format!("{}/lnurlp/{}", lnurl_base_url, encoded_path),
+          max_sendable: price.value(),
+          min_sendable: price.value(),
+          metadata,
+          tag: "payRequest",
+        })
+      }
+    };
+
+    if amount.parse::<u64>().ok() != Some(price.value()) {
+      return Self::lnurl_response(&LnurlError {
+        status: "ERROR",
+        reason: format!(
+          "Invalid amount `{}`, expected {} millisatoshis",
+          amount,
+          price.value()
+        ),
+      });
+    }
+
+    let lnd_client = self.lnd_client.as_mut().ok_or_else(|| {
+      error::LndNotConfiguredPaidFileRequest {
+        path: path.display_path().to_owned(),
+      }
+      .build()
+    })?;
+    let expiry_seconds = self.vfs.invoice_expiry(&path)?;
+    let memo = self.invoice_memo_template.render(&file_path, price);
+    let invoice = lnd_client
+      .add_invoice_with_description_hash(
+        &memo,
+        price,
+        openssl::sha::sha256(metadata.as_bytes()),
+        expiry_seconds,
+      )
+      .await
+      .context(error::LndRpcStatus)?;
+    self.metrics.invoice_created();
+
+    Self::lnurl_response(&LnurlPayInvoice {
+      pr: invoice.payment_request,
+      routes: Vec::new(),
+      success_action: Some(LnurlSuccessAction {
+        tag: "url",
+        description: format!("Access {}", file_path),
+        url: format!(
+          "{}{}{}?invoice={}",
+          lnurl_base_url,
+          self.files_path,
+          encoded_path,
+          hex::encode(invoice.r_hash)
+        ),
+      }),
+    })
+  }
+
+  /// Serve the LNURL-pay endpoint of the lightning address `username`, as
+  /// described in LUD-16. Addresses are at the host of `--lnurl-base-url`.
+  pub(crate) async fn serve_lightning_address(
+    &mut self,
+    request: &Request<Body>,
+    username: &str,
+  ) -> Result<Response<Body>> {
+    let lnurl_base_url = self.lnurl_base_url.clone().ok_or_else(|| {
+      error::LnurlNotConfigured {
+        uri_path: request.uri().path().to_owned(),
+      }
+      .build()
+    })?;
+
+    let address = self.vfs.lightning_address(username)?.ok_or_else(|| {
+      error::LightningAddressNotFound {
+        username: username.to_owned(),
+      }
+      .build()
+    })?;
+
+    let host = lnurl_base_url
+      .parse::<Uri>()
+      .ok()
+      .and_then(|uri| uri.host().map(str::to_owned))
+      .ok_or_else(|| Error::internal(format!("Invalid `--lnurl-base-url`: {}", lnurl_base_url)))?;
+    let identifier = format!("{}@{}", username, host);
+    let description = address
+      .description
+      .unwrap_or_else(|| format!("Payment to {}", identifier));
+    let metadata = serde_json::to_string(&[
+      ["text/plain", description.as_str()],
+      ["text/identifier", identifier.as_str()],
+    ])
+    .context(error::LnurlSerialize)?;
+
+    let amount = match Self::lnurl_amount(request) {
+      Some(amount) => amount,
+      None => {
+        return Self::lnurl_response(&LnurlPayRequest {
+          callback: format!("{}/.well-known/lnurlp/{}", lnurl_base_url, username),
+          max_sendable: address.amount.value(),
+          min_sendable: address.amount.value(),
+          metadata,
+          tag: "payRequest",
+        })
+      }
+    };
+
+    if amount.parse::<u64>().ok() != Some(address.amount.value()) {
+      return Self::lnurl_response(&LnurlError {
+        status: "ERROR",
+        reason: format!(
+          "Invalid amount `{}`, expected {} millisatoshis",
+          amount,
+          address.amount.value()
+        ),
+      });
+    }
 
     let lnd_client = self.lnd_client.as_mut().ok_or_else(|| {
       error::LndNotConfiguredInvoiceRequest {
@@ -295,18 +2209,133 @@ impl Files {
       }
       .build()
     })?;
+    // Invoice memos are the paths of the files they pay for, so this memo
+    // starts with a slash, which paths of files never do, so that paying a
+    // lightning address never gives access to a file
     let invoice = lnd_client
-      .lookup_invoice(r_hash)
+      .add_invoice_with_description_hash(
+        &format!("/.well-known/lnurlp/{}", username),
+        address.amount,
+        openssl::sha::sha256(metadata.as_bytes()),
+        None,
+      )
       .await
-      .context(error::LndRpcStatus)?
-      .ok_or_else(|| error::InvoiceNotFound { r_hash }.build())?;
-    let payment_request = invoice.payment_request.to_uppercase();
-    let qr_code = QrCode::encode_text(&payment_request, QrCodeEcc::Medium)
-      .context(error::PaymentRequestTooLongForQrCode { payment_request })?;
+      .context(error::LndRpcStatus)?;
+    self.metrics.invoice_created();
+
+    Self::lnurl_response(&LnurlPayInvoice {
+      pr: invoice.payment_request,
+      routes: Vec::new(),
+      success_action: None,
+    })
+  }
+
+  /// The amount in millisatoshis that an LNURL-pay callback request asks
+  /// to be invoiced.
+  fn lnurl_amount(request: &Request<Body>) -> Option<String> {
+    request.uri().query().and_then(|query| {
+      form_urlencoded::parse(query.as_bytes())
+        .filter(|(key, _value)| key == "amount")
+        .last()
+        .map(|(_key, value)| value.into_owned())
+    })
+  }
+
+  fn lnurl_response(response: &impl Serialize) -> Result<Response<Body>> {
+    Response::builder()
+      .header(header::CONTENT_TYPE, "application/json")
+      .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+      .body(Body::from(
+        serde_json::to_string(response).context(error::LnurlSerialize)?,
+      ))
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+
+  /// Serve the status of the invoice with hash `r_hash` as JSON, which
+  /// invoice pages poll to reload once their invoice is settled. Statuses
+  /// are cached briefly, so polling doesn't hammer LND.
+  pub(crate) async fn serve_invoice_status(
+    &mut self,
+    request: &Request<Body>,
+    r_hash: [u8; 32],
+  ) -> Result<Response<Body>> {
+    let now = Instant::now();
+    let cached = self.invoice_statuses.lock().unwrap().get(r_hash, now);
+    let status = match cached {
+      Some(status) => status,
+      None => {
+        let lnd_client = self.lnd_client.as_mut().ok_or_else(|| {
+          error::LndNotConfiguredInvoiceRequest {
+            uri_path: request.uri().path().to_owned(),
+          }
+          .build()
+        })?;
+        let invoice = lnd_client
+          .lookup_invoice(r_hash)
+          .await
+          .context(error::LndRpcStatus)?
+          .ok_or_else(|| error::InvoiceNotFound { r_hash }.build())?;
+        let status = InvoiceStatus {
+          settled: invoice.state() == InvoiceState::Settled,
+          amount_msat: invoice.value_msat().value(),
+        };
+        self
+          .invoice_statuses
+          .lock()
+          .unwrap()
+          .insert(r_hash, now, status);
+        status
+      }
+    };
+    Ok(
+      Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(
+          serde_json::to_string(&status).expect("invoice status serializes to JSON"),
+        ))
+        .expect("All arguments to response builder are valid"),
+    )
+  }
+
+  /// QR codes are cached, so reloading an invoice page doesn't look up the
+  /// invoice and encode its payment request again.
+  pub(crate) async fn serve_invoice_qr_code(
+    &mut self,
+    request: &Request<Body>,
+    r_hash: [u8; 32],
+  ) -> Result<Response<Body>> {
+    use qrcodegen::{QrCode, QrCodeEcc};
+
+    let cached = self.qr_codes.lock().unwrap().get(r_hash);
+    let svg = match cached {
+      Some(svg) => svg,
+      None => {
+        let lnd_client = self.lnd_client.as_mut().ok_or_else(|| {
+          error::LndNotConfiguredInvoiceRequest {
+            uri_path: request.uri().path().to_owned(),
+          }
+          .build()
+        })?;
+        let invoice = lnd_client
+          .lookup_invoice(r_hash)
+          .await
+          .context(error::LndRpcStatus)?
+          .ok_or_else(|| error::InvoiceNotFound { r_hash }.build())?;
+        let payment_request = invoice.payment_request.to_uppercase();
+        let qr_code = QrCode::encode_text(&payment_request, QrCodeEcc::Medium)
+          .context(error::PaymentRequestTooLongForQrCode { payment_request })?;
+        let svg = Arc::<str>::from(qr_code.to_svg_string(4));
+        if invoice.state() != InvoiceState::Settled {
+          self.qr_codes.lock().unwrap().insert(r_hash, svg.clone());
+        }
+        svg
+      }
+    };
     Ok(
       Response::builder()
         .header(header::CONTENT_TYPE, "image/svg+xml")
-        .body(Body::from(qr_code.to_svg_string(4)))
+        .body(Body::from(svg.to_string()))
         .expect("All arguments to response builder are valid"),
     )
   }
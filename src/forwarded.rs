@@ -0,0 +1,87 @@
+use {crate::common::*, hyper::HeaderMap};
+
+/// Headers set by reverse proxies to describe the original request, which
+/// are only read with `--trust-proxy`, since clients that connect directly
+/// can set them to anything.
+const FOR: &str = "x-forwarded-for";
+const HOST: &str = "x-forwarded-host";
+const PROTO: &str = "x-forwarded-proto";
+
+/// IP address of the client, from the last entry of the last
+/// `X-Forwarded-For` header, which is the one appended by the trusted
+/// proxy. Earlier entries come from the client, and can't be trusted.
+pub(crate) fn client_ip(headers: &HeaderMap) -> Option<IpAddr> {
+  headers
+    .get_all(FOR)
+    .iter()
+    .last()?
+    .to_str()
+    .ok()?
+    .rsplit(',')
+    .next()?
+    .trim()
+    .parse()
+    .ok()
+}
+
+/// `Host` header of the original request, from `X-Forwarded-Host`.
+pub(crate) fn host(headers: &HeaderMap) -> Option<&str> {
+  first(headers, HOST)
+}
+
+/// Scheme of the original request, from `X-Forwarded-Proto`, in lowercase.
+pub(crate) fn proto(headers: &HeaderMap) -> Option<String> {
+  first(headers, PROTO).map(str::to_ascii_lowercase)
+}
+
+fn first<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+  headers
+    .get(name)?
+    .to_str()
+    .ok()?
+    .split(',')
+    .next()
+    .map(str::trim)
+    .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn headers(name: &'static str, value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(name, HeaderValue::from_str(value).unwrap());
+    headers
+  }
+
+  #[test]
+  fn client_ip() {
+    assert_eq!(
+      super::client_ip(&headers(FOR, "198.51.100.1, 203.0.113.1")),
+      Some(IpAddr::from([203, 0, 113, 1]))
+    );
+    let mut appended = headers(FOR, "198.51.100.1");
+    appended.append(FOR, HeaderValue::from_static("203.0.113.1"));
+    assert_eq!(
+      super::client_ip(&appended),
+      Some(IpAddr::from([203, 0, 113, 1]))
+    );
+    assert_eq!(
+      super::client_ip(&headers(FOR, "2001:db8::1")),
+      Some("2001:db8::1".parse().unwrap())
+    );
+    assert_eq!(super::client_ip(&headers(FOR, "unknown")), None);
+    assert_eq!(super::client_ip(&HeaderMap::new()), None);
+  }
+
+  #[test]
+  fn host_and_proto() {
+    assert_eq!(
+      host(&headers(HOST, "example.com, proxy.internal")),
+      Some("example.com")
+    );
+    assert_eq!(proto(&headers(PROTO, "HTTPS")).as_deref(), Some("https"));
+    assert_eq!(proto(&headers(PROTO, "")), None);
+  }
+}
@@ -0,0 +1,156 @@
+use {
+  crate::common::*,
+  maud::{html, PreEscaped},
+  pulldown_cmark::{CodeBlockKind, CowStr, Event, Tag},
+};
+
+/// Syntax highlighting for fenced code blocks in rendered Markdown, which
+/// marks up tokens with `hl-`-prefixed classes, like `hl-keyword`, that are
+/// styled by `static/index.css`. Highlighting needs agora to be built with
+/// the `syntax-highlighting` feature. Without it, and for code blocks in
+/// unknown languages, code blocks are rendered as they are.
+#[derive(Debug)]
+pub(crate) struct Highlighter {
+  #[cfg(feature = "syntax-highlighting")]
+  syntax_set: syntect::parsing::SyntaxSet,
+}
+
+impl Highlighter {
+  pub(crate) fn new() -> Self {
+    Self {
+      #[cfg(feature = "syntax-highlighting")]
+      syntax_set: syntect::parsing::SyntaxSet::load_defaults_newlines(),
+    }
+  }
+
+  /// Replace fenced code blocks in `events` whose info string starts with a
+  /// known language, like `rust` or `py`, with highlighted HTML.
+  pub(crate) fn highlight_code_blocks<'a>(
+    &self,
+    events: impl IntoIterator<Item = Event<'a>>,
+  ) -> Vec<Event<'a>> {
+    let mut output = Vec::new();
+    let mut current: Option<(CowStr<'a>, String, Vec<Event<'a>>)> = None;
+
+    for event in events {
+      match (&mut current, event) {
+        (None, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))) => {
+          let start = Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info.clone())));
+          current = Some((info, String::new(), vec![start]))
+        }
+        (Some((info, code, inner)), Event::End(Tag::CodeBlock(kind))) => {
+          let language = info.split_whitespace().next().unwrap_or_default();
+          match self.highlight(language, code) {
+            Some(highlighted) => output.push(Event::Html(CowStr::from(
+              html! {
+                pre class="highlight" {
+                  code class=(format!("language-{}", language)) {
+                    (PreEscaped(highlighted))
+                  }
+                }
+              }
+              .into_string()
+                + "\n",
+            ))),
+            None => {
+              output.append(inner);
+              output.push(Event::End(Tag::CodeBlock(kind)));
+            }
+          }
+          current = None;
+        }
+        (Some((_, code, inner)), event) => {
+          if let Event::Text(text) = &event {
+            code.push_str(text);
+          }
+          inner.push(event);
+        }
+        (None, event) => output.push(event),
+      }
+    }
+
+    output
+  }
+
+  #[cfg(feature = "syntax-highlighting")]
+  fn highlight(&self, language: &str, code: &str) -> Option<String> {
+    use syntect::{
+      html::{ClassStyle, ClassedHTMLGenerator},
+      util::LinesWithEndings,
+    };
+
+    let syntax = self.syntax_set.find_syntax_by_token(language)?;
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(
+      syntax,
+      &self.syntax_set,
+      ClassStyle::SpacedPrefixed { prefix: "hl-" },
+    );
+    for line in LinesWithEndings::from(code) {
+      generator.parse_html_for_line_which_includes_newline(line);
+    }
+    Some(generator.finalize())
+  }
+
+  #[cfg(not(feature = "syntax-highlighting"))]
+  fn highlight(&self, _language: &str, _code: &str) -> Option<String> {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    pulldown_cmark::{html::push_html, Parser},
+  };
+
+  fn render(markdown: &str) -> String {
+    let mut html = String::new();
+    push_html(
+      &mut html,
+      Highlighter::new()
+        .highlight_code_blocks(Parser::new(markdown))
+        .into_iter(),
+    );
+    html
+  }
+
+  #[test]
+  fn code_blocks_without_known_language_are_unchanged() {
+    for markdown in &[
+      "```\nfoo\n```\n",
+      "```not-a-language\n<foo>\n```\n",
+      "    indented\n",
+      "text `code`\n",
+    ] {
+      let mut expected = String::new();
+      push_html(&mut expected, Parser::new(markdown));
+      assert_eq!(render(markdown), expected);
+    }
+  }
+
+  #[test]
+  #[cfg(not(feature = "syntax-highlighting"))]
+  fn code_blocks_are_unchanged_without_feature() {
+    assert_eq!(
+      render("```rust\nfn main() {}\n```\n"),
+      "<pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n"
+    );
+  }
+
+  #[test]
+  #[cfg(feature = "syntax-highlighting")]
+  fn known_languages_are_highlighted() {
+    let html = render("```rust\nfn main() {}\n```\n");
+    assert!(
+      html.starts_with("<pre class=\"highlight\"><code class=\"language-rust\">"),
+      "{}",
+      html
+    );
+    assert!(
+      html.contains("<span class=\"hl-storage hl-type hl-function"),
+      "{}",
+      html
+    );
+  }
+}
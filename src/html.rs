@@ -3,7 +3,12 @@ use {
   maud::{html, DOCTYPE},
 };
 
-pub(crate) fn wrap_body(title_slug: &str, body: Markup) -> Response<Body> {
+pub(crate) fn wrap_body(
+  base_path: &str,
+  title_slug: &str,
+  head: Markup,
+  body: Markup,
+) -> Response<Body> {
   let html = html! {
     (DOCTYPE)
     html lang="en" {
@@ -13,8 +18,9 @@ pub(crate) fn wrap_body(title_slug: &str, body: Markup) -> Response<Body> {
         title {
           (format!("{} · Agora", title_slug))
         }
-        link rel="stylesheet" href="/static/index.css";
-        script type="module" src="/static/index.js" {}
+        link rel="stylesheet" href=(format!("{}/static/index.css", base_path));
+        script type="module" src=(format!("{}/static/index.js", base_path)) {}
+        (head)
       }
       body {
         main {
@@ -1,9 +1,15 @@
-use {crate::common::*, tower::make::Shared};
+use {
+  crate::{common::*, forwarded},
+  tower::make::Shared,
+};
 
 #[derive(Clone)]
 pub(crate) struct HttpsRedirectService {
   https_port: u16,
   stderr: Stderr,
+  log_format: LogFormat,
+  base_path: String,
+  trust_proxy: bool,
 }
 
 impl HttpsRedirectService {
@@ -32,34 +38,63 @@ impl HttpsRedirectService {
           HttpsRedirectService {
             https_port: https_request_handler.https_port(),
             stderr: environment.stderr.clone(),
+            log_format: arguments.log_format,
+            base_path: arguments.base_path(),
+            trust_proxy: arguments.trust_proxy,
           },
         ))))
       }
     }
   }
 
+  /// With `--trust-proxy`, the host is taken from `X-Forwarded-Host`, and
+  /// requests that a proxy received over HTTPS, according to
+  /// `X-Forwarded-Proto`, are rejected, since redirecting them again would
+  /// loop.
   fn response(&mut self, request: Request<Body>) -> Result<Response<Body>> {
-    let authority = request.headers().get(header::HOST).ok_or_else(|| {
+    if self.trust_proxy && forwarded::proto(request.headers()).as_deref() == Some("https") {
+      return Err(
+        error::Custom {
+          message: "Request was already made over HTTPS according to X-Forwarded-Proto header",
+          status_code: StatusCode::BAD_REQUEST,
+        }
+        .build(),
+      );
+    }
+
+    let forwarded_host = if self.trust_proxy {
+      forwarded::host(request.headers())
+    } else {
+      None
+    };
+
+    let authority = match forwarded_host {
+      Some(host) => host.as_bytes(),
+      None => request
+        .headers()
+        .get(header::HOST)
+        .ok_or_else(|| {
+          error::Custom {
+            message: "Missing HOST header",
+            status_code: StatusCode::BAD_REQUEST,
+          }
+          .build()
+        })?
+        .as_bytes(),
+    };
+
+    let authority = Authority::from_maybe_shared(authority.to_vec()).map_err(|error| {
       error::Custom {
-        message: "Missing HOST header",
+        message: format!(
+          "Invalid HOST header `{}`: {}",
+          String::from_utf8_lossy(authority),
+          error
+        ),
         status_code: StatusCode::BAD_REQUEST,
       }
       .build()
     })?;
 
-    let authority =
-      Authority::from_maybe_shared(authority.as_bytes().to_vec()).map_err(|error| {
-        error::Custom {
-          message: format!(
-            "Invalid HOST header `{}`: {}",
-            String::from_utf8_lossy(authority.as_bytes()),
-            error
-          ),
-          status_code: StatusCode::BAD_REQUEST,
-        }
-        .build()
-      })?;
-
     redirect(format!(
       "https://{}:{}{}",
       authority.host(),
@@ -79,7 +114,91 @@ impl Service<Request<Body>> for HttpsRedirectService {
   }
 
   fn call(&mut self, request: Request<Body>) -> Self::Future {
+    let request_headers = request.headers().clone();
     let result = self.response(request);
-    future::ready(Ok(error_page::map_error(self.stderr.clone(), result)))
+    if let Err(error) = &result {
+      error_page::log_error(&mut self.stderr, self.log_format, None, error);
+    }
+    future::ready(Ok(error_page::map_error(
+      &request_headers,
+      false,
+      None,
+      &self.base_path,
+      result,
+    )))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn location(trust_proxy: bool, headers: &[(&str, &str)]) -> Result<String> {
+    let mut service = HttpsRedirectService {
+      https_port: 8443,
+      stderr: Stderr::test(),
+      log_format: LogFormat::Text,
+      base_path: String::new(),
+      trust_proxy,
+    };
+    let mut request = Request::builder().uri("/files/foo");
+    for (name, value) in headers {
+      request = request.header(*name, *value);
+    }
+    let response = service.response(request.body(Body::empty()).unwrap())?;
+    Ok(
+      response
+        .headers()
+        .get(header::LOCATION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned(),
+    )
+  }
+
+  #[test]
+  fn redirects_to_host() {
+    assert_eq!(
+      location(false, &[("host", "example.com:8080")]).unwrap(),
+      "https://example.com:8443/files/foo"
+    );
+  }
+
+  #[test]
+  fn forwarded_headers_are_ignored_without_trust_proxy() {
+    assert_eq!(
+      location(
+        false,
+        &[
+          ("host", "internal"),
+          ("x-forwarded-host", "example.com"),
+          ("x-forwarded-proto", "https"),
+        ]
+      )
+      .unwrap(),
+      "https://internal:8443/files/foo"
+    );
+  }
+
+  #[test]
+  fn forwarded_headers_are_honored_with_trust_proxy() {
+    assert_eq!(
+      location(
+        true,
+        &[("host", "internal"), ("x-forwarded-host", "example.com")]
+      )
+      .unwrap(),
+      "https://example.com:8443/files/foo"
+    );
+    assert_eq!(
+      location(
+        true,
+        &[("host", "internal"), ("x-forwarded-proto", "https")]
+      )
+      .unwrap_err()
+      .status(),
+      StatusCode::BAD_REQUEST
+    );
   }
 }
@@ -1,12 +1,17 @@
 use {
   crate::common::*,
+  futures::channel::mpsc,
   hyper::server::conn::Http,
   rustls_acme::{
     acme::{ACME_TLS_ALPN_NAME, LETS_ENCRYPT_PRODUCTION_DIRECTORY, LETS_ENCRYPT_STAGING_DIRECTORY},
     ResolvesServerCertUsingAcme,
   },
+  std::time::Duration,
+  tokio::sync::Semaphore,
   tokio_rustls::{
-    rustls::{NoClientAuth, ServerConfig, Session},
+    rustls::{
+      sign::CertifiedKey, ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig, Session,
+    },
     server::TlsStream,
   },
   tokio_stream::wrappers::TcpListenerStream,
@@ -18,6 +23,10 @@ pub(crate) struct HttpsRequestHandler {
   listener: tokio::net::TcpListener,
   cache_dir: PathBuf,
   acme_domains: Vec<String>,
+  min_tls_version: Option<TlsVersion>,
+  http2_max_concurrent_streams: u32,
+  max_tls_handshakes: NonZeroUsize,
+  tls_handshake_timeout: Duration,
 }
 
 impl HttpsRequestHandler {
@@ -26,9 +35,8 @@ impl HttpsRequestHandler {
     arguments: &Arguments,
     acme_cache_directory: &Path,
     https_port: u16,
-    lnd_client: Option<agora_lnd_client::Client>,
+    request_handler: RequestHandler,
   ) -> Result<HttpsRequestHandler> {
-    let request_handler = RequestHandler::new(environment, &arguments.directory, lnd_client);
     let socket_addr = (arguments.address.as_str(), https_port)
       .to_socket_addrs()
       .context(error::AddressResolutionIo {
@@ -47,17 +55,23 @@ impl HttpsRequestHandler {
     let local_addr = listener
       .local_addr()
       .context(error::SocketIo { socket_addr })?;
-    writeln!(
-      environment.stderr,
-      "Listening for HTTPS connections on `{}`",
-      local_addr,
-    )
-    .context(error::StderrWrite)?;
+    arguments
+      .log_format
+      .message(
+        &mut environment.stderr,
+        log::Level::Info,
+        format_args!("Listening for HTTPS connections on `{}`", local_addr),
+      )
+      .context(error::StderrWrite)?;
     let https_port = local_addr.port();
     let cache_dir = environment.working_directory.join(acme_cache_directory);
     assert!(!arguments.acme_domain.is_empty());
     Ok(HttpsRequestHandler {
       acme_domains: arguments.acme_domain.clone(),
+      min_tls_version: arguments.min_tls_version,
+      http2_max_concurrent_streams: arguments.http2_max_concurrent_streams,
+      max_tls_handshakes: arguments.max_tls_handshakes,
+      tls_handshake_timeout: Duration::from_secs(arguments.tls_handshake_timeout),
       request_handler,
       https_port,
       listener,
@@ -65,7 +79,9 @@ impl HttpsRequestHandler {
     })
   }
 
-  pub(crate) async fn run(self) {
+  /// Accept connections until `shutdown` resolves, and then wait for open
+  /// connections to finish their in-flight requests.
+  pub(crate) async fn run(self, mut shutdown: Shutdown) {
     let resolver = ResolvesServerCertUsingAcme::new();
     let resolver_clone = resolver.clone();
     let acme_domains = self.acme_domains.clone();
@@ -89,27 +105,83 @@ impl HttpsRequestHandler {
       b"h2".to_vec(),
       b"http/1.1".to_vec(),
     ]);
-    config.cert_resolver = resolver;
+    config.cert_resolver = Arc::new(AcmeDomainResolver {
+      acme_domains: self.acme_domains.clone(),
+      resolver,
+    });
+    if let Some(min_tls_version) = self.min_tls_version {
+      config.versions = min_tls_version.accepted_versions();
+    }
     let config = Arc::new(config);
     let mut tcp_listener_stream = TcpListenerStream::new(self.listener);
-    while let Some(result) = tcp_listener_stream.next().await {
+    // Each connection task holds a sender, so once they have all finished,
+    // receiving from `connections` returns `None`
+    let (connection_sender, mut connections) = mpsc::channel::<Infallible>(0);
+    // Permits are acquired before accepting connections, so that when all
+    // of them are taken, further connections wait in the listen backlog,
+    // instead of each getting a task
+    let handshake_permits = Arc::new(Semaphore::new(self.max_tls_handshakes.get()));
+    loop {
+      let handshake_permit = match handshake_permits.clone().try_acquire_owned() {
+        Ok(handshake_permit) => handshake_permit,
+        Err(_) => {
+          log::warn!(
+            "Reached limit of {} concurrent TLS handshakes, waiting for one to finish",
+            self.max_tls_handshakes
+          );
+          tokio::select! {
+            handshake_permit = handshake_permits.clone().acquire_owned() =>
+              handshake_permit.expect("handshake semaphore is never closed"),
+            () = &mut shutdown => break,
+          }
+        }
+      };
+      let result = tokio::select! {
+        result = tcp_listener_stream.next() => match result {
+          Some(result) => result,
+          None => break,
+        },
+        () = &mut shutdown => break,
+      };
       match result {
         Ok(connection) => {
-          let request_handler = self.request_handler.clone();
+          let request_handler = RequestHandler {
+            remote_addr: connection.peer_addr().ok(),
+            ..self.request_handler.clone()
+          };
           let config = config.clone();
+          let http2_max_concurrent_streams = self.http2_max_concurrent_streams;
+          let tls_handshake_timeout = self.tls_handshake_timeout;
+          let shutdown = shutdown.clone();
+          let connection_sender = connection_sender.clone();
           tokio::spawn(async move {
-            match Self::accept(config, connection).await {
+            let peer_addr = connection.peer_addr().ok();
+            let result = Self::handshake(config, connection, tls_handshake_timeout).await;
+            drop(handshake_permit);
+            match result {
               Ok(Some(tls_stream)) => {
-                if let Err(err) = Http::new()
-                  .serve_connection(tls_stream, request_handler)
-                  .await
-                {
+                let connection = Http::new()
+                  .http2_max_concurrent_streams(http2_max_concurrent_streams)
+                  .serve_connection(tls_stream, request_handler);
+                tokio::pin!(connection);
+                let result = tokio::select! {
+                  result = connection.as_mut() => result,
+                  () = shutdown => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                  }
+                };
+                if let Err(err) = result {
                   log::debug!("Error closing TLS connection: {}", err);
                 }
               }
               Ok(None) => {}
+              Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+                log::debug!("TLS handshake with {:?} timed out", peer_addr)
+              }
               Err(err) => log::error!("TLS accept error: {:?}", err),
             };
+            drop(connection_sender);
           });
         }
         Err(err) => {
@@ -117,8 +189,29 @@ impl HttpsRequestHandler {
         }
       }
     }
+    drop(connection_sender);
+    connections.next().await;
+  }
+
+  /// Complete the TLS handshake for `stream` with `accept`, failing with
+  /// `io::ErrorKind::TimedOut` if it takes longer than `timeout`.
+  async fn handshake(
+    config: Arc<ServerConfig>,
+    stream: tokio::net::TcpStream,
+    timeout: Duration,
+  ) -> io::Result<Option<TlsStream<tokio::net::TcpStream>>> {
+    tokio::time::timeout(timeout, Self::accept(config, stream))
+      .await
+      .unwrap_or_else(|_elapsed| {
+        Err(io::Error::new(
+          io::ErrorKind::TimedOut,
+          "TLS handshake timed out",
+        ))
+      })
   }
 
+  /// Complete the TLS handshake for `stream`, returning `None` for ACME
+  /// challenge handshakes.
   pub(crate) async fn accept(
     config: Arc<ServerConfig>,
     stream: tokio::net::TcpStream,
@@ -137,3 +230,54 @@ impl HttpsRequestHandler {
     self.https_port
   }
 }
+
+/// Resolves certificates with `resolver`, but only for clients that ask
+/// for one of `acme_domains`, or don't send a host name at all, like those
+/// connecting to the server's IP address. Handshakes for other host names
+/// fail, instead of being answered with a certificate that doesn't match.
+struct AcmeDomainResolver {
+  acme_domains: Vec<String>,
+  resolver: Arc<ResolvesServerCertUsingAcme>,
+}
+
+impl ResolvesServerCert for AcmeDomainResolver {
+  fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+    if let Some(server_name) = client_hello.server_name() {
+      let server_name: &str = server_name.into();
+      if !self
+        .acme_domains
+        .iter()
+        .any(|domain| domain.eq_ignore_ascii_case(server_name))
+      {
+        log::debug!(
+          "Rejecting TLS handshake for unconfigured host `{}`",
+          server_name
+        );
+        return None;
+      }
+    }
+    self.resolver.resolve(client_hello)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn stalled_handshakes_time_out() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let _client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+      .await
+      .unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+    let error = HttpsRequestHandler::handshake(
+      Arc::new(ServerConfig::new(NoClientAuth::new())),
+      stream,
+      Duration::from_millis(10),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+  }
+}
@@ -0,0 +1,95 @@
+use {
+  crate::common::*,
+  std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+  },
+};
+
+/// Status of an invoice, served as JSON at `/invoice/R_HASH/status`, so
+/// invoice pages can poll for settlement without reloading.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct InvoiceStatus {
+  pub(crate) settled: bool,
+  pub(crate) amount_msat: u64,
+}
+
+/// Recently looked up invoice statuses, keyed by invoice hash. Statuses
+/// are reused for `INTERVAL` after they were looked up, so clients polling
+/// an invoice's status can't make agora look it up in LND more often than
+/// that. Holds at most `capacity` statuses, evicting the least recently
+/// looked up.
+#[derive(Debug)]
+pub(crate) struct InvoiceStatusCache {
+  capacity: usize,
+  /// Most recently looked up last
+  entries: VecDeque<([u8; 32], Instant, InvoiceStatus)>,
+}
+
+impl InvoiceStatusCache {
+  pub(crate) const CAPACITY: usize = 256;
+
+  pub(crate) const INTERVAL: Duration = Duration::from_secs(1);
+
+  pub(crate) fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      entries: VecDeque::new(),
+    }
+  }
+
+  /// The status of the invoice with hash `r_hash`, if it was looked up less
+  /// than `INTERVAL` before `now`.
+  pub(crate) fn get(&self, r_hash: [u8; 32], now: Instant) -> Option<InvoiceStatus> {
+    self
+      .entries
+      .iter()
+      .find(|(entry_r_hash, looked_up, _status)| {
+        *entry_r_hash == r_hash && now.saturating_duration_since(*looked_up) < Self::INTERVAL
+      })
+      .map(|(_r_hash, _looked_up, status)| *status)
+  }
+
+  pub(crate) fn insert(&mut self, r_hash: [u8; 32], now: Instant, status: InvoiceStatus) {
+    self
+      .entries
+      .retain(|(entry_r_hash, _looked_up, _status)| *entry_r_hash != r_hash);
+    if self.entries.len() >= self.capacity {
+      self.entries.pop_front();
+    }
+    self.entries.push_back((r_hash, now, status));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const STATUS: InvoiceStatus = InvoiceStatus {
+    settled: false,
+    amount_msat: 1000,
+  };
+
+  #[test]
+  fn statuses_expire_after_interval() {
+    let mut cache = InvoiceStatusCache::new(2);
+    let now = Instant::now();
+    cache.insert([0; 32], now, STATUS);
+    assert_eq!(cache.get([0; 32], now), Some(STATUS));
+    assert_eq!(cache.get([1; 32], now), None);
+    assert_eq!(cache.get([0; 32], now + InvoiceStatusCache::INTERVAL), None);
+  }
+
+  #[test]
+  fn evicts_least_recently_looked_up() {
+    let mut cache = InvoiceStatusCache::new(2);
+    let now = Instant::now();
+    cache.insert([0; 32], now, STATUS);
+    cache.insert([1; 32], now, STATUS);
+    cache.insert([0; 32], now, STATUS);
+    cache.insert([2; 32], now, STATUS);
+    assert_eq!(cache.get([0; 32], now), Some(STATUS));
+    assert_eq!(cache.get([1; 32], now), None);
+    assert_eq!(cache.get([2; 32], now), Some(STATUS));
+  }
+}
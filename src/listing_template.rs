@@ -0,0 +1,213 @@
+use {crate::common::*, maud::html};
+
+/// Template for HTML directory listings from `--listing-template`, which is
+/// served as a complete HTML document, instead of agora's own listing page.
+/// `{path}`, `{header}`, `{index}`, and `{footer}` are replaced with the
+/// path of the directory and its rendered Markdown files. The text between
+/// `{entries}` and `{/entries}` is repeated for each entry, with `{name}`,
+/// `{href}`, `{size}`, `{is_dir}`, and `{paid}` replaced with the entry's
+/// values. Loaded at startup, like `--error-template`, so that a missing or
+/// malformed template is reported right away.
+#[derive(Clone, Debug)]
+pub(crate) struct ListingTemplate {
+  before: Arc<str>,
+  entry: Arc<str>,
+  after: Arc<str>,
+}
+
+/// A directory listing entry, as exposed to listing templates.
+pub(crate) struct ListingTemplateEntry {
+  pub(crate) name: String,
+  pub(crate) href: String,
+  pub(crate) size: Option<u64>,
+  pub(crate) is_dir: bool,
+  pub(crate) paid: bool,
+}
+
+/// Rendered Markdown files of a listed directory.
+pub(crate) struct ListingTemplatePartials {
+  pub(crate) header: Option<Markup>,
+  pub(crate) index: Option<Markup>,
+  pub(crate) footer: Option<Markup>,
+}
+
+impl ListingTemplate {
+  const ENTRIES_START: &'static str = "{entries}";
+  const ENTRIES_END: &'static str = "{/entries}";
+
+  pub(crate) fn load(environment: &Environment, arguments: &Arguments) -> Result<Option<Self>> {
+    let path = match &arguments.listing_template {
+      Some(path) => environment.working_directory.join(path),
+      None => return Ok(None),
+    };
+    let template = fs::read_to_string(&path).context(error::FilesystemIo { path: &path })?;
+    Self::parse(&template, &path).map(Some)
+  }
+
+  fn parse(template: &str, path: &Path) -> Result<Self> {
+    let invalid = || error::ListingTemplateParse { path }.build();
+    if template.matches(Self::ENTRIES_START).count() != 1
+      || template.matches(Self::ENTRIES_END).count() != 1
+    {
+      return Err(invalid());
+    }
+    let start = template
+      .find(Self::ENTRIES_START)
+      .expect("template contains start of entries");
+    let end = template
+      .find(Self::ENTRIES_END)
+      .expect("template contains end of entries");
+    if end < start {
+      return Err(invalid());
+    }
+    Ok(Self {
+      before: template[..start].into(),
+      entry: template[start + Self::ENTRIES_START.len()..end].into(),
+      after: template[end + Self::ENTRIES_END.len()..].into(),
+    })
+  }
+
+  pub(crate) fn render(
+    &self,
+    path: &str,
+    entries: &[ListingTemplateEntry],
+    partials: &ListingTemplatePartials,
+  ) -> Response<Body> {
+    let directory = |key: &str| match key {
+      "path" => Some(escape(path)),
+      "header" => Some(Self::partial(&partials.header)),
+      "index" => Some(Self::partial(&partials.index)),
+      "footer" => Some(Self::partial(&partials.footer)),
+      _ => None,
+    };
+
+    let mut html = substitute(&self.before, directory);
+    for entry in entries {
+      html.push_str(&substitute(&self.entry, |key| match key {
+        "name" => Some(escape(&entry.name)),
+        "href" => Some(escape(&entry.href)),
+        "size" => Some(entry.size.map(|size| size.to_string()).unwrap_or_default()),
+        "is_dir" => Some(entry.is_dir.to_string()),
+        "paid" => Some(entry.paid.to_string()),
+        _ => directory(key),
+      }));
+    }
+    html.push_str(&substitute(&self.after, directory));
+
+    Response::builder()
+      .header(header::CONTENT_TYPE, "text/html")
+      .body(Body::from(html))
+      .expect("builder arguments are valid")
+  }
+
+  fn partial(partial: &Option<Markup>) -> String {
+    partial
+      .as_ref()
+      .map(|markup| markup.0.clone())
+      .unwrap_or_default()
+  }
+}
+
+fn escape(text: &str) -> String {
+  html! { (text) }.into_string()
+}
+
+/// Replace each `{key}` in `template` for which `value` returns a value in
+/// a single pass, so that values which contain placeholders, like file
+/// names, aren't substituted again. Unknown placeholders are left as they
+/// are.
+fn substitute(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+  let mut output = String::with_capacity(template.len());
+  let mut rest = template;
+  while let Some(start) = rest.find('{') {
+    output.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let replacement = rest
+      .find('}')
+      .and_then(|end| value(&rest[1..end]).map(|value| (end, value)));
+    match replacement {
+      Some((end, value)) => {
+        output.push_str(&value);
+        rest = &rest[end + 1..];
+      }
+      None => {
+        output.push('{');
+        rest = &rest[1..];
+      }
+    }
+  }
+  output.push_str(rest);
+  output
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn render(template: &str, entries: &[ListingTemplateEntry]) -> String {
+    let response = ListingTemplate::parse(template, "listing.html".as_ref())
+      .unwrap()
+      .render(
+        "/dir/",
+        entries,
+        &ListingTemplatePartials {
+          header: None,
+          index: Some(maud::PreEscaped("<h1>index</h1>".into())),
+          footer: None,
+        },
+      );
+    futures::executor::block_on(hyper::body::to_bytes(response.into_body()))
+      .map(|bytes| String::from_utf8(bytes.to_vec()).unwrap())
+      .unwrap()
+  }
+
+  #[test]
+  fn entries_are_repeated() {
+    assert_eq!(
+      render(
+        "<h2>{path}</h2><ul>{entries}<li>{name} {href} {size} {is_dir} {paid}</li>{/entries}</ul>{index}{footer}",
+        &[
+          ListingTemplateEntry {
+            name: "dir/".into(),
+            href: "dir/".into(),
+            size: None,
+            is_dir: true,
+            paid: false,
+          },
+          ListingTemplateEntry {
+            name: "<foo> {href}".into(),
+            href: "%3Cfoo%3E%20%7Bhref%7D".into(),
+            size: Some(3),
+            is_dir: false,
+            paid: true,
+          },
+        ],
+      ),
+      "<h2>/dir/</h2><ul><li>dir/ dir/  true false</li><li>&lt;foo&gt; {href} %3Cfoo%3E%20%7Bhref%7D 3 false true</li></ul><h1>index</h1>",
+    );
+  }
+
+  #[test]
+  fn unknown_placeholders_are_left_as_they_are() {
+    assert_eq!(
+      render("{entries}{/entries}{unknown} {path", &[]),
+      "{unknown} {path"
+    );
+  }
+
+  #[test]
+  fn entries_block_is_required() {
+    for template in &[
+      "",
+      "{entries}",
+      "{/entries}",
+      "{/entries}{entries}",
+      "{entries}{/entries}{entries}{/entries}",
+    ] {
+      assert_matches!(
+        ListingTemplate::parse(template, "listing.html".as_ref()),
+        Err(Error::ListingTemplateParse { .. })
+      );
+    }
+  }
+}
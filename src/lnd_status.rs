@@ -0,0 +1,73 @@
+use {
+  crate::common::*,
+  std::sync::atomic::{self, AtomicBool},
+};
+
+/// Whether LND was reachable when it was last pinged, shared between the
+/// task that pings LND periodically and the `/health` route, so that health
+/// checks never wait for LND.
+#[derive(Clone, Debug)]
+pub(crate) struct LndStatus {
+  reachable: Option<Arc<AtomicBool>>,
+}
+
+impl LndStatus {
+  pub(crate) fn disabled() -> Self {
+    Self { reachable: None }
+  }
+
+  pub(crate) fn new(reachable: bool) -> Self {
+    Self {
+      reachable: Some(Arc::new(AtomicBool::new(reachable))),
+    }
+  }
+
+  pub(crate) fn set_reachable(&self, reachable: bool) {
+    if let Some(status) = &self.reachable {
+      status.store(reachable, atomic::Ordering::Relaxed);
+    }
+  }
+
+  fn lnd(&self) -> &'static str {
+    match &self.reachable {
+      None => "disabled",
+      Some(reachable) if reachable.load(atomic::Ordering::Relaxed) => "connected",
+      Some(_) => "unreachable",
+    }
+  }
+
+  /// Response for the `/health` route, which is always `200 OK`, even if
+  /// LND is unreachable, since files can still be served.
+  pub(crate) fn health_response(&self) -> Result<Response<Body>> {
+    Response::builder()
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(Body::from(
+        serde_json::to_string(&Health {
+          status: "ok",
+          lnd: self.lnd(),
+        })
+        .expect("serializing to a string cannot fail"),
+      ))
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+}
+
+#[derive(Serialize)]
+struct Health {
+  status: &'static str,
+  lnd: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lnd() {
+    assert_eq!(LndStatus::disabled().lnd(), "disabled");
+    let status = LndStatus::new(true);
+    assert_eq!(status.lnd(), "connected");
+    status.clone().set_reachable(false);
+    assert_eq!(status.lnd(), "unreachable");
+  }
+}
@@ -0,0 +1,158 @@
+use {
+  crate::common::*,
+  log::{Level, LevelFilter, Record},
+  serde_json::{Map, Value as JsonValue},
+};
+
+/// Format of log messages and fatal errors written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LogFormat {
+  /// `env_logger`'s human-readable format.
+  Text,
+  /// One JSON object per line, for log aggregators.
+  Json,
+}
+
+impl LogFormat {
+  /// With JSON, messages that are otherwise written to stderr directly, like
+  /// the addresses being listened on, are logged instead, so `agora`'s own
+  /// messages are logged at info level unless `RUST_LOG` is set.
+  pub(crate) fn init_logger(self) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if self == Self::Json {
+      if env::var_os("RUST_LOG").is_none() {
+        builder
+          .filter_level(LevelFilter::Error)
+          .filter_module("agora", LevelFilter::Info);
+      }
+      builder
+        .format(|buf, record| writeln!(buf, "{}", Self::record_line(record, SystemTime::now())));
+    }
+    builder.init();
+  }
+
+  /// Write `message` to `stderr`, prefixed with `warning: ` for warnings,
+  /// or with JSON, log it at `level`, so that it's written as a JSON line.
+  pub(crate) fn message(
+    self,
+    stderr: &mut impl Write,
+    level: Level,
+    message: fmt::Arguments,
+  ) -> io::Result<()> {
+    match self {
+      Self::Text if level == Level::Warn => writeln!(stderr, "warning: {}", message),
+      Self::Text => writeln!(stderr, "{}", message),
+      Self::Json => {
+        log::log!(target: "agora", level, "{}", message);
+        Ok(())
+      }
+    }
+  }
+
+  /// A JSON object with the time, level, target, and message of a log
+  /// message, as well as `fields`, like the method, path, and status logged
+  /// for each request.
+  pub(crate) fn json_line(
+    level: Level,
+    target: &str,
+    message: &str,
+    mut fields: Map<String, JsonValue>,
+    time: SystemTime,
+  ) -> JsonValue {
+    fields.insert(
+      "timestamp".into(),
+      humantime::format_rfc3339_millis(time).to_string().into(),
+    );
+    fields.insert("level".into(), level.as_str().into());
+    fields.insert("target".into(), target.into());
+    fields.insert("message".into(), message.into());
+    fields.into()
+  }
+
+  fn record_line(record: &Record, time: SystemTime) -> JsonValue {
+    Self::json_line(
+      record.level(),
+      record.target(),
+      &record.args().to_string(),
+      Map::new(),
+      time,
+    )
+  }
+
+  /// Write a fatal error as a JSON line, like those of log messages, with
+  /// its backtrace, if any, as a string.
+  pub(crate) fn error_line(error: &Error, time: SystemTime) -> JsonValue {
+    let mut fields = Map::new();
+    if let Some(backtrace) = error.backtrace_string() {
+      fields.insert("backtrace".into(), backtrace.into());
+    }
+    Self::json_line(Level::Error, "agora", &error.to_string(), fields, time)
+  }
+}
+
+impl str::FromStr for LogFormat {
+  type Err = Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    match input {
+      "text" => Ok(Self::Text),
+      "json" => Ok(Self::Json),
+      _ => Err(
+        error::LogFormatParse {
+          input: input.to_owned(),
+        }
+        .build(),
+      ),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+    assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+    assert_eq!(
+      "xml".parse::<LogFormat>().unwrap_err().to_string(),
+      "Invalid log format `xml`, expected `text` or `json`",
+    );
+  }
+
+  #[test]
+  fn record_line() {
+    assert_eq!(
+      LogFormat::record_line(
+        &Record::builder()
+          .args(format_args!("Listening"))
+          .level(Level::Info)
+          .target("agora::server")
+          .build(),
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_001),
+      )
+      .to_string(),
+      r#"{"level":"INFO","message":"Listening","target":"agora::server","timestamp":"2001-09-09T01:46:41.000Z"}"#,
+    );
+  }
+
+  #[test]
+  fn json_line() {
+    let mut fields = Map::new();
+    fields.insert("method".into(), "GET".into());
+    fields.insert("status".into(), 200.into());
+    assert_eq!(
+      LogFormat::json_line(
+        Level::Debug,
+        "agora::request_handler",
+        "Outgoing",
+        fields,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_001),
+      )
+      .to_string(),
+      r#"{"level":"DEBUG","message":"Outgoing","method":"GET","status":200,"target":"agora::request_handler","timestamp":"2001-09-09T01:46:41.000Z"}"#,
+    );
+  }
+}
@@ -0,0 +1,47 @@
+use crate::common::*;
+
+/// LND macaroon given in hex with `--lnd-rpc-macaroon-hex`, decoded when
+/// arguments are parsed, so that invalid hex is reported right away.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MacaroonHex(Vec<u8>);
+
+impl MacaroonHex {
+  pub(crate) fn bytes(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+impl str::FromStr for MacaroonHex {
+  type Err = Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    hex::decode(input.trim())
+      .map(Self)
+      .context(error::MacaroonHexParse)
+  }
+}
+
+/// Macaroons are secrets, so `--print-config` only shows whether one was
+/// given.
+impl Serialize for MacaroonHex {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("<redacted>")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    assert_eq!(
+      "0201ab\n".parse::<MacaroonHex>().unwrap().bytes(),
+      &[0x02, 0x01, 0xab]
+    );
+    assert_matches!(
+      "0201xy".parse::<MacaroonHex>(),
+      Err(Error::MacaroonHexParse { .. })
+    );
+  }
+}
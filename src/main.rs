@@ -7,33 +7,72 @@ use {
 #[macro_use]
 mod test_utils;
 
+mod access_log;
+mod archive;
 mod arguments;
+mod backoff;
+mod bundle;
 mod common;
+mod compression;
+mod conditional_get;
 mod display_size;
+mod download_counts;
 mod environment;
 mod error;
 mod error_page;
+mod file_cache;
 mod file_stream;
 mod files;
+mod forwarded;
+mod highlighter;
 mod html;
 mod https_redirect_service;
 mod https_request_handler;
 mod input_path;
+mod invoice_status_cache;
+mod listing_template;
+mod lnd_status;
+mod log_format;
+mod macaroon_hex;
+mod memo_template;
+mod metrics;
+mod paid_splits;
+mod percent;
+mod price_feed;
+mod qr_code_cache;
+mod recent_invoices;
 mod redirect;
 mod request_handler;
+mod robots;
+mod route;
 mod server;
+mod settlement_webhook;
+mod settlements;
+mod shutdown;
+mod sitemap;
 mod static_assets;
 mod stderr;
+mod subcommand;
+mod table_of_contents;
 #[cfg(test)]
 mod tests;
+mod throttle;
+mod tls_version;
+mod torrent;
+mod trailing_slash_policy;
+mod url_signer;
 mod vfs;
+mod webdav;
 
 #[tokio::main]
 async fn main() {
-  env_logger::init();
-  if let Err(error) = run().await {
+  let mut log_format = LogFormat::Text;
+  if let Err(error) = run(&mut log_format).await {
     if let crate::error::Error::Clap { source, .. } = error {
       source.exit();
+    } else if log_format == LogFormat::Json {
+      eprintln!("{}", LogFormat::error_line(&error, SystemTime::now()));
+      std::process::exit(1);
     } else {
       let mut stderr = StandardStream::stderr(ColorChoice::Auto);
 
@@ -52,8 +91,18 @@ async fn main() {
   }
 }
 
-async fn run() -> Result<()> {
+async fn run(log_format: &mut LogFormat) -> Result<()> {
   let mut environment = Environment::production()?;
+  let arguments = environment.arguments()?;
+  *log_format = arguments.log_format;
+  log_format.init_logger();
+  if let Some(subcommand) = arguments.subcommand {
+    return subcommand.run(&mut environment);
+  }
+  if arguments.print_config {
+    print!("{}", arguments.to_yaml()?);
+    return Ok(());
+  }
   let server = Server::setup(&mut environment).await?;
-  server.run().await
+  server.run(Shutdown::signal()?).await
 }
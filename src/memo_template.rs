@@ -0,0 +1,107 @@
+use crate::common::*;
+
+/// Template for the memos of invoices for paid files, which wallets show to
+/// buyers. `{path}` is replaced with the path of the file, and `{price}`
+/// with the invoiced amount. Since memos identify the files that invoices
+/// pay for, `{path}` must occur exactly once, so that the path can be
+/// recovered from a memo and the invoice's amount.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub(crate) struct MemoTemplate {
+  template: String,
+}
+
+impl MemoTemplate {
+  const PATH: &'static str = "{path}";
+  const PRICE: &'static str = "{price}";
+
+  pub(crate) fn render(&self, path: &str, price: Millisatoshi) -> String {
+    let (prefix, suffix) = self.affixes(price);
+    format!("{}{}{}", prefix, path, suffix)
+  }
+
+  /// The path that `memo` was rendered with, if it was rendered from this
+  /// template with `price`.
+  pub(crate) fn path<'a>(&self, memo: &'a str, price: Millisatoshi) -> Option<&'a str> {
+    let (prefix, suffix) = self.affixes(price);
+    memo.strip_prefix(&prefix)?.strip_suffix(&suffix)
+  }
+
+  /// The text before and after `{path}`, with `{price}` replaced.
+  fn affixes(&self, price: Millisatoshi) -> (String, String) {
+    let mut parts = self.template.splitn(2, Self::PATH);
+    let price = price.to_string();
+    let prefix = parts
+      .next()
+      .unwrap_or_default()
+      .replace(Self::PRICE, &price);
+    let suffix = parts
+      .next()
+      .unwrap_or_default()
+      .replace(Self::PRICE, &price);
+    (prefix, suffix)
+  }
+}
+
+impl Default for MemoTemplate {
+  fn default() -> Self {
+    Self {
+      template: Self::PATH.to_owned(),
+    }
+  }
+}
+
+impl str::FromStr for MemoTemplate {
+  type Err = Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    if input.matches(Self::PATH).count() != 1 {
+      return Err(
+        error::MemoTemplateParse {
+          input: input.to_owned(),
+        }
+        .build(),
+      );
+    }
+    Ok(Self {
+      template: input.to_owned(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn default_memo_is_path() {
+    let template = MemoTemplate::default();
+    let price = Millisatoshi::new(1_000_000);
+    assert_eq!(template.render("foo/bar", price), "foo/bar");
+    assert_eq!(template.path("foo/bar", price), Some("foo/bar"));
+  }
+
+  #[test]
+  fn round_trip() {
+    let template = "Agora: {path} ({price})".parse::<MemoTemplate>().unwrap();
+    let price = Millisatoshi::new(1_000_000);
+    let memo = template.render("foo (1)", price);
+    assert_eq!(memo, "Agora: foo (1) (1,000 satoshis)");
+    assert_eq!(template.path(&memo, price), Some("foo (1)"));
+    assert_eq!(template.path(&memo, Millisatoshi::new(2_000_000)), None);
+    assert_eq!(template.path("foo", price), None);
+  }
+
+  #[test]
+  fn parse() {
+    for input in &["", "{price}", "{path} {path}"] {
+      assert_eq!(
+        input.parse::<MemoTemplate>().unwrap_err().to_string(),
+        format!(
+          "Invalid invoice memo template `{}`, expected `{{path}}` exactly once",
+          input
+        ),
+      );
+    }
+  }
+}
@@ -0,0 +1,194 @@
+use {
+  crate::common::*,
+  std::{
+    collections::BTreeMap,
+    sync::{
+      atomic::{self, AtomicU64},
+      Mutex,
+    },
+  },
+};
+
+/// Whether a file was served for free, or after its invoice was paid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Payment {
+  Free,
+  Paid,
+}
+
+impl Payment {
+  fn label(self) -> &'static str {
+    match self {
+      Self::Free => "free",
+      Self::Paid => "paid",
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+struct PaymentCounter {
+  free: AtomicU64,
+  paid: AtomicU64,
+}
+
+impl PaymentCounter {
+  fn add(&self, payment: Payment, value: u64) {
+    let counter = match payment {
+      Payment::Free => &self.free,
+      Payment::Paid => &self.paid,
+    };
+    counter.fetch_add(value, atomic::Ordering::Relaxed);
+  }
+
+  fn get(&self, payment: Payment) -> u64 {
+    match payment {
+      Payment::Free => &self.free,
+      Payment::Paid => &self.paid,
+    }
+    .load(atomic::Ordering::Relaxed)
+  }
+}
+
+/// Counters exposed at `/metrics` in the Prometheus text format, shared by
+/// the HTTP and HTTPS request handlers.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+  responses: Mutex<BTreeMap<u16, u64>>,
+  files_served: PaymentCounter,
+  bytes_served: PaymentCounter,
+  invoices_created: AtomicU64,
+  invoices_settled: AtomicU64,
+}
+
+impl Metrics {
+  pub(crate) fn response(&self, status: StatusCode) {
+    *self
+      .responses
+      .lock()
+      .unwrap()
+      .entry(status.as_u16())
+      .or_insert(0) += 1;
+  }
+
+  pub(crate) fn file_served(&self, payment: Payment) {
+    self.files_served.add(payment, 1);
+  }
+
+  pub(crate) fn bytes_served(&self, payment: Payment, bytes: usize) {
+    self.bytes_served.add(payment, bytes as u64);
+  }
+
+  pub(crate) fn invoice_created(&self) {
+    self
+      .invoices_created
+      .fetch_add(1, atomic::Ordering::Relaxed);
+  }
+
+  pub(crate) fn invoice_settled(&self) {
+    self
+      .invoices_settled
+      .fetch_add(1, atomic::Ordering::Relaxed);
+  }
+
+  fn render(&self) -> String {
+    let mut output = String::new();
+
+    let mut metric = |name: &str, help: &str, samples: Vec<(String, u64)>| {
+      output.push_str(&format!("# HELP {} {}\n", name, help));
+      output.push_str(&format!("# TYPE {} counter\n", name));
+      for (labels, value) in samples {
+        output.push_str(&format!("{}{} {}\n", name, labels, value));
+      }
+    };
+
+    let by_payment = |counter: &PaymentCounter| {
+      [Payment::Free, Payment::Paid]
+        .iter()
+        .map(|payment| {
+          (
+            format!("{{payment=\"{}\"}}", payment.label()),
+            counter.get(*payment),
+          )
+        })
+        .collect()
+    };
+
+    metric(
+      "agora_http_responses_total",
+      "HTTP responses, by status code.",
+      self
+        .responses
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(status, count)| (format!("{{status=\"{}\"}}", status), *count))
+        .collect(),
+    );
+    metric(
+      "agora_files_served_total",
+      "File downloads, by whether the file was free or paid.",
+      by_payment(&self.files_served),
+    );
+    metric(
+      "agora_bytes_served_total",
+      "Bytes of file downloads and archives sent, by whether they were free or paid.",
+      by_payment(&self.bytes_served),
+    );
+    metric(
+      "agora_invoices_created_total",
+      "Invoices created for paid files.",
+      vec![(
+        String::new(),
+        self.invoices_created.load(atomic::Ordering::Relaxed),
+      )],
+    );
+    metric(
+      "agora_invoices_settled_total",
+      "Settled invoices for paid files.",
+      vec![(
+        String::new(),
+        self.invoices_settled.load(atomic::Ordering::Relaxed),
+      )],
+    );
+
+    output
+  }
+
+  pub(crate) fn metrics_response(&self) -> Result<Response<Body>> {
+    Response::builder()
+      .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+      .body(Body::from(self.render()))
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::test_utils::assert_contains};
+
+  #[test]
+  fn render() {
+    let metrics = Metrics::default();
+    metrics.response(StatusCode::OK);
+    metrics.response(StatusCode::OK);
+    metrics.response(StatusCode::NOT_FOUND);
+    metrics.file_served(Payment::Paid);
+    metrics.bytes_served(Payment::Free, 5);
+    metrics.bytes_served(Payment::Free, 6);
+    metrics.invoice_created();
+
+    let output = metrics.render();
+    assert_contains(
+      &output,
+      "# HELP agora_http_responses_total HTTP responses, by status code.\n\
+       # TYPE agora_http_responses_total counter\n\
+       agora_http_responses_total{status=\"200\"} 2\n\
+       agora_http_responses_total{status=\"404\"} 1\n",
+    );
+    assert_contains(&output, "agora_files_served_total{payment=\"free\"} 0\n");
+    assert_contains(&output, "agora_files_served_total{payment=\"paid\"} 1\n");
+    assert_contains(&output, "agora_bytes_served_total{payment=\"free\"} 11\n");
+    assert_contains(&output, "agora_invoices_created_total 1\n");
+    assert_contains(&output, "agora_invoices_settled_total 0\n");
+  }
+}
@@ -0,0 +1,120 @@
+use {
+  crate::common::*,
+  std::{collections::BTreeSet, sync::Mutex},
+};
+
+/// Settled invoices whose revenue splits have been paid, and the settle
+/// index of the last settlement handled, from which LND's invoice
+/// subscription is resumed. Optionally persisted to a YAML file so splits
+/// are paid exactly once per invoice across restarts.
+#[derive(Clone, Debug)]
+pub(crate) struct PaidSplits {
+  path: Option<PathBuf>,
+  state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct State {
+  settle_index: u64,
+  invoices: BTreeSet<String>,
+}
+
+impl PaidSplits {
+  pub(crate) fn load(path: Option<PathBuf>) -> Result<Self> {
+    let state = match &path {
+      Some(path) => match fs::read_to_string(path) {
+        Ok(yaml) => serde_yaml::from_str(&yaml).context(error::PaidSplitsDeserialize { path })?,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => State::default(),
+        Err(source) => return Err(error::FilesystemIo { path }.into_error(source)),
+      },
+      None => State::default(),
+    };
+
+    Ok(Self {
+      path,
+      state: Arc::new(Mutex::new(state)),
+    })
+  }
+
+  pub(crate) fn settle_index(&self) -> u64 {
+    self.state.lock().unwrap().settle_index
+  }
+
+  /// Record that the splits of the invoice with hash `r_hash` are being
+  /// paid. Returns whether they weren't already.
+  pub(crate) fn record(&self, r_hash: &[u8]) -> Result<bool> {
+    let mut state = self.state.lock().unwrap();
+    let inserted = state.invoices.insert(hex::encode(r_hash));
+    if inserted {
+      self.save(&state)?;
+    }
+    Ok(inserted)
+  }
+
+  /// Record that settlements up to `settle_index` have been handled.
+  pub(crate) fn settled(&self, settle_index: u64) -> Result<()> {
+    let mut state = self.state.lock().unwrap();
+    if settle_index > state.settle_index {
+      state.settle_index = settle_index;
+      self.save(&state)?;
+    }
+    Ok(())
+  }
+
+  fn save(&self, state: &State) -> Result<()> {
+    if let Some(path) = &self.path {
+      let yaml = serde_yaml::to_string(state).context(error::PaidSplitsSerialize)?;
+      let tmp = path.with_extension("tmp");
+      fs::write(&tmp, yaml).context(error::FilesystemIo { path: &tmp })?;
+      fs::rename(&tmp, path).context(error::FilesystemIo { path })?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn invoices_are_recorded_once() {
+    let paid_splits = PaidSplits::load(None).unwrap();
+    assert!(paid_splits.record(&[0; 32]).unwrap());
+    assert!(!paid_splits.record(&[0; 32]).unwrap());
+    assert!(paid_splits.record(&[1; 32]).unwrap());
+  }
+
+  #[test]
+  fn settle_index_only_increases() {
+    let paid_splits = PaidSplits::load(None).unwrap();
+    assert_eq!(paid_splits.settle_index(), 0);
+    paid_splits.settled(5).unwrap();
+    paid_splits.settled(3).unwrap();
+    assert_eq!(paid_splits.settle_index(), 5);
+  }
+
+  #[test]
+  fn paid_splits_are_persisted() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("paid-splits.yaml");
+    let paid_splits = PaidSplits::load(Some(path.clone())).unwrap();
+    paid_splits.record(&[0; 32]).unwrap();
+    paid_splits.settled(7).unwrap();
+    let paid_splits = PaidSplits::load(Some(path)).unwrap();
+    assert_eq!(paid_splits.settle_index(), 7);
+    assert!(!paid_splits.record(&[0; 32]).unwrap());
+    assert!(paid_splits.record(&[1; 32]).unwrap());
+  }
+
+  #[test]
+  fn invalid_paid_splits_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let path = temp_dir.path().join("paid-splits.yaml");
+    fs::write(&path, "{{{").unwrap();
+    assert_matches!(
+      PaidSplits::load(Some(path.clone())),
+      Err(Error::PaidSplitsDeserialize { path: error_path, .. }) if error_path == path
+    );
+  }
+}
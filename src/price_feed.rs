@@ -0,0 +1,269 @@
+use {
+  crate::common::*,
+  hyper::{body::HttpBody, client::HttpConnector, Uri},
+  hyper_openssl::HttpsConnector,
+  std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+  },
+};
+
+/// Maximum time to wait for the price feed to respond, so that a hanging
+/// feed delays invoice creation by at most this long.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum size of price feed responses, so that a misbehaving feed can't
+/// make agora buffer an arbitrarily large body.
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// Millisatoshis in one bitcoin.
+const MILLISATOSHI_PER_BITCOIN: f64 = 100_000_000_000.0;
+
+/// Bitcoin exchange rates, fetched from `--price-feed-url`, which responds
+/// with a JSON object mapping currency codes to the price of one bitcoin,
+/// e.g. `{"USD": 65000.5}`. Rates are fetched when first needed, and again
+/// once they are older than `--exchange-rate-refresh-interval`. If the feed
+/// can't be reached, the last rates are used until they are older than
+/// `--exchange-rate-max-staleness`.
+#[derive(Clone, Debug)]
+pub(crate) struct PriceFeed {
+  client: hyper::Client<HttpsConnector<HttpConnector>>,
+  url: Uri,
+  refresh_interval: Duration,
+  max_staleness: Duration,
+  /// The last rates fetched, and when they were fetched. Held across
+  /// fetches, so concurrent requests wait for one fetch instead of each
+  /// making their own.
+  rates: Arc<tokio::sync::Mutex<Option<Rates>>>,
+}
+
+#[derive(Debug)]
+struct Rates {
+  fetched: Instant,
+  rates: BTreeMap<String, f64>,
+}
+
+impl PriceFeed {
+  pub(crate) fn new(url: Uri, refresh_interval: Duration, max_staleness: Duration) -> Result<Self> {
+    Ok(Self {
+      client: hyper::Client::builder().build(HttpsConnector::new().context(error::PriceFeedTls)?),
+      url,
+      refresh_interval,
+      max_staleness,
+      rates: Arc::new(tokio::sync::Mutex::new(None)),
+    })
+  }
+
+  /// Convert `amount` of `currency` to millisatoshis, at the current rate.
+  /// Returns `None` if the converted amount doesn't fit into a
+  /// `Millisatoshi`.
+  pub(crate) async fn convert(&self, amount: f64, currency: &str) -> Result<Option<Millisatoshi>> {
+    Ok(convert(amount, self.rate(currency).await?))
+  }
+
+  async fn rate(&self, currency: &str) -> Result<f64> {
+    let mut rates = self.rates.lock().await;
+    let now = Instant::now();
+    let age = rates
+      .as_ref()
+      .map(|rates| now.saturating_duration_since(rates.fetched));
+    if age.map_or(true, |age| age >= self.refresh_interval) {
+      match self.fetch().await {
+        Ok(fetched) => {
+          *rates = Some(Rates {
+            fetched: now,
+            rates: fetched,
+          })
+        }
+        Err(message) => match age {
+          Some(age) if age < self.max_staleness => log::warn!(
+            "Failed to refresh exchange rates from `{}`, using rates from {} ago: {}",
+            self.url,
+            humantime::format_duration(Duration::from_secs(age.as_secs())),
+            message
+          ),
+          _ => {
+            return Err(
+              error::PriceFeedUnavailable {
+                url: self.url.clone(),
+                message,
+              }
+              .build(),
+            )
+          }
+        },
+      }
+    }
+    rates
+      .as_ref()
+      .and_then(|rates| rates.rates.get(currency))
+      .copied()
+      .filter(|rate| rate.is_finite() && *rate > 0.0)
+      .ok_or_else(|| {
+        error::PriceFeedCurrency {
+          currency: currency.to_owned(),
+          url: self.url.clone(),
+        }
+        .build()
+      })
+  }
+
+  async fn fetch(&self) -> Result<BTreeMap<String, f64>, String> {
+    tokio::time::timeout(FETCH_TIMEOUT, async {
+      let response = self
+        .client
+        .get(self.url.clone())
+        .await
+        .map_err(|error| error.to_string())?;
+      if !response.status().is_success() {
+        return Err(format!("Price feed responded with {}", response.status()));
+      }
+      let mut body = response.into_body();
+      let mut bytes = Vec::new();
+      while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|error| error.to_string())?;
+        if bytes.len() + chunk.len() > MAX_RESPONSE_SIZE {
+          return Err(format!(
+            "Price feed response is larger than {} bytes",
+            MAX_RESPONSE_SIZE
+          ));
+        }
+        bytes.extend_from_slice(&chunk);
+      }
+      serde_json::from_slice(&bytes)
+        .map_err(|error| format!("Invalid price feed response: {}", error))
+    })
+    .await
+    .map_err(|_elapsed| {
+      format!(
+        "Price feed did not respond within {}",
+        humantime::format_duration(FETCH_TIMEOUT)
+      )
+    })?
+  }
+}
+
+/// Convert `amount` at `rate`, the price of one bitcoin, to millisatoshis,
+/// rounded to the nearest millisatoshi.
+fn convert(amount: f64, rate: f64) -> Option<Millisatoshi> {
+  let millisatoshi = (amount / rate * MILLISATOSHI_PER_BITCOIN).round();
+  if millisatoshi.is_finite() && millisatoshi >= 0.0 && millisatoshi < u64::MAX as f64 {
+    Some(Millisatoshi::new(millisatoshi as u64))
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {
+    super::*,
+    std::{
+      io::{BufRead, BufReader, Write},
+      net::TcpListener,
+    },
+  };
+
+  /// Serve one response with `body` to each of `count` requests, returning
+  /// the URL to request.
+  fn serve(body: &'static str, count: usize) -> Uri {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/rates", listener.local_addr().unwrap())
+      .parse()
+      .unwrap();
+    std::thread::spawn(move || {
+      for stream in listener.incoming().take(count) {
+        let mut reader = BufReader::new(stream.unwrap());
+        loop {
+          let mut line = String::new();
+          reader.read_line(&mut line).unwrap();
+          if line == "\r\n" {
+            break;
+          }
+        }
+        write!(
+          reader.get_mut(),
+          "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+          body.len(),
+          body
+        )
+        .unwrap();
+      }
+    });
+    url
+  }
+
+  fn price_feed(url: Uri, refresh_interval: u64) -> PriceFeed {
+    PriceFeed::new(
+      url,
+      Duration::from_secs(refresh_interval),
+      Duration::from_secs(3600),
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn convert_rounds_to_nearest_millisatoshi() {
+    assert_eq!(convert(1.0, 50_000.0), Some(Millisatoshi::new(2_000_000)));
+    assert_eq!(convert(1.99, 3.0), Some(Millisatoshi::new(66_333_333_333)));
+    assert_eq!(convert(1.0, f64::MIN_POSITIVE), None);
+  }
+
+  #[tokio::test]
+  async fn rates_are_cached() {
+    let price_feed = price_feed(serve(r#"{"USD": 50000, "EUR": 40000}"#, 1), 3600);
+    assert_eq!(
+      price_feed.convert(1.0, "USD").await.unwrap(),
+      Some(Millisatoshi::new(2_000_000))
+    );
+    assert_eq!(
+      price_feed.convert(1.0, "EUR").await.unwrap(),
+      Some(Millisatoshi::new(2_500_000))
+    );
+    assert_eq!(
+      price_feed
+        .convert(1.0, "JPY")
+        .await
+        .unwrap_err()
+        .to_string(),
+      format!(
+        "Price feed at `{}` has no exchange rate for `JPY`",
+        price_feed.url
+      )
+    );
+  }
+
+  #[tokio::test]
+  async fn stale_rates_are_used_when_feed_is_unreachable() {
+    let price_feed = price_feed(serve(r#"{"USD": 50000}"#, 1), 0);
+    assert_eq!(
+      price_feed.convert(1.0, "USD").await.unwrap(),
+      Some(Millisatoshi::new(2_000_000))
+    );
+    assert_eq!(
+      price_feed.convert(1.0, "USD").await.unwrap(),
+      Some(Millisatoshi::new(2_000_000))
+    );
+  }
+
+  #[tokio::test]
+  async fn unreachable_feed_without_rates_is_an_error() {
+    let url = serve("", 0);
+    let price_feed = price_feed(url.clone(), 3600);
+    assert_matches!(
+      price_feed.convert(1.0, "USD").await,
+      Err(Error::PriceFeedUnavailable { url: error_url, .. }) if error_url == url
+    );
+  }
+
+  #[tokio::test]
+  async fn large_responses_are_rejected() {
+    let body = Box::leak(format!("\"{}\"", "x".repeat(MAX_RESPONSE_SIZE)).into_boxed_str());
+    let price_feed = price_feed(serve(body, 1), 3600);
+    assert_matches!(
+      price_feed.convert(1.0, "USD").await,
+      Err(Error::PriceFeedUnavailable { message, .. })
+        if message == format!("Price feed response is larger than {} bytes", MAX_RESPONSE_SIZE)
+    );
+  }
+}
@@ -0,0 +1,77 @@
+use {crate::common::*, std::collections::VecDeque};
+
+/// Rendered invoice QR code SVGs, keyed by invoice hash. Payment requests
+/// never change, so cached QR codes stay valid until their invoice settles,
+/// at which point they are no longer needed and are removed. Holds at most
+/// `capacity` QR codes, evicting the least recently used.
+#[derive(Debug)]
+pub(crate) struct QrCodeCache {
+  capacity: usize,
+  /// Most recently used last
+  entries: VecDeque<([u8; 32], Arc<str>)>,
+}
+
+impl QrCodeCache {
+  pub(crate) const CAPACITY: usize = 256;
+
+  pub(crate) fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      entries: VecDeque::new(),
+    }
+  }
+
+  pub(crate) fn get(&mut self, r_hash: [u8; 32]) -> Option<Arc<str>> {
+    let index = self.position(r_hash)?;
+    let entry = self.entries.remove(index)?;
+    let svg = entry.1.clone();
+    self.entries.push_back(entry);
+    Some(svg)
+  }
+
+  pub(crate) fn insert(&mut self, r_hash: [u8; 32], svg: Arc<str>) {
+    self.remove(r_hash);
+    if self.entries.len() >= self.capacity {
+      self.entries.pop_front();
+    }
+    self.entries.push_back((r_hash, svg));
+  }
+
+  pub(crate) fn remove(&mut self, r_hash: [u8; 32]) {
+    if let Some(index) = self.position(r_hash) {
+      self.entries.remove(index);
+    }
+  }
+
+  fn position(&self, r_hash: [u8; 32]) -> Option<usize> {
+    self
+      .entries
+      .iter()
+      .position(|(entry_r_hash, _svg)| *entry_r_hash == r_hash)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn evicts_least_recently_used() {
+    let mut cache = QrCodeCache::new(2);
+    cache.insert([0; 32], "a".into());
+    cache.insert([1; 32], "b".into());
+    assert_eq!(cache.get([0; 32]).as_deref(), Some("a"));
+    cache.insert([2; 32], "c".into());
+    assert_eq!(cache.get([1; 32]), None);
+    assert_eq!(cache.get([0; 32]).as_deref(), Some("a"));
+    assert_eq!(cache.get([2; 32]).as_deref(), Some("c"));
+  }
+
+  #[test]
+  fn remove() {
+    let mut cache = QrCodeCache::new(2);
+    cache.insert([0; 32], "a".into());
+    cache.remove([0; 32]);
+    assert_eq!(cache.get([0; 32]), None);
+  }
+}
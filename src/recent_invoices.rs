@@ -0,0 +1,127 @@
+use {crate::common::*, std::collections::VecDeque};
+
+/// The most recent invoice created for each paid file and client, so that
+/// reloading a paid file redirects to the same invoice, instead of creating
+/// a new one. Clients are identified by a random ID stored in a cookie.
+/// Holds at most `capacity` invoices, evicting the least recently created.
+#[derive(Debug)]
+pub(crate) struct RecentInvoices {
+  capacity: usize,
+  /// Most recently created last
+  entries: VecDeque<(String, [u8; 16], [u8; 32])>,
+}
+
+impl RecentInvoices {
+  pub(crate) const CAPACITY: usize = 1024;
+
+  /// Name of the cookie that holds a client's hex-encoded ID.
+  pub(crate) const COOKIE: &'static str = "agora_client";
+
+  pub(crate) fn new(capacity: usize) -> Self {
+    Self {
+      capacity,
+      entries: VecDeque::new(),
+    }
+  }
+
+  pub(crate) fn get(&self, file_path: &str, client_id: [u8; 16]) -> Option<[u8; 32]> {
+    self
+      .position(file_path, client_id)
+      .map(|index| self.entries[index].2)
+  }
+
+  pub(crate) fn insert(&mut self, file_path: &str, client_id: [u8; 16], r_hash: [u8; 32]) {
+    self.remove(file_path, client_id);
+    if self.entries.len() >= self.capacity {
+      self.entries.pop_front();
+    }
+    self
+      .entries
+      .push_back((file_path.to_owned(), client_id, r_hash));
+  }
+
+  pub(crate) fn remove(&mut self, file_path: &str, client_id: [u8; 16]) {
+    if let Some(index) = self.position(file_path, client_id) {
+      self.entries.remove(index);
+    }
+  }
+
+  fn position(&self, file_path: &str, client_id: [u8; 16]) -> Option<usize> {
+    self
+      .entries
+      .iter()
+      .position(|(entry_file_path, entry_client_id, _r_hash)| {
+        entry_file_path == file_path && *entry_client_id == client_id
+      })
+  }
+
+  /// The client ID in the `agora_client` cookie of `request`, if any.
+  pub(crate) fn client_id(request: &Request<Body>) -> Option<[u8; 16]> {
+    request
+      .headers()
+      .get_all(header::COOKIE)
+      .iter()
+      .filter_map(|value| value.to_str().ok())
+      .flat_map(|value| value.split(';'))
+      .filter_map(|cookie| {
+        let mut parts = cookie.trim().splitn(2, '=');
+        match (parts.next(), parts.next()) {
+          (Some(Self::COOKIE), Some(value)) => {
+            let mut client_id = [0; 16];
+            hex::decode_to_slice(value, &mut client_id).ok()?;
+            Some(client_id)
+          }
+          _ => None,
+        }
+      })
+      .next()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn evicts_least_recently_created() {
+    let mut invoices = RecentInvoices::new(2);
+    invoices.insert("foo", [0; 16], [0; 32]);
+    invoices.insert("foo", [1; 16], [1; 32]);
+    invoices.insert("bar", [0; 16], [2; 32]);
+    assert_eq!(invoices.get("foo", [0; 16]), None);
+    assert_eq!(invoices.get("foo", [1; 16]), Some([1; 32]));
+    assert_eq!(invoices.get("bar", [0; 16]), Some([2; 32]));
+  }
+
+  #[test]
+  fn insert_replaces_previous_invoice() {
+    let mut invoices = RecentInvoices::new(2);
+    invoices.insert("foo", [0; 16], [0; 32]);
+    invoices.insert("foo", [0; 16], [1; 32]);
+    assert_eq!(invoices.get("foo", [0; 16]), Some([1; 32]));
+    invoices.remove("foo", [0; 16]);
+    assert_eq!(invoices.get("foo", [0; 16]), None);
+  }
+
+  #[test]
+  fn client_id() {
+    let request = |cookie: &str| {
+      Request::builder()
+        .header(header::COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap()
+    };
+    assert_eq!(
+      RecentInvoices::client_id(&request(&format!(
+        "foo=bar; agora_client={}",
+        "ab".repeat(16)
+      ))),
+      Some([0xab; 16])
+    );
+    assert_eq!(
+      RecentInvoices::client_id(&request("agora_client=xyz")),
+      None
+    );
+    assert_eq!(RecentInvoices::client_id(&request("foo=bar")), None);
+  }
+}
@@ -1,8 +1,16 @@
 use crate::common::*;
 
 pub(crate) fn redirect(location: String) -> Result<Response<Body>> {
+  redirect_with_status(location, StatusCode::FOUND)
+}
+
+pub(crate) fn permanent_redirect(location: String) -> Result<Response<Body>> {
+  redirect_with_status(location, StatusCode::MOVED_PERMANENTLY)
+}
+
+fn redirect_with_status(location: String, status: StatusCode) -> Result<Response<Body>> {
   Response::builder()
-    .status(StatusCode::FOUND)
+    .status(status)
     .header(header::LOCATION, location)
     .body(Body::empty())
     .map_err(|error| Error::internal(format!("Failed to construct redirect response: {}", error)))
@@ -1,21 +1,182 @@
-use crate::{common::*, error_page, files::Files, static_assets::StaticAssets};
+use {
+  crate::{
+    access_log::{AccessLog, AccessLogRequest},
+    common::*,
+    compression,
+    error_page::{self, ErrorTemplate},
+    files::Files,
+    forwarded,
+    robots::Robots,
+    static_assets::StaticAssets,
+    webdav,
+  },
+  hyper::{body::HttpBody, server::conn::AddrStream, Method},
+  log::Level,
+  serde_json::Map,
+  std::time::Duration,
+};
 
 #[derive(Clone)]
 pub(crate) struct RequestHandler {
   pub(crate) stderr: Stderr,
+  pub(crate) log_format: LogFormat,
   pub(crate) files: Files,
+  pub(crate) remote_addr: Option<SocketAddr>,
+  pub(crate) serve_single: Option<PathBuf>,
+  pub(crate) disabled_routes: Vec<Route>,
+  pub(crate) disable_hidden_file_404_leak: bool,
+  pub(crate) max_request_body: u64,
+  pub(crate) image_error_placeholder: bool,
+  pub(crate) error_template: Option<ErrorTemplate>,
+  pub(crate) access_log: Option<AccessLog>,
+  pub(crate) robots: Robots,
+  pub(crate) lnd_status: LndStatus,
+  pub(crate) metrics: Arc<Metrics>,
+  pub(crate) expose_metrics: bool,
+  pub(crate) sitemap: bool,
+  pub(crate) base_path: String,
+  pub(crate) files_path: String,
+  pub(crate) trust_proxy: bool,
+  pub(crate) canonical_host: Option<Authority>,
+  pub(crate) https: bool,
 }
 
 impl RequestHandler {
+  /// Minimum response time of `404 Not Found` responses with
+  /// `--disable-hidden-file-404-leak`.
+  const NOT_FOUND_RESPONSE_TIME: Duration = Duration::from_millis(100);
+
   pub(crate) fn new(
     environment: &Environment,
-    base_directory: &Path,
+    arguments: &Arguments,
     lnd_client: Option<agora_lnd_client::Client>,
-  ) -> Self {
-    Self {
+    lnd_status: LndStatus,
+    download_counts: DownloadCounts,
+    metrics: Arc<Metrics>,
+  ) -> Result<Self> {
+    Ok(Self {
       stderr: environment.stderr.clone(),
-      files: Files::new(InputPath::new(environment, base_directory), lnd_client),
+      log_format: arguments.log_format,
+      files: Files::new(
+        InputPath::new(environment, arguments.directory()),
+        arguments
+          .config
+          .as_ref()
+          .map(|config| InputPath::new(environment, config)),
+        arguments,
+        lnd_client,
+        download_counts,
+        metrics.clone(),
+        ListingTemplate::load(environment, arguments)?,
+      )?,
+      remote_addr: None,
+      serve_single: arguments
+        .serve_single
+        .as_ref()
+        .map(|path| environment.working_directory.join(path)),
+      disabled_routes: arguments.disable_route.clone(),
+      disable_hidden_file_404_leak: arguments.disable_hidden_file_404_leak,
+      max_request_body: arguments.max_request_body,
+      image_error_placeholder: arguments.image_error_placeholder,
+      error_template: ErrorTemplate::load(environment, arguments)?,
+      access_log: AccessLog::open(environment, arguments)?,
+      robots: Robots::load(environment, arguments)?,
+      lnd_status,
+      metrics,
+      expose_metrics: arguments.metrics,
+      sitemap: arguments.sitemap,
+      base_path: arguments.base_path(),
+      files_path: arguments.files_path(),
+      trust_proxy: arguments.trust_proxy,
+      canonical_host: arguments.canonical_host.clone(),
+      https: arguments.https_port.is_some(),
+    })
+  }
+
+  /// IP address of the client that made `request`, taken from
+  /// `X-Forwarded-For` with `--trust-proxy`, since connections are then
+  /// made by the proxy.
+  fn remote_ip(&self, request: &Request<Body>) -> Option<IpAddr> {
+    if self.trust_proxy {
+      if let Some(client_ip) = forwarded::client_ip(request.headers()) {
+        return Some(client_ip);
+      }
+    }
+    self.remote_addr.map(|remote_addr| remote_addr.ip())
+  }
+
+  /// Redirect to `--canonical-host` if `request` was made to another host,
+  /// taken from `X-Forwarded-Host` with `--trust-proxy`. Health checks are
+  /// never redirected.
+  fn canonical_host_redirect(&self, request: &Request<Body>) -> Option<Result<Response<Body>>> {
+    let canonical_host = self.canonical_host.as_ref()?;
+
+    if request.uri().path() == format!("{}/health", self.base_path) {
+      return None;
     }
+
+    let host = self.host(request)?;
+
+    if host.eq_ignore_ascii_case(canonical_host.as_str()) {
+      return None;
+    }
+
+    Some(permanent_redirect(format!(
+      "{}://{}{}",
+      self.scheme(request),
+      canonical_host,
+      request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str())
+    )))
+  }
+
+  /// Host that `request` was made to, taken from `X-Forwarded-Host` with
+  /// `--trust-proxy`.
+  fn host<'a>(&self, request: &'a Request<Body>) -> Option<&'a str> {
+    let forwarded_host = if self.trust_proxy {
+      forwarded::host(request.headers())
+    } else {
+      None
+    };
+
+    forwarded_host
+      .or_else(|| {
+        request
+          .headers()
+          .get(header::HOST)
+          .and_then(|host| host.to_str().ok())
+      })
+      .or_else(|| request.uri().authority().map(Authority::as_str))
+  }
+
+  /// Scheme that `request` was made with, taken from `X-Forwarded-Proto`
+  /// with `--trust-proxy`.
+  fn scheme(&self, request: &Request<Body>) -> String {
+    let forwarded_proto = if self.trust_proxy {
+      forwarded::proto(request.headers())
+    } else {
+      None
+    };
+
+    forwarded_proto.unwrap_or_else(|| if self.https { "https" } else { "http" }.to_owned())
+  }
+
+  /// Origin of absolute URLs in responses to `request`, like
+  /// `https://example.com`, on `--canonical-host` if it's given.
+  fn origin(&self, request: &Request<Body>) -> Result<String> {
+    let host = match &self.canonical_host {
+      Some(canonical_host) => canonical_host.as_str(),
+      None => self.host(request).ok_or_else(|| {
+        error::Custom {
+          message: "Missing HOST header",
+          status_code: StatusCode::BAD_REQUEST,
+        }
+        .build()
+      })?,
+    };
+    Ok(format!("{}://{}", self.scheme(request), host))
   }
 
   async fn response(mut self, request: Request<Body>) -> Result<Response<Body>> {
@@ -24,11 +185,13 @@ impl RequestHandler {
       .context(error::RequestHandlerPanic)?
   }
 
+  /// Responses are not stored by clients, unless they set their own
+  /// `Cache-Control` header, which only static assets and free files do.
   fn add_global_headers(mut response: Response<Body>) -> Response<Body> {
-    response.headers_mut().insert(
-      header::CACHE_CONTROL,
-      HeaderValue::from_static("no-store, max-age=0"),
-    );
+    response
+      .headers_mut()
+      .entry(header::CACHE_CONTROL)
+      .or_insert_with(|| HeaderValue::from_static("no-store, max-age=0"));
     response
   }
 
@@ -53,13 +216,80 @@ impl RequestHandler {
     result
   }
 
+  fn check_route(&self, route: Route, request: &Request<Body>) -> Result<()> {
+    if self.disabled_routes.contains(&route) {
+      Err(Error::RouteNotFound {
+        uri_path: request.uri().path().to_owned(),
+      })
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Request bodies are never read, so they're checked up front, using
+  /// their `Content-Length`. Bodies of unknown length, like chunked ones,
+  /// could be arbitrarily large, and are rejected.
+  fn check_request_body(&self, request: &Request<Body>) -> Result<()> {
+    match HttpBody::size_hint(request.body()).exact() {
+      Some(length) if length > self.max_request_body => Err(
+        error::RequestBodyTooLarge {
+          length,
+          max_request_body: self.max_request_body,
+        }
+        .build(),
+      ),
+      Some(_) => Ok(()),
+      None => Err(error::RequestBodyLengthRequired.build()),
+    }
+  }
+
+  async fn serve_single(path: &Path) -> Result<Response<Body>> {
+    let contents = tokio::fs::read(path)
+      .await
+      .context(error::FilesystemIo { path })?;
+    let mut builder = Response::builder();
+    if let Some(guess) = mime_guess::from_path(path).first() {
+      builder = builder.header(header::CONTENT_TYPE, guess.essence_str());
+    }
+    builder
+      .body(contents.into())
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+
   async fn dispatch(&mut self, request: Request<Body>) -> Result<Response<Body>> {
-    let path = percent_encoding::percent_decode_str(request.uri().path())
-      .decode_utf8()
-      .context(error::InvalidUriPath {
-        uri_path: request.uri().path(),
-      })?;
-    let components = Self::split_path_inclusive(&path);
+    self.check_request_body(&request)?;
+
+    if let Some(response) = self.canonical_host_redirect(&request) {
+      return response;
+    }
+
+    let path = percent::decode_path(request.uri().path()).context(error::InvalidUriPath {
+      uri_path: request.uri().path(),
+    })?;
+
+    let path = if self.base_path.is_empty() {
+      &path
+    } else {
+      match path.strip_prefix(self.base_path.as_str()) {
+        Some("") => return redirect(String::from(request.uri().path()) + "/"),
+        Some(rest) if rest.starts_with('/') => rest,
+        _ => {
+          return Err(Error::RouteNotFound {
+            uri_path: request.uri().path().to_owned(),
+          })
+        }
+      }
+    };
+    let components = Self::split_path_inclusive(path);
+
+    if let Some(serve_single) = &self.serve_single {
+      if !matches!(
+        components.as_slice(),
+        ["/", "static/", ..] | ["/", "health"] | ["/", "metrics"] | ["/", "robots.txt"]
+      ) {
+        return Self::serve_single(serve_single).await;
+      }
+    }
 
     let invoice_parameter = request.uri().query().and_then(|query| {
       form_urlencoded::parse(query.as_bytes())
@@ -69,19 +299,33 @@ impl RequestHandler {
     });
 
     match components.as_slice() {
-      ["/"] => redirect(String::from(request.uri().path()) + "files/"),
+      ["/"] if self.files_path != "/" => {
+        redirect(String::from(request.uri().path()) + &self.files_path[1..])
+      }
       ["/", asset] if ["apple-touch-icon.png", "favicon.ico"].contains(asset) => {
-        StaticAssets::serve(&[asset])
+        self.check_route(Route::Static, &request)?;
+        match self.files.serve_site_icon(&request, asset).await? {
+          Some(response) => Ok(response),
+          None => StaticAssets::serve_conditional(request.headers(), &[asset]).await,
+        }
+      }
+      ["/", "static/", tail @ ..] => {
+        self.check_route(Route::Static, &request)?;
+        StaticAssets::serve_conditional(request.headers(), tail).await
+      }
+      ["/", "health"] => {
+        self.check_route(Route::Health, &request)?;
+        self.lnd_status.health_response()
       }
-      ["/", "static/", tail @ ..] => StaticAssets::serve(tail),
-      ["/", "files"] => redirect(String::from(request.uri().path()) + "/"),
-      ["/", "files/", tail @ ..] if invoice_parameter.is_some() => {
-        let invoice_id = invoice_parameter.expect("invoice_parameter is some");
-        let invoice_id = Self::decode_invoice_id(&invoice_id)?;
-        self.files.serve_invoice(&request, tail, invoice_id).await
+      ["/", "metrics"] if self.expose_metrics => self.metrics.metrics_response(),
+      ["/", "robots.txt"] => self.robots.response(),
+      ["/", "sitemap.xml"] if self.sitemap => {
+        self.check_route(Route::Files, &request)?;
+        let origin = self.origin(&request)?;
+        self.files.serve_sitemap(&origin)
       }
-      ["/", "files/", tail @ ..] => self.files.serve(&request, tail).await,
       ["/", "invoice/", file_name] if file_name.ends_with(".svg") => {
+        self.check_route(Route::InvoiceQr, &request)?;
         let invoice_id = Self::decode_invoice_id(
           file_name
             .strip_suffix(".svg")
@@ -89,9 +333,95 @@ impl RequestHandler {
         )?;
         self.files.serve_invoice_qr_code(&request, invoice_id).await
       }
-      _ => Err(Error::RouteNotFound {
-        uri_path: request.uri().path().to_owned(),
-      }),
+      ["/", "invoice/", invoice_id, "status"] if invoice_id.ends_with('/') => {
+        self.check_route(Route::InvoiceStatus, &request)?;
+        let invoice_id = Self::decode_invoice_id(
+          invoice_id
+            .strip_suffix('/')
+            .expect("invoice_id ends with `/`"),
+        )?;
+        self.files.serve_invoice_status(&request, invoice_id).await
+      }
+      ["/", ".well-known/", "lnurlp/", username] => {
+        self.check_route(Route::Lnurlp, &request)?;
+        self.files.serve_lightning_address(&request, username).await
+      }
+      ["/", "lnurlp/", tail @ ..] => {
+        self.check_route(Route::Lnurlp, &request)?;
+        self.files.serve_lnurlp(&request, tail).await
+      }
+      _ => match path.strip_prefix(self.files_path.as_str()) {
+        Some(tail) => {
+          let tail = Self::split_path_inclusive(tail);
+          self.serve_files(&request, &tail, invoice_parameter).await
+        }
+        None if path == self.files_path.trim_end_matches('/') => {
+          redirect(String::from(request.uri().path()) + "/")
+        }
+        None => Err(Error::RouteNotFound {
+          uri_path: request.uri().path().to_owned(),
+        }),
+      },
+    }
+  }
+
+  /// Serve requests under `--files-prefix`, for the file at `tail`.
+  async fn serve_files(
+    &mut self,
+    request: &Request<Body>,
+    tail: &[&str],
+    invoice_parameter: Option<String>,
+  ) -> Result<Response<Body>> {
+    if request.method() == Method::OPTIONS {
+      self.check_route(Route::Files, request)?;
+      Ok(webdav::options_response())
+    } else if request.method().as_str() == "PROPFIND" {
+      self.check_route(Route::Files, request)?;
+      self.files.serve_propfind(request, tail).await
+    } else if let Some(invoice_id) = invoice_parameter {
+      self.check_route(Route::Invoice, request)?;
+      let invoice_id = Self::decode_invoice_id(&invoice_id)?;
+      self.files.serve_invoice(request, tail, invoice_id).await
+    } else {
+      self.check_route(Route::Files, request)?;
+      self.files.serve(request, tail).await
+    }
+  }
+}
+
+impl RequestHandler {
+  /// Log a request at debug level. With `--log-format json`, its method,
+  /// path, and, once it has been handled, status, are written as fields.
+  fn log_request(
+    log_format: LogFormat,
+    method: &Method,
+    path: &str,
+    status: Option<StatusCode>,
+    message: fmt::Arguments,
+  ) {
+    if !log::log_enabled!(Level::Debug) {
+      return;
+    }
+    match log_format {
+      LogFormat::Text => log::debug!("{}", message),
+      LogFormat::Json => {
+        let mut fields = Map::new();
+        fields.insert("method".into(), method.as_str().into());
+        fields.insert("path".into(), path.into());
+        if let Some(status) = status {
+          fields.insert("status".into(), status.as_u16().into());
+        }
+        eprintln!(
+          "{}",
+          LogFormat::json_line(
+            Level::Debug,
+            module_path!(),
+            &message.to_string(),
+            fields,
+            SystemTime::now(),
+          )
+        );
+      }
     }
   }
 }
@@ -106,20 +436,88 @@ impl Service<Request<Body>> for RequestHandler {
   }
 
   fn call(&mut self, request: Request<Body>) -> Self::Future {
-    log::debug!("Incoming: {:?}", request);
-    let stderr = self.stderr.clone();
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let log_format = self.log_format;
+    Self::log_request(
+      log_format,
+      &method,
+      &path,
+      None,
+      format_args!("Incoming: {:?}", request),
+    );
+    let mut stderr = self.stderr.clone();
+    let remote_ip = self.remote_ip(&request);
+    let image_error_placeholder = self.image_error_placeholder;
+    let error_template = self.error_template.clone();
+    let request_headers = request.headers().clone();
+    let base_path = self.base_path.clone();
+    let metrics = self.metrics.clone();
+    let access_log = self
+      .access_log
+      .clone()
+      .map(|access_log| (access_log, AccessLogRequest::new(&request)));
+    let not_found_deadline = if self.disable_hidden_file_404_leak {
+      Some(tokio::time::Instant::now() + Self::NOT_FOUND_RESPONSE_TIME)
+    } else {
+      None
+    };
     self
       .clone()
       .response(request)
-      .map(move |result| {
-        let response = error_page::map_error(stderr, result);
-        log::debug!("Outgoing: {:?}", response);
+      .then(move |result| async move {
+        if let Err(error) = &result {
+          error_page::log_error(&mut stderr, log_format, remote_ip, error);
+        }
+        let response = compression::compress(
+          &request_headers,
+          error_page::map_error(
+            &request_headers,
+            image_error_placeholder,
+            error_template.as_ref(),
+            &base_path,
+            result,
+          ),
+        );
+        if let Some(deadline) = not_found_deadline {
+          if response.status() == StatusCode::NOT_FOUND {
+            tokio::time::sleep_until(deadline).await;
+          }
+        }
+        metrics.response(response.status());
+        if let Some((access_log, request)) = access_log {
+          access_log.log(remote_ip, &request, &response);
+        }
+        Self::log_request(
+          log_format,
+          &method,
+          &path,
+          Some(response.status()),
+          format_args!("Outgoing: {:?}", response),
+        );
         Ok(response)
       })
       .boxed()
   }
 }
 
+impl Service<&AddrStream> for RequestHandler {
+  type Response = RequestHandler;
+  type Error = Infallible;
+  type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+  fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Ok(()).into()
+  }
+
+  fn call(&mut self, stream: &AddrStream) -> Self::Future {
+    future::ready(Ok(Self {
+      remote_addr: Some(stream.remote_addr()),
+      ..self.clone()
+    }))
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
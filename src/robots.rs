@@ -0,0 +1,63 @@
+use crate::common::*;
+
+/// Contents of `/robots.txt`, either from `--robots`, or else generated.
+/// Paid files can only be sold with LND, so when it's configured, crawlers
+/// are kept out of `/files/`, or `--files-prefix`, where they would otherwise request invoices
+/// and index invoice pages.
+#[derive(Clone, Debug)]
+pub(crate) struct Robots {
+  text: Arc<str>,
+}
+
+impl Robots {
+  pub(crate) fn load(environment: &Environment, arguments: &Arguments) -> Result<Self> {
+    let text = match &arguments.robots {
+      Some(path) => {
+        let path = environment.working_directory.join(path);
+        fs::read_to_string(&path).context(error::FilesystemIo { path: &path })?
+      }
+      None => Self::default_text(
+        &arguments.base_path(),
+        &arguments.files_path(),
+        arguments.lnd_rpc_authority.is_some(),
+      ),
+    };
+    Ok(Self { text: text.into() })
+  }
+
+  fn default_text(base_path: &str, files_path: &str, paid_files: bool) -> String {
+    if paid_files {
+      format!("User-agent: *\nDisallow: {}{}\n", base_path, files_path)
+    } else {
+      "User-agent: *\nDisallow:\n".to_owned()
+    }
+  }
+
+  pub(crate) fn response(&self) -> Result<Response<Body>> {
+    Response::builder()
+      .header(header::CONTENT_TYPE, "text/plain")
+      .body(Body::from(self.text.to_string()))
+      .map_err(|error| Error::internal(format!("Failed to construct response: {}", error)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn default_text() {
+    assert_eq!(
+      Robots::default_text("", "/files/", false),
+      "User-agent: *\nDisallow:\n"
+    );
+    assert_eq!(
+      Robots::default_text("/share", "/files/", true),
+      "User-agent: *\nDisallow: /share/files/\n"
+    );
+    assert_eq!(
+      Robots::default_text("", "/d/", true),
+      "User-agent: *\nDisallow: /d/\n"
+    );
+  }
+}
@@ -0,0 +1,66 @@
+use crate::common::*;
+
+/// Routes that can be disabled with `--disable-route`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Route {
+  /// File listings and downloads under `/files/`
+  Files,
+  /// Health checks at `/health`
+  Health,
+  /// Invoice pages under `/files/…?invoice=…`
+  Invoice,
+  /// Invoice QR codes under `/invoice/`
+  InvoiceQr,
+  /// Invoice statuses at `/invoice/…/status`
+  InvoiceStatus,
+  /// LNURL-pay endpoints under `/lnurlp/`
+  Lnurlp,
+  /// Built-in assets under `/static/`, and `/favicon.ico` and `/apple-touch-icon.png`
+  Static,
+}
+
+impl str::FromStr for Route {
+  type Err = Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    match input {
+      "files" => Ok(Self::Files),
+      "health" => Ok(Self::Health),
+      "invoice" => Ok(Self::Invoice),
+      "invoice-qr" => Ok(Self::InvoiceQr),
+      "invoice-status" => Ok(Self::InvoiceStatus),
+      "lnurlp" => Ok(Self::Lnurlp),
+      "static" => Ok(Self::Static),
+      _ => Err(
+        error::RouteParse {
+          input: input.to_owned(),
+        }
+        .build(),
+      ),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    assert_eq!("files".parse::<Route>().unwrap(), Route::Files);
+    assert_eq!("health".parse::<Route>().unwrap(), Route::Health);
+    assert_eq!("invoice".parse::<Route>().unwrap(), Route::Invoice);
+    assert_eq!("invoice-qr".parse::<Route>().unwrap(), Route::InvoiceQr);
+    assert_eq!(
+      "invoice-status".parse::<Route>().unwrap(),
+      Route::InvoiceStatus
+    );
+    assert_eq!("lnurlp".parse::<Route>().unwrap(), Route::Lnurlp);
+    assert_eq!("static".parse::<Route>().unwrap(), Route::Static);
+    assert_matches!(
+      "foo".parse::<Route>(),
+      Err(Error::RouteParse { input, .. }) if input == "foo"
+    );
+  }
+}
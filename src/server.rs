@@ -1,9 +1,19 @@
-use {crate::common::*, openssl::x509::X509, tower::make::Shared};
+use {
+  crate::{backoff::backoff, common::*, paid_splits::PaidSplits, settlements::Settlements},
+  log::Level,
+  openssl::x509::X509,
+  tower::make::Shared,
+};
+
+/// How often LND is pinged to update the status reported by `/health`, if
+/// `--lnd-keepalive-interval` isn't given.
+const LND_STATUS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
 
 pub(crate) struct Server {
-  http_request_handler: Option<hyper::Server<AddrIncoming, Shared<RequestHandler>>>,
+  http_request_handlers: Vec<hyper::Server<AddrIncoming, RequestHandler>>,
   https_request_handler: Option<HttpsRequestHandler>,
   https_redirect_server: Option<hyper::Server<AddrIncoming, Shared<HttpsRedirectService>>>,
+  shutdown_timeout: std::time::Duration,
   #[cfg(test)]
   directory: std::path::PathBuf,
 }
@@ -12,16 +22,58 @@ impl Server {
   pub(crate) async fn setup(environment: &mut Environment) -> Result<Self> {
     let arguments = environment.arguments()?;
 
-    let directory = environment.working_directory.join(&arguments.directory);
+    let directory = environment.working_directory.join(arguments.directory());
     let _ = tokio::fs::read_dir(&directory)
       .await
       .context(error::FilesystemIo { path: &directory })?;
 
-    let http_request_handler = match arguments.http_port {
+    let download_counts = DownloadCounts::load(
+      arguments
+        .download_counts_file
+        .as_ref()
+        .map(|path| environment.working_directory.join(path)),
+    )?;
+
+    let paid_splits = PaidSplits::load(
+      arguments
+        .paid_splits_file
+        .as_ref()
+        .map(|path| environment.working_directory.join(path)),
+    )?;
+
+    let (lnd_client, lnd_status) = Self::setup_lnd_client(environment, &arguments).await?;
+
+    let metrics = Arc::new(Metrics::default());
+
+    let request_handler = RequestHandler::new(
+      environment,
+      &arguments,
+      lnd_client.clone(),
+      lnd_status,
+      download_counts,
+      metrics.clone(),
+    )?;
+
+    if let Some(lnd_client) = lnd_client {
+      Settlements::spawn(
+        request_handler.files.clone(),
+        lnd_client,
+        metrics,
+        paid_splits,
+      );
+    }
+
+    let http_request_handlers = match arguments.http_port {
       Some(http_port) => {
-        Some(Self::setup_http_request_handler(environment, &arguments, http_port).await?)
+        Self::setup_http_request_handlers(
+          environment,
+          &arguments,
+          http_port,
+          request_handler.clone(),
+        )
+        .await?
       }
-      None => None,
+      None => Vec::new(),
     };
 
     let (https_request_handler, https_redirect_server) =
@@ -30,13 +82,12 @@ impl Server {
           .acme_cache_directory
           .as_ref()
           .expect("<https-port> requires <acme-cache-directory>");
-        let lnd_client = Self::setup_lnd_client(environment, &arguments).await?;
         let https_request_handler = HttpsRequestHandler::new(
           environment,
           &arguments,
           acme_cache_directory,
           https_port,
-          lnd_client,
+          request_handler,
         )
         .await?;
         let https_redirect_server =
@@ -47,51 +98,81 @@ impl Server {
       };
 
     Ok(Self {
-      http_request_handler,
+      http_request_handlers,
       https_request_handler,
       https_redirect_server,
+      shutdown_timeout: std::time::Duration::from_secs(arguments.shutdown_timeout),
       #[cfg(test)]
       directory,
     })
   }
 
-  async fn setup_http_request_handler(
+  /// Bind an HTTP listener on the first address that `--address` resolves
+  /// to, or on each of them with `--bind-all-addresses`. If `http_port` is
+  /// zero, every listener uses the port assigned to the first.
+  async fn setup_http_request_handlers(
     environment: &mut Environment,
     arguments: &Arguments,
     http_port: u16,
-  ) -> Result<hyper::Server<AddrIncoming, Shared<RequestHandler>>> {
-    let lnd_client = Self::setup_lnd_client(environment, arguments).await?;
-
-    let socket_addr = (arguments.address.as_str(), http_port)
+    request_handler: RequestHandler,
+  ) -> Result<Vec<hyper::Server<AddrIncoming, RequestHandler>>> {
+    let mut socket_addrs = Vec::<SocketAddr>::new();
+    for socket_addr in (arguments.address.as_str(), http_port)
       .to_socket_addrs()
       .context(error::AddressResolutionIo {
         input: &arguments.address,
       })?
-      .next()
-      .ok_or_else(|| {
+    {
+      // Resolvers may return the same address more than once
+      if !socket_addrs.contains(&socket_addr) {
+        socket_addrs.push(socket_addr);
+      }
+    }
+
+    if socket_addrs.is_empty() {
+      return Err(
         error::AddressResolutionNoAddresses {
           input: arguments.address.clone(),
         }
-        .build()
-      })?;
-
-    let request_handler = hyper::Server::bind(&socket_addr).serve(Shared::new(
-      RequestHandler::new(environment, &arguments.directory, lnd_client),
-    ));
-
-    writeln!(
-      environment.stderr,
-      "Listening for HTTP connections on `{}`",
-      request_handler.local_addr()
-    )
-    .context(error::StderrWrite)?;
-    Ok(request_handler)
+        .build(),
+      );
+    }
+
+    if !arguments.bind_all_addresses {
+      socket_addrs.truncate(1);
+    }
+
+    let mut port = http_port;
+    let mut request_handlers = Vec::new();
+    for mut socket_addr in socket_addrs {
+      socket_addr.set_port(port);
+
+      let http_request_handler = hyper::Server::bind(&socket_addr)
+        .http2_max_concurrent_streams(arguments.http2_max_concurrent_streams)
+        .serve(request_handler.clone());
+
+      arguments
+        .log_format
+        .message(
+          &mut environment.stderr,
+          Level::Info,
+          format_args!(
+            "Listening for HTTP connections on `{}`",
+            http_request_handler.local_addr()
+          ),
+        )
+        .context(error::StderrWrite)?;
+
+      port = http_request_handler.local_addr().port();
+      request_handlers.push(http_request_handler);
+    }
+    Ok(request_handlers)
   }
 
   async fn setup_lnd_client(
     environment: &mut Environment,
     arguments: &Arguments,
-  ) -> Result<Option<agora_lnd_client::Client>> {
+  ) -> Result<(Option<agora_lnd_client::Client>, LndStatus)> {
     match &arguments.lnd_rpc_authority {
       Some(lnd_rpc_authority) => {
         let lnd_rpc_cert = match &arguments.lnd_rpc_cert_path {
@@ -104,53 +185,207 @@ impl Server {
           None => None,
         };
 
-        let lnd_rpc_macaroon = match &arguments.lnd_rpc_macaroon_path {
-          Some(path) => Some(
+        let lnd_rpc_macaroon = match (
+          &arguments.lnd_rpc_macaroon_hex,
+          &arguments.lnd_rpc_macaroon_path,
+        ) {
+          (Some(macaroon), _) => Some(macaroon.bytes().to_vec()),
+          (None, Some(path)) => Some(
             tokio::fs::read(&path)
               .await
               .context(error::FilesystemIo { path })?,
           ),
-          None => None,
+          (None, None) => None,
         };
 
-        let mut client =
-          agora_lnd_client::Client::new(lnd_rpc_authority.clone(), lnd_rpc_cert, lnd_rpc_macaroon)
-            .await
-            .context(error::LndRpcConnect)?;
-
-        match client.ping().await.context(error::LndRpcStatus) {
-          Err(error) => {
-            writeln!(
-              environment.stderr,
-              "warning: Cannot connect to LND gRPC server at `{}`: {}",
-              lnd_rpc_authority, error,
+        let mut client = agora_lnd_client::Client::new(
+          lnd_rpc_authority.clone(),
+          lnd_rpc_cert,
+          arguments.lnd_rpc_cert_pinned,
+          lnd_rpc_macaroon,
+        )
+        .await
+        .context(error::LndRpcConnect)?;
+
+        let reachable =
+          Self::connect_lnd(environment, arguments, &mut client, lnd_rpc_authority).await?;
+        let lnd_status = LndStatus::new(reachable);
+
+        Self::spawn_lnd_keepalive(
+          client.clone(),
+          lnd_rpc_authority.clone(),
+          arguments.lnd_keepalive_interval,
+          lnd_status.clone(),
+          reachable,
+        );
+
+        if let Some(url) = &arguments.settlement_webhook {
+          SettlementWebhook::spawn(client.clone(), url.clone())?;
+        }
+
+        Ok((Some(client), lnd_status))
+      }
+      None => Ok((None, LndStatus::disabled())),
+    }
+  }
+
+  /// Ping LND, retrying up to `--lnd-connect-retries` times with
+  /// exponential backoff, and waiting at most `--lnd-connect-timeout`
+  /// seconds for each attempt. Returns whether LND is reachable.
+  async fn connect_lnd(
+    environment: &mut Environment,
+    arguments: &Arguments,
+    client: &mut agora_lnd_client::Client,
+    lnd_rpc_authority: &Authority,
+  ) -> Result<bool> {
+    let retries = arguments.lnd_connect_retries.unwrap_or(0);
+    let mut attempt = 0;
+    loop {
+      let ping = match arguments.lnd_connect_timeout {
+        Some(timeout) => {
+          match tokio::time::timeout(std::time::Duration::from_secs(timeout), client.ping()).await {
+            Ok(ping) => ping,
+            Err(_elapsed) => Err(tonic::Status::deadline_exceeded(format!(
+              "No response within {} seconds",
+              timeout
+            ))),
+          }
+        }
+        None => client.ping().await,
+      };
+      match ping.context(error::LndRpcStatus) {
+        Ok(()) => {
+          arguments
+            .log_format
+            .message(
+              &mut environment.stderr,
+              Level::Info,
+              format_args!("Connected to LND RPC server at {}", lnd_rpc_authority),
             )
             .context(error::StderrWrite)?;
-          }
-          Ok(()) => {
-            writeln!(
-              environment.stderr,
-              "Connected to LND RPC server at {}",
-              lnd_rpc_authority
+          return Ok(true);
+        }
+        Err(error) if attempt < retries => {
+          let delay = backoff(attempt);
+          arguments
+            .log_format
+            .message(
+              &mut environment.stderr,
+              Level::Warn,
+              format_args!(
+                "Cannot connect to LND gRPC server at `{}`: {}, retrying in {}",
+                lnd_rpc_authority,
+                error,
+                humantime::format_duration(delay),
+              ),
             )
             .context(error::StderrWrite)?;
-          }
+          tokio::time::sleep(delay).await;
+          attempt += 1;
+        }
+        Err(error) => {
+          arguments
+            .log_format
+            .message(
+              &mut environment.stderr,
+              Level::Warn,
+              format_args!(
+                "Cannot connect to LND gRPC server at `{}`: {}, retrying in the background",
+                lnd_rpc_authority, error,
+              ),
+            )
+            .context(error::StderrWrite)?;
+          return Ok(false);
         }
-
-        Ok(Some(client))
       }
-      None => Ok(None),
     }
   }
 
-  pub(crate) async fn run(self) -> Result<()> {
-    futures::try_join!(
-      OptionFuture::from(self.http_request_handler)
-        .map(|option| option.unwrap_or(Ok(())).context(error::ServerRun)),
-      OptionFuture::from(self.https_request_handler.map(|x| x.run())).map(Ok),
-      OptionFuture::from(self.https_redirect_server)
-        .map(|option| option.unwrap_or(Ok(())).context(error::ServerRun)),
-    )?;
+  /// Ping LND periodically to update `lnd_status`. While LND is
+  /// unreachable, it is pinged with exponential backoff instead, so that
+  /// it's reconnected soon after it comes back up.
+  fn spawn_lnd_keepalive(
+    mut client: agora_lnd_client::Client,
+    lnd_rpc_authority: Authority,
+    keepalive_interval: Option<NonZeroU64>,
+    lnd_status: LndStatus,
+    mut reachable: bool,
+  ) {
+    let log = keepalive_interval.is_some();
+    let interval = keepalive_interval
+      .map(|interval| std::time::Duration::from_secs(interval.get()))
+      .unwrap_or(LND_STATUS_INTERVAL);
+    task::spawn(async move {
+      let mut attempt = 0;
+      loop {
+        if reachable {
+          tokio::time::sleep(interval).await;
+        } else {
+          tokio::time::sleep(backoff(attempt)).await;
+          attempt += 1;
+        }
+        let ping = client.ping().await;
+        lnd_status.set_reachable(ping.is_ok());
+        match ping {
+          Ok(()) => {
+            if !reachable {
+              log::info!(
+                "LND gRPC server at `{}` is reachable again",
+                lnd_rpc_authority
+              );
+            }
+            reachable = true;
+            attempt = 0;
+          }
+          Err(status) => {
+            if log {
+              log::warn!(
+                "Cannot reach LND gRPC server at `{}`: {}",
+                lnd_rpc_authority,
+                status
+              );
+            }
+            reachable = false;
+          }
+        }
+      }
+    });
+  }
+
+  /// Serve requests until `shutdown` resolves, and then until in-flight
+  /// requests have finished, or `--shutdown-timeout` has elapsed.
+  pub(crate) async fn run(self, shutdown: Shutdown) -> Result<()> {
+    let servers = futures::future::try_join3(
+      futures::future::try_join_all(
+        self
+          .http_request_handlers
+          .into_iter()
+          .map(|server| server.with_graceful_shutdown(shutdown.clone())),
+      )
+      .map(|result| result.map(|_| ()).context(error::ServerRun)),
+      OptionFuture::from(self.https_request_handler.map(|x| x.run(shutdown.clone()))).map(Ok),
+      OptionFuture::from(
+        self
+          .https_redirect_server
+          .map(|server| server.with_graceful_shutdown(shutdown.clone())),
+      )
+      .map(|option| option.unwrap_or(Ok(())).context(error::ServerRun)),
+    );
+
+    let shutdown_timeout = self.shutdown_timeout;
+    let timeout = shutdown.then(move |()| tokio::time::sleep(shutdown_timeout));
+
+    tokio::select! {
+      result = servers => {
+        result?;
+      }
+      () = timeout => {
+        log::warn!(
+          "Closing connections still open {} seconds after shutdown",
+          shutdown_timeout.as_secs()
+        );
+      }
+    }
 
     Ok(())
   }
@@ -160,8 +395,8 @@ impl Server {
     let http_url = reqwest::Url::parse(&format!(
       "http://localhost:{}",
       self
-        .http_request_handler
-        .as_ref()
+        .http_request_handlers
+        .first()
         .unwrap()
         .local_addr()
         .port()
@@ -250,7 +485,7 @@ mod tests {
       .unwrap()
       .block_on(async {
         let server = Server::setup(&mut environment).await.unwrap();
-        let ip = server.http_request_handler.unwrap().local_addr().ip();
+        let ip = server.http_request_handlers[0].local_addr().ip();
         assert!(
           ip == IpAddr::from([127, 0, 0, 1])
             || ip == IpAddr::from([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
@@ -260,6 +495,48 @@ mod tests {
       });
   }
 
+  #[test]
+  fn bind_all_addresses() {
+    let mut environment = Environment::test();
+    environment.arguments = vec![
+      "agora".into(),
+      "--address=localhost".into(),
+      "--bind-all-addresses".into(),
+      "--http-port=0".into(),
+      "--directory=www".into(),
+    ];
+
+    let www = environment.working_directory.join("www");
+    std::fs::create_dir(&www).unwrap();
+
+    tokio::runtime::Builder::new_multi_thread()
+      .enable_all()
+      .build()
+      .unwrap()
+      .block_on(async {
+        let server = Server::setup(&mut environment).await.unwrap();
+        let mut expected = ("localhost", 0)
+          .to_socket_addrs()
+          .unwrap()
+          .map(|socket_addr| socket_addr.ip())
+          .collect::<Vec<IpAddr>>();
+        let mut ips = server
+          .http_request_handlers
+          .iter()
+          .map(|http_request_handler| http_request_handler.local_addr().ip())
+          .collect::<Vec<IpAddr>>();
+        expected.sort();
+        expected.dedup();
+        ips.sort();
+        assert_eq!(ips, expected);
+        let port = server.http_request_handlers[0].local_addr().port();
+        assert!(server
+          .http_request_handlers
+          .iter()
+          .all(|http_request_handler| http_request_handler.local_addr().port() == port));
+      });
+  }
+
   #[test]
   fn address_resolution_failure_error() {
     let mut environment = Environment::test();
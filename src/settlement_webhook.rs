@@ -0,0 +1,163 @@
+use {
+  crate::{backoff::backoff, common::*},
+  agora_lnd_client::lnrpc::{invoice::InvoiceState, Invoice},
+  hyper::{client::HttpConnector, Uri},
+  hyper_openssl::HttpsConnector,
+};
+
+/// Attempts to deliver a notification before giving up on it.
+const POST_ATTEMPTS: u32 = 5;
+
+/// Notifies `--settlement-webhook` of settled invoices, by subscribing to
+/// LND's invoice updates and POSTing a JSON notification for each
+/// settlement. Lost subscriptions are reestablished, catching up on
+/// settlements that happened while disconnected.
+pub(crate) struct SettlementWebhook {
+  http_client: hyper::Client<HttpsConnector<HttpConnector>>,
+  lnd_client: agora_lnd_client::Client,
+  url: Uri,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Settlement {
+  payment_hash: String,
+  memo: String,
+  amount_msat: i64,
+}
+
+impl Settlement {
+  fn new(invoice: &Invoice) -> Option<Self> {
+    if invoice.state() != InvoiceState::Settled {
+      return None;
+    }
+    Some(Self {
+      payment_hash: hex::encode(&invoice.r_hash),
+      memo: invoice.memo.clone(),
+      amount_msat: invoice.amt_paid_msat,
+    })
+  }
+}
+
+impl SettlementWebhook {
+  pub(crate) fn spawn(lnd_client: agora_lnd_client::Client, url: Uri) -> Result<()> {
+    let http_client =
+      hyper::Client::builder().build(HttpsConnector::new().context(error::SettlementWebhookTls)?);
+    task::spawn(
+      Self {
+        http_client,
+        lnd_client,
+        url,
+      }
+      .run(),
+    );
+    Ok(())
+  }
+
+  async fn run(mut self) {
+    let mut settle_index = 0;
+    let mut attempt = 0;
+    loop {
+      match self.lnd_client.subscribe_invoices(settle_index).await {
+        Ok(mut invoices) => {
+          log::info!(
+            "Subscribed to LND invoices, posting settlements to `{}`",
+            self.url
+          );
+          attempt = 0;
+          loop {
+            match invoices.message().await {
+              Ok(Some(invoice)) => {
+                if let Some(settlement) = Settlement::new(&invoice) {
+                  settle_index = invoice.settle_index;
+                  self.post(&settlement).await;
+                }
+              }
+              Ok(None) => {
+                log::warn!("LND closed the invoice subscription");
+                break;
+              }
+              Err(status) => {
+                log::warn!("LND invoice subscription failed: {}", status);
+                break;
+              }
+            }
+          }
+        }
+        Err(status) => log::warn!("Cannot subscribe to LND invoices: {}", status),
+      }
+      tokio::time::sleep(backoff(attempt)).await;
+      attempt += 1;
+    }
+  }
+
+  async fn post(&self, settlement: &Settlement) {
+    let body = serde_json::to_string(settlement).expect("settlements can be serialized");
+    for attempt in 0..POST_ATTEMPTS {
+      if attempt > 0 {
+        tokio::time::sleep(backoff(attempt - 1)).await;
+      }
+      match self.try_post(body.clone()).await {
+        Ok(()) => return,
+        Err(error) => log::warn!(
+          "Failed to post settlement of invoice {} to `{}`: {}",
+          settlement.payment_hash,
+          self.url,
+          error
+        ),
+      }
+    }
+    log::error!(
+      "Giving up on posting settlement of invoice {} to `{}` after {} attempts",
+      settlement.payment_hash,
+      self.url,
+      POST_ATTEMPTS
+    );
+  }
+
+  async fn try_post(&self, body: String) -> Result<(), String> {
+    let request = Request::post(self.url.clone())
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(Body::from(body))
+      .map_err(|error| error.to_string())?;
+    let response = self
+      .http_client
+      .request(request)
+      .await
+      .map_err(|error| error.to_string())?;
+    if response.status().is_success() {
+      Ok(())
+    } else {
+      Err(format!("webhook responded with {}", response.status()))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn settlement() {
+    let invoice = Invoice {
+      r_hash: vec![0xab; 32],
+      memo: "foo".into(),
+      amt_paid_msat: 1234,
+      ..Invoice::default()
+    };
+    assert_eq!(Settlement::new(&invoice), None);
+
+    let settlement = Settlement::new(&Invoice {
+      state: InvoiceState::Settled as i32,
+      ..invoice
+    })
+    .unwrap();
+    assert_eq!(
+      serde_json::to_value(&settlement).unwrap(),
+      serde_json::json!({
+        "payment_hash": "ab".repeat(32),
+        "memo": "foo",
+        "amount_msat": 1234,
+      })
+    );
+  }
+}
@@ -0,0 +1,116 @@
+use {
+  crate::{backoff::backoff, common::*, files::Files, paid_splits::PaidSplits, vfs::Splits},
+  agora_lnd_client::lnrpc::{invoice::InvoiceState, Invoice},
+};
+
+/// Handles settlements of invoices for paid files as they happen, by
+/// subscribing to LND's invoice updates, whether or not buyers ever open
+/// the invoice page again. Each settlement is counted in `/metrics`, and
+/// pays the revenue splits of its file. Lost subscriptions are
+/// reestablished, resuming from the last settlement handled.
+pub(crate) struct Settlements {
+  files: Files,
+  lnd_client: agora_lnd_client::Client,
+  metrics: Arc<Metrics>,
+  paid_splits: PaidSplits,
+}
+
+impl Settlements {
+  pub(crate) fn spawn(
+    files: Files,
+    lnd_client: agora_lnd_client::Client,
+    metrics: Arc<Metrics>,
+    paid_splits: PaidSplits,
+  ) {
+    task::spawn(
+      Self {
+        files,
+        lnd_client,
+        metrics,
+        paid_splits,
+      }
+      .run(),
+    );
+  }
+
+  async fn run(mut self) {
+    let mut attempt = 0;
+    loop {
+      match self
+        .lnd_client
+        .subscribe_invoices(self.paid_splits.settle_index())
+        .await
+      {
+        Ok(mut invoices) => {
+          attempt = 0;
+          loop {
+            match invoices.message().await {
+              Ok(Some(invoice)) => {
+                if invoice.state() == InvoiceState::Settled {
+                  if let Err(error) = self.settle(&invoice) {
+                    log::error!(
+                      "Failed to handle settlement of invoice {}: {}",
+                      hex::encode(&invoice.r_hash),
+                      error
+                    );
+                  }
+                }
+              }
+              Ok(None) => {
+                log::warn!("LND closed the invoice subscription");
+                break;
+              }
+              Err(status) => {
+                log::warn!("LND invoice subscription failed: {}", status);
+                break;
+              }
+            }
+          }
+        }
+        Err(status) => log::warn!("Cannot subscribe to LND invoices: {}", status),
+      }
+      tokio::time::sleep(backoff(attempt)).await;
+      attempt += 1;
+    }
+  }
+
+  fn settle(&self, invoice: &Invoice) -> Result<()> {
+    if let Some(path) = self.files.settled_file(invoice)? {
+      self.metrics.invoice_settled();
+      if let Some(splits) = self.files.splits(&path)? {
+        // Splits are recorded as paid before sending them, so that they're
+        // never sent twice, even if agora stops while sending them
+        if self.paid_splits.record(&invoice.r_hash)? {
+          self.pay(splits, invoice);
+        }
+      }
+    }
+    self.paid_splits.settled(invoice.settle_index)
+  }
+
+  fn pay(&self, splits: Splits, invoice: &Invoice) {
+    let mut lnd_client = self.lnd_client.clone();
+    let value = invoice.value_msat();
+    let r_hash = hex::encode(&invoice.r_hash);
+    task::spawn(async move {
+      for (destination, amount) in splits.amounts(value) {
+        let destination_hex = hex::encode(destination);
+        match lnd_client.keysend(destination, amount).await {
+          Ok(()) => log::info!(
+            "Sent split of {} for invoice {} to `{}`",
+            amount,
+            r_hash,
+            destination_hex
+          ),
+          Err(status) => log::error!(
+            "Failed to send split of {} for invoice {} to `{}`: {}",
+            amount,
+            r_hash,
+            destination_hex,
+            status
+          ),
+        }
+      }
+    });
+  }
+}
@@ -0,0 +1,56 @@
+use {crate::common::*, futures::future::Shared};
+
+/// Resolves once the server should shut down, at which point listeners stop
+/// accepting connections, and open connections are closed after their
+/// in-flight requests have been served. Cloned for each listener and
+/// connection.
+#[derive(Clone)]
+pub(crate) struct Shutdown {
+  future: Shared<BoxFuture<'static, ()>>,
+}
+
+impl Shutdown {
+  pub(crate) fn new(future: impl future::Future<Output = ()> + Send + 'static) -> Self {
+    Self {
+      future: future.boxed().shared(),
+    }
+  }
+
+  /// Shut down when the process receives SIGINT or SIGTERM.
+  #[cfg(unix)]
+  pub(crate) fn signal() -> Result<Self> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt()).context(error::SignalHandlerInstall)?;
+    let mut terminate = signal(SignalKind::terminate()).context(error::SignalHandlerInstall)?;
+
+    Ok(Self::new(async move {
+      tokio::select! {
+        _ = interrupt.recv() => log::info!("Received SIGINT, shutting down"),
+        _ = terminate.recv() => log::info!("Received SIGTERM, shutting down"),
+      }
+    }))
+  }
+
+  /// Shut down when the process receives Ctrl-C.
+  #[cfg(not(unix))]
+  pub(crate) fn signal() -> Result<Self> {
+    Ok(Self::new(async {
+      match tokio::signal::ctrl_c().await {
+        Ok(()) => log::info!("Received Ctrl-C, shutting down"),
+        Err(error) => {
+          log::error!("Failed to listen for Ctrl-C: {}", error);
+          future::pending().await
+        }
+      }
+    }))
+  }
+}
+
+impl future::Future for Shutdown {
+  type Output = ();
+
+  fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+    self.future.poll_unpin(context)
+  }
+}
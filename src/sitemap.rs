@@ -0,0 +1,53 @@
+use {
+  crate::{common::*, webdav::escape},
+  hyper::body::Sender,
+};
+
+/// Start of sitemaps served at `/sitemap.xml` with `--sitemap`, in the
+/// format described at <https://www.sitemaps.org/protocol.html>.
+pub(crate) const HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n";
+
+pub(crate) const FOOTER: &str = "</urlset>\n";
+
+/// Sitemap entry for the file at `url`, last modified at `modified`.
+pub(crate) fn url(url: &str, modified: Option<SystemTime>) -> String {
+  match modified {
+    Some(modified) => format!(
+      "<url><loc>{}</loc><lastmod>{}</lastmod></url>\n",
+      escape(url),
+      humantime::format_rfc3339_seconds(modified)
+    ),
+    None => format!("<url><loc>{}</loc></url>\n", escape(url)),
+  }
+}
+
+pub(crate) async fn send_data(sender: &mut Sender, xml: String) -> Result<()> {
+  if xml.is_empty() {
+    return Ok(());
+  }
+  sender
+    .send_data(xml.into())
+    .await
+    .map_err(|error| Error::internal(format!("Failed to send sitemap data: {}", error)))
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, std::time::Duration};
+
+  #[test]
+  fn url() {
+    assert_eq!(
+      super::url("https://example.com/files/a%20b.txt?x&y", None),
+      "<url><loc>https://example.com/files/a%20b.txt?x&amp;y</loc></url>\n"
+    );
+    assert_eq!(
+      super::url(
+        "https://example.com/files/foo.txt",
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(86400))
+      ),
+      "<url><loc>https://example.com/files/foo.txt</loc><lastmod>1970-01-02T00:00:00Z</lastmod></url>\n"
+    );
+  }
+}
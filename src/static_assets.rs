@@ -1,10 +1,28 @@
-use {crate::common::*, rust_embed::RustEmbed};
+use {
+  crate::{common::*, conditional_get::Validators},
+  hyper::HeaderMap,
+  rust_embed::RustEmbed,
+};
 
 #[derive(RustEmbed)]
 #[folder = "static/"]
 pub(crate) struct StaticAssets;
 
 impl StaticAssets {
+  /// Serve the asset at `tail`, with a strong ETag computed from its
+  /// contents when agora is built, so clients can revalidate cached copies.
+  pub(crate) async fn serve_conditional(
+    request_headers: &HeaderMap,
+    tail: &[&str],
+  ) -> Result<Response<Body>> {
+    let path = tail.join("");
+    let asset = StaticAssets::get(&path)
+      .ok_or_else(|| error::StaticAssetNotFound { uri_path: path }.build())?;
+    Validators::strong(&asset.metadata.sha256_hash())
+      .respond(request_headers, async { Self::serve(tail) })
+      .await
+  }
+
   pub(crate) fn serve(tail: &[&str]) -> Result<Response<Body>> {
     let path = tail.join("");
     match StaticAssets::get(&path) {
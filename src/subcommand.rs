@@ -0,0 +1,49 @@
+use crate::{common::*, vfs::Vfs};
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum Subcommand {
+  #[structopt(
+    about = "Write a copy of the agora executable to <output>, with <directory> bundled into it. When run without `--directory`, the copy serves the bundled directory, including its `.agora.yaml` files."
+  )]
+  Bundle {
+    #[structopt(long, help = "Bundle the contents of <directory>")]
+    directory: PathBuf,
+    #[structopt(long, help = "Write the executable to <output>")]
+    output: PathBuf,
+  },
+  #[structopt(
+    about = "Check that every `.agora.yaml` file in <directory> and its subdirectories is valid, print an error for each one that isn't, and exit with a non-zero status if any were invalid."
+  )]
+  Check {
+    #[structopt(long, help = "Check config files in <directory>")]
+    directory: PathBuf,
+  },
+}
+
+impl Subcommand {
+  pub(crate) fn run(self, environment: &mut Environment) -> Result<()> {
+    match self {
+      Self::Bundle { directory, output } => Bundle::create(
+        &env::current_exe().context(error::CurrentExe)?,
+        &environment.working_directory.join(directory),
+        &environment.working_directory.join(output),
+      ),
+      Self::Check { directory } => {
+        let errors = Vfs::check_configs(&environment.working_directory.join(directory))?;
+        for error in &errors {
+          writeln!(environment.stderr, "error: {}", error).context(error::StderrWrite)?;
+        }
+        if errors.is_empty() {
+          Ok(())
+        } else {
+          Err(
+            error::ConfigCheck {
+              count: errors.len(),
+            }
+            .build(),
+          )
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,136 @@
+use {
+  crate::common::*,
+  maud::html,
+  pulldown_cmark::{CowStr, Event, Tag},
+  std::collections::BTreeSet,
+};
+
+pub(crate) struct Heading {
+  level: u32,
+  id: String,
+  text: String,
+}
+
+/// Give each heading in `events` a unique, URL-safe `id` attribute, and
+/// return the modified events along with the headings found.
+pub(crate) fn add_heading_ids<'a>(events: Vec<Event<'a>>) -> (Vec<Event<'a>>, Vec<Heading>) {
+  let mut ids = BTreeSet::new();
+  let mut headings = Vec::new();
+  let mut output = Vec::new();
+  let mut current: Option<(u32, String, Vec<Event<'a>>)> = None;
+
+  for event in events {
+    match (&mut current, event) {
+      (None, Event::Start(Tag::Heading(level))) => {
+        current = Some((level, String::new(), Vec::new()))
+      }
+      (Some((level, text, inner)), Event::End(Tag::Heading(_))) => {
+        let id = unique_id(&mut ids, &slug(text));
+        output.push(Event::Html(CowStr::from(format!(
+          "<h{} id=\"{}\">",
+          level, id
+        ))));
+        output.append(inner);
+        output.push(Event::Html(CowStr::from(format!("</h{}>", level))));
+        headings.push(Heading {
+          level: *level,
+          id,
+          text: text.clone(),
+        });
+        current = None;
+      }
+      (Some((_, text, inner)), event) => {
+        if let Event::Text(content) | Event::Code(content) = &event {
+          text.push_str(content);
+        }
+        inner.push(event);
+      }
+      (None, event) => output.push(event),
+    }
+  }
+
+  (output, headings)
+}
+
+pub(crate) fn render(headings: &[Heading]) -> Markup {
+  html! {
+    nav class="toc" {
+      ul {
+        @for heading in headings {
+          li class=(format!("toc-h{}", heading.level)) {
+            a href=(format!("#{}", heading.id)) {
+              (heading.text)
+            }
+          }
+        }
+      }
+    }
+  }
+}
+
+fn slug(text: &str) -> String {
+  let mut slug = String::new();
+  for character in text.trim().chars() {
+    if character.is_ascii_alphanumeric() {
+      slug.push(character.to_ascii_lowercase());
+    } else if (character.is_whitespace() || character == '-' || character == '_')
+      && !slug.is_empty()
+      && !slug.ends_with('-')
+    {
+      slug.push('-');
+    }
+  }
+  let slug = slug.trim_end_matches('-');
+  if slug.is_empty() {
+    "section".to_owned()
+  } else {
+    slug.to_owned()
+  }
+}
+
+fn unique_id(ids: &mut BTreeSet<String>, slug: &str) -> String {
+  let mut id = slug.to_owned();
+  let mut suffix = 1;
+  while ids.contains(&id) {
+    id = format!("{}-{}", slug, suffix);
+    suffix += 1;
+  }
+  ids.insert(id.clone());
+  id
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, pulldown_cmark::Parser};
+
+  fn ids(markdown: &str) -> Vec<String> {
+    add_heading_ids(Parser::new(markdown).collect())
+      .1
+      .into_iter()
+      .map(|heading| heading.id)
+      .collect()
+  }
+
+  #[test]
+  fn slugs_are_url_safe() {
+    assert_eq!(slug("Hello, World!"), "hello-world");
+    assert_eq!(slug("  foo -- bar_baz  "), "foo-bar-baz");
+    assert_eq!(slug("Über"), "ber");
+    assert_eq!(slug("???"), "section");
+  }
+
+  #[test]
+  fn ids_are_unique() {
+    assert_eq!(
+      ids("# Foo\n## Foo\n# Foo-1\n# Foo"),
+      vec!["foo", "foo-1", "foo-1-1", "foo-2"]
+    );
+  }
+
+  #[test]
+  fn heading_text_includes_code() {
+    let (_, headings) = add_heading_ids(Parser::new("# The `agora` binary").collect());
+    assert_eq!(headings[0].text, "The agora binary");
+    assert_eq!(headings[0].id, "the-agora-binary");
+  }
+}
@@ -31,6 +31,20 @@ pub(crate) fn assert_contains(haystack: &str, needle: &str) {
 }
 
 pub(crate) fn set_up_test_certificate() -> (TempDir, Certificate) {
+  set_up_test_certificate_with_names(
+    "cached_cert_83kei_h4oopqh8sXFFlhGeQJIS_pkJJv-y5XDpnLtyw",
+    &["localhost"],
+  )
+}
+
+/// Write a certificate for `subject_alt_names` to `cache_file_name`, where
+/// rustls-acme looks for a cached certificate for the configured ACME
+/// domains, and return the certificate cache directory and the root
+/// certificate that signed it.
+pub(crate) fn set_up_test_certificate_with_names(
+  cache_file_name: &str,
+  subject_alt_names: &[&str],
+) -> (TempDir, Certificate) {
   use rcgen::{
     BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair, SanType,
     PKCS_ECDSA_P256_SHA256,
@@ -51,9 +65,11 @@ pub(crate) fn set_up_test_certificate() -> (TempDir, Certificate) {
       certificate_keys,
     )
     .unwrap();
-    params
-      .subject_alt_names
-      .push(SanType::DnsName("localhost".to_string()));
+    for name in subject_alt_names {
+      params
+        .subject_alt_names
+        .push(SanType::DnsName(name.to_string()));
+    }
     Certificate::from_params(params).unwrap()
   };
   let certificate_file = vec![
@@ -65,13 +81,7 @@ pub(crate) fn set_up_test_certificate() -> (TempDir, Certificate) {
   ]
   .join("\r\n");
   let tempdir = TempDir::new().unwrap();
-  fs::write(
-    tempdir
-      .path()
-      .join("cached_cert_83kei_h4oopqh8sXFFlhGeQJIS_pkJJv-y5XDpnLtyw"),
-    certificate_file,
-  )
-  .unwrap();
+  fs::write(tempdir.path().join(cache_file_name), certificate_file).unwrap();
   (
     tempdir,
     reqwest::Certificate::from_pem(root_certificate.serialize_pem().unwrap().as_bytes()).unwrap(),
@@ -151,7 +161,8 @@ where
     .block_on(async {
       let server = Server::setup(environment).await.unwrap();
       let test_context = server.test_context();
-      let server_join_handle = tokio::spawn(async { server.run().await.unwrap() });
+      let server_join_handle =
+        tokio::spawn(async { server.run(Shutdown::new(future::pending())).await.unwrap() });
       let test_result = tokio::task::LocalSet::new()
         .run_until(async move { tokio::task::spawn_local(test_function(test_context)).await })
         .await;
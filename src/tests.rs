@@ -3,7 +3,8 @@ use {
     common::*,
     environment::Environment,
     test_utils::{
-      https_client, set_up_test_certificate, test_with_arguments, test_with_environment,
+      https_client, set_up_test_certificate, set_up_test_certificate_with_names,
+      test_with_arguments, test_with_environment,
     },
   },
   pretty_assertions::assert_eq,
@@ -49,6 +50,98 @@ fn serves_https_requests_with_cert_from_cache_directory() {
   );
 }
 
+#[test]
+fn min_tls_version_rejects_older_clients() {
+  let (certificate_cache, root_certificate) = set_up_test_certificate();
+
+  test_with_arguments(
+    &[
+      "--acme-cache-directory",
+      certificate_cache.path().to_str().unwrap(),
+      "--https-port=0",
+      "--acme-domain=localhost",
+      "--min-tls-version=1.3",
+    ],
+    |context| async move {
+      context.write("file", "encrypted content");
+      let client = https_client(&context, root_certificate.clone()).await;
+      let response = client
+        .get(context.https_files_url().join("file").unwrap())
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(response.text().await.unwrap(), "encrypted content");
+
+      let tls_1_2_client = reqwest::ClientBuilder::new()
+        .add_root_certificate(root_certificate)
+        .max_tls_version(reqwest::tls::Version::TLS_1_2)
+        .build()
+        .unwrap();
+      assert!(tls_1_2_client
+        .get(context.https_files_url().join("file").unwrap())
+        .send()
+        .await
+        .is_err());
+    },
+  );
+}
+
+#[test]
+fn closes_https_connections_for_hosts_other_than_acme_domains() {
+  // Cached certificate for `localhost` and `agora.localhost`, which is also
+  // valid for `other.localhost`, so only agora can reject connections to it
+  let (certificate_cache, root_certificate) = set_up_test_certificate_with_names(
+    "cached_cert_u_OnOlz1A7m_LGQ7ZMh2or4APm3vmI-pcRT5aC9xisM",
+    &["localhost", "agora.localhost", "other.localhost"],
+  );
+
+  test_with_arguments(
+    &[
+      "--acme-cache-directory",
+      certificate_cache.path().to_str().unwrap(),
+      "--https-port=0",
+      "--acme-domain=localhost",
+      "--acme-domain=agora.localhost",
+    ],
+    |context| async move {
+      context.write("file", "encrypted content");
+      https_client(&context, root_certificate.clone()).await;
+      let addr = context.https_files_url().socket_addrs(|| None).unwrap()[0];
+      let get = |host: &str| {
+        reqwest::ClientBuilder::new()
+          .add_root_certificate(root_certificate.clone())
+          .resolve(host, addr)
+          .build()
+          .unwrap()
+          .get(format!("https://{}:{}/files/file", host, addr.port()))
+          .send()
+      };
+      for host in &["localhost", "agora.localhost"] {
+        assert_eq!(
+          get(host).await.unwrap().text().await.unwrap(),
+          "encrypted content"
+        );
+      }
+      assert!(get("other.localhost").await.is_err());
+      // Clients connecting to an IP address don't send a host name
+      assert_eq!(
+        reqwest::ClientBuilder::new()
+          .danger_accept_invalid_certs(true)
+          .build()
+          .unwrap()
+          .get(format!("https://{}/files/file", addr))
+          .send()
+          .await
+          .unwrap()
+          .text()
+          .await
+          .unwrap(),
+        "encrypted content"
+      );
+    },
+  );
+}
+
 #[test]
 fn redirects_requests_from_port_80_to_443() {
   let (certificate_cache, root_certificate) = set_up_test_certificate();
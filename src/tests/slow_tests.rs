@@ -85,6 +85,11 @@ fn invoice_url_serves_bech32_encoded_invoice() {
     guard_unwrap!(let &[payment_link] = css_select(&html, "a.payment-link").as_slice());
     let href = payment_link.value().attr("href").unwrap();
     assert!(href.starts_with("lightning:lnbcrt1"), "href: {}", href);
+    guard_unwrap!(let &[payment_request_text] = css_select(&html, ".payment-request-text").as_slice());
+    assert_eq!(
+      &href["lightning:".len()..],
+      payment_request_text.text().collect::<String>()
+    );
   });
 }
 
@@ -99,6 +104,98 @@ fn invoice_url_contains_filename() {
   });
 }
 
+#[test]
+fn paid_files_and_invoices_are_not_indexed() {
+  test_with_lnd(&LndTestContext::new_blocking(), |context| async move {
+    context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+    context.write("foo", "");
+    context.write("free/bar", "");
+    context.write("free/.agora.yaml", "paid: false");
+    let response = reqwest::get(context.files_url().join("foo").unwrap())
+      .await
+      .unwrap();
+    assert!(response.url().query().unwrap().starts_with("invoice="));
+    assert_eq!(response.headers().get("x-robots-tag").unwrap(), "noindex");
+    let response = reqwest::get(context.files_url().join("free/bar").unwrap())
+      .await
+      .unwrap();
+    assert_eq!(response.headers().get("x-robots-tag"), None);
+    assert_eq!(
+      text(&context.base_url().join("robots.txt").unwrap()).await,
+      "User-agent: *\nDisallow: /files/\n"
+    );
+  });
+}
+
+#[test]
+fn reloading_paid_files_reuses_open_invoices() {
+  test_with_lnd(&LndTestContext::new_blocking(), |context| async move {
+    context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+    context.write("foo", "");
+    let client = reqwest::Client::builder()
+      .redirect(reqwest::redirect::Policy::none())
+      .build()
+      .unwrap();
+    let url = context.files_url().join("foo").unwrap();
+
+    let first = client.get(url.clone()).send().await.unwrap();
+    let cookie = first
+      .headers()
+      .get(reqwest::header::SET_COOKIE)
+      .unwrap()
+      .to_str()
+      .unwrap()
+      .split(';')
+      .next()
+      .unwrap()
+      .to_owned();
+    assert!(cookie.starts_with("agora_client="));
+    let location = first.headers().get(reqwest::header::LOCATION).unwrap();
+
+    let reload = client
+      .get(url.clone())
+      .header(reqwest::header::COOKIE, &cookie)
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(
+      reload.headers().get(reqwest::header::LOCATION).unwrap(),
+      location
+    );
+    assert_eq!(reload.headers().get(reqwest::header::SET_COOKIE), None);
+
+    let other_client = client.get(url).send().await.unwrap();
+    assert_ne!(
+      other_client
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .unwrap(),
+      location
+    );
+  });
+}
+
+#[test]
+fn invoice_pages_describe_invoice_in_headers() {
+  test_with_lnd(&LndTestContext::new_blocking(), |context| async move {
+    context.write(
+      ".agora.yaml",
+      "{paid: true, base-price: 1000 sat, invoice-expiry: 10 min}",
+    );
+    context.write("foo", "");
+    let response = get(&context.files_url().join("foo").unwrap()).await;
+    let headers = response.headers().clone();
+    let html = Html::parse_document(&response.text().await.unwrap());
+    guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request-text").as_slice());
+    assert_eq!(
+      headers.get("x-agora-payment-request").unwrap(),
+      payment_request.inner_html().as_str()
+    );
+    assert_eq!(headers.get("x-agora-amount-msat").unwrap(), "1000000");
+    assert_eq!(headers.get("x-agora-invoice-expiry").unwrap(), "600");
+  });
+}
+
 #[test]
 fn invoice_title_contains_the_word_invoice() {
   test_with_lnd(&LndTestContext::new_blocking(), |context| async move {
@@ -164,6 +261,43 @@ fn invoice_url_links_to_qr_code() {
   });
 }
 
+#[test]
+fn invoice_qr_codes_are_compressed() {
+  use std::io::Read;
+  let receiver = LndTestContext::new_blocking();
+  test_with_lnd(&receiver, |context| async move {
+    context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+    context.write("foo", "precious content");
+    let response = get(&context.files_url().join("foo").unwrap()).await;
+    let invoice_url = response.url().clone();
+    let html = Html::parse_document(&response.text().await.unwrap());
+    guard_unwrap!(let &[qr_code] = css_select(&html, "img.qr-code").as_slice());
+    let qr_code_url = invoice_url
+      .join(qr_code.value().attr("src").unwrap())
+      .unwrap();
+    let response = reqwest::Client::new()
+      .get(qr_code_url)
+      .header(header::ACCEPT_ENCODING, "gzip")
+      .send()
+      .await
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+      response.headers().get(header::CONTENT_TYPE).unwrap(),
+      "image/svg+xml"
+    );
+    assert_eq!(
+      response.headers().get(header::CONTENT_ENCODING).unwrap(),
+      "gzip"
+    );
+    let mut svg = String::new();
+    flate2::read::GzDecoder::new(&*response.bytes().await.unwrap())
+      .read_to_string(&mut svg)
+      .unwrap();
+    assert_contains(&svg, "<svg");
+  });
+}
+
 #[test]
 fn paying_invoice_allows_downloading_file() {
   let receiver = LndTestContext::new_blocking();
@@ -185,6 +319,376 @@ fn paying_invoice_allows_downloading_file() {
   });
 }
 
+#[test]
+fn invoice_status_can_be_polled() {
+  let receiver = LndTestContext::new_blocking();
+  test_with_lnd(&receiver.clone(), |context| async move {
+    context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+    context.write("foo", "precious content");
+    let response = get(&context.files_url().join("foo").unwrap()).await;
+    let invoice_url = response.url().clone();
+    let html = Html::parse_document(&response.text().await.unwrap());
+    guard_unwrap!(let &[invoice] = css_select(&html, ".invoice").as_slice());
+    let status_url = invoice_url
+      .join(invoice.value().attr("data-status-url").unwrap())
+      .unwrap();
+    let status = || async {
+      let response = get(&status_url).await;
+      assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "application/json"
+      );
+      serde_json::from_str::<serde_json::Value>(&response.text().await.unwrap()).unwrap()
+    };
+    assert_eq!(
+      status().await,
+      serde_json::json!({"settled": false, "amount_msat": 1_000_000})
+    );
+    guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+    let payment_request = payment_request.text().collect::<String>();
+    receiver.fulfill_own_payment_request(&payment_request).await;
+    tokio::time::sleep(crate::invoice_status_cache::InvoiceStatusCache::INTERVAL).await;
+    assert_eq!(
+      status().await,
+      serde_json::json!({"settled": true, "amount_msat": 1_000_000})
+    );
+  });
+}
+
+#[test]
+fn invoice_pages_use_qr_code_as_default_open_graph_image() {
+  test_with_lnd(&LndTestContext::new_blocking(), |context| async move {
+    context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+    context.write("foo", "precious content");
+    let response = get(&context.files_url().join("foo").unwrap()).await;
+    let html = Html::parse_document(&response.text().await.unwrap());
+    guard_unwrap!(let &[title] = css_select(&html, "meta[property='og:title']").as_slice());
+    assert_eq!(title.value().attr("content").unwrap(), "foo");
+    guard_unwrap!(let &[image] = css_select(&html, "meta[property='og:image']").as_slice());
+    guard_unwrap!(let &[qr_code] = css_select(&html, "img.qr-code").as_slice());
+    assert_eq!(
+      image.value().attr("content").unwrap(),
+      qr_code.value().attr("src").unwrap()
+    );
+  });
+}
+
+#[test]
+fn settled_invoices_redirect_to_signed_urls() {
+  let receiver = LndTestContext::new_blocking();
+  test_with_arguments(
+    &[
+      "--lnd-rpc-authority",
+      &receiver.lnd_rpc_authority(),
+      "--lnd-rpc-cert-path",
+      receiver.cert_path().to_str().unwrap(),
+      "--lnd-rpc-macaroon-path",
+      receiver.invoice_macaroon_path().to_str().unwrap(),
+      "--url-signing-key",
+      "secret",
+    ],
+    |context| async move {
+      context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+      context.write("foo", "precious content");
+      let response = get(&context.files_url().join("foo").unwrap()).await;
+      let invoice_url = response.url().clone();
+      let html = Html::parse_document(&response.text().await.unwrap());
+      guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+      let payment_request = payment_request.text().collect::<String>();
+      receiver.fulfill_own_payment_request(&payment_request).await;
+      let response = get(&invoice_url).await;
+      let signed_url = response.url().clone();
+      assert_eq!(signed_url.path(), "/files/foo");
+      assert!(signed_url.query().unwrap().starts_with("token="));
+      assert_eq!(response.text().await.unwrap(), "precious content");
+      let mut tampered_url = signed_url.clone();
+      tampered_url.set_query(Some("token=00&expires=9999999999"));
+      assert_eq!(
+        reqwest::get(tampered_url).await.unwrap().status(),
+        StatusCode::FORBIDDEN
+      );
+    },
+  );
+}
+
+#[test]
+fn paying_invoice_allows_downloading_proxied_file() {
+  use std::io::{BufRead, BufReader};
+  let receiver = LndTestContext::new_blocking();
+  let upstream = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let upstream_url = format!("http://{}/foo", upstream.local_addr().unwrap());
+  std::thread::spawn(move || {
+    let (stream, _) = upstream.accept().unwrap();
+    let mut reader = BufReader::new(stream);
+    loop {
+      let mut line = String::new();
+      reader.read_line(&mut line).unwrap();
+      if line == "\r\n" {
+        break;
+      }
+    }
+    reader
+      .get_mut()
+      .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 16\r\n\r\nprecious content")
+      .unwrap();
+  });
+  test_with_lnd(&receiver.clone(), |context| async move {
+    context.write(
+      ".agora.yaml",
+      &format!(
+        "{{paid: true, base-price: 1000 sat, proxy: {{foo: '{}'}}}}",
+        upstream_url
+      ),
+    );
+    let response = get(&context.files_url().join("foo").unwrap()).await;
+    let invoice_url = response.url().clone();
+    let html = Html::parse_document(&response.text().await.unwrap());
+    guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+    let payment_request = payment_request.text().collect::<String>();
+    receiver.fulfill_own_payment_request(&payment_request).await;
+    assert_eq!(text(&invoice_url).await, "precious content");
+  });
+}
+
+#[test]
+fn paid_files_that_were_deleted_show_unavailable_page() {
+  let receiver = LndTestContext::new_blocking();
+  test_with_lnd(&receiver.clone(), |context| async move {
+    context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+    let file = context.write("foo", "precious content");
+    let response = get(&context.files_url().join("foo").unwrap()).await;
+    let invoice_url = response.url().clone();
+    let html = Html::parse_document(&response.text().await.unwrap());
+    guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+    let payment_request = payment_request.text().collect::<String>();
+    receiver.fulfill_own_payment_request(&payment_request).await;
+    std::fs::remove_file(file).unwrap();
+    let response = reqwest::get(invoice_url).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+    let html = Html::parse_document(&response.text().await.unwrap());
+    guard_unwrap!(let &[unavailable] = css_select(&html, ".paid-file-unavailable").as_slice());
+    assert_contains(
+      &unavailable.text().collect::<String>(),
+      "was received, but the file is no longer available",
+    );
+  });
+}
+
+#[test]
+fn files_are_sold_out_after_max_downloads() {
+  let receiver = LndTestContext::new_blocking();
+  test_with_lnd(&receiver.clone(), |context| async move {
+    context.write(
+      ".agora.yaml",
+      "{paid: true, base-price: 1000 sat, max-downloads: 1}",
+    );
+    context.write("foo", "precious content");
+    let response = get(&context.files_url().join("foo").unwrap()).await;
+    let invoice_url = response.url().clone();
+    let html = Html::parse_document(&response.text().await.unwrap());
+    guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+    let payment_request = payment_request.text().collect::<String>();
+    receiver.fulfill_own_payment_request(&payment_request).await;
+    assert_eq!(text(&invoice_url).await, "precious content");
+    assert_eq!(text(&invoice_url).await, "precious content");
+    let response = reqwest::get(context.files_url().join("foo").unwrap())
+      .await
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert_contains(&response.text().await.unwrap(), "is sold out");
+  });
+}
+
+#[test]
+fn lnurl_pay_invoices_give_access_to_files() {
+  let receiver = LndTestContext::new_blocking();
+  test_with_arguments(
+    &[
+      "--lnd-rpc-authority",
+      &receiver.lnd_rpc_authority(),
+      "--lnd-rpc-cert-path",
+      receiver.cert_path().to_str().unwrap(),
+      "--lnd-rpc-macaroon-path",
+      receiver.invoice_macaroon_path().to_str().unwrap(),
+      "--lnurl-base-url=https://example.com",
+    ],
+    |context| async move {
+      use lightning_invoice::Invoice;
+      context.write(".agora.yaml", "{paid: true, base-price: 1234 sat}");
+      context.write("foo", "precious content");
+      let pay_request = text(&context.base_url().join("lnurlp/foo").unwrap()).await;
+      let pay_request = serde_json::from_str::<serde_json::Value>(&pay_request).unwrap();
+      assert_eq!(pay_request["tag"], "payRequest");
+      assert_eq!(pay_request["callback"], "https://example.com/lnurlp/foo");
+      assert_eq!(pay_request["minSendable"], 1234 * 1000);
+      assert_eq!(pay_request["maxSendable"], 1234 * 1000);
+
+      let callback = text(
+        &context
+          .base_url()
+          .join("lnurlp/foo?amount=1234000")
+          .unwrap(),
+      )
+      .await;
+      let callback = serde_json::from_str::<serde_json::Value>(&callback).unwrap();
+      let payment_request = callback["pr"].as_str().unwrap();
+      let invoice = payment_request.parse::<Invoice>().unwrap();
+      assert_eq!(invoice.amount_milli_satoshis().unwrap(), 1234 * 1000);
+      receiver.fulfill_own_payment_request(payment_request).await;
+
+      let url = callback["successAction"]["url"].as_str().unwrap();
+      let path = url.strip_prefix("https://example.com/").unwrap();
+      assert_eq!(
+        text(&context.base_url().join(path).unwrap()).await,
+        "precious content"
+      );
+    },
+  );
+}
+
+#[test]
+fn lightning_addresses_create_invoices() {
+  let receiver = LndTestContext::new_blocking();
+  test_with_arguments(
+    &[
+      "--lnd-rpc-authority",
+      &receiver.lnd_rpc_authority(),
+      "--lnd-rpc-cert-path",
+      receiver.cert_path().to_str().unwrap(),
+      "--lnd-rpc-macaroon-path",
+      receiver.invoice_macaroon_path().to_str().unwrap(),
+      "--lnurl-base-url=https://example.com",
+    ],
+    |context| async move {
+      use lightning_invoice::Invoice;
+      context.write(
+        ".agora.yaml",
+        "{lightning-addresses: {alice: {amount: 21 sat}}}",
+      );
+      let callback = text(
+        &context
+          .base_url()
+          .join(".well-known/lnurlp/alice?amount=21000")
+          .unwrap(),
+      )
+      .await;
+      let callback = serde_json::from_str::<serde_json::Value>(&callback).unwrap();
+      assert_eq!(callback["successAction"], serde_json::Value::Null);
+      let payment_request = callback["pr"].as_str().unwrap();
+      let invoice = payment_request.parse::<Invoice>().unwrap();
+      assert_eq!(invoice.amount_milli_satoshis().unwrap(), 21 * 1000);
+      receiver.fulfill_own_payment_request(payment_request).await;
+    },
+  );
+}
+
+fn receive_webhook(listener: std::net::TcpListener) -> serde_json::Value {
+  use std::io::{BufRead, BufReader, Read};
+  let (stream, _) = listener.accept().unwrap();
+  let mut reader = BufReader::new(stream);
+  let mut content_length = 0;
+  loop {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    if line == "\r\n" {
+      break;
+    }
+    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+      content_length = value.trim().parse().unwrap();
+    }
+  }
+  let mut body = vec![0; content_length];
+  reader.read_exact(&mut body).unwrap();
+  reader
+    .get_mut()
+    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+    .unwrap();
+  serde_json::from_slice(&body).unwrap()
+}
+
+#[test]
+fn settled_invoices_are_posted_to_settlement_webhook() {
+  let receiver = LndTestContext::new_blocking();
+  let webhook = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let webhook_url = format!("http://{}/hook", webhook.local_addr().unwrap());
+  test_with_arguments(
+    &[
+      "--lnd-rpc-authority",
+      &receiver.lnd_rpc_authority(),
+      "--lnd-rpc-cert-path",
+      receiver.cert_path().to_str().unwrap(),
+      "--lnd-rpc-macaroon-path",
+      receiver.invoice_macaroon_path().to_str().unwrap(),
+      "--settlement-webhook",
+      &webhook_url,
+    ],
+    |context| async move {
+      use lightning_invoice::Invoice;
+      context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+      context.write("foo", "precious content");
+      let html = html(&context.files_url().join("foo").unwrap()).await;
+      guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+      let payment_request = payment_request.text().collect::<String>();
+      receiver.fulfill_own_payment_request(&payment_request).await;
+      let settlement = task::spawn_blocking(move || receive_webhook(webhook))
+        .await
+        .unwrap();
+      let invoice = payment_request.parse::<Invoice>().unwrap();
+      assert_eq!(
+        settlement,
+        serde_json::json!({
+          "payment_hash": hex::encode(&invoice.payment_hash()[..]),
+          "memo": "foo",
+          "amount_msat": 1_000_000,
+        })
+      );
+    },
+  );
+}
+
+#[test]
+fn paid_files_can_require_payment_confirmations() {
+  let receiver = LndTestContext::new_blocking();
+  test_with_arguments(
+    &[
+      "--lnd-rpc-authority",
+      &receiver.lnd_rpc_authority(),
+      "--lnd-rpc-cert-path",
+      receiver.cert_path().to_str().unwrap(),
+      "--lnd-rpc-macaroon-path",
+      receiver.admin_macaroon_path().to_str().unwrap(),
+      "--require-payment-confirmations=1",
+    ],
+    |context| async move {
+      context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+      context.write("foo", "precious content");
+      let response = get(&context.files_url().join("foo").unwrap()).await;
+      let invoice_url = response.url().clone();
+      let html = Html::parse_document(&response.text().await.unwrap());
+      guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+      let payment_request = payment_request.text().collect::<String>();
+      receiver.fulfill_own_payment_request(&payment_request).await;
+
+      let response = reqwest::get(invoice_url.clone()).await.unwrap();
+      assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+      assert_contains(
+        &response.text().await.unwrap(),
+        "will be available after 1 more confirmation.",
+      );
+
+      receiver.generate_blocks(1).await;
+      loop {
+        let response = reqwest::get(invoice_url.clone()).await.unwrap();
+        if response.status() == reqwest::StatusCode::OK {
+          assert_eq!(response.text().await.unwrap(), "precious content");
+          break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+      }
+    },
+  );
+}
+
 #[test]
 fn allows_configuring_invoice_amount() {
   test_with_lnd(&LndTestContext::new_blocking(), |context| async move {
@@ -202,6 +706,95 @@ fn allows_configuring_invoice_amount() {
   });
 }
 
+#[test]
+fn invoice_amount_includes_price_per_byte() {
+  test_with_lnd(&LndTestContext::new_blocking(), |context| async move {
+    use lightning_invoice::Invoice;
+    context.write(
+      ".agora.yaml",
+      "{paid: true, base-price: 1 sat, price-per-byte: 1 sat}",
+    );
+    context.write("foo", "precious content");
+    let response = get(&context.files_url().join("foo").unwrap()).await;
+    let html = Html::parse_document(&response.text().await.unwrap());
+    guard_unwrap!(let &[invoice_element] = css_select(&html, ".invoice").as_slice());
+    assert_contains(&invoice_element.inner_html(), "17 satoshis");
+    guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+    let payment_request = payment_request.text().collect::<String>();
+    let invoice = payment_request.parse::<Invoice>().unwrap();
+    assert_eq!(invoice.amount_milli_satoshis().unwrap(), 17 * 1000);
+  });
+}
+
+#[test]
+fn fiat_prices_are_converted_at_price_feed_rate() {
+  use {
+    lightning_invoice::Invoice,
+    std::io::{BufRead, BufReader},
+  };
+  let receiver = LndTestContext::new_blocking();
+  let price_feed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  let price_feed_url = format!("http://{}/rates", price_feed.local_addr().unwrap());
+  std::thread::spawn(move || {
+    let (stream, _) = price_feed.accept().unwrap();
+    let mut reader = BufReader::new(stream);
+    loop {
+      let mut line = String::new();
+      reader.read_line(&mut line).unwrap();
+      if line == "\r\n" {
+        break;
+      }
+    }
+    reader
+      .get_mut()
+      .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 15\r\n\r\n{\"USD\": 50000}")
+      .unwrap();
+  });
+  test_with_arguments(
+    &[
+      "--lnd-rpc-authority",
+      &receiver.lnd_rpc_authority(),
+      "--lnd-rpc-cert-path",
+      receiver.cert_path().to_str().unwrap(),
+      "--lnd-rpc-macaroon-path",
+      receiver.invoice_macaroon_path().to_str().unwrap(),
+      "--price-feed-url",
+      &price_feed_url,
+    ],
+    |context| async move {
+      context.write(".agora.yaml", "{paid: true, fiat-price: 1 USD}");
+      context.write("foo", "precious content");
+      let response = get(&context.files_url().join("foo").unwrap()).await;
+      let html = Html::parse_document(&response.text().await.unwrap());
+      guard_unwrap!(let &[invoice_element] = css_select(&html, ".invoice").as_slice());
+      assert_contains(&invoice_element.inner_html(), "2,000 satoshis (1 USD)");
+      guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+      let payment_request = payment_request.text().collect::<String>();
+      let invoice = payment_request.parse::<Invoice>().unwrap();
+      assert_eq!(invoice.amount_milli_satoshis().unwrap(), 2000 * 1000);
+    },
+  );
+}
+
+#[test]
+fn fiat_prices_without_price_feed_return_error() {
+  let stderr = test_with_lnd(&LndTestContext::new_blocking(), |context| async move {
+    context.write(".agora.yaml", "{paid: true, fiat-price: 1.99 USD}");
+    context.write("foo", "precious content");
+    let response = reqwest::get(context.files_url().join("foo").unwrap())
+      .await
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+  });
+  assert_contains(
+    &stderr,
+    &format!(
+      "File `www{}foo` has a fiat price, but no price feed was configured with `--price-feed-url`",
+      MAIN_SEPARATOR
+    ),
+  );
+}
+
 #[test]
 fn configuring_paid_without_base_price_returns_error() {
   let stderr = test_with_lnd(&LndTestContext::new_blocking(), |context| async move {
@@ -283,6 +876,27 @@ fn warns_when_lnd_is_unreachable_at_startup() {
     &stderr,
     "warning: Cannot connect to LND gRPC server at `127.0.0.1:12345`: LND RPC call failed: ",
   );
+  assert_contains(&stderr, ", retrying in the background");
+}
+
+#[test]
+fn retries_connecting_to_lnd_at_startup() {
+  let context = LndTestContext::new_blocking();
+  let stderr = test_with_arguments(
+    &[
+      "--lnd-rpc-authority",
+      "127.0.0.1:12345",
+      "--lnd-rpc-cert-path",
+      context.cert_path().to_str().unwrap(),
+      "--lnd-rpc-macaroon-path",
+      context.invoice_macaroon_path().to_str().unwrap(),
+      "--lnd-connect-retries=1",
+      "--lnd-connect-timeout=5",
+    ],
+    |_context| async move {},
+  );
+  assert_contains(&stderr, ", retrying in 1s");
+  assert_contains(&stderr, ", retrying in the background");
 }
 
 #[test]
@@ -387,6 +1001,43 @@ fn payment_request_memo_decodes_percent() {
   });
 }
 
+#[test]
+fn invoice_memos_can_be_templated() {
+  use lightning_invoice::{Description, Invoice, InvoiceDescription};
+
+  let receiver = LndTestContext::new_blocking();
+  test_with_arguments(
+    &[
+      "--lnd-rpc-authority",
+      &receiver.lnd_rpc_authority(),
+      "--lnd-rpc-cert-path",
+      receiver.cert_path().to_str().unwrap(),
+      "--lnd-rpc-macaroon-path",
+      receiver.invoice_macaroon_path().to_str().unwrap(),
+      "--invoice-memo-template",
+      "Agora: {path} ({price})",
+    ],
+    |context| async move {
+      context.write(".agora.yaml", "{paid: true, base-price: 1000 sat}");
+      context.write("foo", "precious content");
+      let response = get(&context.files_url().join("foo").unwrap()).await;
+      let invoice_url = response.url().clone();
+      let html = Html::parse_document(&response.text().await.unwrap());
+      guard_unwrap!(let &[payment_request] = css_select(&html, ".payment-request").as_slice());
+      let payment_request = payment_request.text().collect::<String>();
+      let invoice = payment_request.parse::<Invoice>().unwrap();
+      assert_eq!(
+        invoice.description(),
+        InvoiceDescription::Direct(
+          &Description::new("Agora: foo (1,000 satoshis)".to_string()).unwrap()
+        )
+      );
+      receiver.fulfill_own_payment_request(&payment_request).await;
+      assert_eq!(text(&invoice_url).await, "precious content");
+    },
+  );
+}
+
 #[test]
 fn filenames_with_percent_encoding() {
   let receiver = LndTestContext::new_blocking();
@@ -0,0 +1,94 @@
+use {
+  crate::common::*,
+  hyper::body::Bytes,
+  pin_project::pin_project,
+  std::{future::Future, time::Duration},
+  tokio::time::{Instant, Sleep},
+};
+
+/// Limits the throughput of a stream of chunks to `bytes_per_second`. Each
+/// chunk is passed on as soon as it's ready, and the next one is delayed
+/// until the chunks so far would have taken that long at the limit.
+#[pin_project]
+pub(crate) struct Throttle<S> {
+  #[pin]
+  stream: S,
+  bytes_per_second: NonZeroU64,
+  start: Option<Instant>,
+  sent: u64,
+  delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Throttle<S> {
+  pub(crate) fn new(stream: S, bytes_per_second: NonZeroU64) -> Self {
+    Self {
+      stream,
+      bytes_per_second,
+      start: None,
+      sent: 0,
+      delay: None,
+    }
+  }
+}
+
+impl<S, E> Stream for Throttle<S>
+where
+  S: Stream<Item = Result<Bytes, E>>,
+{
+  type Item = S::Item;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let projected = self.project();
+
+    if let Some(delay) = projected.delay {
+      futures::ready!(delay.as_mut().poll(cx));
+      *projected.delay = None;
+    }
+
+    let item = futures::ready!(projected.stream.poll_next(cx));
+
+    if let Some(Ok(chunk)) = &item {
+      let start = *projected.start.get_or_insert_with(Instant::now);
+      *projected.sent += chunk.len() as u64;
+      let due = start
+        + Duration::from_secs_f64(*projected.sent as f64 / projected.bytes_per_second.get() as f64);
+      if due > Instant::now() {
+        *projected.delay = Some(Box::pin(tokio::time::sleep_until(due)));
+      }
+    }
+
+    Poll::Ready(item)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn limits_throughput() {
+    let chunks = vec![Ok::<_, Infallible>(Bytes::from(vec![0; 100])); 3];
+    let mut throttle = Throttle::new(
+      futures::stream::iter(chunks),
+      NonZeroU64::new(1000).unwrap(),
+    );
+    let start = Instant::now();
+    let mut received = 0;
+    while let Some(chunk) = throttle.next().await {
+      received += chunk.unwrap().len();
+    }
+    assert_eq!(received, 300);
+    assert!(start.elapsed() >= Duration::from_millis(300));
+  }
+
+  #[tokio::test]
+  async fn first_chunk_is_not_delayed() {
+    let mut throttle = Throttle::new(
+      futures::stream::iter(vec![Ok::<_, Infallible>(Bytes::from(vec![0; 100]))]),
+      NonZeroU64::new(1).unwrap(),
+    );
+    let start = Instant::now();
+    throttle.next().await.unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1));
+  }
+}
@@ -0,0 +1,51 @@
+use {crate::common::*, tokio_rustls::rustls::ProtocolVersion};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) enum TlsVersion {
+  #[serde(rename = "1.2")]
+  Tls12,
+  #[serde(rename = "1.3")]
+  Tls13,
+}
+
+impl TlsVersion {
+  /// Protocol versions to accept when this is the minimum version.
+  pub(crate) fn accepted_versions(self) -> Vec<ProtocolVersion> {
+    match self {
+      Self::Tls12 => vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+      Self::Tls13 => vec![ProtocolVersion::TLSv1_3],
+    }
+  }
+}
+
+impl str::FromStr for TlsVersion {
+  type Err = Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    match input {
+      "1.2" => Ok(Self::Tls12),
+      "1.3" => Ok(Self::Tls13),
+      _ => Err(
+        error::TlsVersionParse {
+          input: input.to_owned(),
+        }
+        .build(),
+      ),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    assert_eq!("1.2".parse::<TlsVersion>().unwrap(), TlsVersion::Tls12);
+    assert_eq!("1.3".parse::<TlsVersion>().unwrap(), TlsVersion::Tls13);
+    assert_matches!(
+      "1.1".parse::<TlsVersion>(),
+      Err(Error::TlsVersionParse { input, .. }) if input == "1.1"
+    );
+  }
+}
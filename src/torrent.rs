@@ -0,0 +1,235 @@
+use {
+  crate::common::*,
+  openssl::sha::Sha1,
+  std::{collections::BTreeMap, io::Read, sync::Mutex},
+};
+
+const MIN_PIECE_LENGTH: u64 = 256 * 1024;
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+const TARGET_PIECE_COUNT: u64 = 1500;
+
+/// Generated torrents, by path, along with the size and modification time
+/// of the file they were generated from.
+type Cache = BTreeMap<PathBuf, (u64, SystemTime, Arc<Vec<u8>>)>;
+
+/// Generates single-file `.torrent` metainfo for large files, using the
+/// file's direct download URL as a web seed. Generated torrents are cached
+/// until the file's size or modification time changes.
+#[derive(Clone, Debug)]
+pub(crate) struct Torrents {
+  min_size: u64,
+  /// URL of the files route, like `https://example.com/files/`
+  files_url: String,
+  trackers: Vec<String>,
+  cache: Arc<Mutex<Cache>>,
+}
+
+impl Torrents {
+  pub(crate) fn new(arguments: &Arguments) -> Option<Self> {
+    Some(Self {
+      min_size: arguments.torrent_min_size?,
+      files_url: format!(
+        "{}{}{}",
+        arguments.torrent_base_url.as_ref()?.trim_end_matches('/'),
+        arguments.base_path(),
+        arguments.files_path()
+      ),
+      trackers: arguments.torrent_tracker.clone(),
+      cache: Arc::new(Mutex::new(BTreeMap::new())),
+    })
+  }
+
+  pub(crate) fn offered(&self, file_size: u64) -> bool {
+    file_size >= self.min_size
+  }
+
+  pub(crate) fn web_seed(&self, encoded_path: &str) -> String {
+    format!("{}{}", self.files_url, encoded_path)
+  }
+
+  pub(crate) async fn get(&self, path: &InputPath, web_seed: String) -> Result<Arc<Vec<u8>>> {
+    let metadata = path
+      .as_ref()
+      .metadata()
+      .with_context(|| Error::filesystem_io(path))?;
+    let len = metadata.len();
+    let modified = metadata
+      .modified()
+      .with_context(|| Error::filesystem_io(path))?;
+
+    if let Some((cached_len, cached_modified, torrent)) =
+      self.cache.lock().unwrap().get(path.as_ref())
+    {
+      if *cached_len == len && *cached_modified == modified {
+        return Ok(torrent.clone());
+      }
+    }
+
+    let trackers = self.trackers.clone();
+    let blocking_path = path.clone();
+    let torrent =
+      task::spawn_blocking(move || Self::generate(&blocking_path, len, &web_seed, &trackers))
+        .await
+        .map_err(|error| Error::internal(format!("Torrent generation failed: {}", error)))??;
+    let torrent = Arc::new(torrent);
+
+    self
+      .cache
+      .lock()
+      .unwrap()
+      .insert(path.as_ref().to_owned(), (len, modified, torrent.clone()));
+
+    Ok(torrent)
+  }
+
+  fn piece_length(len: u64) -> u64 {
+    let mut piece_length = MIN_PIECE_LENGTH;
+    while piece_length < MAX_PIECE_LENGTH && len / piece_length > TARGET_PIECE_COUNT {
+      piece_length *= 2;
+    }
+    piece_length
+  }
+
+  fn generate(path: &InputPath, len: u64, web_seed: &str, trackers: &[String]) -> Result<Vec<u8>> {
+    let piece_length = Self::piece_length(len);
+
+    let mut file = fs::File::open(path).with_context(|| Error::filesystem_io(path))?;
+    let mut pieces = Vec::new();
+    let mut buffer = vec![0; piece_length as usize];
+    loop {
+      let mut filled = 0;
+      while filled < buffer.len() {
+        let read = file
+          .read(&mut buffer[filled..])
+          .with_context(|| Error::filesystem_io(path))?;
+        if read == 0 {
+          break;
+        }
+        filled += read;
+      }
+      if filled == 0 {
+        break;
+      }
+      let mut hasher = Sha1::new();
+      hasher.update(&buffer[..filled]);
+      pieces.extend_from_slice(&hasher.finish());
+      if filled < buffer.len() {
+        break;
+      }
+    }
+
+    let name = path
+      .display_path()
+      .file_name()
+      .map(|name| name.to_string_lossy().into_owned())
+      .unwrap_or_default();
+
+    let mut info = BTreeMap::new();
+    info.insert("length", Value::Integer(len));
+    info.insert("name", Value::string(&name));
+    info.insert("piece length", Value::Integer(piece_length));
+    info.insert("pieces", Value::Bytes(pieces));
+
+    let mut torrent = BTreeMap::new();
+    if let Some(tracker) = trackers.first() {
+      torrent.insert("announce", Value::string(tracker));
+    }
+    if trackers.len() > 1 {
+      torrent.insert(
+        "announce-list",
+        Value::List(
+          trackers
+            .iter()
+            .map(|tracker| Value::List(vec![Value::string(tracker)]))
+            .collect(),
+        ),
+      );
+    }
+    torrent.insert("info", Value::Dictionary(info));
+    torrent.insert("url-list", Value::List(vec![Value::string(web_seed)]));
+
+    let mut output = Vec::new();
+    Value::Dictionary(torrent).encode(&mut output);
+    Ok(output)
+  }
+}
+
+/// A bencoded value. Dictionary keys are kept sorted, as bencoding requires.
+enum Value {
+  Bytes(Vec<u8>),
+  Dictionary(BTreeMap<&'static str, Value>),
+  Integer(u64),
+  List(Vec<Value>),
+}
+
+impl Value {
+  fn string(s: &str) -> Self {
+    Value::Bytes(s.as_bytes().to_vec())
+  }
+
+  fn encode(&self, output: &mut Vec<u8>) {
+    match self {
+      Value::Bytes(bytes) => {
+        output.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+        output.extend_from_slice(bytes);
+      }
+      Value::Dictionary(dictionary) => {
+        output.push(b'd');
+        for (key, value) in dictionary {
+          Value::string(key).encode(output);
+          value.encode(output);
+        }
+        output.push(b'e');
+      }
+      Value::Integer(integer) => {
+        output.extend_from_slice(format!("i{}e", integer).as_bytes());
+      }
+      Value::List(list) => {
+        output.push(b'l');
+        for value in list {
+          value.encode(output);
+        }
+        output.push(b'e');
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn piece_length() {
+    assert_eq!(Torrents::piece_length(0), MIN_PIECE_LENGTH);
+    assert_eq!(Torrents::piece_length(1024 * 1024 * 1024), 1024 * 1024);
+    assert_eq!(Torrents::piece_length(u64::MAX), MAX_PIECE_LENGTH);
+  }
+
+  #[test]
+  fn generate() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("foo"), "foo").unwrap();
+    let path = InputPath::new_unchecked(temp_dir.path(), "foo");
+    let torrent = Torrents::generate(
+      &path,
+      3,
+      "https://example.com/files/foo",
+      &["udp://a.example.com".into(), "udp://b.example.com".into()],
+    )
+    .unwrap();
+
+    let mut expected = b"d\
+      8:announce19:udp://a.example.com\
+      13:announce-listll19:udp://a.example.comel19:udp://b.example.comee\
+      4:infod6:lengthi3e4:name3:foo12:piece lengthi262144e6:pieces20:"
+      .to_vec();
+    expected.extend_from_slice(&hex::decode("0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33").unwrap());
+    expected.extend_from_slice(b"e8:url-listl29:https://example.com/files/fooee");
+
+    assert_eq!(
+      String::from_utf8_lossy(&torrent),
+      String::from_utf8_lossy(&expected)
+    );
+  }
+}
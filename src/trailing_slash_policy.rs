@@ -0,0 +1,69 @@
+use crate::common::*;
+
+/// How requests for directories and files are redirected based on whether
+/// their URL path ends with a slash.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum TrailingSlashPolicy {
+  /// Redirect directories to paths with, and files to paths without, a trailing slash.
+  Enforce,
+  /// Never redirect.
+  Off,
+  /// Redirect directories to paths without, and files to paths with, a trailing slash.
+  Inverse,
+}
+
+impl TrailingSlashPolicy {
+  /// The canonical trailing slash for a directory or file, or `None` if
+  /// either form is served.
+  pub(crate) fn trailing_slash(self, is_dir: bool) -> Option<bool> {
+    match self {
+      Self::Enforce => Some(is_dir),
+      Self::Off => None,
+      Self::Inverse => Some(!is_dir),
+    }
+  }
+}
+
+impl str::FromStr for TrailingSlashPolicy {
+  type Err = Error;
+
+  fn from_str(input: &str) -> Result<Self> {
+    match input {
+      "enforce" => Ok(Self::Enforce),
+      "off" => Ok(Self::Off),
+      "inverse" => Ok(Self::Inverse),
+      _ => Err(
+        error::TrailingSlashPolicyParse {
+          input: input.to_owned(),
+        }
+        .build(),
+      ),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    assert_eq!(
+      "enforce".parse::<TrailingSlashPolicy>().unwrap(),
+      TrailingSlashPolicy::Enforce
+    );
+    assert_eq!(
+      "off".parse::<TrailingSlashPolicy>().unwrap(),
+      TrailingSlashPolicy::Off
+    );
+    assert_eq!(
+      "inverse".parse::<TrailingSlashPolicy>().unwrap(),
+      TrailingSlashPolicy::Inverse
+    );
+    assert_matches!(
+      "always".parse::<TrailingSlashPolicy>(),
+      Err(Error::TrailingSlashPolicyParse { input, .. }) if input == "always"
+    );
+  }
+}
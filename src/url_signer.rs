@@ -0,0 +1,120 @@
+use {
+  crate::common::*,
+  openssl::{
+    hash::MessageDigest,
+    memcmp,
+    pkey::{PKey, Private},
+    sign::Signer,
+  },
+  std::time::Duration,
+};
+
+/// Signs URLs of paid files with `--url-signing-key`, so that once a file's
+/// invoice is settled, it can be downloaded until the URL expires, without
+/// looking up the invoice in LND for every request.
+#[derive(Clone)]
+pub(crate) struct UrlSigner {
+  key: PKey<Private>,
+}
+
+impl fmt::Debug for UrlSigner {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_struct("UrlSigner")
+      .field("key", &"<redacted>")
+      .finish()
+  }
+}
+
+impl UrlSigner {
+  pub(crate) const LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+  pub(crate) fn new(key: &str) -> Result<Self> {
+    Ok(Self {
+      key: PKey::hmac(key.as_bytes()).context(error::UrlSign)?,
+    })
+  }
+
+  /// Query string of a signed URL for `file_path`, which expires
+  /// `LIFETIME` after `now`.
+  pub(crate) fn query(&self, file_path: &str, now: SystemTime) -> Result<String> {
+    let expires = Self::timestamp(now + Self::LIFETIME);
+    Ok(format!(
+      "token={}&expires={}",
+      hex::encode(self.token(file_path, expires)?),
+      expires
+    ))
+  }
+
+  /// Whether `token` is a valid signature for `file_path` and `expires`.
+  pub(crate) fn verify(&self, file_path: &str, expires: u64, token: &str) -> Result<bool> {
+    let token = match hex::decode(token) {
+      Ok(token) => token,
+      Err(_) => return Ok(false),
+    };
+    let expected = self.token(file_path, expires)?;
+    Ok(token.len() == expected.len() && memcmp::eq(&token, &expected))
+  }
+
+  pub(crate) fn expired(expires: u64, now: SystemTime) -> bool {
+    Self::timestamp(now) >= expires
+  }
+
+  fn token(&self, file_path: &str, expires: u64) -> Result<Vec<u8>> {
+    let mut signer = Signer::new(MessageDigest::sha256(), &self.key).context(error::UrlSign)?;
+    signer
+      .update(format!("{}\n{}", file_path, expires).as_bytes())
+      .context(error::UrlSign)?;
+    signer.sign_to_vec().context(error::UrlSign)
+  }
+
+  fn timestamp(time: SystemTime) -> u64 {
+    time
+      .duration_since(SystemTime::UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_secs()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(query: &str) -> (String, u64) {
+    let mut token = None;
+    let mut expires = None;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+      match key.as_ref() {
+        "token" => token = Some(value.into_owned()),
+        "expires" => expires = Some(value.parse().unwrap()),
+        _ => panic!("unexpected query parameter `{}`", key),
+      }
+    }
+    (token.unwrap(), expires.unwrap())
+  }
+
+  #[test]
+  fn signed_urls_are_valid_until_they_expire() {
+    let signer = UrlSigner::new("key").unwrap();
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let (token, expires) = parse(&signer.query("foo", now).unwrap());
+    assert_eq!(expires, 1_000_000 + 3600);
+    assert!(signer.verify("foo", expires, &token).unwrap());
+    assert!(!UrlSigner::expired(expires, now));
+    assert!(UrlSigner::expired(expires, now + UrlSigner::LIFETIME));
+  }
+
+  #[test]
+  fn tampered_urls_are_invalid() {
+    let signer = UrlSigner::new("key").unwrap();
+    let now = SystemTime::UNIX_EPOCH;
+    let (token, expires) = parse(&signer.query("foo", now).unwrap());
+    assert!(!signer.verify("bar", expires, &token).unwrap());
+    assert!(!signer.verify("foo", expires + 1, &token).unwrap());
+    assert!(!signer.verify("foo", expires, "00").unwrap());
+    assert!(!signer.verify("foo", expires, "xyz").unwrap());
+    assert!(!UrlSigner::new("other key")
+      .unwrap()
+      .verify("foo", expires, &token)
+      .unwrap());
+  }
+}
@@ -1,41 +1,284 @@
-use crate::common::*;
+use {crate::common::*, std::collections::BTreeSet};
+mod basic_auth;
 mod config;
+mod content_types;
+mod deny_extensions;
+mod fiat_price;
+mod force_download;
+mod invoice_expiry;
+mod lightning_addresses;
+mod meta;
+mod price_per_byte;
+mod proxy;
+mod splits;
 
-use config::Config;
+pub(crate) use {
+  config::AmountPrecision, fiat_price::FiatPrice, lightning_addresses::LightningAddress,
+  meta::Meta, splits::Splits,
+};
+
+use {
+  basic_auth::BasicAuth, config::Config, content_types::ContentTypes,
+  deny_extensions::DenyExtensions, force_download::ForceDownload, hyper::Uri,
+  invoice_expiry::InvoiceExpiry, lightning_addresses::LightningAddresses,
+  price_per_byte::PricePerByte, proxy::Proxy,
+};
 
 #[derive(Debug, Clone)]
 pub(crate) struct Vfs {
   base_directory: InputPath,
+  fast_listing: bool,
+  follow_escaping_symlinks: bool,
+  global_config: Config,
+  group_directories_first: bool,
+  index_filenames: Vec<String>,
+  inherit_partials: bool,
 }
 
 impl Vfs {
-  pub(crate) fn new(base_directory: InputPath) -> Self {
-    Self { base_directory }
+  pub(crate) fn new(
+    base_directory: InputPath,
+    global_config: Option<&InputPath>,
+    follow_escaping_symlinks: bool,
+    fast_listing: bool,
+    group_directories_first: bool,
+    inherit_partials: bool,
+    index_filenames: Vec<String>,
+  ) -> Result<Self> {
+    Ok(Self {
+      base_directory,
+      fast_listing,
+      follow_escaping_symlinks,
+      group_directories_first,
+      index_filenames,
+      inherit_partials,
+      global_config: match global_config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+      },
+    })
+  }
+
+  /// Check the `.agora.yaml` files in `directory` and its subdirectories,
+  /// returning an error for each invalid one.
+  pub(crate) fn check_configs(directory: &Path) -> Result<Vec<Error>> {
+    Config::check_tree(directory)
   }
 
   fn config(&self, path: &InputPath) -> Result<Config> {
-    Config::for_dir(
-      self.base_directory.as_ref(),
-      path.as_ref().parent().ok_or_else(|| {
-        Error::internal(format!(
-          "Path {} has no parent",
-          path.display_path().display()
-        ))
-      })?,
-    )
+    self.dir_config(path.as_ref().parent().ok_or_else(|| {
+      Error::internal(format!(
+        "Path {} has no parent",
+        path.display_path().display()
+      ))
+    })?)
   }
 
-  /// If an `.index.md` file exists in this directory, return its contents as a string.
+  fn dir_config(&self, dir: &Path) -> Result<Config> {
+    self
+      .global_config
+      .for_dir(self.base_directory.as_ref(), dir)
+  }
+
+  /// If the markdown file named by `index` in `.agora.yaml`, or else the
+  /// first of `--index-filenames`, which are matched ignoring case, exists
+  /// in this directory, return its contents as a string. Paid files are
+  /// skipped, so their contents aren't shown for free.
   pub(crate) fn index_file_markdown(&self, dir_path: &InputPath) -> Result<Option<String>> {
     self.check_path(&dir_path)?;
-    let file = dir_path.join_relative(".index.md".as_ref())?;
-    match fs::read_to_string(&file) {
+    if let Some(index) = self.dir_config(dir_path.as_ref())?.index {
+      if index.file_name() != Some(index.as_os_str()) {
+        return Err(
+          error::IndexFileName {
+            dir: dir_path.display_path(),
+            index,
+          }
+          .build(),
+        );
+      }
+      let file = dir_path.join_relative(&index)?;
+      if file.as_ref().exists() && self.index_file_allowed(&file, &index.to_string_lossy())? {
+        if let Some(markdown) = Self::read_optional(&file)? {
+          return Ok(Some(markdown));
+        }
+      }
+    }
+    for name in &self.index_filenames {
+      let file = match Self::find_ignoring_case(dir_path, name)? {
+        Some(file) => file,
+        None => continue,
+      };
+      if !self.index_file_allowed(&file, name)? {
+        continue;
+      }
+      if let Some(markdown) = Self::read_optional(&file)? {
+        return Ok(Some(markdown));
+      }
+    }
+    Ok(None)
+  }
+
+  /// Whether the index file `file`, named `name`, may be shown. Hidden index
+  /// files, like `.index.md`, can't be downloaded, so only escaping symlinks
+  /// are rejected. Other index files are checked like any other file, and
+  /// skipped if they're paid.
+  fn index_file_allowed(&self, file: &InputPath, name: &str) -> Result<bool> {
+    if name.starts_with('.') {
+      if !self.follow_escaping_symlinks
+        && escapes_base(self.base_directory.as_ref(), file.as_ref()).unwrap_or(false)
+      {
+        return Err(
+          error::SymlinkAccess {
+            path: file.display_path(),
+          }
+          .build(),
+        );
+      }
+      Ok(true)
+    } else {
+      Ok(!self.paid(file)?)
+    }
+  }
+
+  /// The file in `dir_path` named `name`, ignoring ASCII case. An exact
+  /// match is preferred, even if it isn't a file, so that reading it fails
+  /// loudly. Otherwise the first matching file in byte order is chosen, so
+  /// the choice doesn't depend on the order of directory entries.
+  fn find_ignoring_case(dir_path: &InputPath, name: &str) -> Result<Option<InputPath>> {
+    let exact = dir_path.join_relative(name.as_ref())?;
+    if exact.as_ref().exists() {
+      return Ok(Some(exact));
+    }
+    let mut matches = Vec::new();
+    for entry in fs::read_dir(dir_path).with_context(|| Error::filesystem_io(dir_path))? {
+      let file_name = entry
+        .with_context(|| Error::filesystem_io(dir_path))?
+        .file_name();
+      if file_name.to_string_lossy().eq_ignore_ascii_case(name) {
+        matches.push(file_name);
+      }
+    }
+    matches.sort();
+    for file_name in matches {
+      let file = dir_path.join_relative(file_name.as_ref())?;
+      if file.as_ref().is_file() {
+        return Ok(Some(file));
+      }
+    }
+    Ok(None)
+  }
+
+  /// If a `.header.md` file exists in this directory, return its contents,
+  /// to be rendered above the listing.
+  pub(crate) fn header_file_markdown(&self, dir_path: &InputPath) -> Result<Option<String>> {
+    self.partial_markdown(dir_path, ".header.md")
+  }
+
+  /// If a `.footer.md` file exists in this directory, return its contents,
+  /// to be rendered below the listing and index.
+  pub(crate) fn footer_file_markdown(&self, dir_path: &InputPath) -> Result<Option<String>> {
+    self.partial_markdown(dir_path, ".footer.md")
+  }
+
+  /// With `--inherit-partials`, directories without a partial use that of
+  /// their closest ancestor within the base directory.
+  fn partial_markdown(&self, dir_path: &InputPath, file_name: &str) -> Result<Option<String>> {
+    self.check_path(dir_path)?;
+    let mut dir = dir_path.clone();
+    loop {
+      let file = dir.join_relative(file_name.as_ref())?;
+      if !self.follow_escaping_symlinks
+        && escapes_base(self.base_directory.as_ref(), file.as_ref()).unwrap_or(false)
+      {
+        return Err(
+          error::SymlinkAccess {
+            path: file.display_path(),
+          }
+          .build(),
+        );
+      }
+      if let Some(markdown) = Self::read_optional(&file)? {
+        return Ok(Some(markdown));
+      }
+      if !self.inherit_partials
+        || !dir.as_ref().starts_with(self.base_directory.as_ref())
+        || dir.as_ref() == self.base_directory.as_ref()
+      {
+        return Ok(None);
+      }
+      dir = dir.join_relative("..".as_ref())?;
+    }
+  }
+
+  /// If an `index.html` file exists in this directory and is free, return
+  /// its path.
+  pub(crate) fn index_html(&self, dir_path: &InputPath) -> Result<Option<InputPath>> {
+    self.check_path(dir_path)?;
+    let file = dir_path.join_relative("index.html".as_ref())?;
+    if !file.as_ref().is_file() {
+      return Ok(None);
+    }
+    self.check_path(&file)?;
+    if self.paid(&file)? {
+      return Ok(None);
+    }
+    Ok(Some(file))
+  }
+
+  /// If a free file named `file_name`, like `favicon.ico`, exists in the
+  /// base directory, return its path, so it can be served instead of the
+  /// built-in icon of the same name.
+  pub(crate) fn site_icon(&self, file_name: &str) -> Result<Option<InputPath>> {
+    let file = self.base_directory.join_file_path(file_name)?;
+    if !file.as_ref().is_file() {
+      return Ok(None);
+    }
+    self.check_path(&file)?;
+    if self.paid(&file)? {
+      return Ok(None);
+    }
+    Ok(Some(file))
+  }
+
+  /// If a pre-compressed copy of the file at `path` exists next to it, named
+  /// like it with `extension` appended, like `foo.js.gz` for `foo.js`,
+  /// return its path.
+  pub(crate) fn precompressed(
+    &self,
+    path: &InputPath,
+    extension: &str,
+  ) -> Result<Option<InputPath>> {
+    let mut file_name = match path.as_ref().file_name() {
+      Some(file_name) => file_name.to_owned(),
+      None => return Ok(None),
+    };
+    file_name.push(extension);
+    let file = path.join_relative(&Path::new("..").join(file_name))?;
+    if !file.as_ref().is_file() {
+      return Ok(None);
+    }
+    self.check_path(&file)?;
+    Ok(Some(file))
+  }
+
+  fn read_optional(file: &InputPath) -> Result<Option<String>> {
+    match fs::read_to_string(file) {
       Ok(markdown) => Ok(Some(markdown)),
       Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
-      Err(source) => Err(Error::filesystem_io(&file).into_error(source)),
+      Err(source) => Err(Error::filesystem_io(file).into_error(source)),
     }
   }
 
+  pub(crate) fn index_toc(&self, dir_path: &InputPath) -> Result<bool> {
+    self.check_path(dir_path)?;
+    Ok(self.dir_config(dir_path.as_ref())?.index_toc())
+  }
+
+  pub(crate) fn listing(&self, dir_path: &InputPath) -> Result<bool> {
+    self.check_path(dir_path)?;
+    Ok(self.dir_config(dir_path.as_ref())?.listing())
+  }
+
   pub(crate) fn paid(&self, path: &InputPath) -> Result<bool> {
     self.check_path(path)?;
     Ok(self.config(path)?.paid())
@@ -46,15 +289,263 @@ impl Vfs {
     Ok(self.config(path)?.base_price)
   }
 
+  /// Price of the paid file at `path` in a fiat currency, which takes
+  /// precedence over its `base-price`.
+  pub(crate) fn fiat_price(&self, path: &InputPath) -> Result<Option<FiatPrice>> {
+    self.check_path(path)?;
+    Ok(self.config(path)?.fiat_price)
+  }
+
+  pub(crate) fn price_per_byte(&self, path: &InputPath) -> Result<Option<Millisatoshi>> {
+    self.check_path(path)?;
+    Ok(
+      self
+        .config(path)?
+        .price_per_byte
+        .map(PricePerByte::millisatoshi),
+    )
+  }
+
+  /// Expiry of invoices for `path` in seconds, or `None` for LND's default.
+  pub(crate) fn invoice_expiry(&self, path: &InputPath) -> Result<Option<i64>> {
+    self.check_path(path)?;
+    Ok(
+      self
+        .config(path)?
+        .invoice_expiry
+        .map(InvoiceExpiry::seconds),
+    )
+  }
+
+  /// Content type for `path` configured with `content-types`, if any.
+  pub(crate) fn content_type(&self, path: &InputPath) -> Result<Option<String>> {
+    self.check_path(path)?;
+    let file_name = match path.as_ref().file_name() {
+      Some(file_name) => file_name.to_string_lossy(),
+      None => return Ok(None),
+    };
+    Ok(
+      self
+        .config(path)?
+        .content_types
+        .and_then(|content_types| content_types.get(&file_name).map(str::to_owned)),
+    )
+  }
+
+  /// Shares of payments for `path` to forward to other nodes.
+  pub(crate) fn splits(&self, path: &InputPath) -> Result<Option<Splits>> {
+    self.check_path(path)?;
+    Ok(self.config(path)?.splits)
+  }
+
+  pub(crate) fn invoice_amount_precision(&self, path: &InputPath) -> Result<AmountPrecision> {
+    self.check_path(path)?;
+    Ok(self.config(path)?.invoice_amount_precision())
+  }
+
+  pub(crate) fn max_downloads(&self, path: &InputPath) -> Result<Option<u64>> {
+    self.check_path(&path)?;
+    Ok(self.config(path)?.max_downloads)
+  }
+
+  /// The lightning address with `username`, configured with
+  /// `lightning-addresses` in `--config` or the base directory's
+  /// `.agora.yaml`.
+  pub(crate) fn lightning_address(&self, username: &str) -> Result<Option<LightningAddress>> {
+    Ok(
+      self
+        .dir_config(self.base_directory.as_ref())?
+        .lightning_addresses
+        .and_then(|addresses| addresses.get(username).cloned()),
+    )
+  }
+
+  /// Credentials required to access the file or directory at `path`,
+  /// configured with `basic-auth` in its directory or one of its parents.
+  pub(crate) fn basic_auth(&self, path: &InputPath, is_dir: bool) -> Result<Option<BasicAuth>> {
+    self.check_path(path)?;
+    let config = if is_dir {
+      self.dir_config(path.as_ref())?
+    } else {
+      self.config(path)?
+    };
+    Ok(config.basic_auth)
+  }
+
+  /// Whether `path` is served as an attachment, because its name matches
+  /// `force-download`.
+  pub(crate) fn force_download(&self, path: &InputPath) -> Result<bool> {
+    self.check_path(path)?;
+    let file_name = match path.as_ref().file_name() {
+      Some(file_name) => file_name.to_string_lossy(),
+      None => return Ok(false),
+    };
+    Ok(
+      self
+        .config(path)?
+        .force_download
+        .map_or(false, |force_download| force_download.matches(&file_name)),
+    )
+  }
+
+  /// Reject files whose extension is in `deny-extensions`, with the same
+  /// error as hidden files, so that they can't be told apart from missing
+  /// files.
+  pub(crate) fn check_extension(&self, path: &InputPath) -> Result<()> {
+    let file_name = match path.as_ref().file_name() {
+      Some(file_name) => file_name.to_string_lossy(),
+      None => return Ok(()),
+    };
+    if self
+      .config(path)?
+      .deny_extensions
+      .map_or(false, |deny_extensions| deny_extensions.matches(&file_name))
+    {
+      return Err(
+        error::HiddenFileAccess {
+          path: path.as_ref().to_owned(),
+        }
+        .build(),
+      );
+    }
+    Ok(())
+  }
+
+  /// Number of bytes at the start of the paid file at `path` that are
+  /// served for free as a preview, configured with `preview-bytes`.
+  pub(crate) fn preview_bytes(&self, path: &InputPath) -> Result<Option<u64>> {
+    self.check_path(path)?;
+    Ok(self.config(path)?.preview_bytes)
+  }
+
+  pub(crate) fn dir_extra_assets(&self, dir: &InputPath) -> Result<ExtraAssets> {
+    self.check_path(dir)?;
+    self.extra_assets(self.dir_config(dir.as_ref())?)
+  }
+
+  pub(crate) fn file_extra_assets(&self, path: &InputPath) -> Result<ExtraAssets> {
+    self.check_path(path)?;
+    self.extra_assets(self.config(path)?)
+  }
+
+  /// OpenGraph metadata for the paid file at `path`. Image paths are
+  /// checked, and returned `/`-separated relative to the base directory.
+  pub(crate) fn file_meta(&self, path: &InputPath) -> Result<Meta> {
+    self.check_path(path)?;
+    let mut meta = self.config(path)?.meta.unwrap_or_default();
+    if let Some(image) = &mut meta.image {
+      if !Meta::is_url(image) {
+        *image = self.check_relative(Path::new(image))?.0;
+      }
+    }
+    Ok(meta)
+  }
+
+  fn extra_assets(&self, config: Config) -> Result<ExtraAssets> {
+    Ok(ExtraAssets {
+      css: self.check_assets(config.extra_css.unwrap_or_default())?,
+      js: self.check_assets(config.extra_js.unwrap_or_default())?,
+    })
+  }
+
+  /// Check that configured assets are servable via the files route, and
+  /// return their `/`-separated paths relative to the base directory.
+  fn check_assets(&self, paths: Vec<PathBuf>) -> Result<Vec<String>> {
+    paths
+      .iter()
+      .map(|path| Ok(self.check_relative(path)?.0))
+      .collect()
+  }
+
+  /// Check that `path`, relative to the base directory, is servable via the
+  /// files route, and return it `/`-separated, along with its file type.
+  fn check_relative(&self, path: &Path) -> Result<(String, FileType)> {
+    let path = path
+      .components()
+      .map(|component| component.as_os_str().to_string_lossy())
+      .collect::<Vec<_>>()
+      .join("/");
+    let file_type = self.file_type(&path.split_inclusive('/').collect::<Vec<&str>>())?;
+    Ok((path, file_type))
+  }
+
+  /// Directories configured with `merge` in `dir`'s `.agora.yaml`, as
+  /// `/`-separated paths relative to the base directory.
+  fn merge_sources(&self, dir: &InputPath) -> Result<Vec<String>> {
+    self
+      .dir_config(dir.as_ref())?
+      .merge
+      .unwrap_or_default()
+      .iter()
+      .map(|path| {
+        let (path, file_type) = self.check_relative(path)?;
+        if !file_type.is_dir() {
+          return Err(
+            error::MergeSourceNotDirectory {
+              path: PathBuf::from(&path),
+            }
+            .build(),
+          );
+        }
+        Ok(path)
+      })
+      .collect()
+  }
+
+  /// Resolve a request path to the path of the file or directory it refers
+  /// to, relative to the base directory. Components that don't exist are
+  /// looked up in the merged directories of their parent, in order.
+  pub(crate) fn resolve(&self, tail: &[&str]) -> Result<String> {
+    self.base_directory.join_file_path(&tail.join(""))?;
+    let mut resolved = String::new();
+    for component in tail {
+      let candidate = format!("{}{}", resolved, component);
+      if self.exists(&candidate)? {
+        resolved = candidate;
+        continue;
+      }
+
+      let dir = self.base_directory.join_file_path(&resolved)?;
+      if !dir.as_ref().is_dir() {
+        return Ok(tail.join(""));
+      }
+
+      let mut found = None;
+      for source in self.merge_sources(&dir)? {
+        let candidate = if source.is_empty() {
+          component.to_string()
+        } else {
+          format!("{}/{}", source, component)
+        };
+        if self.exists(&candidate)? {
+          found = Some(candidate);
+          break;
+        }
+      }
+
+      match found {
+        Some(candidate) => resolved = candidate,
+        None => return Ok(tail.join("")),
+      }
+    }
+    Ok(resolved)
+  }
+
+  fn exists(&self, path: &str) -> Result<bool> {
+    let path = self.base_directory.join_file_path(path)?;
+    match path.as_ref().symlink_metadata() {
+      Ok(_) => Ok(true),
+      Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+      Err(source) => Err(Error::filesystem_io(&path).into_error(source)),
+    }
+  }
+
   pub(crate) fn file_path(&self, path: &str) -> Result<InputPath> {
     self.base_directory.join_file_path(path)
   }
 
   pub(crate) fn file_type(&self, tail: &[&str]) -> Result<FileType> {
-    for result in self.base_directory.iter_prefixes(tail) {
-      let prefix = result?;
-      self.check_path(&prefix)?;
-    }
+    self.check_prefixes(tail)?;
 
     let file_path = self.base_directory.join_file_path(&tail.join(""))?;
     let file_type = file_path
@@ -65,31 +556,57 @@ impl Vfs {
     Ok(file_type)
   }
 
-  fn check_path(&self, path: &InputPath) -> Result<()> {
-    if path
-      .as_ref()
-      .symlink_metadata()
-      .with_context(|| Error::filesystem_io(path))?
-      .file_type()
-      .is_symlink()
-    {
-      let link = fs::read_link(path.as_ref()).with_context(|| Error::filesystem_io(path))?;
+  /// Check the path of every directory leading up to the file at `tail`,
+  /// and of the file itself.
+  pub(crate) fn check_prefixes(&self, tail: &[&str]) -> Result<()> {
+    for result in self.base_directory.iter_prefixes(tail) {
+      let prefix = result?;
+      self.check_path(&prefix)?;
+    }
+    Ok(())
+  }
 
-      let destination = path
-        .as_ref()
-        .parent()
-        .expect("Input paths are always absolute, and thus have parents or are `/`, and `/` cannot be a symlink.")
-        .join(link)
-        .lexiclean();
+  /// Upstream URL of the file at `path`, if it's configured with `proxy` in
+  /// its directory's `.agora.yaml`. Proxied files don't exist on disk.
+  pub(crate) fn proxy_url(&self, path: &InputPath) -> Result<Option<Uri>> {
+    let (dir, file_name) = match (path.as_ref().parent(), path.as_ref().file_name()) {
+      (Some(dir), Some(file_name))
+        if dir.starts_with(self.base_directory.as_ref()) && dir.is_dir() =>
+      {
+        (dir, file_name)
+      }
+      _ => return Ok(None),
+    };
+    Ok(
+      self
+        .dir_config(dir)?
+        .proxy
+        .and_then(|proxy| proxy.get(&file_name.to_string_lossy()).cloned()),
+    )
+  }
 
-      if !destination.starts_with(&self.base_directory) {
-        return Err(
-          error::SymlinkAccess {
-            path: path.display_path().to_owned(),
-          }
-          .build(),
-        );
+  /// Whether `path` is a symlink that points outside of the base directory.
+  /// Proxied files don't exist, so they aren't symlinks.
+  fn is_escaping_symlink(&self, path: &InputPath) -> Result<bool> {
+    match escapes_base(self.base_directory.as_ref(), path.as_ref()) {
+      Err(error) if error.kind() == io::ErrorKind::NotFound && self.proxy_url(path)?.is_some() => {
+        Ok(false)
       }
+      result => result.with_context(|| Error::filesystem_io(path)),
+    }
+  }
+
+  /// Reject hidden files, and symlinks that point outside of the base
+  /// directory, unless `--follow-escaping-symlinks` was passed. `.agora.yaml`
+  /// files are never read through escaping symlinks.
+  fn check_path(&self, path: &InputPath) -> Result<()> {
+    if !self.follow_escaping_symlinks && self.is_escaping_symlink(path)? {
+      return Err(
+        error::SymlinkAccess {
+          path: path.display_path().to_owned(),
+        }
+        .build(),
+      );
     }
 
     if path
@@ -109,10 +626,68 @@ impl Vfs {
     Ok(())
   }
 
-  pub(crate) async fn read_dir(&self, path: &InputPath) -> Result<Vec<DirEntry>> {
+  /// Walk the tree below `dir`, for building archives and sitemaps. The
+  /// subdirectories for which `descend` returns false are skipped.
+  pub(crate) fn walk<F>(&self, dir: InputPath, descend: F) -> Walk<'_, F>
+  where
+    F: Fn(&InputPath) -> Result<bool>,
+  {
+    Walk {
+      vfs: self,
+      descend,
+      pending: vec![(dir, String::new())],
+      visited: BTreeSet::new(),
+    }
+  }
+
+  pub(crate) async fn read_dir(
+    &self,
+    path: &InputPath,
+    order: ListingOrder,
+  ) -> Result<Vec<DirEntry>> {
+    let mut entries = self.read_dir_entries(path).await?;
+
+    for source in self.merge_sources(path)? {
+      let source = self.base_directory.join_file_path(&source)?;
+      for entry in self.read_dir_entries(&source).await? {
+        if !entries
+          .iter()
+          .any(|existing| existing.file_name == entry.file_name)
+        {
+          entries.push(entry);
+        }
+      }
+    }
+
+    let dirs_first = self
+      .dir_config(path.as_ref())?
+      .listing_dirs_first(self.group_directories_first);
+    entries.sort_by(|a, b| {
+      let group = if dirs_first {
+        b.file_type.is_dir().cmp(&a.file_type.is_dir())
+      } else {
+        Ordering::Equal
+      };
+      let by_name = a.file_name.cmp(&b.file_name);
+      let ordering = match order.key {
+        SortKey::Name => by_name,
+        SortKey::Size => a.file_size.cmp(&b.file_size).then(by_name),
+        SortKey::Modified => a.modified.cmp(&b.modified).then(by_name),
+      };
+      group.then(if order.descending {
+        ordering.reverse()
+      } else {
+        ordering
+      })
+    });
+    Ok(entries)
+  }
+
+  async fn read_dir_entries(&self, path: &InputPath) -> Result<Vec<DirEntry>> {
     let mut read_dir = tokio::fs::read_dir(path)
       .await
       .with_context(|| Error::filesystem_io(path))?;
+    let deny_extensions = self.dir_config(path.as_ref())?.deny_extensions;
     let mut entries = Vec::new();
     while let Some(entry) = read_dir
       .next_entry()
@@ -123,31 +698,173 @@ impl Vfs {
       if self.check_path(&input_path).is_err() {
         continue;
       }
-      let metadata = entry
-        .metadata()
-        .await
-        .with_context(|| Error::filesystem_io(&input_path))?;
-      let file_type = metadata.file_type();
-      let file_size = if metadata.is_dir() {
-        None
+      // With `--fast-listing`, only the file type is read, which doesn't
+      // need a `stat` call on most platforms, and sizes and modification
+      // times are left out. Symlinks, which `check_path` has already
+      // checked, are followed, so that they're listed like their
+      // destinations. Broken symlinks are listed as they are.
+      let (symlink, file_type, file_size, modified) = if self.fast_listing {
+        let mut file_type = entry
+          .file_type()
+          .await
+          .with_context(|| Error::filesystem_io(&input_path))?;
+        let symlink = file_type.is_symlink();
+        if symlink {
+          if let Ok(metadata) = tokio::fs::metadata(entry.path()).await {
+            file_type = metadata.file_type();
+          }
+        }
+        (symlink, file_type, None, None)
       } else {
-        Some(metadata.len())
+        let mut metadata = entry
+          .metadata()
+          .await
+          .with_context(|| Error::filesystem_io(&input_path))?;
+        let symlink = metadata.file_type().is_symlink();
+        if symlink {
+          if let Ok(destination) = tokio::fs::metadata(entry.path()).await {
+            metadata = destination;
+          }
+        }
+        let file_size = if metadata.is_dir() {
+          None
+        } else {
+          Some(metadata.len())
+        };
+        (
+          symlink,
+          metadata.file_type(),
+          file_size,
+          metadata.modified().ok(),
+        )
       };
+      if !file_type.is_dir()
+        && deny_extensions.as_ref().map_or(false, |deny_extensions| {
+          deny_extensions.matches(&entry.file_name().to_string_lossy())
+        })
+      {
+        continue;
+      }
       entries.push(DirEntry {
+        paid: self.paid(&input_path)?,
+        path: input_path,
         file_name: entry.file_name(),
         file_type,
+        symlink,
         file_size,
-        paid: self.paid(&path.join_relative(entry.file_name().as_ref())?)?,
+        modified,
       });
     }
-    entries.sort_by(|a, b| a.file_name.cmp(&b.file_name));
     Ok(entries)
   }
 }
 
+/// Returns whether `path` is a symlink whose destination lies outside of
+/// `base_directory`.
+fn escapes_base(base_directory: &Path, path: &Path) -> io::Result<bool> {
+  if !path.symlink_metadata()?.file_type().is_symlink() {
+    return Ok(false);
+  }
+
+  let link = fs::read_link(path)?;
+
+  let destination = path
+    .parent()
+    .expect("Input paths are always absolute, and thus have parents or are `/`, and `/` cannot be a symlink.")
+    .join(link)
+    .lexiclean();
+
+  Ok(!destination.starts_with(base_directory))
+}
+
+pub(crate) struct ExtraAssets {
+  pub(crate) css: Vec<String>,
+  pub(crate) js: Vec<String>,
+}
+
 pub(crate) struct DirEntry {
+  pub(crate) path: InputPath,
   pub(crate) file_name: OsString,
   pub(crate) file_type: FileType,
+  pub(crate) symlink: bool,
   pub(crate) file_size: Option<u64>,
+  pub(crate) modified: Option<SystemTime>,
   pub(crate) paid: bool,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SortKey {
+  Name,
+  Size,
+  Modified,
+}
+
+/// Order of entries in a directory listing. Directories are still listed
+/// first if `listing-dirs-first` or `--group-directories-first` is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ListingOrder {
+  pub(crate) key: SortKey,
+  pub(crate) descending: bool,
+}
+
+impl Default for ListingOrder {
+  fn default() -> Self {
+    Self {
+      key: SortKey::Name,
+      descending: false,
+    }
+  }
+}
+
+impl DirEntry {
+  pub(crate) fn display_name(&self) -> String {
+    let mut file_name = self.file_name.to_string_lossy().into_owned();
+    if self.file_type.is_dir() {
+      file_name.push('/');
+    }
+    file_name
+  }
+}
+
+/// A walk of a directory tree, returned by `Vfs::walk`. Symlinked
+/// directories aren't descended into, and neither are directories that have
+/// already been walked, which can be reached again through `merge`, so that
+/// cycles can't make the walk recurse forever.
+pub(crate) struct Walk<'a, F> {
+  vfs: &'a Vfs,
+  descend: F,
+  pending: Vec<(InputPath, String)>,
+  visited: BTreeSet<PathBuf>,
+}
+
+impl<'a, F> Walk<'a, F>
+where
+  F: Fn(&InputPath) -> Result<bool>,
+{
+  /// The files in the next directory of the walk, with their paths relative
+  /// to the directory the walk started in, or `None` once the walk is done.
+  pub(crate) async fn next_dir(&mut self) -> Result<Option<Vec<(String, DirEntry)>>> {
+    while let Some((dir, prefix)) = self.pending.pop() {
+      let canonical = dir
+        .as_ref()
+        .canonicalize()
+        .with_context(|| Error::filesystem_io(&dir))?;
+      if !self.visited.insert(canonical) || !(self.descend)(&dir)? {
+        continue;
+      }
+      let mut files = Vec::new();
+      for entry in self.vfs.read_dir(&dir, ListingOrder::default()).await? {
+        let path = format!("{}{}", prefix, entry.file_name.to_string_lossy());
+        if entry.file_type.is_dir() {
+          if !entry.symlink {
+            self.pending.push((entry.path, path + "/"));
+          }
+        } else {
+          files.push((path, entry));
+        }
+      }
+      return Ok(Some(files));
+    }
+    Ok(None)
+  }
+}
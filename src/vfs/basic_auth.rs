@@ -0,0 +1,107 @@
+use {crate::common::*, std::convert::TryFrom};
+
+/// Credentials required to access a directory and its subdirectories,
+/// configured with `basic-auth`, a `username`, and the bcrypt hash of the
+/// password, so that the password itself isn't stored in the config.
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(try_from = "Credentials")]
+pub(crate) struct BasicAuth {
+  username: String,
+  password_hash: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Credentials {
+  username: String,
+  password_hash: String,
+}
+
+impl TryFrom<Credentials> for BasicAuth {
+  type Error = String;
+
+  fn try_from(credentials: Credentials) -> Result<Self, Self::Error> {
+    if credentials.username.contains(':') {
+      return Err(format!(
+        "invalid basic auth username `{}`, usernames may not contain `:`",
+        credentials.username
+      ));
+    }
+    credentials
+      .password_hash
+      .parse::<bcrypt::HashParts>()
+      .map_err(|error| format!("invalid basic auth password hash: {}", error))?;
+    Ok(Self {
+      username: credentials.username,
+      password_hash: credentials.password_hash,
+    })
+  }
+}
+
+impl BasicAuth {
+  /// Whether the `Authorization` header holds these credentials. Verifying
+  /// bcrypt hashes is deliberately slow, so this should be called from a
+  /// blocking task.
+  pub(crate) fn verify(&self, authorization: Option<&HeaderValue>) -> bool {
+    let credentials = match authorization
+      .and_then(|authorization| authorization.to_str().ok())
+      .and_then(|authorization| authorization.strip_prefix("Basic "))
+      .and_then(|encoded| base64::decode(encoded.trim()).ok())
+      .and_then(|decoded| String::from_utf8(decoded).ok())
+    {
+      Some(credentials) => credentials,
+      None => return false,
+    };
+    let mut parts = credentials.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+      (Some(username), Some(password)) if username == self.username => {
+        bcrypt::verify(password, &self.password_hash).unwrap_or(false)
+      }
+      _ => false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::test_utils::assert_contains};
+
+  fn parse(yaml: &str) -> Result<BasicAuth, String> {
+    serde_yaml::from_str::<BasicAuth>(yaml).map_err(|error| error.to_string())
+  }
+
+  fn authorization(credentials: &str) -> HeaderValue {
+    HeaderValue::from_str(&format!("Basic {}", base64::encode(credentials))).unwrap()
+  }
+
+  #[test]
+  fn verify() {
+    let basic_auth = parse(&format!(
+      "{{username: alice, password-hash: '{}'}}",
+      bcrypt::hash("secret", 4).unwrap()
+    ))
+    .unwrap();
+    assert!(basic_auth.verify(Some(&authorization("alice:secret"))));
+    assert!(!basic_auth.verify(Some(&authorization("alice:wrong"))));
+    assert!(!basic_auth.verify(Some(&authorization("bob:secret"))));
+    assert!(!basic_auth.verify(Some(&authorization("alice"))));
+    assert!(!basic_auth.verify(Some(&HeaderValue::from_static("Bearer secret"))));
+    assert!(!basic_auth.verify(None));
+  }
+
+  #[test]
+  fn invalid_credentials() {
+    assert_contains(
+      &parse("{username: alice, password-hash: secret}").unwrap_err(),
+      "invalid basic auth password hash",
+    );
+    assert_contains(
+      &parse("{username: 'a:b', password-hash: secret}").unwrap_err(),
+      "invalid basic auth username `a:b`",
+    );
+    assert_contains(
+      &parse("{username: alice, password: secret}").unwrap_err(),
+      "unknown field `password`",
+    );
+  }
+}
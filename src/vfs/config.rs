@@ -1,10 +1,51 @@
-use crate::common::*;
+use {
+  super::{
+    BasicAuth, ContentTypes, DenyExtensions, FiatPrice, ForceDownload, InvoiceExpiry,
+    LightningAddresses, Meta, PricePerByte, Proxy, Splits,
+  },
+  crate::common::*,
+};
 
-#[derive(PartialEq, Debug, Default, Deserialize)]
+#[derive(PartialEq, Debug, Clone, Default, Deserialize)]
 #[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
 pub(crate) struct Config {
   paid: Option<bool>,
   pub(super) base_price: Option<Millisatoshi>,
+  pub(super) fiat_price: Option<FiatPrice>,
+  pub(super) extra_css: Option<Vec<PathBuf>>,
+  pub(super) extra_js: Option<Vec<PathBuf>>,
+  listing_dirs_first: Option<bool>,
+  pub(super) max_downloads: Option<u64>,
+  index_toc: Option<bool>,
+  invoice_amount_precision: Option<AmountPrecision>,
+  listing: Option<bool>,
+  pub(super) merge: Option<Vec<PathBuf>>,
+  pub(super) invoice_expiry: Option<InvoiceExpiry>,
+  pub(super) splits: Option<Splits>,
+  pub(super) index: Option<PathBuf>,
+  pub(super) price_per_byte: Option<PricePerByte>,
+  pub(super) content_types: Option<ContentTypes>,
+  pub(super) proxy: Option<Proxy>,
+  pub(super) preview_bytes: Option<u64>,
+  pub(super) lightning_addresses: Option<LightningAddresses>,
+  pub(super) force_download: Option<ForceDownload>,
+  pub(super) basic_auth: Option<BasicAuth>,
+  pub(super) meta: Option<Meta>,
+  pub(super) deny_extensions: Option<DenyExtensions>,
+}
+
+/// Start of references to environment variables in `.agora.yaml` files,
+/// like `${AGORA_CFG_PRICE}`. References to variables without the
+/// `AGORA_CFG_` prefix are left as they are, so config files can't read
+/// variables meant for other programs.
+const ENVIRONMENT_VARIABLE_PREFIX: &str = "${AGORA_CFG_";
+
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize)]
+pub(crate) enum AmountPrecision {
+  #[serde(rename = "msat")]
+  Millisatoshi,
+  #[serde(rename = "sat")]
+  Satoshi,
 }
 
 impl Config {
@@ -12,7 +53,76 @@ impl Config {
     self.paid.unwrap_or(false)
   }
 
-  pub(super) fn for_dir(base_directory: &Path, path: &Path) -> Result<Self> {
+  pub(super) fn index_toc(&self) -> bool {
+    self.index_toc.unwrap_or(false)
+  }
+
+  pub(super) fn invoice_amount_precision(&self) -> AmountPrecision {
+    self
+      .invoice_amount_precision
+      .unwrap_or(AmountPrecision::Millisatoshi)
+  }
+
+  pub(super) fn listing(&self) -> bool {
+    self.listing.unwrap_or(true)
+  }
+
+  /// Whether directories are listed before files, defaulting to `default`,
+  /// which is set with `--group-directories-first`.
+  pub(super) fn listing_dirs_first(&self, default: bool) -> bool {
+    self.listing_dirs_first.unwrap_or(default)
+  }
+
+  /// Load the global config file given with `--config`. Its asset paths
+  /// are relative to the base directory, and it can't declare `merge` or
+  /// `proxy`, since it applies to every directory.
+  pub(super) fn load(path: &InputPath) -> Result<Self> {
+    let yaml = fs::read_to_string(path).with_context(|| Error::filesystem_io(path))?;
+    let mut config = Self::parse(&yaml, path.display_path())?;
+    config.merge = None;
+    config.proxy = None;
+    Ok(config)
+  }
+
+  /// Deserialize every `.agora.yaml` file in `directory` and its
+  /// non-hidden subdirectories, returning the errors for those that are
+  /// invalid, instead of stopping at the first.
+  pub(super) fn check_tree(directory: &Path) -> Result<Vec<Error>> {
+    let mut errors = Vec::new();
+    let file_path = directory.join(".agora.yaml");
+    match fs::read_to_string(&file_path) {
+      Ok(yaml) => {
+        if let Err(error) = Self::parse(&yaml, &file_path)
+          .and_then(|config| config.check_proxy_price_per_byte(&file_path))
+        {
+          errors.push(error);
+        }
+      }
+      Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+      Err(source) => return Err(error::FilesystemIo { path: file_path }.into_error(source)),
+    }
+    let mut entries = directory
+      .read_dir()
+      .and_then(|entries| entries.collect::<io::Result<Vec<fs::DirEntry>>>())
+      .context(error::FilesystemIo { path: directory })?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+      let file_type = entry
+        .file_type()
+        .context(error::FilesystemIo { path: entry.path() })?;
+      // Hidden directories are never served, and symlinks are skipped to
+      // avoid cycles
+      if file_type.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+        errors.extend(Self::check_tree(&entry.path())?);
+      }
+    }
+    Ok(errors)
+  }
+
+  /// The config for `path`, merged from the `.agora.yaml` files in it and
+  /// its ancestors up to `base_directory`, with `self`, the global config,
+  /// beneath them all.
+  pub(super) fn for_dir(&self, base_directory: &Path, path: &Path) -> Result<Self> {
     if !path.starts_with(base_directory) {
       return Err(Error::internal(format!(
         "Config::for_dir: `{}` does not start with `{}`",
@@ -21,29 +131,159 @@ impl Config {
       )));
     }
     path.read_dir().context(error::FilesystemIo { path })?;
+    let directory = path;
     let mut config = Self::default();
     for path in path.ancestors() {
       if !path.starts_with(base_directory) {
         break;
       }
       let file_path = path.join(".agora.yaml");
+      match super::escapes_base(base_directory, &file_path) {
+        Ok(false) => {}
+        Ok(true) => return Err(error::SymlinkAccess { path: file_path }.build()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+        Err(source) => return Err(error::FilesystemIo { path: file_path }.into_error(source)),
+      }
       match fs::read_to_string(&file_path) {
         Ok(yaml) => {
-          let parent =
-            serde_yaml::from_str(&yaml).context(error::ConfigDeserialize { path: file_path })?;
+          let mut parent = Self::parse(&yaml, &file_path)?;
+          // Merged directories and proxied files only belong to the
+          // directory whose config declares them, not to its subdirectories
+          if path != directory {
+            parent.merge = None;
+            parent.proxy = None;
+          }
+          parent.make_paths_relative_to_base(
+            path
+              .strip_prefix(base_directory)
+              .expect("config directory is inside base directory"),
+          );
           config.merge_parent(parent);
         }
         Err(error) if error.kind() == io::ErrorKind::NotFound => {}
         Err(source) => return Err(error::FilesystemIo { path: file_path }.into_error(source)),
       }
     }
+    config.merge_parent(self.clone());
+    config.check_proxy_price_per_byte(&directory.join(".agora.yaml"))?;
     Ok(config)
   }
 
+  /// Proxied files don't exist on disk, so their size isn't known before
+  /// they're fetched, and they can't be priced with `price-per-byte`.
+  fn check_proxy_price_per_byte(&self, path: &Path) -> Result<()> {
+    if self.proxy.is_some() && self.price_per_byte.is_some() {
+      return Err(error::ConfigProxyPricePerByte { path }.build());
+    }
+    Ok(())
+  }
+
+  /// Deserialize the config file at `path`, after expanding references to
+  /// `AGORA_CFG_` environment variables in it.
+  fn parse(yaml: &str, path: &Path) -> Result<Self> {
+    let yaml = Self::expand_environment_variables(yaml, path, |name| env::var(name).ok())?;
+    serde_yaml::from_str(&yaml).context(error::ConfigDeserialize { path })
+  }
+
+  /// Replace each `${AGORA_CFG_NAME}` in `yaml` with the value of the
+  /// environment variable `AGORA_CFG_NAME`, as returned by `var`. Values
+  /// are inserted as they are, so values with YAML syntax in them should
+  /// be quoted in the config file.
+  fn expand_environment_variables(
+    yaml: &str,
+    path: &Path,
+    var: impl Fn(&str) -> Option<String>,
+  ) -> Result<String> {
+    let mut expanded = String::with_capacity(yaml.len());
+    let mut rest = yaml;
+    while let Some(start) = rest.find(ENVIRONMENT_VARIABLE_PREFIX) {
+      let end = match rest[start..].find('}') {
+        Some(end) => start + end,
+        None => break,
+      };
+      let name = &rest[start + 2..end];
+      expanded.push_str(&rest[..start]);
+      if name
+        .chars()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+      {
+        let value = var(name).ok_or_else(|| {
+          error::ConfigEnvironmentVariable {
+            path,
+            name: name.to_owned(),
+          }
+          .build()
+        })?;
+        expanded.push_str(&value);
+      } else {
+        expanded.push_str(&rest[start..=end]);
+      }
+      rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+  }
+
+  /// Rewrite asset paths, which are relative to the directory containing
+  /// `.agora.yaml`, to be relative to the base directory.
+  fn make_paths_relative_to_base(&mut self, config_directory: &Path) {
+    for path in self
+      .extra_css
+      .iter_mut()
+      .chain(self.extra_js.iter_mut())
+      .chain(self.merge.iter_mut())
+      .flatten()
+    {
+      *path = config_directory.join(&path).lexiclean();
+    }
+    if let Some(image) = self.meta.as_mut().and_then(|meta| meta.image.as_mut()) {
+      if !Meta::is_url(image) {
+        *image = config_directory
+          .join(&image)
+          .lexiclean()
+          .to_string_lossy()
+          .into_owned();
+      }
+    }
+  }
+
   fn merge_parent(&mut self, parent: Self) {
     *self = Self {
       paid: self.paid.or(parent.paid),
       base_price: self.base_price.or(parent.base_price),
+      fiat_price: match self.fiat_price.take() {
+        // A base price set closer to the file overrides an inherited fiat price
+        None if self.base_price.is_some() => None,
+        fiat_price => fiat_price.or(parent.fiat_price),
+      },
+      extra_css: self.extra_css.take().or(parent.extra_css),
+      extra_js: self.extra_js.take().or(parent.extra_js),
+      listing_dirs_first: self.listing_dirs_first.or(parent.listing_dirs_first),
+      max_downloads: self.max_downloads.or(parent.max_downloads),
+      index_toc: self.index_toc.or(parent.index_toc),
+      invoice_amount_precision: self
+        .invoice_amount_precision
+        .or(parent.invoice_amount_precision),
+      listing: self.listing.or(parent.listing),
+      merge: self.merge.take().or(parent.merge),
+      invoice_expiry: self.invoice_expiry.or(parent.invoice_expiry),
+      splits: self.splits.take().or(parent.splits),
+      index: self.index.take().or(parent.index),
+      price_per_byte: self.price_per_byte.or(parent.price_per_byte),
+      content_types: match (self.content_types.take(), parent.content_types) {
+        (Some(content_types), Some(parent)) => Some(content_types.merge_parent(parent)),
+        (content_types, parent) => content_types.or(parent),
+      },
+      proxy: self.proxy.take().or(parent.proxy),
+      preview_bytes: self.preview_bytes.or(parent.preview_bytes),
+      lightning_addresses: self
+        .lightning_addresses
+        .take()
+        .or(parent.lightning_addresses),
+      force_download: self.force_download.take().or(parent.force_download),
+      basic_auth: self.basic_auth.take().or(parent.basic_auth),
+      meta: self.meta.take().or(parent.meta),
+      deny_extensions: self.deny_extensions.take().or(parent.deny_extensions),
     };
   }
 }
@@ -59,7 +299,28 @@ mod tests {
     assert_eq!(
       Config {
         paid: None,
-        base_price: None
+        base_price: None,
+        fiat_price: None,
+        extra_css: None,
+        extra_js: None,
+        listing_dirs_first: None,
+        max_downloads: None,
+        index_toc: None,
+        invoice_amount_precision: None,
+        listing: None,
+        merge: None,
+        invoice_expiry: None,
+        splits: None,
+        index: None,
+        price_per_byte: None,
+        content_types: None,
+        proxy: None,
+        preview_bytes: None,
+        lightning_addresses: None,
+        force_download: None,
+        basic_auth: None,
+        meta: None,
+        deny_extensions: None,
       },
       Config::default()
     );
@@ -68,7 +329,9 @@ mod tests {
   #[test]
   fn loads_the_default_config_when_no_files_given() {
     let temp_dir = TempDir::new().unwrap();
-    let config = Config::for_dir(temp_dir.path(), temp_dir.path()).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
     assert_eq!(config, Config::default());
   }
 
@@ -76,12 +339,15 @@ mod tests {
   fn loads_config_from_files() {
     let temp_dir = TempDir::new().unwrap();
     fs::write(temp_dir.path().join(".agora.yaml"), "paid: true").unwrap();
-    let config = Config::for_dir(temp_dir.path(), temp_dir.path()).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
     assert_eq!(
       config,
       Config {
         paid: Some(true),
-        base_price: None
+        base_price: None,
+        ..Config::default()
       }
     );
   }
@@ -89,7 +355,8 @@ mod tests {
   #[test]
   fn directory_does_not_exist() {
     let temp_dir = TempDir::new().unwrap();
-    let result = Config::for_dir(temp_dir.path(), &temp_dir.path().join("does-not-exist"));
+    let result =
+      Config::default().for_dir(temp_dir.path(), &temp_dir.path().join("does-not-exist"));
     assert_matches!(
       result,
       Err(Error::FilesystemIo { path, source, .. })
@@ -101,7 +368,7 @@ mod tests {
   fn io_error_when_reading_config_file() {
     let temp_dir = TempDir::new().unwrap();
     fs::create_dir(temp_dir.path().join(".agora.yaml")).unwrap();
-    let result = Config::for_dir(temp_dir.path(), temp_dir.path());
+    let result = Config::default().for_dir(temp_dir.path(), temp_dir.path());
     assert_matches!(
       result,
       Err(Error::FilesystemIo { path, .. })
@@ -109,11 +376,39 @@ mod tests {
     );
   }
 
+  #[test]
+  #[cfg(unix)]
+  fn config_symlink_escaping_base_directory_is_rejected() {
+    let temp_dir = TempDir::new().unwrap();
+    let base = temp_dir.path().join("base");
+    fs::create_dir(&base).unwrap();
+    fs::write(temp_dir.path().join("outside.yaml"), "paid: true").unwrap();
+    std::os::unix::fs::symlink("../outside.yaml", base.join(".agora.yaml")).unwrap();
+    let result = Config::default().for_dir(&base, &base);
+    assert_matches!(
+      result,
+      Err(Error::SymlinkAccess { path, .. })
+        if path == base.join(".agora.yaml")
+    );
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn config_symlink_within_base_directory_is_followed() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join("config.yaml"), "paid: true").unwrap();
+    std::os::unix::fs::symlink("config.yaml", temp_dir.path().join(".agora.yaml")).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
+    assert!(config.paid());
+  }
+
   #[test]
   fn invalid_config() {
     let temp_dir = TempDir::new().unwrap();
     fs::write(temp_dir.path().join(".agora.yaml"), "{{{").unwrap();
-    let result = Config::for_dir(temp_dir.path(), temp_dir.path());
+    let result = Config::default().for_dir(temp_dir.path(), temp_dir.path());
     assert_matches!(
       result,
       Err(Error::ConfigDeserialize { path, .. })
@@ -121,11 +416,85 @@ mod tests {
     );
   }
 
+  #[test]
+  fn expands_environment_variables() {
+    let expand = |yaml: &str| {
+      Config::expand_environment_variables(yaml, Path::new(".agora.yaml"), |name| match name {
+        "AGORA_CFG_PRICE" => Some("1000 sat".to_owned()),
+        _ => None,
+      })
+    };
+    assert_eq!(
+      expand("{paid: true, base-price: ${AGORA_CFG_PRICE}}").unwrap(),
+      "{paid: true, base-price: 1000 sat}"
+    );
+    assert_eq!(
+      expand("${AGORA_CFG_PRICE}${AGORA_CFG_PRICE}").unwrap(),
+      "1000 sat1000 sat"
+    );
+    for unexpanded in &[
+      "${HOME}",
+      "${AGORA_CFG_price}",
+      "${AGORA_CFG_PRICE",
+      "$AGORA_CFG_PRICE",
+    ] {
+      assert_eq!(expand(unexpanded).unwrap(), *unexpanded);
+    }
+    assert_eq!(
+      expand("base-price: ${AGORA_CFG_UNSET}")
+        .unwrap_err()
+        .to_string(),
+      "Config file at `.agora.yaml` references unset environment variable `AGORA_CFG_UNSET`"
+    );
+  }
+
+  #[test]
+  fn check_tree_reports_all_invalid_configs() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir_all(temp_dir.path().join("foo/bar")).unwrap();
+    fs::create_dir(temp_dir.path().join(".hidden")).unwrap();
+    fs::write(temp_dir.path().join(".agora.yaml"), "paid: true").unwrap();
+    fs::write(temp_dir.path().join("foo/.agora.yaml"), "{{{").unwrap();
+    fs::write(temp_dir.path().join("foo/bar/.agora.yaml"), "unknown: 1").unwrap();
+    fs::write(temp_dir.path().join(".hidden/.agora.yaml"), "{{{").unwrap();
+    let paths = Config::check_tree(temp_dir.path())
+      .unwrap()
+      .into_iter()
+      .map(|error| match error {
+        Error::ConfigDeserialize { path, .. } => path,
+        error => panic!("unexpected error: {}", error),
+      })
+      .collect::<Vec<PathBuf>>();
+    assert_eq!(
+      paths,
+      vec![
+        temp_dir.path().join("foo/.agora.yaml"),
+        temp_dir.path().join("foo/bar/.agora.yaml"),
+      ]
+    );
+  }
+
+  #[test]
+  fn check_tree_reports_proxied_price_per_byte() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+      temp_dir.path().join(".agora.yaml"),
+      "{price-per-byte: 1 msat, proxy: {foo: 'http://example.com/foo'}}",
+    )
+    .unwrap();
+    let errors = Config::check_tree(temp_dir.path()).unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_matches!(
+      &errors[0],
+      Error::ConfigProxyPricePerByte { path, .. } if path == &temp_dir.path().join(".agora.yaml")
+    );
+  }
+
   #[test]
   fn unknown_fields() {
     let temp_dir = TempDir::new().unwrap();
     fs::write(temp_dir.path().join(".agora.yaml"), "unknown_field: foo").unwrap();
-    let result = Config::for_dir(temp_dir.path(), temp_dir.path());
+    let result = Config::default().for_dir(temp_dir.path(), temp_dir.path());
     assert_matches!(
       result,
       Err(Error::ConfigDeserialize { path, source, .. })
@@ -138,7 +507,9 @@ mod tests {
   fn paid_is_optional() {
     let temp_dir = TempDir::new().unwrap();
     fs::write(temp_dir.path().join(".agora.yaml"), "{}").unwrap();
-    let config = Config::for_dir(temp_dir.path(), temp_dir.path()).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
     assert_eq!(config, Config::default());
   }
 
@@ -151,10 +522,130 @@ mod tests {
     "
     .unindent();
     fs::write(temp_dir.path().join(".agora.yaml"), yaml).unwrap();
-    let config = Config::for_dir(temp_dir.path(), temp_dir.path()).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
     assert_eq!(config.base_price, Some(Millisatoshi::new(3000)));
   }
 
+  #[test]
+  fn parses_price_per_byte() {
+    let temp_dir = TempDir::new().unwrap();
+    let yaml = "
+      paid: true
+      price-per-byte: 2 msat
+    "
+    .unindent();
+    fs::write(temp_dir.path().join(".agora.yaml"), yaml).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
+    assert_eq!(
+      config.price_per_byte.map(PricePerByte::millisatoshi),
+      Some(Millisatoshi::new(2))
+    );
+  }
+
+  #[test]
+  fn base_price_overrides_inherited_fiat_price() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+      temp_dir.path().join(".agora.yaml"),
+      "{paid: true, fiat-price: 1.99 USD}",
+    )
+    .unwrap();
+    fs::create_dir(temp_dir.path().join("dir")).unwrap();
+    fs::write(
+      temp_dir.path().join("dir/.agora.yaml"),
+      "base-price: 23 sat",
+    )
+    .unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
+    assert_eq!(
+      config.fiat_price.unwrap().to_string(),
+      "1.99 USD".to_owned()
+    );
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("dir"))
+      .unwrap();
+    assert_eq!(config.fiat_price, None);
+    assert_eq!(config.base_price, Some(Millisatoshi::new(23_000)));
+  }
+
+  #[test]
+  fn rejects_base_price_that_overflows() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+      temp_dir.path().join(".agora.yaml"),
+      "base-price: 18446744073709552 sat",
+    )
+    .unwrap();
+    assert_matches!(
+      Config::default().for_dir(temp_dir.path(), temp_dir.path()),
+      Err(Error::ConfigDeserialize { .. })
+    );
+  }
+
+  #[test]
+  fn parses_invoice_amount_precision() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+      temp_dir.path().join(".agora.yaml"),
+      "invoice-amount-precision: sat",
+    )
+    .unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
+    assert_eq!(config.invoice_amount_precision(), AmountPrecision::Satoshi);
+    assert_eq!(
+      Config::default().invoice_amount_precision(),
+      AmountPrecision::Millisatoshi
+    );
+  }
+
+  #[test]
+  fn parses_invoice_expiry() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+      temp_dir.path().join(".agora.yaml"),
+      "invoice-expiry: 10 min",
+    )
+    .unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
+    assert_eq!(config.invoice_expiry.map(InvoiceExpiry::seconds), Some(600));
+  }
+
+  #[test]
+  fn preview_bytes_is_inherited() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("dir")).unwrap();
+    fs::write(temp_dir.path().join(".agora.yaml"), "preview-bytes: 1000").unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("dir"))
+      .unwrap();
+    assert_eq!(config.preview_bytes, Some(1000));
+  }
+
+  #[test]
+  fn merge_is_not_inherited() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir_all(temp_dir.path().join("hub/dir")).unwrap();
+    fs::write(temp_dir.path().join("hub/.agora.yaml"), "merge: [../a]").unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("hub"))
+      .unwrap();
+    assert_eq!(config.merge, Some(vec![PathBuf::from("a")]));
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("hub/dir"))
+      .unwrap();
+    assert_eq!(config.merge, None);
+  }
+
   #[test]
   fn inherits_config() {
     let temp_dir = TempDir::new().unwrap();
@@ -164,16 +655,64 @@ mod tests {
     )
     .unwrap();
     fs::create_dir(temp_dir.path().join("dir")).unwrap();
-    let config = Config::for_dir(temp_dir.path(), &temp_dir.path().join("dir")).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("dir"))
+      .unwrap();
     assert_eq!(
       config,
       Config {
         paid: Some(true),
-        base_price: Some(Millisatoshi::new(42_000))
+        base_price: Some(Millisatoshi::new(42_000)),
+        ..Config::default()
       }
     );
   }
 
+  #[test]
+  fn inherits_global_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let global: Config = serde_yaml::from_str("{paid: true, base-price: 42 sat}").unwrap();
+    fs::create_dir(temp_dir.path().join("dir")).unwrap();
+    let config = global
+      .for_dir(temp_dir.path(), &temp_dir.path().join("dir"))
+      .unwrap();
+    assert_eq!(
+      config,
+      Config {
+        paid: Some(true),
+        base_price: Some(Millisatoshi::new(42_000)),
+        ..Config::default()
+      }
+    );
+  }
+
+  #[test]
+  fn directory_configs_override_global_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let global: Config =
+      serde_yaml::from_str("{paid: true, base-price: 1 sat, invoice-expiry: 1 hour}").unwrap();
+    fs::write(temp_dir.path().join(".agora.yaml"), "base-price: 2 sat").unwrap();
+    fs::create_dir(temp_dir.path().join("dir")).unwrap();
+    fs::write(
+      temp_dir.path().join("dir/.agora.yaml"),
+      "{base-price: 3 sat, invoice-expiry: 10 min}",
+    )
+    .unwrap();
+    let config = global.for_dir(temp_dir.path(), temp_dir.path()).unwrap();
+    assert_eq!(config.paid, Some(true));
+    assert_eq!(config.base_price, Some(Millisatoshi::new(2_000)));
+    assert_eq!(
+      config.invoice_expiry.map(InvoiceExpiry::seconds),
+      Some(3600)
+    );
+    let config = global
+      .for_dir(temp_dir.path(), &temp_dir.path().join("dir"))
+      .unwrap();
+    assert_eq!(config.paid, Some(true));
+    assert_eq!(config.base_price, Some(Millisatoshi::new(3_000)));
+    assert_eq!(config.invoice_expiry.map(InvoiceExpiry::seconds), Some(600));
+  }
+
   #[test]
   fn override_paid() {
     let temp_dir = TempDir::new().unwrap();
@@ -184,12 +723,15 @@ mod tests {
     .unwrap();
     fs::create_dir(temp_dir.path().join("dir")).unwrap();
     fs::write(temp_dir.path().join("dir/.agora.yaml"), "paid: false").unwrap();
-    let config = Config::for_dir(temp_dir.path(), &temp_dir.path().join("dir")).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("dir"))
+      .unwrap();
     assert_eq!(
       config,
       Config {
         paid: Some(false),
-        base_price: Some(Millisatoshi::new(42_000))
+        base_price: Some(Millisatoshi::new(42_000)),
+        ..Config::default()
       }
     );
   }
@@ -208,12 +750,15 @@ mod tests {
       "base-price: 23 sat",
     )
     .unwrap();
-    let config = Config::for_dir(temp_dir.path(), &temp_dir.path().join("dir")).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("dir"))
+      .unwrap();
     assert_eq!(
       config,
       Config {
         paid: Some(true),
-        base_price: Some(Millisatoshi::new(23_000))
+        base_price: Some(Millisatoshi::new(23_000)),
+        ..Config::default()
       }
     );
   }
@@ -232,12 +777,15 @@ mod tests {
       "base-price: 23 sat",
     )
     .unwrap();
-    let config = Config::for_dir(temp_dir.path(), temp_dir.path()).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), temp_dir.path())
+      .unwrap();
     assert_eq!(
       config,
       Config {
         paid: Some(true),
-        base_price: Some(Millisatoshi::new(42_000))
+        base_price: Some(Millisatoshi::new(42_000)),
+        ..Config::default()
       }
     );
   }
@@ -257,12 +805,15 @@ mod tests {
       "{paid: true, base-price: 23 sat}",
     )
     .unwrap();
-    let config = Config::for_dir(temp_dir.path(), &temp_dir.path().join("foo")).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("foo"))
+      .unwrap();
     assert_eq!(
       config,
       Config {
         paid: Some(true),
-        base_price: Some(Millisatoshi::new(42_000))
+        base_price: Some(Millisatoshi::new(42_000)),
+        ..Config::default()
       }
     );
   }
@@ -277,26 +828,93 @@ mod tests {
     .unwrap();
     fs::create_dir(temp_dir.path().join("root")).unwrap();
     fs::create_dir(temp_dir.path().join("root/dir")).unwrap();
-    let config =
-      Config::for_dir(&temp_dir.path().join("root"), &temp_dir.path().join("root")).unwrap();
+    let config = Config::default()
+      .for_dir(&temp_dir.path().join("root"), &temp_dir.path().join("root"))
+      .unwrap();
     assert_eq!(
       config,
       Config {
         paid: None,
-        base_price: None
+        base_price: None,
+        ..Config::default()
       }
     );
-    let config = Config::for_dir(
-      &temp_dir.path().join("root"),
-      &temp_dir.path().join("root/dir"),
+    let config = Config::default()
+      .for_dir(
+        &temp_dir.path().join("root"),
+        &temp_dir.path().join("root/dir"),
+      )
+      .unwrap();
+    assert_eq!(
+      config,
+      Config {
+        paid: None,
+        base_price: None,
+        ..Config::default()
+      }
+    );
+  }
+
+  #[test]
+  fn extra_assets_are_relative_to_config_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("site")).unwrap();
+    fs::write(
+      temp_dir.path().join("site/.agora.yaml"),
+      "{extra-css: [style.css], extra-js: [js/script.js]}",
     )
     .unwrap();
+    fs::create_dir(temp_dir.path().join("site/dir")).unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("site/dir"))
+      .unwrap();
     assert_eq!(
       config,
       Config {
-        paid: None,
-        base_price: None
+        extra_css: Some(vec![Path::new("site").join("style.css")]),
+        extra_js: Some(vec![Path::new("site").join("js/script.js")]),
+        ..Config::default()
       }
     );
   }
+
+  #[test]
+  fn meta_image_paths_are_relative_to_config_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir(temp_dir.path().join("site")).unwrap();
+    fs::write(
+      temp_dir.path().join("site/.agora.yaml"),
+      "meta: {title: Site, image: cover.png}",
+    )
+    .unwrap();
+    fs::create_dir(temp_dir.path().join("site/other")).unwrap();
+    fs::write(
+      temp_dir.path().join("site/other/.agora.yaml"),
+      "meta: {image: 'https://example.com/cover.png'}",
+    )
+    .unwrap();
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("site"))
+      .unwrap();
+    assert_eq!(
+      config.meta.unwrap(),
+      Meta {
+        title: Some("Site".into()),
+        description: None,
+        image: Some(
+          Path::new("site")
+            .join("cover.png")
+            .to_string_lossy()
+            .into_owned()
+        ),
+      }
+    );
+    let config = Config::default()
+      .for_dir(temp_dir.path(), &temp_dir.path().join("site/other"))
+      .unwrap();
+    assert_eq!(
+      config.meta.unwrap().image.unwrap(),
+      "https://example.com/cover.png"
+    );
+  }
 }
@@ -0,0 +1,118 @@
+use {
+  crate::common::*,
+  std::{collections::BTreeMap, convert::TryFrom},
+};
+
+/// Content types for files whose names match a pattern, overriding the
+/// content type guessed from their extension. Patterns are either globs
+/// matched against file names, where `*` matches any number of characters
+/// and `?` matches one, or plain extensions, like `wasm` or `.wasm`.
+/// Matching is case-insensitive.
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(try_from = "BTreeMap<String, String>")]
+pub(crate) struct ContentTypes {
+  /// Checked in order, so more specific patterns come first.
+  overrides: Vec<(String, String)>,
+}
+
+impl TryFrom<BTreeMap<String, String>> for ContentTypes {
+  type Error = String;
+
+  fn try_from(map: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+    let mut overrides = Vec::new();
+    for (pattern, content_type) in map {
+      content_type
+        .parse::<mime_guess::mime::Mime>()
+        .map_err(|error| format!("invalid content type `{}`: {}", content_type, error))?;
+      overrides.push((normalize_pattern(pattern), content_type));
+    }
+    // Longer patterns are more specific, e.g. `*.tar.gz` over `*.gz`
+    overrides.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+    Ok(Self { overrides })
+  }
+}
+
+impl ContentTypes {
+  pub(crate) fn get(&self, file_name: &str) -> Option<&str> {
+    let file_name = file_name.to_lowercase();
+    self
+      .overrides
+      .iter()
+      .find(|(pattern, _content_type)| {
+        glob_match(
+          &pattern.chars().collect::<Vec<char>>(),
+          &file_name.chars().collect::<Vec<char>>(),
+        )
+      })
+      .map(|(_pattern, content_type)| content_type.as_str())
+  }
+
+  /// Combine with the content types of a parent directory's config, which
+  /// only apply to files that none of these patterns match.
+  pub(crate) fn merge_parent(mut self, parent: Self) -> Self {
+    self.overrides.extend(parent.overrides);
+    self
+  }
+}
+
+/// Turn plain extensions into globs, and lowercase them, so that matching
+/// is case-insensitive.
+pub(super) fn normalize_pattern(pattern: String) -> String {
+  let pattern = if pattern.contains(|c| c == '*' || c == '?') {
+    pattern
+  } else {
+    format!("*.{}", pattern.trim_start_matches('.'))
+  };
+  pattern.to_lowercase()
+}
+
+pub(super) fn glob_match(pattern: &[char], name: &[char]) -> bool {
+  match pattern.split_first() {
+    None => name.is_empty(),
+    Some(('*', rest)) => (0..=name.len()).any(|skip| glob_match(rest, &name[skip..])),
+    Some(('?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+    Some((c, rest)) => name.first() == Some(c) && glob_match(rest, &name[1..]),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::test_utils::assert_contains};
+
+  fn parse(yaml: &str) -> Result<ContentTypes, String> {
+    serde_yaml::from_str::<ContentTypes>(yaml).map_err(|error| error.to_string())
+  }
+
+  #[test]
+  fn globs_and_extensions() {
+    let content_types = parse(
+      "{'*.wasm': application/wasm, .gz: application/gzip, tar.gz: application/x-tgz, 'data-??': text/csv}",
+    )
+    .unwrap();
+    assert_eq!(content_types.get("foo.wasm"), Some("application/wasm"));
+    assert_eq!(content_types.get("FOO.WASM"), Some("application/wasm"));
+    assert_eq!(content_types.get("foo.gz"), Some("application/gzip"));
+    assert_eq!(content_types.get("foo.tar.gz"), Some("application/x-tgz"));
+    assert_eq!(content_types.get("data-01"), Some("text/csv"));
+    assert_eq!(content_types.get("data-001"), None);
+    assert_eq!(content_types.get("wasm"), None);
+  }
+
+  #[test]
+  fn invalid_content_type() {
+    assert_contains(
+      &parse("{wasm: application}").unwrap_err(),
+      "invalid content type `application`",
+    );
+  }
+
+  #[test]
+  fn parent_patterns_come_last() {
+    let child = parse("{'*': text/plain}").unwrap();
+    let parent = parse("{'*.tar.gz': application/x-tgz}").unwrap();
+    assert_eq!(
+      child.merge_parent(parent).get("foo.tar.gz"),
+      Some("text/plain")
+    );
+  }
+}
@@ -0,0 +1,50 @@
+use crate::common::*;
+
+/// Extensions of files that are never served or listed, configured with
+/// `deny-extensions`, like `[.env, .key]`. Requests for them get the same
+/// `404 Not Found` as requests for hidden files, so their presence isn't
+/// revealed. Matching is case-insensitive, and extensions may be given
+/// with or without their leading dot.
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(from = "Vec<String>")]
+pub(crate) struct DenyExtensions {
+  /// Lowercase, with a leading dot
+  extensions: Vec<String>,
+}
+
+impl From<Vec<String>> for DenyExtensions {
+  fn from(extensions: Vec<String>) -> Self {
+    Self {
+      extensions: extensions
+        .into_iter()
+        .map(|extension| format!(".{}", extension.trim_start_matches('.')).to_lowercase())
+        .collect(),
+    }
+  }
+}
+
+impl DenyExtensions {
+  pub(crate) fn matches(&self, file_name: &str) -> bool {
+    let file_name = file_name.to_lowercase();
+    self
+      .extensions
+      .iter()
+      .any(|extension| file_name.ends_with(extension.as_str()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn matches() {
+    let deny_extensions = serde_yaml::from_str::<DenyExtensions>("[.env, key, .tar.gz]").unwrap();
+    assert!(deny_extensions.matches("production.env"));
+    assert!(deny_extensions.matches("SERVER.KEY"));
+    assert!(deny_extensions.matches("backup.tar.gz"));
+    assert!(!deny_extensions.matches("backup.gz"));
+    assert!(!deny_extensions.matches("keys.txt"));
+    assert!(!deny_extensions.matches("monkey"));
+  }
+}
@@ -0,0 +1,106 @@
+use {crate::common::*, std::convert::TryFrom};
+
+/// Price of paid files in a fiat currency, e.g. `1.99 USD`, configured with
+/// `fiat-price`, which is converted to bitcoin at the exchange rate from
+/// `--price-feed-url` whenever an invoice is created.
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct FiatPrice {
+  /// The amount as written in the config file, for display
+  text: String,
+  amount: f64,
+  currency: String,
+}
+
+impl FiatPrice {
+  pub(crate) fn amount(&self) -> f64 {
+    self.amount
+  }
+
+  pub(crate) fn currency(&self) -> &str {
+    &self.currency
+  }
+}
+
+impl TryFrom<String> for FiatPrice {
+  type Error = String;
+
+  fn try_from(input: String) -> Result<Self, Self::Error> {
+    let invalid = || {
+      format!(
+        "invalid fiat price `{}`, expected a positive decimal amount and a three-letter currency code, e.g. `1.99 USD`",
+        input
+      )
+    };
+    let mut parts = input.splitn(2, ' ');
+    let text = parts
+      .next()
+      .filter(|text| {
+        !text.is_empty()
+          && text.chars().all(|c| c.is_ascii_digit() || c == '.')
+          && text.matches('.').count() <= 1
+      })
+      .ok_or_else(invalid)?;
+    let amount = text
+      .parse::<f64>()
+      .ok()
+      .filter(|amount| amount.is_finite() && *amount > 0.0)
+      .ok_or_else(invalid)?;
+    let currency = parts
+      .next()
+      .filter(|currency| currency.len() == 3 && currency.chars().all(|c| c.is_ascii_uppercase()))
+      .ok_or_else(invalid)?;
+    Ok(Self {
+      text: text.to_owned(),
+      amount,
+      currency: currency.to_owned(),
+    })
+  }
+}
+
+impl Display for FiatPrice {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "{} {}", self.text, self.currency)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    let price = FiatPrice::try_from("1.99 USD".to_owned()).unwrap();
+    assert_eq!(price.amount(), 1.99);
+    assert_eq!(price.currency(), "USD");
+    assert_eq!(price.to_string(), "1.99 USD");
+    assert_eq!(
+      FiatPrice::try_from("5 EUR".to_owned()).unwrap().to_string(),
+      "5 EUR"
+    );
+  }
+
+  #[test]
+  fn invalid() {
+    for input in &[
+      "",
+      "1.99",
+      "USD",
+      "0 USD",
+      "-1 USD",
+      "1.9.9 USD",
+      "1.99 usd",
+      "1.99 US",
+      "1e3 USD",
+      "1.99  USD",
+    ] {
+      assert_eq!(
+        FiatPrice::try_from(input.to_string()).unwrap_err(),
+        format!(
+          "invalid fiat price `{}`, expected a positive decimal amount and a three-letter currency code, e.g. `1.99 USD`",
+          input
+        )
+      );
+    }
+  }
+}
@@ -0,0 +1,48 @@
+use {
+  super::content_types::{glob_match, normalize_pattern},
+  crate::common::*,
+};
+
+/// Files that are served as attachments, so browsers download them instead
+/// of displaying them, configured with `force-download`, a list of the same
+/// patterns as `content-types`.
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(from = "Vec<String>")]
+pub(crate) struct ForceDownload {
+  patterns: Vec<String>,
+}
+
+impl From<Vec<String>> for ForceDownload {
+  fn from(patterns: Vec<String>) -> Self {
+    Self {
+      patterns: patterns.into_iter().map(normalize_pattern).collect(),
+    }
+  }
+}
+
+impl ForceDownload {
+  pub(crate) fn matches(&self, file_name: &str) -> bool {
+    let file_name = file_name.to_lowercase().chars().collect::<Vec<char>>();
+    self
+      .patterns
+      .iter()
+      .any(|pattern| glob_match(&pattern.chars().collect::<Vec<char>>(), &file_name))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn matches() {
+    let force_download =
+      serde_yaml::from_str::<ForceDownload>("[pdf, '*.tar.*', 'data-??']").unwrap();
+    assert!(force_download.matches("foo.pdf"));
+    assert!(force_download.matches("FOO.PDF"));
+    assert!(force_download.matches("foo.tar.gz"));
+    assert!(force_download.matches("data-01"));
+    assert!(!force_download.matches("foo.txt"));
+    assert!(!force_download.matches("pdf"));
+  }
+}
@@ -0,0 +1,85 @@
+use {
+  crate::common::*,
+  serde::{
+    de::{self, Visitor},
+    Deserializer,
+  },
+};
+
+/// How long invoices stay payable, e.g. `3600 sec` or `10 min`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) struct InvoiceExpiry {
+  seconds: i64,
+}
+
+impl InvoiceExpiry {
+  pub(crate) fn seconds(self) -> i64 {
+    self.seconds
+  }
+}
+
+impl<'de> Deserialize<'de> for InvoiceExpiry {
+  fn deserialize<D>(deserializer: D) -> Result<InvoiceExpiry, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_str(InvoiceExpiryVisitor)
+  }
+}
+
+struct InvoiceExpiryVisitor;
+
+impl<'de> Visitor<'de> for InvoiceExpiryVisitor {
+  type Value = InvoiceExpiry;
+
+  fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+    formatter.write_str("a string, e.g. \"10 min\"")
+  }
+
+  fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+  where
+    E: de::Error,
+  {
+    let invalid = || {
+      de::Error::invalid_value(
+        de::Unexpected::Str(value),
+        &"positive integer duration, including unit `sec`, `min`, or `hour`, e.g. \"10 min\"",
+      )
+    };
+
+    let mut parts = value.splitn(2, ' ');
+    let amount = parts
+      .next()
+      .and_then(|amount| amount.parse::<i64>().ok())
+      .filter(|amount| *amount > 0)
+      .ok_or_else(invalid)?;
+    let unit = match parts.next() {
+      Some("sec") => 1,
+      Some("min") => 60,
+      Some("hour") => 60 * 60,
+      _ => return Err(invalid()),
+    };
+
+    Ok(InvoiceExpiry {
+      seconds: amount.checked_mul(unit).ok_or_else(invalid)?,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    let parse = |yaml| serde_yaml::from_str::<InvoiceExpiry>(yaml).map(InvoiceExpiry::seconds);
+    assert_eq!(parse("3600 sec").unwrap(), 3600);
+    assert_eq!(parse("10 min").unwrap(), 600);
+    assert_eq!(parse("2 hour").unwrap(), 7200);
+    assert!(parse("10").is_err());
+    assert!(parse("0 sec").is_err());
+    assert!(parse("-1 sec").is_err());
+    assert!(parse("10 days").is_err());
+    assert!(parse("9223372036854775807 hour").is_err());
+  }
+}
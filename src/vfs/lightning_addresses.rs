@@ -0,0 +1,79 @@
+use {
+  crate::common::*,
+  std::{collections::BTreeMap, convert::TryFrom},
+};
+
+/// Lightning addresses served at `/.well-known/lnurlp/USERNAME`, configured
+/// with `lightning-addresses`, a map from usernames to the amount that is
+/// invoiced, and an optional description shown by wallets.
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(try_from = "BTreeMap<String, LightningAddress>")]
+pub(crate) struct LightningAddresses {
+  addresses: BTreeMap<String, LightningAddress>,
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct LightningAddress {
+  pub(crate) amount: Millisatoshi,
+  pub(crate) description: Option<String>,
+}
+
+impl TryFrom<BTreeMap<String, LightningAddress>> for LightningAddresses {
+  type Error = String;
+
+  fn try_from(addresses: BTreeMap<String, LightningAddress>) -> Result<Self, Self::Error> {
+    for username in addresses.keys() {
+      // Lightning addresses only allow these characters in usernames
+      if username.is_empty()
+        || !username
+          .chars()
+          .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_' || c == '.')
+      {
+        return Err(format!(
+          "invalid lightning address username `{}`, expected only `a-z`, `0-9`, `-`, `_`, and `.`",
+          username
+        ));
+      }
+    }
+    Ok(Self { addresses })
+  }
+}
+
+impl LightningAddresses {
+  pub(crate) fn get(&self, username: &str) -> Option<&LightningAddress> {
+    self.addresses.get(username)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::test_utils::assert_contains};
+
+  fn parse(yaml: &str) -> Result<LightningAddresses, String> {
+    serde_yaml::from_str::<LightningAddresses>(yaml).map_err(|error| error.to_string())
+  }
+
+  #[test]
+  fn addresses() {
+    let addresses = parse("{alice: {amount: 1000 sat, description: Tips for Alice}}").unwrap();
+    assert_eq!(
+      addresses.get("alice"),
+      Some(&LightningAddress {
+        amount: Millisatoshi::new(1_000_000),
+        description: Some("Tips for Alice".to_owned()),
+      })
+    );
+    assert_eq!(addresses.get("bob"), None);
+  }
+
+  #[test]
+  fn invalid_usernames() {
+    for username in &["Alice", "a b", "a/b", ""] {
+      assert_contains(
+        &parse(&format!("{{'{}': {{amount: 1 sat}}}}", username)).unwrap_err(),
+        &format!("invalid lightning address username `{}`", username),
+      );
+    }
+  }
+}
@@ -0,0 +1,40 @@
+use crate::common::*;
+
+/// OpenGraph metadata for the invoice pages of paid files, configured with
+/// `meta`, so links to paid files shared in chat apps and social media
+/// render with a preview.
+#[derive(PartialEq, Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Meta {
+  pub(crate) title: Option<String>,
+  pub(crate) description: Option<String>,
+  /// Either an absolute URL, or the path of a file relative to the
+  /// directory containing `.agora.yaml`
+  pub(crate) image: Option<String>,
+}
+
+impl Meta {
+  /// Whether `image` is an absolute URL, which is used as it is, instead
+  /// of the path of a file.
+  pub(crate) fn is_url(image: &str) -> bool {
+    image.starts_with("http://") || image.starts_with("https://")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn is_url() {
+    assert!(Meta::is_url("https://example.com/cover.png"));
+    assert!(Meta::is_url("http://example.com/cover.png"));
+    assert!(!Meta::is_url("cover.png"));
+    assert!(!Meta::is_url("images/https.png"));
+  }
+
+  #[test]
+  fn rejects_unknown_fields() {
+    assert!(serde_yaml::from_str::<Meta>("{title: foo, color: red}").is_err());
+  }
+}
@@ -0,0 +1,87 @@
+use {
+  crate::common::*,
+  serde::{
+    de::{self, Visitor},
+    Deserializer,
+  },
+};
+
+/// Price charged for each byte of a paid file, e.g. `2 msat`. Unlike
+/// `base-price`, it may be given in millisatoshis, since even one satoshi
+/// per byte is a lot for large files.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) struct PricePerByte {
+  millisatoshi: Millisatoshi,
+}
+
+impl PricePerByte {
+  pub(crate) fn millisatoshi(self) -> Millisatoshi {
+    self.millisatoshi
+  }
+}
+
+impl<'de> Deserialize<'de> for PricePerByte {
+  fn deserialize<D>(deserializer: D) -> Result<PricePerByte, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_str(PricePerByteVisitor)
+  }
+}
+
+struct PricePerByteVisitor;
+
+impl<'de> Visitor<'de> for PricePerByteVisitor {
+  type Value = PricePerByte;
+
+  fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+    formatter.write_str("a string, e.g. \"2 msat\"")
+  }
+
+  fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+  where
+    E: de::Error,
+  {
+    let invalid = || {
+      de::Error::invalid_value(
+        de::Unexpected::Str(value),
+        &"integer number of millisatoshis or satoshis, including unit, e.g. \"2 msat\"",
+      )
+    };
+
+    let mut parts = value.splitn(2, ' ');
+    let amount = parts
+      .next()
+      .filter(|amount| amount.chars().all(|c| c.is_ascii_digit()))
+      .and_then(|amount| amount.parse::<u64>().ok())
+      .ok_or_else(invalid)?;
+    let unit = match parts.next() {
+      Some("msat") => 1,
+      Some("sat") => 1000,
+      _ => return Err(invalid()),
+    };
+
+    Ok(PricePerByte {
+      millisatoshi: Millisatoshi::new(amount.checked_mul(unit).ok_or_else(invalid)?),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    let parse =
+      |yaml| serde_yaml::from_str::<PricePerByte>(yaml).map(|price| price.millisatoshi().value());
+    assert_eq!(parse("2 msat").unwrap(), 2);
+    assert_eq!(parse("3 sat").unwrap(), 3000);
+    assert_eq!(parse("0 msat").unwrap(), 0);
+    assert!(parse("2").is_err());
+    assert!(parse("+2 msat").is_err());
+    assert!(parse("-2 msat").is_err());
+    assert!(parse("2 btc").is_err());
+    assert!(parse("18446744073709552 sat").is_err());
+  }
+}
@@ -0,0 +1,85 @@
+use {
+  crate::common::*,
+  hyper::Uri,
+  std::{collections::BTreeMap, convert::TryFrom},
+};
+
+/// Files in a directory that are streamed from an upstream URL, instead of
+/// read from disk, configured with `proxy`, a map from file names to
+/// absolute `http` or `https` URLs.
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(try_from = "BTreeMap<String, String>")]
+pub(crate) struct Proxy {
+  urls: BTreeMap<String, Uri>,
+}
+
+impl TryFrom<BTreeMap<String, String>> for Proxy {
+  type Error = String;
+
+  fn try_from(map: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+    let mut urls = BTreeMap::new();
+    for (file_name, url) in map {
+      if file_name.is_empty() || file_name.starts_with('.') || file_name.contains('/') {
+        return Err(format!("invalid proxied file name `{}`", file_name));
+      }
+      let uri = url
+        .parse::<Uri>()
+        .map_err(|error| format!("invalid proxy URL `{}`: {}", url, error))?;
+      if !matches!(uri.scheme_str(), Some("http") | Some("https")) || uri.authority().is_none() {
+        return Err(format!(
+          "proxy URL `{}` is not an absolute `http` or `https` URL",
+          url
+        ));
+      }
+      urls.insert(file_name, uri);
+    }
+    Ok(Self { urls })
+  }
+}
+
+impl Proxy {
+  pub(crate) fn get(&self, file_name: &str) -> Option<&Uri> {
+    self.urls.get(file_name)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::test_utils::assert_contains};
+
+  fn parse(yaml: &str) -> Result<Proxy, String> {
+    serde_yaml::from_str::<Proxy>(yaml).map_err(|error| error.to_string())
+  }
+
+  #[test]
+  fn urls() {
+    let proxy = parse("{foo.mp4: 'https://example.com/foo.mp4?a=b'}").unwrap();
+    assert_eq!(
+      proxy.get("foo.mp4").unwrap(),
+      "https://example.com/foo.mp4?a=b"
+    );
+    assert_eq!(proxy.get("bar.mp4"), None);
+  }
+
+  #[test]
+  fn only_absolute_http_urls_are_allowed() {
+    assert_contains(
+      &parse("{foo: 'ftp://example.com/foo'}").unwrap_err(),
+      "proxy URL `ftp://example.com/foo` is not an absolute `http` or `https` URL",
+    );
+    assert_contains(
+      &parse("{foo: /foo}").unwrap_err(),
+      "proxy URL `/foo` is not an absolute `http` or `https` URL",
+    );
+  }
+
+  #[test]
+  fn invalid_file_names() {
+    for file_name in &[".hidden", "dir/foo"] {
+      assert_contains(
+        &parse(&format!("{{'{}': 'https://example.com'}}", file_name)).unwrap_err(),
+        &format!("invalid proxied file name `{}`", file_name),
+      );
+    }
+  }
+}
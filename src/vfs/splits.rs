@@ -0,0 +1,154 @@
+use {crate::common::*, std::convert::TryFrom};
+
+/// Shares of the payments for paid files that are forwarded to other nodes
+/// with keysend payments, e.g. to collaborators. Weights are percentages of
+/// each payment, and whatever isn't split off stays with this node.
+#[derive(PartialEq, Debug, Clone, Deserialize)]
+#[serde(try_from = "Vec<SplitConfig>")]
+pub(crate) struct Splits {
+  splits: Vec<Split>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Split {
+  destination: Vec<u8>,
+  weight: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SplitConfig {
+  address: String,
+  weight: u64,
+}
+
+impl TryFrom<Vec<SplitConfig>> for Splits {
+  type Error = String;
+
+  fn try_from(configs: Vec<SplitConfig>) -> Result<Self, Self::Error> {
+    let mut splits = Vec::new();
+    for SplitConfig { address, weight } in configs {
+      let destination = hex::decode(&address)
+        .ok()
+        .filter(|destination| destination.len() == 33)
+        .ok_or_else(|| {
+          format!(
+            "invalid split address `{}`, expected hex-encoded node public key",
+            address
+          )
+        })?;
+      splits.push(Split {
+        destination,
+        weight,
+      });
+    }
+
+    let total = splits
+      .iter()
+      .try_fold(0u64, |total, split| total.checked_add(split.weight));
+    match total {
+      Some(0) => Err("split weights must not all be zero".to_owned()),
+      Some(total) if total <= 100 => Ok(Self { splits }),
+      _ => Err("split weights must add up to at most 100".to_owned()),
+    }
+  }
+}
+
+impl Splits {
+  /// Amounts to send to each destination out of a payment of `value`,
+  /// rounded down to whole millisatoshis. Zero amounts are skipped.
+  pub(crate) fn amounts(&self, value: Millisatoshi) -> Vec<(&[u8], Millisatoshi)> {
+    self
+      .splits
+      .iter()
+      .map(|split| {
+        let amount = u128::from(value.value()) * u128::from(split.weight) / 100;
+        (
+          split.destination.as_slice(),
+          Millisatoshi::new(amount as u64),
+        )
+      })
+      .filter(|(_destination, amount)| amount.value() > 0)
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, crate::test_utils::assert_contains};
+
+  const ALICE: &str = "02aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+  const BOB: &str = "03bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+
+  fn parse(yaml: &str) -> Result<Splits, String> {
+    serde_yaml::from_str::<Splits>(yaml).map_err(|error| error.to_string())
+  }
+
+  #[test]
+  fn parse_valid() {
+    let splits = parse(&format!(
+      "[{{address: {}, weight: 10}}, {{address: {}, weight: 0}}]",
+      ALICE, BOB
+    ))
+    .unwrap();
+    assert_eq!(
+      splits.splits,
+      vec![
+        Split {
+          destination: hex::decode(ALICE).unwrap(),
+          weight: 10,
+        },
+        Split {
+          destination: hex::decode(BOB).unwrap(),
+          weight: 0,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn parse_invalid() {
+    assert_contains(
+      &parse(&format!("[{{address: {}, weight: 0}}]", ALICE)).unwrap_err(),
+      "split weights must not all be zero",
+    );
+    assert_contains(
+      &parse("[]").unwrap_err(),
+      "split weights must not all be zero",
+    );
+    assert_contains(
+      &parse(&format!(
+        "[{{address: {}, weight: 60}}, {{address: {}, weight: 41}}]",
+        ALICE, BOB
+      ))
+      .unwrap_err(),
+      "split weights must add up to at most 100",
+    );
+    assert_contains(
+      &parse("[{address: alice@example.com, weight: 10}]").unwrap_err(),
+      "invalid split address `alice@example.com`",
+    );
+    assert!(parse(&format!("[{{address: {}, weight: -1}}]", ALICE)).is_err());
+    assert!(parse(&format!("[{{address: {}}}]", ALICE)).is_err());
+  }
+
+  #[test]
+  fn amounts() {
+    let splits = parse(&format!(
+      "[{{address: {}, weight: 10}}, {{address: {}, weight: 25}}]",
+      ALICE, BOB
+    ))
+    .unwrap();
+    assert_eq!(
+      splits.amounts(Millisatoshi::new(1_001)),
+      vec![
+        (
+          hex::decode(ALICE).unwrap().as_slice(),
+          Millisatoshi::new(100)
+        ),
+        (hex::decode(BOB).unwrap().as_slice(), Millisatoshi::new(250)),
+      ]
+    );
+    assert_eq!(splits.amounts(Millisatoshi::new(3)), vec![]);
+  }
+}
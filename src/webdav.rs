@@ -0,0 +1,169 @@
+use crate::common::*;
+
+/// Methods allowed on `/files/` paths, advertised in responses to `OPTIONS`
+/// requests.
+const ALLOW: &str = "OPTIONS, GET, HEAD, PROPFIND";
+
+/// Value of the `Depth` header of a `PROPFIND` request. Only `0`, for just
+/// the requested resource, and `1`, for a directory and its entries, are
+/// supported. `infinity`, which is also the default, would mean walking
+/// entire trees, so it is rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Depth {
+  Zero,
+  One,
+}
+
+impl Depth {
+  pub(crate) fn from_request(request: &Request<Body>) -> Result<Self> {
+    let depth = request
+      .headers()
+      .get("depth")
+      .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+      .unwrap_or_else(|| "infinity".to_owned());
+    match depth.trim() {
+      "0" => Ok(Self::Zero),
+      "1" => Ok(Self::One),
+      _ => Err(error::WebdavDepth { depth }.build()),
+    }
+  }
+}
+
+/// A file or directory in the response to a `PROPFIND` request.
+pub(crate) struct Resource {
+  pub(crate) href: String,
+  pub(crate) display_name: String,
+  pub(crate) is_collection: bool,
+  pub(crate) content_length: Option<u64>,
+  pub(crate) last_modified: Option<SystemTime>,
+}
+
+/// Response to an `OPTIONS` request, which WebDAV clients send to check
+/// whether a server supports WebDAV, and at which compliance class.
+pub(crate) fn options_response() -> Response<Body> {
+  Response::builder()
+    .header("dav", "1")
+    .header(header::ALLOW, ALLOW)
+    .body(Body::empty())
+    .expect("All arguments to response builder are valid")
+}
+
+/// `207 Multi-Status` response to a `PROPFIND` request, with the
+/// properties of `resources`.
+pub(crate) fn multistatus_response(resources: &[Resource]) -> Response<Body> {
+  Response::builder()
+    .status(StatusCode::MULTI_STATUS)
+    .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+    .body(multistatus(resources).into())
+    .expect("All arguments to response builder are valid")
+}
+
+fn multistatus(resources: &[Resource]) -> String {
+  let mut xml =
+    String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+  for resource in resources {
+    xml.push_str("<D:response>");
+    xml.push_str(&format!("<D:href>{}</D:href>", escape(&resource.href)));
+    xml.push_str("<D:propstat><D:prop>");
+    xml.push_str(&format!(
+      "<D:displayname>{}</D:displayname>",
+      escape(&resource.display_name)
+    ));
+    if resource.is_collection {
+      xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+      xml.push_str("<D:resourcetype/>");
+    }
+    if let Some(content_length) = resource.content_length {
+      xml.push_str(&format!(
+        "<D:getcontentlength>{}</D:getcontentlength>",
+        content_length
+      ));
+    }
+    if let Some(last_modified) = resource.last_modified {
+      xml.push_str(&format!(
+        "<D:getlastmodified>{}</D:getlastmodified>",
+        httpdate::fmt_http_date(last_modified)
+      ));
+    }
+    xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat>");
+    xml.push_str("</D:response>\n");
+  }
+  xml.push_str("</D:multistatus>\n");
+  xml
+}
+
+pub(crate) fn escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use {super::*, std::time::Duration};
+
+  #[test]
+  fn depth() {
+    let depth = |value: Option<&str>| {
+      let mut builder = Request::builder();
+      if let Some(value) = value {
+        builder = builder.header("depth", value);
+      }
+      Depth::from_request(&builder.body(Body::empty()).unwrap())
+    };
+    assert_eq!(depth(Some("0")).unwrap(), Depth::Zero);
+    assert_eq!(depth(Some("1")).unwrap(), Depth::One);
+    assert_eq!(
+      depth(Some("infinity")).unwrap_err().to_string(),
+      "Unsupported WebDAV depth `infinity`, expected `0` or `1`"
+    );
+    assert_eq!(
+      depth(None).unwrap_err().to_string(),
+      "Unsupported WebDAV depth `infinity`, expected `0` or `1`"
+    );
+  }
+
+  #[test]
+  fn multistatus_lists_properties() {
+    assert_eq!(
+      multistatus(&[
+        Resource {
+          href: "/files/".to_owned(),
+          display_name: "".to_owned(),
+          is_collection: true,
+          content_length: None,
+          last_modified: None,
+        },
+        Resource {
+          href: "/files/a%20&%20b".to_owned(),
+          display_name: "a & b".to_owned(),
+          is_collection: false,
+          content_length: Some(3),
+          last_modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000)),
+        },
+      ]),
+      concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<D:multistatus xmlns:D=\"DAV:\">\n",
+        "<D:response><D:href>/files/</D:href><D:propstat><D:prop>",
+        "<D:displayname></D:displayname><D:resourcetype><D:collection/></D:resourcetype>",
+        "</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        "<D:response><D:href>/files/a%20&amp;%20b</D:href><D:propstat><D:prop>",
+        "<D:displayname>a &amp; b</D:displayname><D:resourcetype/>",
+        "<D:getcontentlength>3</D:getcontentlength>",
+        "<D:getlastmodified>Sun, 09 Sep 2001 01:46:40 GMT</D:getlastmodified>",
+        "</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        "</D:multistatus>\n",
+      )
+    );
+  }
+}
@@ -5,3 +5,22 @@ if (location.protocol === 'https:') {
     element.classList.add("enabled");
   }
 }
+
+let invoice = document.querySelector('.invoice[data-status-url]');
+
+if (invoice) {
+  let poll = async () => {
+    try {
+      let response = await fetch(invoice.dataset.statusUrl);
+      if (response.ok && (await response.json()).settled) {
+        location.reload();
+        return;
+      }
+    } catch (error) {
+      // Keep polling if the server is temporarily unreachable
+    }
+    setTimeout(poll, 2000);
+  };
+
+  setTimeout(poll, 2000);
+}
@@ -4,13 +4,14 @@ use {
   guard::guard_unwrap,
   hyper::{header, StatusCode},
   lexiclean::Lexiclean,
+  regex::Regex,
   scraper::{ElementRef, Html, Selector},
   std::{
     fs,
-    io::{Read, Write},
+    io::{BufRead, BufReader, Read, Write},
     net::TcpListener,
     path::{Path, MAIN_SEPARATOR},
-    process::Command,
+    process::{Command, Stdio},
     str, thread,
     time::Duration,
   },
@@ -172,6 +173,242 @@ fn listing_is_sorted_alphabetically() {
   assert_eq!(haystack, vec!["a", "b", "c"]);
 }
 
+#[test]
+fn listing_interleaves_directories_and_files_by_default() {
+  let context = AgoraTestContext::builder().build();
+  context.write("a", "");
+  context.create_dir_all("b");
+  context.write("c", "");
+  assert_eq!(
+    context.text("files/?format=txt"),
+    "0 B  a\n     b/\n0 B  c\n"
+  );
+}
+
+#[test]
+fn listing_can_group_directories_first() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "listing-dirs-first: true");
+  context.write("a", "");
+  context.create_dir_all("b");
+  context.write("c", "");
+  context.create_dir_all("d");
+  let html = context.html("files/");
+  let haystack: Vec<&str> = css_select(&html, ".listing a:not([download])")
+    .into_iter()
+    .map(|x| x.text())
+    .flatten()
+    .collect();
+  assert_eq!(haystack, vec!["b/", "d/", "a", "c"]);
+}
+
+#[test]
+fn config_files_can_reference_environment_variables() {
+  let context = AgoraTestContext::builder()
+    .env("AGORA_CFG_LISTING", "false")
+    .build();
+  context.write("dir/.agora.yaml", "listing: ${AGORA_CFG_LISTING}");
+  context.write("dir/foo", "");
+  context.write("other/.agora.yaml", "listing: ${AGORA_CFG_UNSET}");
+  assert_eq!(context.text("files/dir/?format=txt"), "");
+  assert_eq!(
+    context.status("files/other/"),
+    StatusCode::INTERNAL_SERVER_ERROR
+  );
+  let stderr = context.kill();
+  assert_contains(
+    &stderr,
+    "references unset environment variable `AGORA_CFG_UNSET`",
+  );
+}
+
+#[test]
+fn group_directories_first_flag_lists_directories_first() {
+  let context = AgoraTestContext::builder()
+    .args(&["--group-directories-first"])
+    .build();
+  context.write("a", "");
+  context.create_dir_all("b");
+  context.write("c", "");
+  context.write("sub/.agora.yaml", "listing-dirs-first: false");
+  context.write("sub/a", "");
+  context.create_dir_all("sub/b");
+  assert_eq!(
+    context.text("files/?format=txt"),
+    "     b/\n     sub/\n0 B  a\n0 B  c\n"
+  );
+  assert_eq!(context.text("files/sub/?format=txt"), "0 B  a\n     b/\n");
+}
+
+#[test]
+fn listings_can_be_paginated() {
+  let context = AgoraTestContext::builder()
+    .args(&["--dir-listing-page-size=2"])
+    .build();
+  context.write("a", "");
+  context.write("b", "");
+  context.write("c", "");
+
+  let listed = |html: &Html| -> Vec<String> {
+    css_select(html, ".listing a:not([download])")
+      .into_iter()
+      .map(|element| element.text().collect())
+      .collect()
+  };
+
+  let html = context.html("files/");
+  assert_eq!(listed(&html), vec!["a", "b"]);
+  assert_eq!(
+    css_select(&html, ".pages span")[0]
+      .text()
+      .collect::<String>(),
+    "page 1 of 2, 3 entries"
+  );
+  assert!(css_select(&html, ".pages a[rel=prev]").is_empty());
+  assert_eq!(
+    css_select(&html, ".pages a[rel=next]")[0]
+      .value()
+      .attr("href"),
+    Some("?page=2")
+  );
+
+  let html = context.html("files/?sort=name&order=desc&page=2");
+  assert_eq!(listed(&html), vec!["a"]);
+  assert_eq!(
+    css_select(&html, ".pages a[rel=prev]")[0]
+      .value()
+      .attr("href"),
+    Some("?sort=name&order=desc&page=1")
+  );
+  assert!(css_select(&html, ".pages a[rel=next]").is_empty());
+
+  assert_eq!(context.status("files/?page=3"), StatusCode::NOT_FOUND);
+  assert_eq!(context.status("files/?page=0"), StatusCode::NOT_FOUND);
+  assert_eq!(context.status("files/?page=x"), StatusCode::NOT_FOUND);
+
+  assert_eq!(
+    context.text("files/?format=txt&page=2"),
+    "0 B  a\n0 B  b\n0 B  c\n"
+  );
+}
+
+#[test]
+fn listings_that_fit_on_one_page_are_not_paginated() {
+  let context = AgoraTestContext::builder()
+    .args(&["--dir-listing-page-size=2"])
+    .build();
+  context.write("a", "");
+  assert!(css_select(&context.html("files/"), ".pages").is_empty());
+}
+
+#[test]
+fn merged_directories_are_listed_and_served() {
+  let context = AgoraTestContext::builder().build();
+  context.write("hub/.agora.yaml", "merge: [../a, ../b]");
+  context.write("hub/own", "hub");
+  context.write("a/own", "a");
+  context.write("a/shared", "a");
+  context.write("a/sub/nested", "nested");
+  context.write("b/shared", "b");
+  context.write("b/only-b", "b");
+  let html = context.html("files/hub/");
+  let haystack: Vec<&str> = css_select(&html, ".listing a:not([download])")
+    .into_iter()
+    .map(|x| x.text())
+    .flatten()
+    .collect();
+  assert_eq!(haystack, vec!["only-b", "own", "shared", "sub/"]);
+  assert_eq!(context.text("files/hub/own"), "hub");
+  assert_eq!(context.text("files/hub/shared"), "a");
+  assert_eq!(context.text("files/hub/only-b"), "b");
+  assert_eq!(context.text("files/hub/sub/nested"), "nested");
+  assert_eq!(context.status("files/a/only-b"), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn agora_can_be_served_under_a_base_path() {
+  let context = AgoraTestContext::builder()
+    .args(&["--base-path=/share/", "--trailing-slash-policy=off"])
+    .build();
+  context.write("foo", "bar");
+  assert_eq!(
+    context.redirect_url("share"),
+    context.base_url().join("share/").unwrap()
+  );
+  assert_eq!(
+    context.redirect_url("share/"),
+    context.base_url().join("share/files/").unwrap()
+  );
+  let html = context.html("share/files/");
+  guard_unwrap!(let &[a] = css_select(&html, ".listing a:not([download])").as_slice());
+  assert_eq!(a.value().attr("href").unwrap(), "/share/files/foo");
+  guard_unwrap!(let &[link] = css_select(&html, "link[rel=stylesheet]").as_slice());
+  assert_eq!(
+    link.value().attr("href").unwrap(),
+    "/share/static/index.css"
+  );
+  assert_eq!(context.text("share/files/foo"), "bar");
+  assert_eq!(context.status("share/static/index.css"), StatusCode::OK);
+  assert_eq!(context.status("files/foo"), StatusCode::NOT_FOUND);
+  assert_eq!(context.status("shared/files/foo"), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn files_prefix_can_be_changed() {
+  let context = AgoraTestContext::builder()
+    .args(&["--files-prefix=d", "--trailing-slash-policy=off"])
+    .build();
+  context.write("foo", "bar");
+  assert_eq!(
+    context.redirect_url(""),
+    context.base_url().join("d/").unwrap()
+  );
+  assert_eq!(
+    context.redirect_url("d"),
+    context.base_url().join("d/").unwrap()
+  );
+  let html = context.html("d/");
+  guard_unwrap!(let &[a] = css_select(&html, ".listing a.view").as_slice());
+  assert_eq!(a.value().attr("href").unwrap(), "/d/foo");
+  assert_eq!(context.text("d/foo"), "bar");
+  assert_eq!(context.status("files/foo"), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn files_can_be_served_at_the_root() {
+  let context = AgoraTestContext::builder()
+    .args(&["--files-prefix=", "--trailing-slash-policy=off"])
+    .build();
+  context.write("foo", "bar");
+  context.write("static", "shadowed");
+  let html = context.html("");
+  guard_unwrap!(let &[foo, _] = css_select(&html, ".listing a.view").as_slice());
+  assert_eq!(foo.value().attr("href").unwrap(), "/foo");
+  assert_eq!(context.text("foo"), "bar");
+  assert_eq!(context.status("static/index.css"), StatusCode::OK);
+  assert_eq!(context.status("files/foo"), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn no_listing_forbids_directory_listings() {
+  let context = AgoraTestContext::builder().args(&["--no-listing"]).build();
+  context.write("foo", "bar");
+  context.write("dir/.index.md", "# Hello");
+  context.write("dir/baz", "qux");
+  assert_eq!(context.status("files/"), StatusCode::FORBIDDEN);
+  assert_eq!(context.status("files/?format=json"), StatusCode::FORBIDDEN);
+  assert_eq!(context.status("files/?archive=zip"), StatusCode::FORBIDDEN);
+  assert_eq!(context.text("files/foo"), "bar");
+  let html = context.html("files/dir/");
+  assert_eq!(css_select(&html, "h1").len(), 1);
+  assert!(css_select(&html, ".listing").is_empty());
+  assert_eq!(
+    context.status("files/dir/?format=txt"),
+    StatusCode::FORBIDDEN
+  );
+  assert_eq!(context.text("files/dir/baz"), "qux");
+}
+
 #[test]
 fn listed_files_can_be_played_in_browser() {
   let context = AgoraTestContext::builder().build();
@@ -211,6 +448,131 @@ fn listed_files_have_percent_encoded_hrefs() {
   }
 }
 
+/// Read the entries of a zip archive from its central directory.
+fn unzip(archive: &[u8]) -> Vec<(String, String)> {
+  let u16_at = |i: usize| u16::from_le_bytes([archive[i], archive[i + 1]]) as usize;
+  let u32_at = |i: usize| {
+    u32::from_le_bytes([archive[i], archive[i + 1], archive[i + 2], archive[i + 3]]) as usize
+  };
+  let end = archive.len() - 22;
+  assert_eq!(u32_at(end), 0x0605_4b50);
+  let mut record = u32_at(end + 16);
+  let mut entries = Vec::new();
+  for _ in 0..u16_at(end + 10) {
+    assert_eq!(u32_at(record), 0x0201_4b50);
+    let compressed_size = u32_at(record + 20);
+    let name_len = u16_at(record + 28);
+    let name = str::from_utf8(&archive[record + 46..record + 46 + name_len]).unwrap();
+    let local = u32_at(record + 42);
+    let data = local + 30 + u16_at(local + 26) + u16_at(local + 28);
+    let mut contents = String::new();
+    flate2::read::DeflateDecoder::new(&archive[data..data + compressed_size])
+      .read_to_string(&mut contents)
+      .unwrap();
+    entries.push((name.to_owned(), contents));
+    record += 46 + name_len + u16_at(record + 30) + u16_at(record + 32);
+  }
+  entries
+}
+
+#[test]
+fn directories_can_be_downloaded_as_zip_archives() {
+  let context = AgoraTestContext::builder().build();
+  context.write("dir/foo", "foo");
+  context.write("dir/sub/bar", "bar");
+  context.write("dir/.hidden", "hidden");
+  context.write("dir/paid/.agora.yaml", "{paid: true, base-price: 1 sat}");
+  context.write("dir/paid/secret", "secret");
+  context.write("dir/unlisted/.agora.yaml", "listing: false");
+  context.write("dir/unlisted/baz", "baz");
+  context.write("escaping", "escaping");
+  symlink("../../escaping", context.files_directory().join("dir/link"));
+
+  let response = context.get("files/dir/?archive=zip");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "application/zip"
+  );
+  assert_eq!(
+    response.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+    "attachment; filename=\"dir.zip\""
+  );
+  assert_eq!(
+    unzip(&response.bytes().unwrap()),
+    vec![
+      ("foo".to_owned(), "foo".to_owned()),
+      ("sub/bar".to_owned(), "bar".to_owned()),
+    ]
+  );
+}
+
+#[test]
+fn zip_archives_skip_symlinked_directories() {
+  let context = AgoraTestContext::builder().build();
+  context.write("dir/foo", "foo");
+  context.write("dir/sub/bar", "bar");
+  symlink("..", context.files_directory().join("dir/sub/loop"));
+  symlink("..", context.files_directory().join("dir/sub/other-loop"));
+  assert_eq!(
+    unzip(&context.get("files/dir/?archive=zip").bytes().unwrap()),
+    vec![
+      ("foo".to_owned(), "foo".to_owned()),
+      ("sub/bar".to_owned(), "bar".to_owned()),
+    ]
+  );
+}
+
+#[test]
+fn basic_auth_protects_directories() {
+  let context = AgoraTestContext::builder().build();
+  context.write("public", "public");
+  context.write(
+    "private/.agora.yaml",
+    &format!(
+      "basic-auth: {{username: alice, password-hash: '{}'}}",
+      bcrypt::hash("secret", 4).unwrap()
+    ),
+  );
+  context.write("private/sub/foo", "foo");
+
+  let get = |path: &str, password: Option<&str>| {
+    let mut request = reqwest::blocking::Client::new().get(context.files_url().join(path).unwrap());
+    if let Some(password) = password {
+      request = request.basic_auth("alice", Some(password));
+    }
+    request.send().unwrap()
+  };
+
+  let response = get("private/sub/foo", None);
+  assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+  assert_eq!(
+    response.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+    "Basic realm=\"agora\", charset=\"UTF-8\""
+  );
+  assert_eq!(
+    get("private/sub/foo", Some("wrong")).status(),
+    StatusCode::UNAUTHORIZED
+  );
+  assert_eq!(get("private/", None).status(), StatusCode::UNAUTHORIZED);
+  assert_eq!(
+    get("private/sub/foo", Some("secret")).text().unwrap(),
+    "foo"
+  );
+  assert_eq!(get("private/", Some("secret")).status(), StatusCode::OK);
+  assert_eq!(get("public", None).text().unwrap(), "public");
+  assert_eq!(
+    unzip(&get("?archive=zip", None).bytes().unwrap()),
+    vec![("public".to_owned(), "public".to_owned())]
+  );
+  assert_contains(
+    &context.kill(),
+    &format!(
+      "Missing or invalid basic auth credentials for `files{}private{}sub{}foo`",
+      MAIN_SEPARATOR, MAIN_SEPARATOR, MAIN_SEPARATOR
+    ),
+  );
+}
+
 #[test]
 fn serves_error_pages() {
   let context = AgoraTestContext::builder().build();
@@ -218,6 +580,199 @@ fn serves_error_pages() {
   assert_contains(&response.text().unwrap(), "404 Not Found");
 }
 
+#[test]
+fn error_pages_can_use_markdown_template() {
+  let context = AgoraTestContext::builder()
+    .args(&["--error-template=error.md"])
+    .write("error.md", "# Oops: {status} {message}")
+    .build();
+  let response = context.response("files/foo.txt");
+  assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  let html = Html::parse_document(&response.text().unwrap());
+  guard_unwrap!(let &[h1] = css_select(&html, "main h1").as_slice());
+  assert_eq!(h1.inner_html(), "Oops: 404 Not Found");
+  guard_unwrap!(let &[_footer] = css_select(&html, "footer").as_slice());
+}
+
+#[test]
+fn error_pages_can_use_html_template() {
+  let context = AgoraTestContext::builder()
+    .args(&["--error-template=error.html"])
+    .write("error.html", "<p>{message} ({status})</p>")
+    .build();
+  let response = context.response("files/foo.txt");
+  assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "text/html"
+  );
+  assert_eq!(response.text().unwrap(), "<p>Not Found (404)</p>");
+}
+
+#[test]
+fn errors_are_json_for_clients_that_prefer_json() {
+  let context = AgoraTestContext::builder()
+    .args(&["--error-template=error.html"])
+    .write("error.html", "<p>{message} ({status})</p>")
+    .build();
+  let response = reqwest::blocking::Client::new()
+    .get(context.files_url().join("foo.txt").unwrap())
+    .header(header::ACCEPT, "application/json")
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "application/json"
+  );
+  assert_eq!(
+    response.text().unwrap(),
+    r#"{"error":"Not Found","status":404}"#
+  );
+}
+
+#[test]
+fn server_aborts_when_error_template_does_not_exist() {
+  let output = Command::new(executable_path("agora"))
+    .arg("--directory=.")
+    .arg("--http-port=0")
+    .arg("--error-template=does/not/exist.md")
+    .output()
+    .unwrap();
+
+  assert!(!output.status.success());
+
+  let stderr = str::from_utf8(&output.stderr).unwrap();
+  assert_contains(stderr, "IO error accessing filesystem");
+  assert_contains(stderr, "exist.md");
+  assert_not_contains(stderr, "Listening for HTTP connections");
+}
+
+#[test]
+fn listings_can_use_template() {
+  let context = AgoraTestContext::builder()
+    .args(&["--listing-template=listing.html"])
+    .write(
+      "listing.html",
+      "<h1>{path}</h1>{entries}<a href=\"{href}\" data-size=\"{size}\" data-dir=\"{is_dir}\">{name}</a>{/entries}{index}",
+    )
+    .build();
+  context.write("dir/foo.txt", "foo");
+  context.write("dir/<bar>.txt", "");
+  context.write("dir/sub/baz.txt", "");
+  context.write("dir/.index.md", "*index*");
+  let response = context.response("files/dir/");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "text/html"
+  );
+  assert_eq!(
+    response.text().unwrap(),
+    concat!(
+      "<h1>/dir/</h1>",
+      "<a href=\"%3Cbar%3E.txt\" data-size=\"0\" data-dir=\"false\">&lt;bar&gt;.txt</a>",
+      "<a href=\"foo.txt\" data-size=\"3\" data-dir=\"false\">foo.txt</a>",
+      "<a href=\"sub/\" data-size=\"\" data-dir=\"true\">sub/</a>",
+      "<p><em>index</em></p>\n",
+    )
+  );
+}
+
+#[test]
+fn server_aborts_when_listing_template_has_no_entries_block() {
+  let tempdir = tempfile::tempdir().unwrap();
+  fs::write(tempdir.path().join("listing.html"), "<h1>{path}</h1>").unwrap();
+
+  let output = Command::new(executable_path("agora"))
+    .current_dir(tempdir.path())
+    .arg("--directory=.")
+    .arg("--http-port=0")
+    .arg("--listing-template=listing.html")
+    .output()
+    .unwrap();
+
+  assert!(!output.status.success());
+
+  let stderr = str::from_utf8(&output.stderr).unwrap();
+  assert_contains(stderr, "Invalid listing template");
+  assert_not_contains(stderr, "Listening for HTTP connections");
+}
+
+#[test]
+fn fatal_errors_can_be_written_as_json() {
+  let output = Command::new(executable_path("agora"))
+    .arg("--directory=.")
+    .arg("--http-port=0")
+    .arg("--error-template=does/not/exist.md")
+    .arg("--log-format=json")
+    .env("AGORA_SUPPRESS_BACKTRACE", "")
+    .output()
+    .unwrap();
+
+  assert!(!output.status.success());
+
+  let error = serde_json::from_slice::<serde_json::Value>(&output.stderr).unwrap();
+  assert_eq!(error["level"], "ERROR");
+  assert_contains(
+    error["message"].as_str().unwrap(),
+    "IO error accessing filesystem",
+  );
+}
+
+#[test]
+fn requests_can_be_logged_as_json() {
+  let context = AgoraTestContext::builder()
+    .args(&["--log-format=json"])
+    .env("RUST_LOG", "agora=debug")
+    .build();
+  context.get("files/");
+  let stderr = context.kill();
+
+  let outgoing = stderr
+    .lines()
+    .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+    .find(|line| line["message"].as_str().unwrap().starts_with("Outgoing"))
+    .unwrap();
+  assert_eq!(outgoing["level"], "DEBUG");
+  assert_eq!(outgoing["method"], "GET");
+  assert_eq!(outgoing["path"], "/files/");
+  assert_eq!(outgoing["status"], 200);
+}
+
+#[test]
+fn stderr_only_contains_json_lines_with_json_log_format() {
+  let context = AgoraTestContext::builder()
+    .args(&["--log-format=json"])
+    .build();
+  assert_eq!(context.status("files/missing"), StatusCode::NOT_FOUND);
+  let stderr = context.kill();
+
+  let lines = stderr
+    .lines()
+    .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+    .collect::<Vec<serde_json::Value>>();
+  assert_eq!(lines[0]["level"], "INFO");
+  assert_contains(
+    lines[0]["message"].as_str().unwrap(),
+    "Listening for HTTP connections",
+  );
+  assert!(lines
+    .iter()
+    .any(|line| line["level"] == "ERROR" && line["message"].as_str().unwrap().contains("missing")));
+}
+
+#[test]
+fn downloads_are_rate_limited() {
+  let context = AgoraTestContext::builder()
+    .args(&["--rate-limit=40960"])
+    .build();
+  let contents = "x".repeat(20 * 1024);
+  context.write("foo", &contents);
+  let start = std::time::Instant::now();
+  assert_eq!(context.text("files/foo"), contents);
+  assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
 #[test]
 #[cfg(unix)]
 fn downloaded_files_are_streamed() {
@@ -271,6 +826,142 @@ fn downloaded_files_are_streamed() {
   context.kill();
 }
 
+#[test]
+#[cfg(unix)]
+fn range_requests_for_fifos_are_streamed_in_full() {
+  use {
+    futures::StreamExt,
+    tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::oneshot},
+  };
+
+  let context = AgoraTestContext::builder().build();
+
+  let files_url = context.files_url().clone();
+  let files_directory = context.files_directory();
+
+  tokio::runtime::Builder::new_multi_thread()
+    .enable_all()
+    .build()
+    .unwrap()
+    .block_on(async move {
+      let fifo_path = files_directory.join("fifo");
+
+      nix::unistd::mkfifo(&fifo_path, nix::sys::stat::Mode::S_IRWXU).unwrap();
+
+      let (sender, receiver) = oneshot::channel();
+
+      let writer = tokio::spawn(async move {
+        let mut fifo = OpenOptions::new()
+          .write(true)
+          .open(&fifo_path)
+          .await
+          .unwrap();
+        fifo.write_all(b"hello").await.unwrap();
+        receiver.await.unwrap();
+      });
+
+      let response = reqwest::Client::new()
+        .get(files_url.join("fifo").unwrap())
+        .header(header::RANGE, "bytes=2-3")
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(response.status(), StatusCode::OK);
+      assert_eq!(response.headers().get(header::CONTENT_RANGE), None);
+
+      let mut stream = response.bytes_stream();
+
+      assert_eq!(stream.next().await.unwrap().unwrap(), "hello");
+
+      sender.send(()).unwrap();
+
+      writer.await.unwrap();
+    });
+
+  context.kill();
+}
+
+#[cfg(unix)]
+fn terminate(context: &AgoraTestContext) {
+  use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+  };
+
+  kill(Pid::from_raw(context.pid() as i32), Signal::SIGTERM).unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn in_flight_downloads_finish_after_sigterm() {
+  let context = AgoraTestContext::builder()
+    .args(&["--rate-limit=10240"])
+    .build();
+  let contents = "x".repeat(20 * 1024);
+  context.write("foo", &contents);
+
+  let response = context.get("files/foo");
+  terminate(&context);
+  assert_eq!(response.text().unwrap(), contents);
+
+  let (status, _stderr) = context.wait();
+  assert!(status.success());
+}
+
+#[test]
+#[cfg(unix)]
+fn shutdown_timeout_limits_waiting_for_in_flight_downloads() {
+  let context = AgoraTestContext::builder()
+    .args(&["--rate-limit=1024", "--shutdown-timeout=1"])
+    .build();
+  context.write("foo", &"x".repeat(100 * 1024));
+
+  let _response = context.get("files/foo");
+  let start = std::time::Instant::now();
+  terminate(&context);
+
+  let (status, _stderr) = context.wait();
+  assert!(status.success());
+  assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn cached_files_are_reread_when_modified() {
+  let context = AgoraTestContext::builder()
+    .args(&["--cache-file-max-size=8"])
+    .build();
+  context.write("small", "hello");
+  context.write("large", "too large to cache");
+  assert_eq!(context.text("files/small"), "hello");
+  assert_eq!(context.text("files/small"), "hello");
+  assert_eq!(context.text("files/large"), "too large to cache");
+  context.write("small", "goodbye");
+  assert_eq!(context.text("files/small"), "goodbye");
+}
+
+#[test]
+fn max_concurrent_downloads_limits_downloads() {
+  let context = AgoraTestContext::builder()
+    .args(&["--max-concurrent-downloads=1", "--rate-limit=10240"])
+    .build();
+  let contents = "x".repeat(20 * 1024);
+  context.write("foo", &contents);
+  context.write("bar", "bar");
+
+  let response = context.get("files/foo");
+  let refused = context.response("files/bar");
+  assert_eq!(refused.status(), StatusCode::SERVICE_UNAVAILABLE);
+  assert_eq!(refused.headers().get(header::RETRY_AFTER).unwrap(), "5");
+  assert_eq!(context.status("files/"), StatusCode::OK);
+
+  assert_eq!(response.text().unwrap(), contents);
+  assert_eq!(context.text("files/bar"), "bar");
+  assert_contains(
+    &context.kill(),
+    "Download refused, already serving the maximum of 1 concurrent downloads",
+  );
+}
+
 #[test]
 fn downloaded_files_have_correct_content_type() {
   let context = AgoraTestContext::builder().build();
@@ -294,6 +985,64 @@ fn unknown_files_have_no_content_type() {
   assert_eq!(response.headers().get(header::CONTENT_TYPE), None);
 }
 
+#[test]
+fn files_matching_force_download_are_served_as_attachments() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "force-download: [pdf]");
+  context.write("foo.pdf", "");
+  context.write("föö.pdf", "");
+  context.write("foo.txt", "");
+
+  let content_disposition = |path: &str| {
+    context
+      .get(path)
+      .headers()
+      .get(header::CONTENT_DISPOSITION)
+      .map(|value| value.to_str().unwrap().to_owned())
+  };
+  assert_eq!(
+    content_disposition("files/foo.pdf").unwrap(),
+    "attachment; filename=\"foo.pdf\""
+  );
+  assert_eq!(
+    content_disposition("files/f%C3%B6%C3%B6.pdf").unwrap(),
+    "attachment; filename=\"f__.pdf\"; filename*=UTF-8''f%C3%B6%C3%B6.pdf"
+  );
+  assert_eq!(content_disposition("files/foo.txt"), None);
+  assert_eq!(
+    content_disposition("files/foo.txt?download").unwrap(),
+    "attachment; filename=\"foo.txt\""
+  );
+}
+
+#[test]
+fn content_types_can_be_configured() {
+  let context = AgoraTestContext::builder().build();
+  context.write(
+    ".agora.yaml",
+    "content-types: {'*.wasm': application/wasm, txt: text/markdown, LICENSE*: text/plain}",
+  );
+  context.write("foo.wasm", "");
+  context.write("foo.txt", "");
+  context.write("sub/LICENSE", "");
+  context.write("sub/foo.html", "");
+
+  let content_type = |path: &str| {
+    context
+      .get(path)
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .unwrap()
+      .to_str()
+      .unwrap()
+      .to_owned()
+  };
+  assert_eq!(content_type("files/foo.wasm"), "application/wasm");
+  assert_eq!(content_type("files/foo.txt"), "text/markdown");
+  assert_eq!(content_type("files/sub/LICENSE"), "text/plain");
+  assert_eq!(content_type("files/sub/foo.html"), "text/html");
+}
+
 #[test]
 fn filenames_with_spaces() {
   let context = AgoraTestContext::builder().build();
@@ -302,41 +1051,172 @@ fn filenames_with_spaces() {
 }
 
 #[test]
-fn subdirectories_appear_in_listings() {
-  let context = AgoraTestContext::builder().build();
-  context.write("foo/bar.txt", "hello");
-  let root_listing = context.html("files/");
-  guard_unwrap!(let &[a] = css_select(&root_listing, ".listing a").as_slice());
-  assert_eq!(a.inner_html(), "foo/");
-  let subdir_url = context
-    .files_url()
-    .join(a.value().attr("href").unwrap())
-    .unwrap();
-  let subdir_listing = context.html(&subdir_url);
-  guard_unwrap!(let &[a] = css_select(&subdir_listing, ".listing a:not([download])").as_slice());
-  assert_eq!(a.inner_html(), "bar.txt");
-  let file_url = subdir_url.join(a.value().attr("href").unwrap()).unwrap();
-  assert_eq!(context.text(&file_url), "hello");
+fn subdirectories_appear_in_listings() {
+  let context = AgoraTestContext::builder().build();
+  context.write("foo/bar.txt", "hello");
+  let root_listing = context.html("files/");
+  guard_unwrap!(let &[a] = css_select(&root_listing, ".listing a").as_slice());
+  assert_eq!(a.inner_html(), "foo/");
+  let subdir_url = context
+    .files_url()
+    .join(a.value().attr("href").unwrap())
+    .unwrap();
+  let subdir_listing = context.html(&subdir_url);
+  guard_unwrap!(let &[a] = css_select(&subdir_listing, ".listing a:not([download])").as_slice());
+  assert_eq!(a.inner_html(), "bar.txt");
+  let file_url = subdir_url.join(a.value().attr("href").unwrap()).unwrap();
+  assert_eq!(context.text(&file_url), "hello");
+}
+
+#[test]
+fn redirects_correctly_for_two_layers_of_subdirectories() {
+  let context = AgoraTestContext::builder().build();
+  context.write("foo/bar/baz.txt", "");
+  let listing = context.html("files/foo/bar");
+  guard_unwrap!(let &[a] = css_select(&listing, ".listing a:not([download])").as_slice());
+  assert_eq!(a.inner_html(), "baz.txt")
+}
+
+#[test]
+fn requesting_files_with_trailing_slash_redirects() {
+  let context = AgoraTestContext::builder().build();
+  context.write("foo", "");
+  let response = reqwest::blocking::get(context.files_url().join("foo/").unwrap()).unwrap();
+  assert!(
+    response.url().as_str().ends_with("/files/foo"),
+    "{} didn't end with /files/foo",
+    response.url()
+  );
+}
+
+#[test]
+fn trailing_slash_policy_off_does_not_redirect() {
+  let context = AgoraTestContext::builder()
+    .args(&["--trailing-slash-policy=off"])
+    .build();
+  context.write("dir/foo", "hello");
+  context.create_dir_all("dir/subdir");
+  assert_eq!(context.text("files/dir/foo/"), "hello");
+  let html = context.html("files/dir");
+  let hrefs = css_select(&html, ".listing a:not([download])")
+    .into_iter()
+    .map(|a| a.value().attr("href").unwrap().to_owned())
+    .collect::<Vec<String>>();
+  assert_eq!(hrefs, vec!["/files/dir/foo", "/files/dir/subdir/"]);
+}
+
+#[test]
+fn trailing_slash_policy_inverse_redirects_to_inverse_paths() {
+  let context = AgoraTestContext::builder()
+    .args(&["--trailing-slash-policy=inverse"])
+    .build();
+  context.write("dir/foo", "hello");
+  context.create_dir_all("dir/subdir");
+  assert_eq!(
+    context.redirect_url("files/dir/"),
+    context.files_url().join("dir").unwrap()
+  );
+  assert_eq!(
+    context.redirect_url("files/dir/foo"),
+    context.files_url().join("dir/foo/").unwrap()
+  );
+  assert_eq!(context.text("files/dir/foo/"), "hello");
+  let html = context.html("files/dir");
+  let hrefs = css_select(&html, ".listing a:not([download])")
+    .into_iter()
+    .map(|a| a.value().attr("href").unwrap().to_owned())
+    .collect::<Vec<String>>();
+  assert_eq!(hrefs, vec!["/files/dir/foo/", "/files/dir/subdir"]);
+  assert_eq!(context.status("files/"), StatusCode::OK);
+}
+
+#[test]
+fn sold_out_files_are_marked_in_listing_and_not_sold() {
+  let context = AgoraTestContext::builder()
+    .args(&["--download-counts-file", "downloads.yaml"])
+    .write("downloads.yaml", &format!("dir/foo: [{}]", "00".repeat(32)))
+    .build();
+  context.write(
+    "dir/.agora.yaml",
+    "{paid: true, base-price: 1 sat, max-downloads: 1}",
+  );
+  context.write("dir/foo", "");
+  context.write("dir/bar", "");
+  let html = context.html("files/dir/");
+  guard_unwrap!(let &[bar, foo] = css_select(&html, ".listing li").as_slice());
+  assert_not_contains(&bar.text().collect::<String>(), "sold out");
+  assert_contains(&foo.text().collect::<String>(), "sold out");
+  let response = context.response("files/dir/foo");
+  assert_eq!(response.status(), StatusCode::GONE);
+  assert_contains(&response.text().unwrap(), "is sold out");
+}
+
+#[test]
+fn paid_file_pages_have_open_graph_tags() {
+  let context = AgoraTestContext::builder()
+    .args(&["--download-counts-file", "downloads.yaml"])
+    .write("downloads.yaml", &format!("dir/foo: [{}]", "00".repeat(32)))
+    .build();
+  context.write(
+    "dir/.agora.yaml",
+    "{paid: true, base-price: 1 sat, max-downloads: 1, meta: {title: Foo, description: A foo., image: cover.png}}",
+  );
+  context.write("dir/foo", "");
+  context.write("dir/cover.png", "");
+  let response = context.response("files/dir/foo");
+  assert_eq!(response.status(), StatusCode::GONE);
+  let html = Html::parse_document(&response.text().unwrap());
+  let content = |selector: &str| {
+    guard_unwrap!(let &[element] = css_select(&html, selector).as_slice());
+    element.value().attr("content").unwrap().to_owned()
+  };
+  assert_eq!(content("meta[property='og:title']"), "Foo");
+  assert_eq!(content("meta[property='og:description']"), "A foo.");
+  assert_eq!(content("meta[property='og:image']"), "/files/dir/cover.png");
+  assert_eq!(content("meta[name='twitter:card']"), "summary_large_image");
 }
 
 #[test]
-fn redirects_correctly_for_two_layers_of_subdirectories() {
+fn paid_files_can_have_free_previews() {
   let context = AgoraTestContext::builder().build();
-  context.write("foo/bar/baz.txt", "");
-  let listing = context.html("files/foo/bar");
-  guard_unwrap!(let &[a] = css_select(&listing, ".listing a:not([download])").as_slice());
-  assert_eq!(a.inner_html(), "baz.txt")
+  context.write(
+    ".agora.yaml",
+    "{paid: true, base-price: 1 sat, preview-bytes: 5}",
+  );
+  context.write("foo.mp3", "hello world");
+  context.write("bar.mp3", "hello");
+
+  let response = context.response("files/foo.mp3?preview");
+  assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+  assert_eq!(response.headers()["content-range"], "bytes 0-4/11");
+  assert_eq!(response.headers()["content-type"], "audio/mpeg");
+  assert_eq!(response.headers()["x-agora-preview"], "true");
+  assert_eq!(response.headers()["x-robots-tag"], "noindex");
+  assert_eq!(response.text().unwrap(), "hello");
+
+  let html = context.html("files/");
+  guard_unwrap!(let &[bar, foo] = css_select(&html, ".listing li").as_slice());
+  assert!(css_select(&Html::parse_fragment(&bar.html()), ".preview").is_empty());
+  assert_eq!(
+    css_select(&Html::parse_fragment(&foo.html()), ".preview")[0]
+      .value()
+      .attr("href"),
+    Some("foo.mp3?preview")
+  );
 }
 
 #[test]
-fn requesting_files_with_trailing_slash_redirects() {
+fn previews_are_not_served_if_they_would_contain_the_whole_file() {
   let context = AgoraTestContext::builder().build();
-  context.write("foo", "");
-  let response = reqwest::blocking::get(context.files_url().join("foo/").unwrap()).unwrap();
-  assert!(
-    response.url().as_str().ends_with("/files/foo"),
-    "{} didn't end with /files/foo",
-    response.url()
+  context.write(
+    ".agora.yaml",
+    "{paid: true, base-price: 1 sat, preview-bytes: 5}",
+  );
+  context.write("foo", "hello");
+  // Without a preview, the file has to be bought, which fails without LND
+  assert_eq!(
+    context.status("files/foo?preview"),
+    StatusCode::INTERNAL_SERVER_ERROR
   );
 }
 
@@ -351,15 +1231,100 @@ fn listings_are_not_cached() {
 }
 
 #[test]
-fn files_are_not_cached() {
+fn files_are_revalidated_before_reuse() {
   let context = AgoraTestContext::builder().build();
   context.write("foo", "bar");
   let response = reqwest::blocking::get(context.files_url().join("foo").unwrap()).unwrap();
   assert_eq!(
     response.headers().get(header::CACHE_CONTROL).unwrap(),
-    "no-store, max-age=0",
+    "no-cache",
   );
+  let etag = response.headers().get(header::ETAG).unwrap().clone();
+  assert!(etag.to_str().unwrap().starts_with("W/\""));
+  let last_modified = response
+    .headers()
+    .get(header::LAST_MODIFIED)
+    .unwrap()
+    .clone();
   assert_eq!(response.text().unwrap(), "bar");
+
+  let client = reqwest::blocking::Client::new();
+  let url = context.files_url().join("foo").unwrap();
+  let response = client
+    .get(url.clone())
+    .header(header::IF_NONE_MATCH, etag.clone())
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+  assert_eq!(response.headers().get(header::ETAG).unwrap(), etag);
+  assert_eq!(response.text().unwrap(), "");
+  let response = client
+    .get(url.clone())
+    .header(header::IF_MODIFIED_SINCE, last_modified)
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+  context.write("foo", "changed");
+  let response = client
+    .get(url)
+    .header(header::IF_NONE_MATCH, etag)
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(response.text().unwrap(), "changed");
+}
+
+#[test]
+fn free_files_can_be_cached_with_static_cache_max_age() {
+  let context = AgoraTestContext::builder()
+    .args(&["--static-cache-max-age=86400"])
+    .build();
+  context.write("foo", "bar");
+  let response = context.get("files/foo");
+  assert_eq!(
+    response.headers().get(header::CACHE_CONTROL).unwrap(),
+    "public, max-age=86400",
+  );
+  let response = reqwest::blocking::Client::new()
+    .get(context.files_url().join("foo").unwrap())
+    .header(
+      header::IF_NONE_MATCH,
+      response.headers().get(header::ETAG).unwrap().clone(),
+    )
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+  assert_eq!(
+    response.headers().get(header::CACHE_CONTROL).unwrap(),
+    "public, max-age=86400",
+  );
+  assert_eq!(
+    context
+      .get("files/")
+      .headers()
+      .get(header::CACHE_CONTROL)
+      .unwrap(),
+    "no-store, max-age=0",
+  );
+}
+
+#[test]
+fn static_assets_are_revalidated_before_reuse() {
+  let context = AgoraTestContext::builder().build();
+  let response = context.get("static/index.css");
+  assert_eq!(
+    response.headers().get(header::CACHE_CONTROL).unwrap(),
+    "no-cache",
+  );
+  let etag = response.headers().get(header::ETAG).unwrap().clone();
+  assert!(!etag.to_str().unwrap().starts_with("W/"));
+  let response = reqwest::blocking::Client::new()
+    .get(context.base_url().join("static/index.css").unwrap())
+    .header(header::IF_NONE_MATCH, etag)
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
 }
 
 fn symlink(contents: impl AsRef<Path>, link: impl AsRef<Path>) {
@@ -421,6 +1386,19 @@ fn allow_listing_directories_via_intermediate_local_symlinks() {
   assert_eq!(response.status(), StatusCode::OK);
 }
 
+#[test]
+fn escaping_config_symlinks_are_rejected() {
+  let context = AgoraTestContext::builder().build();
+  context.write("../outside.yaml", "paid: false");
+  symlink(
+    "../outside.yaml",
+    context.files_directory().join(".agora.yaml"),
+  );
+  assert_eq!(context.status("files/"), StatusCode::NOT_FOUND);
+  let stderr = context.kill();
+  assert_contains(&stderr, "Forbidden access to escaping symlink");
+}
+
 #[test]
 fn show_local_symlinks_in_listings() {
   let context = AgoraTestContext::builder().build();
@@ -451,6 +1429,315 @@ fn serves_static_assets() {
   assert_eq!(response, expected);
 }
 
+#[test]
+fn serve_single_responds_to_all_requests_with_one_file() {
+  let context = AgoraTestContext::builder()
+    .args(&["--serve-single", "maintenance.html"])
+    .write("maintenance.html", "<h1>Coming soon</h1>")
+    .build();
+  context.write("foo", "bar");
+  for path in &[
+    "",
+    "files/",
+    "files/foo",
+    "invoice/foo.svg",
+    "does/not/exist",
+  ] {
+    let response = context.get(path);
+    assert_eq!(
+      response.headers().get(header::CONTENT_TYPE).unwrap(),
+      "text/html"
+    );
+    assert_eq!(response.text().unwrap(), "<h1>Coming soon</h1>");
+  }
+  assert_eq!(
+    context.text("static/index.css"),
+    fs::read_to_string("static/index.css").unwrap()
+  );
+}
+
+#[test]
+fn health_route_reports_lnd_status() {
+  let context = AgoraTestContext::builder()
+    .args(&["--serve-single", "maintenance.html"])
+    .write("maintenance.html", "<h1>Coming soon</h1>")
+    .build();
+  fs::remove_dir_all(context.files_directory()).unwrap();
+  let response = context.get("health");
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "application/json"
+  );
+  assert_eq!(
+    response.text().unwrap(),
+    r#"{"status":"ok","lnd":"disabled"}"#
+  );
+}
+
+#[test]
+fn robots_txt_allows_crawling_without_lnd() {
+  let context = AgoraTestContext::builder().build();
+  let response = context.get("robots.txt");
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "text/plain"
+  );
+  assert_eq!(response.text().unwrap(), "User-agent: *\nDisallow:\n");
+}
+
+#[test]
+fn robots_txt_can_be_configured() {
+  let context = AgoraTestContext::builder()
+    .args(&["--robots=robots.txt"])
+    .write("robots.txt", "User-agent: *\nDisallow: /\n")
+    .build();
+  assert_eq!(context.text("robots.txt"), "User-agent: *\nDisallow: /\n");
+}
+
+#[test]
+fn sitemap_lists_free_files() {
+  let context = AgoraTestContext::builder().args(&["--sitemap"]).build();
+  context.write("foo.txt", "");
+  context.write("dir/bar baz.txt", "");
+  context.write(".hidden.txt", "");
+  context.write("paid/.agora.yaml", "paid: true\nbase-price: 1000 sat");
+  context.write("paid/secret.txt", "");
+  context.write("unlisted/.agora.yaml", "listing: false");
+  context.write("unlisted/qux.txt", "");
+  let response = context.get("sitemap.xml");
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "application/xml; charset=utf-8"
+  );
+  let xml = response.text().unwrap();
+  let locs = Regex::new("<loc>([^<]*)</loc><lastmod>[^<]*</lastmod>")
+    .unwrap()
+    .captures_iter(&xml)
+    .map(|captures| captures[1].to_owned())
+    .collect::<Vec<String>>();
+  assert_eq!(
+    locs,
+    &[
+      format!("http://localhost:{}/files/foo.txt", context.port()),
+      format!(
+        "http://localhost:{}/files/dir/bar%20baz.txt",
+        context.port()
+      ),
+    ]
+  );
+  assert!(xml.starts_with("<?xml"));
+  assert!(xml.ends_with("</urlset>\n"));
+}
+
+#[test]
+fn sitemap_skips_symlinked_directories() {
+  let context = AgoraTestContext::builder().args(&["--sitemap"]).build();
+  context.write("sub/foo.txt", "");
+  symlink("..", context.files_directory().join("sub/loop"));
+  symlink("..", context.files_directory().join("sub/other-loop"));
+  let xml = context.text("sitemap.xml");
+  let locs = Regex::new("<loc>([^<]*)</loc>")
+    .unwrap()
+    .captures_iter(&xml)
+    .map(|captures| captures[1].to_owned())
+    .collect::<Vec<String>>();
+  assert_eq!(
+    locs,
+    &[format!(
+      "http://localhost:{}/files/sub/foo.txt",
+      context.port()
+    )]
+  );
+  assert!(xml.ends_with("</urlset>\n"));
+}
+
+#[test]
+fn sitemap_is_not_served_by_default() {
+  let context = AgoraTestContext::builder().build();
+  assert_eq!(context.status("sitemap.xml"), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn requests_are_written_to_access_log() {
+  let context = AgoraTestContext::builder()
+    .args(&["--access-log", "access.log"])
+    .build();
+  context.write("foo", "hello");
+  assert_eq!(context.text("files/foo"), "hello");
+  assert_eq!(context.status("files/bar"), StatusCode::NOT_FOUND);
+  let log = fs::read_to_string(context.current_dir().join("access.log")).unwrap();
+  let line = Regex::new(
+    r#"^(127\.0\.0\.1|::1) - - \[\d\d/[A-Z][a-z]{2}/\d{4}:\d\d:\d\d:\d\d \+0000\] "GET (\S+) HTTP/1\.1" (\d{3}) (\S+) "-" "-"$"#,
+  )
+  .unwrap();
+  let lines = log
+    .lines()
+    .map(|l| {
+      let captures = line
+        .captures(l)
+        .unwrap_or_else(|| panic!("unexpected line: {}", l));
+      (captures[2].to_owned(), captures[3].to_owned())
+    })
+    .collect::<Vec<(String, String)>>();
+  assert_eq!(
+    lines,
+    vec![
+      ("/files/foo".to_owned(), "200".to_owned()),
+      ("/files/bar".to_owned(), "404".to_owned()),
+    ]
+  );
+}
+
+#[test]
+fn access_log_uses_forwarded_client_address_with_trust_proxy() {
+  for trust_proxy in &[true, false] {
+    let mut args = vec!["--access-log", "access.log"];
+    if *trust_proxy {
+      args.push("--trust-proxy");
+    }
+    let context = AgoraTestContext::builder().args(&args).build();
+    context.write("foo", "hello");
+    let response = reqwest::blocking::Client::new()
+      .get(context.files_url().join("foo").unwrap())
+      .header("x-forwarded-for", "198.51.100.1, 203.0.113.1")
+      .send()
+      .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let log = fs::read_to_string(context.current_dir().join("access.log")).unwrap();
+    assert_eq!(
+      log.starts_with("203.0.113.1 - - ["),
+      *trust_proxy,
+      "{}",
+      log
+    );
+  }
+}
+
+#[test]
+fn canonical_host_redirects_other_hosts() {
+  let context = AgoraTestContext::builder()
+    .args(&["--canonical-host", "example.com"])
+    .build();
+  context.write("foo", "hello");
+  let client = reqwest::blocking::Client::builder()
+    .redirect(reqwest::redirect::Policy::none())
+    .build()
+    .unwrap();
+  let response = client
+    .get(context.files_url().join("foo?bar=baz").unwrap())
+    .header(header::HOST, "www.example.com")
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+  assert_eq!(
+    response.headers().get(header::LOCATION).unwrap(),
+    "http://example.com/files/foo?bar=baz"
+  );
+  let response = client
+    .get(context.base_url().join("health").unwrap())
+    .header(header::HOST, "www.example.com")
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  let response = client
+    .get(context.files_url().join("foo").unwrap())
+    .header(header::HOST, "example.com")
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(response.text().unwrap(), "hello");
+}
+
+#[test]
+fn metrics_are_not_served_by_default() {
+  let context = AgoraTestContext::builder().build();
+  assert_eq!(context.status("metrics"), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn metrics_count_responses_and_bytes_served() {
+  let context = AgoraTestContext::builder().args(&["--metrics"]).build();
+  context.write("foo", "hello");
+  assert_eq!(context.text("files/foo"), "hello");
+  assert_eq!(context.status("files/bar"), StatusCode::NOT_FOUND);
+  let response = context.get("metrics");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "text/plain; version=0.0.4"
+  );
+  let metrics = response.text().unwrap();
+  assert_contains(&metrics, "agora_http_responses_total{status=\"200\"} 1\n");
+  assert_contains(&metrics, "agora_http_responses_total{status=\"404\"} 1\n");
+  assert_contains(&metrics, "agora_files_served_total{payment=\"free\"} 1\n");
+  assert_contains(&metrics, "agora_bytes_served_total{payment=\"free\"} 5\n");
+  assert_contains(&metrics, "agora_invoices_created_total 0\n");
+}
+
+#[test]
+fn routes_can_be_disabled() {
+  let context = AgoraTestContext::builder()
+    .args(&["--disable-route=static", "--disable-route=invoice-qr"])
+    .build();
+  context.write("foo", "bar");
+  assert_eq!(context.status("static/index.css"), StatusCode::NOT_FOUND);
+  assert_eq!(context.status("favicon.ico"), StatusCode::NOT_FOUND);
+  assert_eq!(
+    context.status(format!("invoice/{}.svg", "00".repeat(32))),
+    StatusCode::NOT_FOUND
+  );
+  assert_eq!(context.text("files/foo"), "bar");
+  let stderr = context.kill();
+  assert_contains(
+    &stderr,
+    &format!(
+      "URI path did not match any route: /invoice/{}.svg",
+      "00".repeat(32)
+    ),
+  );
+}
+
+#[test]
+fn invoice_status_requires_lnd() {
+  let context = AgoraTestContext::builder().build();
+  let path = format!("invoice/{}/status", "00".repeat(32));
+  assert_eq!(context.status(&path), StatusCode::NOT_FOUND);
+  assert_eq!(
+    context.status("invoice/xyz/status"),
+    StatusCode::BAD_REQUEST
+  );
+  let stderr = context.kill();
+  assert_contains(
+    &stderr,
+    &format!(
+      "Invoice request requires LND client configuration: /{}",
+      path
+    ),
+  );
+}
+
+#[test]
+fn unreadable_images_can_be_replaced_with_placeholder() {
+  let context = AgoraTestContext::builder()
+    .args(&["--image-error-placeholder"])
+    .build();
+  let response = context.response("files/missing.png");
+  assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "image/svg+xml"
+  );
+  assert_eq!(
+    response.text().unwrap(),
+    fs::read_to_string("static/broken-image.svg").unwrap()
+  );
+  let response = context.response("files/missing.txt");
+  assert_eq!(response.status(), StatusCode::NOT_FOUND);
+  assert_contains(&response.text().unwrap(), "<h1>");
+}
+
 #[test]
 fn sets_mime_types_for_static_assets() {
   let context = AgoraTestContext::builder().build();
@@ -479,15 +1766,94 @@ fn listing_does_not_contain_hidden_file() {
 }
 
 #[test]
-fn return_404_for_hidden_files() {
-  let context = AgoraTestContext::builder().build();
+fn return_404_for_hidden_files() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".foo.txt", "");
+  assert_eq!(
+    reqwest::blocking::get(context.files_url().join(".foo.txt").unwrap())
+      .unwrap()
+      .status(),
+    StatusCode::NOT_FOUND
+  )
+}
+
+#[test]
+fn return_404_for_denied_extensions() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "deny-extensions: [.env, key]");
+  context.write("production.env", "");
+  context.write("server.KEY", "");
+  context.write("foo.txt", "");
+  for path in &["production.env", "server.KEY"] {
+    assert_eq!(
+      reqwest::blocking::get(context.files_url().join(path).unwrap())
+        .unwrap()
+        .status(),
+      StatusCode::NOT_FOUND
+    );
+  }
+  assert_eq!(
+    reqwest::blocking::get(context.files_url().join("foo.txt").unwrap())
+      .unwrap()
+      .status(),
+    StatusCode::OK
+  );
+}
+
+#[test]
+fn denied_extensions_are_inherited_and_not_listed() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "deny-extensions: [.env]");
+  context.write("dir/production.env", "");
+  context.write("dir/foo.txt", "");
+  context.write("allowed/.agora.yaml", "deny-extensions: []");
+  context.write("allowed/example.env", "");
+  assert_eq!(
+    context.status("files/dir/production.env"),
+    StatusCode::NOT_FOUND
+  );
+  assert_eq!(context.status("files/allowed/example.env"), StatusCode::OK);
+  let html = context.html("files/dir/");
+  guard_unwrap!(let &[a] = css_select(&html, ".listing a:not([download])").as_slice());
+  assert_eq!(a.inner_html(), "foo.txt");
+}
+
+#[test]
+fn hidden_and_missing_files_have_identical_responses() {
+  let context = AgoraTestContext::builder()
+    .args(&["--image-error-placeholder"])
+    .build();
+  context.write(".foo.txt", "");
+  context.write(".foo.png", "");
+  for (hidden, missing) in &[
+    ("files/.foo.txt", "files/bar.txt"),
+    ("files/.foo.png", "files/bar.png"),
+  ] {
+    let hidden = context.response(hidden);
+    let missing = context.response(missing);
+    assert_eq!(hidden.status(), StatusCode::NOT_FOUND);
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+      hidden.headers().get(header::CONTENT_TYPE),
+      missing.headers().get(header::CONTENT_TYPE)
+    );
+    assert_eq!(hidden.bytes().unwrap(), missing.bytes().unwrap());
+  }
+}
+
+#[test]
+fn disable_hidden_file_404_leak_delays_not_found_responses() {
+  let context = AgoraTestContext::builder()
+    .args(&["--disable-hidden-file-404-leak"])
+    .build();
   context.write(".foo.txt", "");
-  assert_eq!(
-    reqwest::blocking::get(context.files_url().join(".foo.txt").unwrap())
-      .unwrap()
-      .status(),
-    StatusCode::NOT_FOUND
-  )
+  context.write("foo.txt", "");
+  for path in &["files/.foo.txt", "files/bar.txt"] {
+    let start = std::time::Instant::now();
+    assert_eq!(context.status(path), StatusCode::NOT_FOUND);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+  }
+  assert_eq!(context.status("files/foo.txt"), StatusCode::OK);
 }
 
 #[test]
@@ -527,6 +1893,45 @@ fn favicon_is_served_at_favicon_ico() {
   );
 }
 
+#[test]
+fn custom_icons_are_served_from_files_directory() {
+  let context = AgoraTestContext::builder().build();
+  context.write("favicon.ico", "custom favicon");
+  context.write("apple-touch-icon.png", "custom touch icon");
+  let response = context.get("favicon.ico");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "image/x-icon"
+  );
+  assert_eq!(response.text().unwrap(), "custom favicon");
+  let response = context.get("apple-touch-icon.png");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "image/png"
+  );
+  assert_eq!(response.text().unwrap(), "custom touch icon");
+}
+
+#[test]
+fn paid_custom_icons_are_not_served() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "{paid: true, base-price: 1 sat}");
+  context.write("favicon.ico", "custom favicon");
+  assert_ne!(context.text("favicon.ico"), "custom favicon");
+}
+
+#[test]
+#[cfg(unix)]
+fn custom_icons_may_not_be_escaping_symlinks() {
+  let context = AgoraTestContext::builder().build();
+  context.write("../favicon.ico", "escaping favicon");
+  symlink(
+    "../favicon.ico",
+    context.files_directory().join("favicon.ico"),
+  );
+  assert_eq!(context.status("favicon.ico"), StatusCode::NOT_FOUND);
+}
+
 #[test]
 #[cfg(unix)]
 fn errors_in_request_handling_cause_500_status_codes() {
@@ -693,7 +2098,7 @@ fn doesnt_percent_encode_allowed_ascii_characters() {
   let allowed_ascii_characters = if cfg!(windows) {
     "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!$&'()+,-.;=@_~"
   } else {
-    "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!$&'()*+,-.:;=?@_~"
+    "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!$&'()*+,-.;=@_~"
   };
   context.write(allowed_ascii_characters, "contents");
   let html = context.html("files/");
@@ -702,41 +2107,555 @@ fn doesnt_percent_encode_allowed_ascii_characters() {
 }
 
 #[test]
-fn percent_encodes_unicode() {
-  let context = AgoraTestContext::builder().build();
-  context.write("Å", "contents");
+fn listing_links_round_trip() {
+  let context = AgoraTestContext::builder().build();
+  let mut file_names = vec!["!$&'()+,-.;=@_~", "#hash", "%25", "a b", "Å", "[brackets]"];
+  if cfg!(unix) {
+    // These characters are not allowed in Windows file names
+    file_names.extend(&["*", "a:b", "c:", "question?mark", "<>", "\"\"", "|"]);
+  }
+  for file_name in &file_names {
+    context.write(file_name, file_name);
+  }
+  let html = context.html("files/");
+  let links = css_select(&html, ".listing a:not([download])");
+  assert_eq!(links.len(), file_names.len());
+  for a in links {
+    let url = context
+      .files_url()
+      .join(a.value().attr("href").unwrap())
+      .unwrap();
+    assert_eq!(context.text(&url), a.text().collect::<String>());
+  }
+}
+
+#[test]
+fn colons_and_question_marks_are_percent_encoded() {
+  if cfg!(windows) {
+    return;
+  }
+  let context = AgoraTestContext::builder().build();
+  context.write("a:b?c", "contents");
+  let html = context.html("files/");
+  guard_unwrap!(let &[a] = css_select(&html, ".listing a:not([download])").as_slice());
+  assert_eq!(a.value().attr("href").unwrap(), "a%3Ab%3Fc");
+}
+
+#[test]
+fn percent_encodes_unicode() {
+  let context = AgoraTestContext::builder().build();
+  context.write("Å", "contents");
+  let html = context.html("files/");
+  guard_unwrap!(let &[a] = css_select(&html, ".listing a:not([download])").as_slice());
+  assert_eq!(a.value().attr("href").unwrap(), "%C3%85");
+}
+
+#[test]
+fn requesting_paid_file_with_no_lnd_returns_internal_error() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "paid: true");
+  context.write("foo", "precious content");
+  let status = reqwest::blocking::get(context.files_url().join("foo").unwrap())
+    .unwrap()
+    .status();
+  assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+  let stderr = context.kill();
+  assert_contains(
+    &stderr,
+    &format!(
+      "Paid file request requires LND client configuration: `files{}foo`",
+      MAIN_SEPARATOR
+    ),
+  );
+}
+
+#[test]
+fn displays_index_markdown_files_as_html() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".index.md", "# test header");
+  let html = context.html("files/");
+  guard_unwrap!(let &[index_header] = css_select(&html, "h1").as_slice());
+  assert_eq!(index_header.inner_html(), "test header");
+}
+
+#[test]
+fn displays_readme_markdown_files_as_html() {
+  let context = AgoraTestContext::builder().build();
+  context.write("readme.md", "# readme header");
+  context.write("dir/README.md", "# dir readme");
+  context.write("dir/.index.md", "# dir index");
+  let html = context.html("files/");
+  guard_unwrap!(let &[index_header] = css_select(&html, "h1").as_slice());
+  assert_eq!(index_header.inner_html(), "readme header");
+  let html = context.html("files/dir/");
+  guard_unwrap!(let &[index_header] = css_select(&html, "h1").as_slice());
+  assert_eq!(index_header.inner_html(), "dir index");
+}
+
+#[test]
+fn index_filenames_can_be_configured() {
+  let context = AgoraTestContext::builder()
+    .args(&["--index-filenames", "index.md,.index.md"])
+    .build();
+  context.write("README.md", "# readme");
+  context.write(".index.md", "# dot index");
+  context.write("index.md", "# index");
+  let html = context.html("files/");
+  guard_unwrap!(let &[index_header] = css_select(&html, "h1").as_slice());
+  assert_eq!(index_header.inner_html(), "index");
+}
+
+#[test]
+fn paid_readme_files_are_not_displayed() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "{paid: true, base-price: 1 sat}");
+  context.write("README.md", "# readme");
+  let html = context.html("files/");
+  assert_eq!(css_select(&html, "h1").len(), 0);
+}
+
+#[test]
+fn header_and_footer_markdown_files_surround_listing() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".header.md", "# header");
+  context.write(".index.md", "# index");
+  context.write(".footer.md", "# footer");
+  context.write("dir/foo", "");
+  let html = context.html("files/");
+  let elements = css_select(&html, "h1, ul.listing a.view")
+    .into_iter()
+    .map(|element| element.inner_html())
+    .collect::<Vec<String>>();
+  assert_eq!(elements, ["header", "dir/", "index", "footer"]);
+  assert!(css_select(&context.html("files/dir/"), "h1").is_empty());
+}
+
+#[test]
+fn header_and_footer_markdown_files_can_be_inherited() {
+  let context = AgoraTestContext::builder()
+    .args(&["--inherit-partials"])
+    .build();
+  context.write(".header.md", "# header");
+  context.write(".footer.md", "# footer");
+  context.write("dir/.footer.md", "# dir footer");
+  context.write("dir/subdir/foo", "");
+  let headings = |path: &str| {
+    css_select(&context.html(path), "h1")
+      .into_iter()
+      .map(|heading| heading.inner_html())
+      .collect::<Vec<String>>()
+  };
+  assert_eq!(headings("files/dir/"), ["header", "dir footer"]);
+  assert_eq!(headings("files/dir/subdir/"), ["header", "dir footer"]);
+}
+
+#[test]
+fn index_markdown_file_can_be_configured() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "index: README.md");
+  context.write("README.md", "# readme");
+  context.write(".index.md", "# index");
+  context.write("dir/.index.md", "# fallback");
+  let html = context.html("files/");
+  guard_unwrap!(let &[header] = css_select(&html, "h1").as_slice());
+  assert_eq!(header.inner_html(), "readme");
+  let html = context.html("files/dir/");
+  guard_unwrap!(let &[header] = css_select(&html, "h1").as_slice());
+  assert_eq!(header.inner_html(), "fallback");
+}
+
+#[test]
+fn configured_paid_index_markdown_files_are_not_displayed() {
+  let context = AgoraTestContext::builder().build();
+  context.write(
+    "paid/.agora.yaml",
+    "{paid: true, base-price: 1 sat, index: secret.md}",
+  );
+  context.write("paid/secret.md", "# secret");
+  context.write("paid/.index.md", "# preview");
+  let html = context.html("files/paid/");
+  guard_unwrap!(let &[header] = css_select(&html, "h1").as_slice());
+  assert_eq!(header.inner_html(), "preview");
+}
+
+#[test]
+fn index_markdown_file_must_be_a_file_name() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "index: ../README.md");
+  assert_eq!(context.status("files/"), StatusCode::INTERNAL_SERVER_ERROR);
+  assert_contains(&context.kill(), "`../README.md` is not a file name");
+}
+
+#[test]
+fn index_html_files_can_replace_listings() {
+  let context = AgoraTestContext::builder()
+    .args(&["--serve-index-html"])
+    .build();
+  context.write("index.html", "<p>custom</p>");
+  context.write("paid/.agora.yaml", "{paid: true, base-price: 1 sat}");
+  context.write("paid/index.html", "<p>paid</p>");
+  let response = context.get("files/");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "text/html"
+  );
+  assert_eq!(response.text().unwrap(), "<p>custom</p>");
+  assert_contains(
+    &context.text("files/?format=json"),
+    r#""name":"index.html""#,
+  );
+  assert_contains(&context.text("files/paid/"), "class=\"listing\"");
+}
+
+#[test]
+fn index_html_files_are_listed_by_default() {
+  let context = AgoraTestContext::builder().build();
+  context.write("index.html", "<p>custom</p>");
+  assert_contains(&context.text("files/"), "class=\"listing\"");
+}
+
+#[test]
+fn index_table_of_contents_is_opt_in() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".index.md", "# Foo\n## Bar");
+  let html = context.html("files/");
+  assert!(css_select(&html, "nav.toc").is_empty());
+  assert_eq!(css_select(&html, "h1")[0].value().attr("id"), None);
+}
+
+#[test]
+fn index_table_of_contents_links_to_headings() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "index-toc: true");
+  context.write(".index.md", "# Foo\n## Bar Baz\n# Foo");
+  let html = context.html("files/");
+  let links = css_select(&html, "nav.toc a")
+    .into_iter()
+    .map(|a| {
+      (
+        a.value().attr("href").unwrap().to_owned(),
+        a.text().collect::<String>(),
+      )
+    })
+    .collect::<Vec<(String, String)>>();
+  assert_eq!(
+    links,
+    vec![
+      ("#foo".to_owned(), "Foo".to_owned()),
+      ("#bar-baz".to_owned(), "Bar Baz".to_owned()),
+      ("#foo-1".to_owned(), "Foo".to_owned()),
+    ]
+  );
+  guard_unwrap!(let &[h2] = css_select(&html, "h2#bar-baz").as_slice());
+  assert_eq!(h2.inner_html(), "Bar Baz");
+}
+
+#[test]
+fn listing_can_be_hidden_while_showing_index() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "listing: false");
+  context.write(".index.md", "# Downloads");
+  context.write("dir/foo", "bar");
+  let html = context.html("files/");
+  assert!(css_select(&html, ".listing").is_empty());
+  guard_unwrap!(let &[h1] = css_select(&html, "h1").as_slice());
+  assert_eq!(h1.inner_html(), "Downloads");
+  let html = context.html("files/dir/");
+  assert!(css_select(&html, ".listing").is_empty());
+  assert_eq!(context.text("files/dir/foo"), "bar");
+}
+
+#[test]
+fn global_config_is_overridden_by_agora_yaml() {
+  let context = AgoraTestContext::builder()
+    .args(&["--config=agora.yaml"])
+    .write("agora.yaml", "listing: false")
+    .build();
+  context.write("dir/.agora.yaml", "listing: true");
+  context.write("dir/foo", "bar");
+  let html = context.html("files/");
+  assert!(css_select(&html, ".listing").is_empty());
+  let html = context.html("files/dir/");
+  guard_unwrap!(let &[a] = css_select(&html, ".listing a:not([download])").as_slice());
+  assert_eq!(a.inner_html(), "foo");
+}
+
+#[test]
+fn listing_includes_extra_css_and_js() {
+  let context = AgoraTestContext::builder().build();
+  context.write(
+    "site/.agora.yaml",
+    "{extra-css: [theme.css], extra-js: [theme.js]}",
+  );
+  context.write("site/theme.css", "body { color: red; }");
+  context.write("site/theme.js", "");
+  context.create_dir_all("site/subdir");
+  let html = context.html("files/site/subdir/");
+  guard_unwrap!(let &[link] = css_select(&html, "link[href='/files/site/theme.css']").as_slice());
+  assert_eq!(link.value().attr("rel").unwrap(), "stylesheet");
+  guard_unwrap!(let &[_] = css_select(&html, "script[src='/files/site/theme.js']").as_slice());
+  assert_eq!(context.text("files/site/theme.css"), "body { color: red; }");
+}
+
+#[test]
+fn listing_can_be_served_as_plain_text() {
+  let context = AgoraTestContext::builder().build();
+  context.write("foo.txt", "hello");
+  context.write("big.bin", &"x".repeat(2048));
+  context.create_dir_all("dir");
+  let response = context.get("files/?format=txt");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "text/plain; charset=utf-8"
+  );
+  assert_eq!(
+    response.text().unwrap(),
+    "2.0 KiB  big.bin\n         dir/\n    5 B  foo.txt\n"
+  );
+}
+
+#[test]
+fn responses_are_compressed_when_gzip_is_accepted() {
+  let context = AgoraTestContext::builder().build();
+  let contents = "hello ".repeat(1000);
+  context.write("foo.txt", &contents);
+  context.write("foo.mp4", &contents);
+  let client = reqwest::blocking::Client::new();
+  let get = |path: &str, headers: &[(header::HeaderName, &str)]| {
+    let mut request = client.get(context.files_url().join(path).unwrap());
+    for (name, value) in headers {
+      request = request.header(name, *value);
+    }
+    request.send().unwrap()
+  };
+
+  let response = get("foo.txt", &[(header::ACCEPT_ENCODING, "gzip")]);
+  assert_eq!(
+    response.headers().get(header::CONTENT_ENCODING).unwrap(),
+    "gzip"
+  );
+  let mut decompressed = String::new();
+  flate2::read::GzDecoder::new(response.bytes().unwrap().as_ref())
+    .read_to_string(&mut decompressed)
+    .unwrap();
+  assert_eq!(decompressed, contents);
+
+  for response in vec![
+    get("foo.txt", &[]),
+    get(
+      "foo.txt",
+      &[
+        (header::ACCEPT_ENCODING, "gzip"),
+        (header::RANGE, "bytes=0-99"),
+      ],
+    ),
+    get("foo.mp4", &[(header::ACCEPT_ENCODING, "gzip")]),
+  ] {
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    assert_eq!(response.text().unwrap(), contents);
+  }
+}
+
+#[test]
+fn precompressed_sidecar_files_are_served_when_accepted() {
+  let context = AgoraTestContext::builder().build();
+  context.write("foo.js", "original");
+  context.write("foo.js.gz", "gzip");
+  context.write("foo.js.br", "brotli");
+  context.write("bar.js", "original");
+  std::fs::write(
+    context
+      .files_directory()
+      .parent()
+      .unwrap()
+      .join("escaping.gz"),
+    "escaping",
+  )
+  .unwrap();
+  symlink(
+    "../escaping.gz",
+    context.files_directory().join("bar.js.gz"),
+  );
+  let client = reqwest::blocking::Client::new();
+  let get = |path: &str, accept_encoding: &str| {
+    client
+      .get(context.files_url().join(path).unwrap())
+      .header(header::ACCEPT_ENCODING, accept_encoding)
+      .send()
+      .unwrap()
+  };
+
+  for (accept_encoding, content_encoding, contents) in &[
+    ("gzip", Some("gzip"), "gzip"),
+    ("gzip, br", Some("br"), "brotli"),
+    ("br;q=0, gzip", Some("gzip"), "gzip"),
+    ("identity", None, "original"),
+  ] {
+    let response = get("foo.js", accept_encoding);
+    assert_eq!(
+      response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .map(|value| value.to_str().unwrap()),
+      *content_encoding
+    );
+    assert_eq!(
+      response.headers().get(header::CONTENT_TYPE).unwrap(),
+      "text/javascript"
+    );
+    assert_eq!(response.text().unwrap(), *contents);
+  }
+
+  assert_eq!(get("bar.js", "gzip").status(), StatusCode::NOT_FOUND);
+  assert_eq!(get("bar.js", "identity").text().unwrap(), "original");
+}
+
+#[test]
+fn large_files_are_offered_as_torrents() {
+  let context = AgoraTestContext::builder()
+    .args(&[
+      "--torrent-min-size=10",
+      "--torrent-base-url=https://example.com/",
+      "--torrent-tracker=udp://tracker.example.com:6969",
+    ])
+    .build();
+  context.write("big file", &"x".repeat(20));
+  context.write("small", "x");
+  let html = context.html("files/");
+  guard_unwrap!(let &[a] = css_select(&html, "a.torrent").as_slice());
+  assert_eq!(a.value().attr("href").unwrap(), "big%20file?torrent");
+
+  let response = context.get("files/big%20file?torrent");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "application/x-bittorrent"
+  );
+  assert_eq!(
+    response.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+    "attachment; filename=\"big file.torrent\""
+  );
+  let torrent = String::from_utf8_lossy(&response.bytes().unwrap()).into_owned();
+  assert_contains(&torrent, "8:announce30:udp://tracker.example.com:6969");
+  assert_contains(&torrent, "6:lengthi20e4:name8:big file");
+  assert_contains(
+    &torrent,
+    "8:url-listl36:https://example.com/files/big%20fileee",
+  );
+
+  assert_eq!(context.status("files/small?torrent"), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn listing_can_be_served_as_json() {
+  let context = AgoraTestContext::builder().build();
+  context.write("dir/.agora.yaml", "{paid: true, base-price: 3 sat}");
+  context.write("dir/paid file", "hello");
+  context.write("free", "hello");
+  let modified = Regex::new(r#","modified":"\d{4}-\d\d-\d\dT\d\d:\d\d:\d\dZ""#).unwrap();
+  let without_modified = |json: String| modified.replace_all(&json, "").into_owned();
+  let expected = r#"[{"name":"dir","is_dir":true,"size":null,"paid":false,"base_price_msat":null,"href":"/files/dir/"},{"name":"free","is_dir":false,"size":5,"paid":false,"base_price_msat":null,"href":"/files/free"}]"#;
+  let response = context.get("files/?format=json");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "application/json"
+  );
+  assert_eq!(without_modified(response.text().unwrap()), expected);
+  let text = reqwest::blocking::Client::new()
+    .get(context.files_url().clone())
+    .header(header::ACCEPT, "application/json")
+    .send()
+    .unwrap()
+    .text()
+    .unwrap();
+  assert_eq!(modified.find_iter(&text).count(), 2);
+  assert_eq!(without_modified(text), expected);
+  assert_eq!(
+    without_modified(context.text("files/dir/?format=json")),
+    r#"[{"name":"paid file","is_dir":false,"size":5,"paid":true,"base_price_msat":3000,"href":"/files/dir/paid%20file"}]"#
+  );
+}
+
+#[test]
+fn listing_can_be_sorted_by_size_and_modification_time() {
+  let context = AgoraTestContext::builder().build();
+  for (name, content) in &[("b", "large file"), ("c", "s"), ("a", "medium")] {
+    context.write(name, content);
+    thread::sleep(Duration::from_millis(20));
+  }
+  let names = |path: &str| -> Vec<String> {
+    css_select(&context.html(path), ".listing a:not([download])")
+      .into_iter()
+      .map(|link| link.inner_html())
+      .collect()
+  };
+  assert_eq!(names("files/"), ["a", "b", "c"]);
+  assert_eq!(names("files/?order=desc"), ["c", "b", "a"]);
+  assert_eq!(names("files/?sort=size"), ["c", "a", "b"]);
+  assert_eq!(names("files/?sort=size&order=desc"), ["b", "a", "c"]);
+  assert_eq!(names("files/?sort=modified"), ["b", "c", "a"]);
+  assert_eq!(names("files/?sort=modified&order=desc"), ["a", "c", "b"]);
+  assert_eq!(
+    css_select(&context.html("files/"), ".listing .mtime time[datetime]").len(),
+    3
+  );
+}
+
+#[test]
+fn fast_listings_omit_sizes_and_modification_times() {
+  let context = AgoraTestContext::builder()
+    .args(&["--fast-listing"])
+    .build();
+  context.write("foo", "bar");
+  fs::create_dir(context.files_directory().join("dir")).unwrap();
+  symlink("dir", context.files_directory().join("dir-link"));
+  symlink("foo", context.files_directory().join("foo-link"));
   let html = context.html("files/");
-  guard_unwrap!(let &[a] = css_select(&html, ".listing a:not([download])").as_slice());
-  assert_eq!(a.value().attr("href").unwrap(), "%C3%85");
+  let names = css_select(&html, ".listing a:not([download])")
+    .into_iter()
+    .map(|link| link.inner_html())
+    .collect::<Vec<String>>();
+  assert_eq!(names, ["dir/", "dir-link/", "foo", "foo-link"]);
+  assert_eq!(css_select(&html, ".listing a[download]").len(), 2);
+  assert!(css_select(&html, ".listing .filesize").is_empty());
+  assert!(css_select(&html, ".listing .mtime").is_empty());
+  assert_eq!(
+    context.text("files/?format=json"),
+    r#"[{"name":"dir","is_dir":true,"size":null,"paid":false,"base_price_msat":null,"modified":null,"href":"/files/dir/"},{"name":"dir-link","is_dir":true,"size":null,"paid":false,"base_price_msat":null,"modified":null,"href":"/files/dir-link/"},{"name":"foo","is_dir":false,"size":null,"paid":false,"base_price_msat":null,"modified":null,"href":"/files/foo"},{"name":"foo-link","is_dir":false,"size":null,"paid":false,"base_price_msat":null,"modified":null,"href":"/files/foo-link"}]"#
+  );
 }
 
 #[test]
-fn requesting_paid_file_with_no_lnd_returns_internal_error() {
+fn listing_is_plain_text_when_only_plain_text_is_accepted() {
   let context = AgoraTestContext::builder().build();
-  context.write(".agora.yaml", "paid: true");
-  context.write("foo", "precious content");
-  let status = reqwest::blocking::get(context.files_url().join("foo").unwrap())
+  context.write("foo.txt", "hello");
+  let client = reqwest::blocking::Client::new();
+  let text = client
+    .get(context.files_url().clone())
+    .header(header::ACCEPT, "text/plain")
+    .send()
     .unwrap()
-    .status();
-  assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
-
-  let stderr = context.kill();
-  assert_contains(
-    &stderr,
-    &format!(
-      "Paid file request requires LND client configuration: `files{}foo`",
-      MAIN_SEPARATOR
-    ),
-  );
+    .text()
+    .unwrap();
+  assert_eq!(text, "5 B  foo.txt\n");
+  let text = client
+    .get(context.files_url().clone())
+    .header(header::ACCEPT, "text/html,text/plain;q=0.9")
+    .send()
+    .unwrap()
+    .text()
+    .unwrap();
+  assert_contains(&text, "<html");
 }
 
 #[test]
-fn displays_index_markdown_files_as_html() {
+fn extra_css_must_not_be_hidden() {
   let context = AgoraTestContext::builder().build();
-  context.write(".index.md", "# test header");
-  let html = context.html("files/");
-  guard_unwrap!(let &[index_header] = css_select(&html, "h1").as_slice());
-  assert_eq!(index_header.inner_html(), "test header");
+  context.write(".agora.yaml", "extra-css: [.theme.css]");
+  context.write(".theme.css", "");
+  assert_eq!(context.status("files/"), StatusCode::NOT_FOUND);
+  let stderr = context.kill();
+  assert_contains(&stderr, "Forbidden access to hidden file");
 }
 
 #[test]
@@ -776,6 +2695,53 @@ fn disallow_file_downloads_via_escaping_symlinks() {
   );
 }
 
+#[test]
+fn escaping_symlinks_can_be_followed() {
+  let context = AgoraTestContext::builder()
+    .args(&["--follow-escaping-symlinks"])
+    .build();
+  context.write("../file", "contents");
+  symlink("../file", context.files_directory().join("link"));
+  let html = context.html("files/");
+  guard_unwrap!(let &[a] = css_select(&html, ".listing a:not([download])").as_slice());
+  assert_eq!(a.inner_html(), "link");
+  assert_eq!(context.text("files/link"), "contents");
+}
+
+#[test]
+fn escaping_symlink_denials_are_logged_as_warnings_with_client_ip() {
+  let context = AgoraTestContext::builder()
+    .env("RUST_LOG", "agora=warn")
+    .build();
+  context.write("../file", "contents");
+  symlink("../file", context.files_directory().join("link"));
+  assert_eq!(context.status("files/link"), StatusCode::NOT_FOUND);
+  let stderr = context.kill();
+  let line = stderr
+    .lines()
+    .find(|line| line.contains("WARN"))
+    .expect("no warning logged");
+  assert_contains(line, "Request from ");
+  assert!(
+    line.contains("127.0.0.1") || line.contains("::1"),
+    "line: {}",
+    line
+  );
+  assert_contains(line, "denied: Forbidden access to escaping symlink");
+}
+
+#[test]
+fn hidden_file_denials_are_not_logged_as_warnings() {
+  let context = AgoraTestContext::builder()
+    .env("RUST_LOG", "agora=warn")
+    .build();
+  context.write(".hidden", "");
+  assert_eq!(context.status("files/.hidden"), StatusCode::NOT_FOUND);
+  let stderr = context.kill();
+  assert_contains(&stderr, "Forbidden access to hidden file");
+  assert_not_contains(&stderr, "WARN");
+}
+
 #[test]
 fn disallow_file_downloads_via_absolute_escaping_symlinks() {
   let context = AgoraTestContext::builder().build();
@@ -887,6 +2853,84 @@ fn configure_files_directory() {
   assert_eq!(file_contents, "hello");
 }
 
+/// Respond to a single HTTP request with `response`, returning the request.
+fn serve_upstream_once(response: &'static str) -> (u16, thread::JoinHandle<String>) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let port = listener.local_addr().unwrap().port();
+  let handle = thread::spawn(move || {
+    let (mut stream, _) = listener.accept().unwrap();
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+      let n = stream.read(&mut buffer).unwrap();
+      assert!(n > 0, "connection closed before end of request");
+      request.extend_from_slice(&buffer[..n]);
+    }
+    stream.write_all(response.as_bytes()).unwrap();
+    String::from_utf8(request).unwrap()
+  });
+  (port, handle)
+}
+
+#[test]
+fn proxied_files_are_streamed_from_upstream() {
+  let (port, upstream) = serve_upstream_once(
+    "HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+  );
+  let context = AgoraTestContext::builder().build();
+  context.write(
+    "dir/.agora.yaml",
+    &format!(
+      "proxy: {{foo.mp4: 'http://127.0.0.1:{}/upstream.mp4'}}",
+      port
+    ),
+  );
+  let response = reqwest::blocking::Client::new()
+    .get(context.files_url().join("dir/foo.mp4").unwrap())
+    .header(header::COOKIE, "secret")
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "video/mp4"
+  );
+  assert_eq!(response.text().unwrap(), "hello");
+  let request = upstream.join().unwrap();
+  assert!(request.starts_with("GET /upstream.mp4 HTTP/1.1\r\n"));
+  assert!(!request.to_lowercase().contains("cookie"), "{}", request);
+  assert_eq!(context.status("files/foo.mp4"), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn proxied_files_cannot_be_priced_per_byte() {
+  let context = AgoraTestContext::builder().build();
+  context.write(
+    ".agora.yaml",
+    "{paid: true, price-per-byte: 1 msat, proxy: {foo: 'http://127.0.0.1:1/foo'}}",
+  );
+  assert_eq!(
+    context.status("files/foo"),
+    StatusCode::INTERNAL_SERVER_ERROR
+  );
+  assert_contains(
+    &context.kill(),
+    "combines `proxy` with `price-per-byte`, but proxied files have no known size",
+  );
+}
+
+#[test]
+fn upstream_errors_for_proxied_files_are_bad_gateway() {
+  let (port, _upstream) =
+    serve_upstream_once("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+  let context = AgoraTestContext::builder().build();
+  context.write(
+    ".agora.yaml",
+    &format!("proxy: {{foo: 'http://127.0.0.1:{}/foo'}}", port),
+  );
+  assert_eq!(context.status("files/foo"), StatusCode::BAD_GATEWAY);
+}
+
 #[test]
 fn configure_port() {
   let free_port = {
@@ -933,6 +2977,215 @@ fn creates_cert_cache_directory_if_it_doesnt_exist() {
   panic!("Cache directory not created after ten seconds");
 }
 
+#[test]
+fn print_config_prints_effective_arguments_and_exits() {
+  let output = Command::new(executable_path("agora"))
+    .arg("--directory=does/not/exist")
+    .arg("--http-port=0")
+    .arg("--print-config")
+    .output()
+    .unwrap();
+
+  assert!(output.status.success());
+
+  let stdout = str::from_utf8(&output.stdout).unwrap();
+  assert_contains(stdout, "directory: does/not/exist\n");
+  assert_contains(stdout, "http2-max-concurrent-streams: 100\n");
+}
+
+#[test]
+fn check_subcommand_reports_invalid_config_files() {
+  let tempdir = tempfile::tempdir().unwrap();
+  fs::create_dir_all(tempdir.path().join("www/foo")).unwrap();
+  fs::create_dir_all(tempdir.path().join("www/bar")).unwrap();
+  fs::write(tempdir.path().join("www/.agora.yaml"), "paid: false").unwrap();
+  fs::write(tempdir.path().join("www/foo/.agora.yaml"), "paid: 1000 sat").unwrap();
+  fs::write(tempdir.path().join("www/bar/.agora.yaml"), "\nunknown: 1").unwrap();
+
+  let output = Command::new(executable_path("agora"))
+    .current_dir(tempdir.path())
+    .arg("check")
+    .arg("--directory=www")
+    .output()
+    .unwrap();
+
+  assert!(!output.status.success());
+
+  let stderr = str::from_utf8(&output.stderr).unwrap();
+  assert_contains(stderr, "www/bar/.agora.yaml");
+  assert_contains(stderr, "unknown field `unknown`");
+  assert_contains(stderr, "at line 2");
+  assert_contains(stderr, "www/foo/.agora.yaml");
+  assert_contains(stderr, "Found 2 invalid config file(s)");
+
+  fs::write(tempdir.path().join("www/foo/.agora.yaml"), "paid: true").unwrap();
+  fs::remove_file(tempdir.path().join("www/bar/.agora.yaml")).unwrap();
+
+  let output = Command::new(executable_path("agora"))
+    .current_dir(tempdir.path())
+    .arg("check")
+    .arg("--directory=www")
+    .output()
+    .unwrap();
+
+  assert!(output.status.success());
+  assert_eq!(str::from_utf8(&output.stderr).unwrap(), "");
+}
+
+#[test]
+fn bundled_directory_is_served_without_directory_argument() {
+  let tempdir = tempfile::tempdir().unwrap();
+  fs::create_dir_all(tempdir.path().join("www/dir")).unwrap();
+  fs::write(tempdir.path().join("www/dir/foo.txt"), "foo").unwrap();
+  fs::write(tempdir.path().join("www/.hidden.txt"), "").unwrap();
+
+  let output = Command::new(executable_path("agora"))
+    .current_dir(tempdir.path())
+    .arg("bundle")
+    .arg("--directory=www")
+    .arg("--output=bundled")
+    .output()
+    .unwrap();
+  assert!(output.status.success());
+
+  fs::remove_dir_all(tempdir.path().join("www")).unwrap();
+
+  let mut child = Command::new(tempdir.path().join("bundled"))
+    .current_dir(tempdir.path())
+    .arg("--address=localhost")
+    .arg("--http-port=0")
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+  let mut first_line = String::new();
+  BufReader::new(child.stderr.take().unwrap())
+    .read_line(&mut first_line)
+    .unwrap();
+  let port = first_line
+    .trim()
+    .trim_end_matches('`')
+    .split(':')
+    .last()
+    .unwrap();
+
+  let get =
+    |path: &str| reqwest::blocking::get(format!("http://localhost:{}/files/{}", port, path));
+  let response = get("dir/foo.txt");
+  let hidden = get(".hidden.txt");
+  child.kill().unwrap();
+  child.wait().unwrap();
+
+  let response = response.unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(response.text().unwrap(), "foo");
+  assert_eq!(hidden.unwrap().status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn options_requests_advertise_webdav() {
+  let context = AgoraTestContext::builder().build();
+  let response = reqwest::blocking::Client::new()
+    .request(reqwest::Method::OPTIONS, context.files_url().clone())
+    .send()
+    .unwrap();
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(response.headers().get("dav").unwrap(), "1");
+  assert_contains(
+    response
+      .headers()
+      .get(header::ALLOW)
+      .unwrap()
+      .to_str()
+      .unwrap(),
+    "PROPFIND",
+  );
+}
+
+#[test]
+fn oversized_request_bodies_are_rejected() {
+  let context = AgoraTestContext::builder()
+    .args(&["--max-request-body=4"])
+    .build();
+  context.write("foo", "bar");
+  let get = |body: &'static str| {
+    reqwest::blocking::Client::new()
+      .get(context.files_url().join("foo").unwrap())
+      .body(body)
+      .send()
+      .unwrap()
+  };
+  let response = get("1234");
+  assert_eq!(response.status(), StatusCode::OK);
+  assert_eq!(response.text().unwrap(), "bar");
+  assert_eq!(get("12345").status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[test]
+fn request_bodies_of_unknown_length_are_rejected() {
+  let context = AgoraTestContext::builder().build();
+  context.write("foo", "bar");
+  let mut stream =
+    std::net::TcpStream::connect(format!("localhost:{}", context.base_url().port().unwrap()))
+      .unwrap();
+  stream
+    .write_all(
+      b"GET /files/foo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n7\r\nchunked\r\n0\r\n\r\n",
+    )
+    .unwrap();
+  let response = &mut [0; 1024];
+  let bytes = stream.read(response).unwrap();
+  let response = str::from_utf8(&response[..bytes]).unwrap();
+  assert_contains(response, "HTTP/1.1 411 Length Required");
+}
+
+#[test]
+fn propfind_lists_free_files_and_directories() {
+  let context = AgoraTestContext::builder().build();
+  context.write("foo", "bar");
+  context.write("dir/baz", "");
+  context.write("paid/.agora.yaml", "{paid: true, base-price: 1000 sat}");
+  context.write("paid/secret", "precious content");
+  let propfind = |path: &str, depth: &str| {
+    reqwest::blocking::Client::new()
+      .request(
+        reqwest::Method::from_bytes(b"PROPFIND").unwrap(),
+        context.files_url().join(path).unwrap(),
+      )
+      .header("depth", depth)
+      .send()
+      .unwrap()
+  };
+  let hrefs = |xml: &str| {
+    Regex::new("<D:href>([^<]*)</D:href>")
+      .unwrap()
+      .captures_iter(xml)
+      .map(|captures| captures[1].to_owned())
+      .collect::<Vec<String>>()
+  };
+
+  let response = propfind("", "1");
+  assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+  let xml = response.text().unwrap();
+  assert_eq!(
+    hrefs(&xml),
+    ["/files/", "/files/dir/", "/files/foo", "/files/paid/"]
+  );
+  assert_contains(&xml, "<D:displayname>foo</D:displayname>");
+  assert_contains(&xml, "<D:getcontentlength>3</D:getcontentlength>");
+  assert_contains(&xml, "<D:resourcetype><D:collection/></D:resourcetype>");
+
+  assert_eq!(
+    hrefs(&propfind("dir", "0").text().unwrap()),
+    ["/files/dir/"]
+  );
+  assert_eq!(
+    hrefs(&propfind("paid/", "1").text().unwrap()),
+    ["/files/paid/"]
+  );
+  assert_eq!(propfind("paid/secret", "0").status(), StatusCode::NOT_FOUND);
+  assert_eq!(propfind("", "infinity").status(), StatusCode::FORBIDDEN);
+}
+
 #[test]
 fn server_aborts_when_directory_does_not_exist() {
   let output = Command::new(executable_path("agora"))
@@ -965,3 +3218,141 @@ fn errors_printed_in_red_and_bold() {
 
   assert_contains(stderr, "\u{1b}[31merror\u{1b}[0m\u{1b}[1m: ");
 }
+
+#[test]
+fn proxied_paid_files_can_be_requested_with_lnurl_pay() {
+  let context = AgoraTestContext::builder()
+    .args(&["--lnurl-base-url=https://example.com/"])
+    .build();
+  context.write(
+    ".agora.yaml",
+    "{paid: true, base-price: 3 sat, proxy: {foo: 'http://127.0.0.1:1/foo'}}",
+  );
+  assert_eq!(
+    context.text("lnurlp/foo"),
+    r#"{"callback":"https://example.com/lnurlp/foo","maxSendable":3000,"minSendable":3000,"metadata":"[[\"text/plain\",\"Access to foo\"]]","tag":"payRequest"}"#
+  );
+}
+
+#[test]
+fn paid_files_can_be_requested_with_lnurl_pay() {
+  let context = AgoraTestContext::builder()
+    .args(&["--lnurl-base-url=https://example.com/"])
+    .build();
+  context.write("dir/.agora.yaml", "{paid: true, base-price: 3 sat}");
+  context.write("dir/paid file", "hello");
+  context.write("free", "hello");
+  let response = context.get("lnurlp/dir/paid%20file");
+  assert_eq!(
+    response.headers().get(header::CONTENT_TYPE).unwrap(),
+    "application/json"
+  );
+  assert_eq!(
+    response.text().unwrap(),
+    r#"{"callback":"https://example.com/lnurlp/dir/paid%20file","maxSendable":3000,"minSendable":3000,"metadata":"[[\"text/plain\",\"Access to dir/paid file\"]]","tag":"payRequest"}"#
+  );
+  assert_eq!(
+    context.text("lnurlp/dir/paid%20file?amount=1000"),
+    r#"{"status":"ERROR","reason":"Invalid amount `1000`, expected 3000 millisatoshis"}"#
+  );
+  assert_eq!(context.status("lnurlp/free"), StatusCode::NOT_FOUND);
+  assert_eq!(context.status("lnurlp/dir/"), StatusCode::NOT_FOUND);
+  assert_contains(
+    &context.kill(),
+    "LNURL-pay is only available for paid files: `free`",
+  );
+}
+
+#[test]
+fn lnurl_pay_requires_base_url() {
+  let context = AgoraTestContext::builder().build();
+  context.write(".agora.yaml", "{paid: true, base-price: 3 sat}");
+  context.write("foo", "hello");
+  assert_eq!(context.status("lnurlp/foo"), StatusCode::NOT_FOUND);
+  assert_contains(
+    &context.kill(),
+    "LNURL-pay request requires `--lnurl-base-url`: /lnurlp/foo",
+  );
+}
+
+#[test]
+fn lightning_addresses_serve_pay_requests() {
+  let context = AgoraTestContext::builder()
+    .args(&["--lnurl-base-url=https://example.com"])
+    .build();
+  context.write(
+    ".agora.yaml",
+    "{lightning-addresses: {alice: {amount: 1 sat}, bob: {amount: 2 sat, description: Tips}}}",
+  );
+  assert_eq!(
+    context.text(".well-known/lnurlp/alice"),
+    r#"{"callback":"https://example.com/.well-known/lnurlp/alice","maxSendable":1000,"minSendable":1000,"metadata":"[[\"text/plain\",\"Payment to alice@example.com\"],[\"text/identifier\",\"alice@example.com\"]]","tag":"payRequest"}"#
+  );
+  assert_contains(
+    &context.text(".well-known/lnurlp/bob"),
+    r#""metadata":"[[\"text/plain\",\"Tips\"],[\"text/identifier\",\"bob@example.com\"]]""#,
+  );
+  assert_eq!(
+    context.text(".well-known/lnurlp/alice?amount=2000"),
+    r#"{"status":"ERROR","reason":"Invalid amount `2000`, expected 1000 millisatoshis"}"#
+  );
+  assert_eq!(
+    context.status(".well-known/lnurlp/carol"),
+    StatusCode::NOT_FOUND
+  );
+  assert_contains(&context.kill(), "Lightning address not found: carol");
+}
+
+#[test]
+fn invoice_amount_is_limited_by_max_invoice_amount() {
+  let context = AgoraTestContext::builder()
+    .args(&[
+      "--lnurl-base-url=https://example.com",
+      "--max-invoice-amount=1000 sat",
+    ])
+    .build();
+  context.write("cheap/.agora.yaml", "{paid: true, base-price: 1000 sat}");
+  context.write("cheap/foo", "hello");
+  context.write(
+    "expensive/.agora.yaml",
+    "{paid: true, base-price: 1001 sat}",
+  );
+  context.write("expensive/foo", "hello");
+  assert_eq!(context.status("lnurlp/cheap/foo"), StatusCode::OK);
+  assert_eq!(
+    context.status("lnurlp/expensive/foo"),
+    StatusCode::INTERNAL_SERVER_ERROR
+  );
+  assert_contains(
+    &context.kill(),
+    &format!(
+      "Invoice amount of 1,001 satoshis for `files{}expensive{}foo` exceeds `--max-invoice-amount` of 1,000 satoshis",
+      MAIN_SEPARATOR, MAIN_SEPARATOR
+    ),
+  );
+}
+
+#[test]
+fn paid_files_can_be_priced_per_byte() {
+  let context = AgoraTestContext::builder()
+    .args(&["--lnurl-base-url=https://example.com"])
+    .build();
+  context.write(
+    "per-byte/.agora.yaml",
+    "{paid: true, price-per-byte: 2 msat}",
+  );
+  context.write("per-byte/foo", "hello");
+  context.write(
+    "both/.agora.yaml",
+    "{paid: true, base-price: 1 sat, price-per-byte: 2 msat}",
+  );
+  context.write("both/foo", "hello");
+  assert_contains(
+    &context.text("lnurlp/per-byte/foo"),
+    r#""maxSendable":10,"minSendable":10,"#,
+  );
+  assert_contains(
+    &context.text("lnurlp/both/foo"),
+    r#""maxSendable":1010,"minSendable":1010,"#,
+  );
+}